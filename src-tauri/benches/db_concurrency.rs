@@ -0,0 +1,98 @@
+//! 数据库并发基准
+//!
+//! 测量截图循环 `insert_trace` 的延迟分布（p50/p99），
+//! 分别在无并发读与多个线程持续执行混合搜索/会话查询时运行，用于验证读写分离的效果。
+//!
+//! 运行：`cargo bench --bench db_concurrency`
+
+use engram_lib::db::{Database, NewTrace};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const INSERTS: usize = 500;
+const SEED_TRACES: usize = 2000;
+const READER_THREADS: usize = 4;
+
+fn new_trace(i: usize) -> NewTrace {
+    NewTrace {
+        timestamp: chrono::Utc::now().timestamp_millis() + i as i64,
+        image_path: format!("bench/{}.webp", i),
+        app_name: Some(format!("App{}", i % 8)),
+        window_title: Some(format!("Window {} 文档", i)),
+        is_fullscreen: false,
+        is_idle: false,
+        ocr_text: Some(format!("benchmark trace {} 搜索 关键词", i)),
+        phash: None,
+    }
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let idx = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+    sorted[idx]
+}
+
+fn run(db: &Arc<Database>, offset: usize, with_readers: bool) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut handles = Vec::new();
+
+    if with_readers {
+        for r in 0..READER_THREADS {
+            let db = db.clone();
+            let stop = stop.clone();
+            handles.push(thread::spawn(move || {
+                let mut queries = 0u64;
+                while !stop.load(Ordering::Relaxed) {
+                    if r % 2 == 0 {
+                        let _ = db.hybrid_search("benchmark 关键词", None, "", None, 50);
+                    } else {
+                        let _ = db.get_activity_sessions(0, i64::MAX, None, 100, 0);
+                    }
+                    queries += 1;
+                }
+                queries
+            }));
+        }
+    }
+
+    let mut latencies = Vec::with_capacity(INSERTS);
+    for i in 0..INSERTS {
+        let trace = new_trace(offset + i);
+        let start = Instant::now();
+        db.insert_trace(&trace).expect("insert_trace failed");
+        latencies.push(start.elapsed());
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    let queries: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+
+    latencies.sort();
+    println!(
+        "{:<28} p50={:>8.3}ms p99={:>8.3}ms max={:>8.3}ms concurrent_queries={}",
+        if with_readers {
+            "insert_trace + readers"
+        } else {
+            "insert_trace (idle)"
+        },
+        percentile(&latencies, 0.50).as_secs_f64() * 1000.0,
+        percentile(&latencies, 0.99).as_secs_f64() * 1000.0,
+        latencies.last().unwrap().as_secs_f64() * 1000.0,
+        queries
+    );
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("engram-bench-{}", uuid::Uuid::new_v4()));
+    let db = Arc::new(Database::open_at(dir.clone()).expect("open database"));
+
+    for i in 0..SEED_TRACES {
+        db.insert_trace(&new_trace(i)).expect("seed failed");
+    }
+
+    run(&db, SEED_TRACES, false);
+    run(&db, SEED_TRACES + INSERTS, true);
+
+    drop(db);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -4,12 +4,26 @@
 //! 1. OpenAI 兼容 API（优先）- 远程或本地服务
 //! 2. 本地 fastembed（回退）- 离线可用
 //!
+//! 本地模型由 `EmbeddingConfig::local_model` 选择（默认 multilingual-e5-small，适配中文
+//! 输出），下载到数据目录的 `models/`。离线环境可预先放置模型文件：
+//! - HuggingFace 缓存布局：`models/models--{org}--{name}/`（从联网机器的缓存目录拷贝）
+//! - 自定义模型目录：`models/{local_model}/` 下放 `model.onnx` 与 tokenizer 文件
+//!   （可用于 fastembed 未内置的模型，如 bge-m3 的 ONNX 导出）
+//!
+//! 向量维度以实际返回的向量为准，不再按模型名猜测；每条向量写库时记录 [`TextEmbedder::model_id`]，
+//! 切换模型后旧向量会被重新嵌入。
+//!
+//! e5 系列模型按训练方式给文本加前缀：检索词用 `query: `（[`TextEmbedder::embed_query`]），
+//! 被检索的 trace 文本用 `passage: `（[`TextEmbedder::embed_batch`]）。
+//!
 //! 性能优化：
 //! - 批量处理：累积文本后批量嵌入
 //! - 内存管理：模型按需加载，空闲时可释放
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
 use tracing::{debug, info, warn};
@@ -39,22 +53,174 @@ pub struct EmbeddingConfig {
     /// API 密钥
     #[serde(default)]
     pub api_key: Option<String>,
+    /// 本地 fastembed 模型（预设名、fastembed 模型代码或 models/ 下的自定义目录名）
+    #[serde(default = "default_local_model")]
+    pub local_model: String,
+    /// 离线模式：本地模型只从 models/ 加载，不联网下载
+    #[serde(default)]
+    pub offline: bool,
 }
 
 fn default_model() -> String {
     "text-embedding-3-small".to_string()
 }
 
+fn default_local_model() -> String {
+    "multilingual-e5-small".to_string()
+}
+
 impl Default for EmbeddingConfig {
     fn default() -> Self {
         Self {
             endpoint: None, // 默认使用本地
             model: default_model(),
             api_key: None,
+            local_model: default_local_model(),
+            offline: false,
         }
     }
 }
 
+/// 本地模型预设（配置名 → fastembed 模型）
+const LOCAL_MODEL_PRESETS: &[(&str, fastembed::EmbeddingModel)] = &[
+    (
+        "multilingual-e5-small",
+        fastembed::EmbeddingModel::MultilingualE5Small,
+    ),
+    (
+        "multilingual-e5-base",
+        fastembed::EmbeddingModel::MultilingualE5Base,
+    ),
+    (
+        "multilingual-e5-large",
+        fastembed::EmbeddingModel::MultilingualE5Large,
+    ),
+    (
+        "bge-small-zh-v1.5",
+        fastembed::EmbeddingModel::BGESmallZHV15,
+    ),
+    (
+        "bge-large-zh-v1.5",
+        fastembed::EmbeddingModel::BGELargeZHV15,
+    ),
+    (
+        "paraphrase-multilingual-minilm-l12-v2",
+        fastembed::EmbeddingModel::ParaphraseMLMiniLML12V2,
+    ),
+    ("all-minilm-l6-v2", fastembed::EmbeddingModel::AllMiniLML6V2),
+];
+
+/// 自定义模型目录中的文件
+const CUSTOM_MODEL_FILE: &str = "model.onnx";
+const CUSTOM_TOKENIZER_FILES: [&str; 4] = [
+    "tokenizer.json",
+    "config.json",
+    "special_tokens_map.json",
+    "tokenizer_config.json",
+];
+
+/// 本地模型信息（用于设置页选择）
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalEmbeddingModel {
+    /// 配置名
+    pub name: String,
+    /// HuggingFace 模型代码
+    pub model_code: String,
+    pub dim: usize,
+    pub description: String,
+    /// 模型文件是否已在 models/ 中（离线可用）
+    pub installed: bool,
+}
+
+/// 按配置名解析 fastembed 模型（预设名或模型代码，不区分大小写）
+fn resolve_local_model(name: &str) -> Option<fastembed::EmbeddingModel> {
+    let name = name.trim();
+    LOCAL_MODEL_PRESETS
+        .iter()
+        .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+        .map(|(_, model)| model.clone())
+        .or_else(|| name.parse().ok())
+}
+
+/// 自定义模型目录（存在 model.onnx 时）
+///
+/// 只接受 models/ 下的单级目录名，`..`、绝对路径或带分隔符的名称不会解析到 models/ 之外。
+fn custom_model_dir(models_dir: &Path, name: &str) -> Option<PathBuf> {
+    let name = Path::new(name.trim());
+    let mut components = name.components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(std::path::Component::Normal(_)), None)
+    ) {
+        return None;
+    }
+    let dir = models_dir.join(name);
+    dir.join(CUSTOM_MODEL_FILE).is_file().then_some(dir)
+}
+
+/// 是否为 e5 系列模型（名称中有独立的 `e5` 片段，如 multilingual-e5-small、intfloat/e5-base-v2）
+fn is_e5_model(name: &str) -> bool {
+    name.to_ascii_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .any(|part| part == "e5")
+}
+
+/// 文本在检索中的角色
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EmbeddingRole {
+    /// 检索词
+    Query,
+    /// 被检索的文档
+    Passage,
+}
+
+/// fastembed 模型文件是否已在 HuggingFace 缓存布局中
+fn is_model_cached(models_dir: &Path, model: &fastembed::EmbeddingModel) -> bool {
+    let Ok(info) = fastembed::TextEmbedding::get_model_info(model) else {
+        return false;
+    };
+    let repo_dir = models_dir.join(format!("models--{}", info.model_code.replace('/', "--")));
+    let Ok(snapshots) = std::fs::read_dir(repo_dir.join("snapshots")) else {
+        return false;
+    };
+    snapshots.flatten().any(|snapshot| {
+        let dir = snapshot.path();
+        dir.join(&info.model_file).is_file()
+            && info.additional_files.iter().all(|f| dir.join(f).is_file())
+    })
+}
+
+/// 列出可选的本地模型及其安装状态
+pub fn list_local_models(models_dir: &Path) -> Vec<LocalEmbeddingModel> {
+    LOCAL_MODEL_PRESETS
+        .iter()
+        .filter_map(|(name, model)| {
+            let info = fastembed::TextEmbedding::get_model_info(model).ok()?;
+            Some(LocalEmbeddingModel {
+                name: name.to_string(),
+                model_code: info.model_code.clone(),
+                dim: info.dim,
+                description: info.description.clone(),
+                installed: is_model_cached(models_dir, model),
+            })
+        })
+        .collect()
+}
+
+/// 读取 sentence-transformers 目录中的池化配置（缺省为 CLS）
+fn read_custom_pooling(dir: &Path) -> fastembed::Pooling {
+    let mean = std::fs::read_to_string(dir.join("1_Pooling").join("config.json"))
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .and_then(|v| v.get("pooling_mode_mean_tokens").and_then(|m| m.as_bool()))
+        .unwrap_or(false);
+    if mean {
+        fastembed::Pooling::Mean
+    } else {
+        fastembed::Pooling::Cls
+    }
+}
+
 impl EmbeddingConfig {
     /// 创建本地配置
     pub fn local() -> Self {
@@ -67,6 +233,7 @@ impl EmbeddingConfig {
             endpoint: Some("https://api.openai.com/v1".to_string()),
             model: "text-embedding-3-small".to_string(),
             api_key: Some(api_key.to_string()),
+            ..Self::default()
         }
     }
 
@@ -76,6 +243,7 @@ impl EmbeddingConfig {
             endpoint: Some(endpoint.to_string()),
             model: model.to_string(),
             api_key: api_key.map(|s| s.to_string()),
+            ..Self::default()
         }
     }
 
@@ -85,6 +253,7 @@ impl EmbeddingConfig {
             endpoint: Some("http://127.0.0.1:11434/v1".to_string()),
             model: model.to_string(),
             api_key: None,
+            ..Self::default()
         }
     }
 }
@@ -101,8 +270,10 @@ pub struct TextEmbedder {
     local_model: Mutex<Option<fastembed::TextEmbedding>>,
     /// 是否已初始化
     initialized: bool,
-    /// 向量维度
-    embedding_dim: usize,
+    /// 本地模型下载 / 加载目录（None 时使用 fastembed 默认缓存目录）
+    models_dir: Option<PathBuf>,
+    /// 向量维度（以实际返回的向量为准，0 表示尚未得知）
+    embedding_dim: AtomicUsize,
     /// 最后使用时间（用于内存管理）
     last_used: Mutex<Instant>,
     /// 模型空闲超时（秒）
@@ -127,14 +298,6 @@ impl TextEmbedder {
             EmbeddingBackend::Local
         };
 
-        // 根据模型设置维度
-        let embedding_dim = match config.model.as_str() {
-            "text-embedding-3-small" => 1536,
-            "text-embedding-3-large" => 3072,
-            "text-embedding-ada-002" => 1536,
-            _ => 384, // MiniLM 默认
-        };
-
         Self {
             config,
             backend,
@@ -144,12 +307,19 @@ impl TextEmbedder {
                 .unwrap(),
             local_model: Mutex::new(None),
             initialized: false,
-            embedding_dim,
+            models_dir: None,
+            embedding_dim: AtomicUsize::new(0),
             last_used: Mutex::new(Instant::now()),
             idle_timeout_secs: 300, // 5 分钟空闲后可释放
         }
     }
 
+    /// 设置本地模型目录（通常为 `Database::get_models_dir`）
+    pub fn with_models_dir(mut self, models_dir: PathBuf) -> Self {
+        self.models_dir = Some(models_dir);
+        self
+    }
+
     /// 初始化嵌入器
     pub async fn initialize(&mut self) -> Result<()> {
         if self.initialized {
@@ -230,21 +400,70 @@ impl TextEmbedder {
 
     /// 初始化本地模型
     fn init_local_model(&mut self) -> Result<()> {
-        info!("Initializing local embedding model (all-MiniLM-L6-v2)...");
+        let name = self.config.local_model.trim().to_string();
+        info!("Initializing local embedding model ({})...", name);
 
-        let model = fastembed::TextEmbedding::try_new(
-            fastembed::InitOptions::new(fastembed::EmbeddingModel::AllMiniLML6V2)
-                .with_show_download_progress(true),
-        )?;
+        let models_dir = self.models_dir.clone();
+        if let Some(dir) = &models_dir {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let (model, dim) = if let Some(dir) = models_dir
+            .as_deref()
+            .and_then(|d| custom_model_dir(d, &name))
+        {
+            (Self::load_custom_model(&dir)?, 0)
+        } else {
+            let model_name = resolve_local_model(&name)
+                .ok_or_else(|| anyhow!("Unknown local embedding model: {}", name))?;
+            let info = fastembed::TextEmbedding::get_model_info(&model_name)?;
+            let dim = info.dim;
+
+            let mut options =
+                fastembed::InitOptions::new(model_name.clone()).with_show_download_progress(true);
+            if let Some(dir) = &models_dir {
+                if self.config.offline && !is_model_cached(dir, &model_name) {
+                    return Err(anyhow!(
+                        "Embedding model {} is not provisioned in {:?} (offline mode)",
+                        info.model_code,
+                        dir
+                    ));
+                }
+                options = options.with_cache_dir(dir.clone());
+            }
+            (fastembed::TextEmbedding::try_new(options)?, dim)
+        };
 
         *self.local_model.lock().unwrap() = Some(model);
-        self.embedding_dim = 384;
+        self.embedding_dim.store(dim, Ordering::SeqCst);
         self.backend = EmbeddingBackend::Local;
 
         info!("Local embedding model initialized");
         Ok(())
     }
 
+    /// 从自定义目录加载 ONNX 模型（维度在第一次嵌入时确定）
+    fn load_custom_model(dir: &Path) -> Result<fastembed::TextEmbedding> {
+        info!("Loading custom embedding model from {:?}", dir);
+        let read = |name: &str| {
+            std::fs::read(dir.join(name)).with_context(|| format!("Failed to read {}", name))
+        };
+        let [tokenizer, config, special_tokens, tokenizer_config] = CUSTOM_TOKENIZER_FILES;
+        let tokenizer_files = fastembed::TokenizerFiles {
+            tokenizer_file: read(tokenizer)?,
+            config_file: read(config)?,
+            special_tokens_map_file: read(special_tokens)?,
+            tokenizer_config_file: read(tokenizer_config)?,
+        };
+        let model =
+            fastembed::UserDefinedEmbeddingModel::new(read(CUSTOM_MODEL_FILE)?, tokenizer_files)
+                .with_pooling(read_custom_pooling(dir));
+        fastembed::TextEmbedding::try_new_from_user_defined(
+            model,
+            fastembed::InitOptionsUserDefined::new(),
+        )
+    }
+
     /// 回退到本地模式
     fn fallback_to_local(&mut self) -> Result<()> {
         self.backend = EmbeddingBackend::Local;
@@ -256,9 +475,59 @@ impl TextEmbedder {
         self.initialized
     }
 
-    /// 获取向量维度
+    /// 获取向量维度（尚未产生过向量且无法从模型信息得知时为 0）
     pub fn embedding_dim(&self) -> usize {
-        self.embedding_dim
+        self.embedding_dim.load(Ordering::SeqCst)
+    }
+
+    /// 当前向量空间的标识，随每条向量写库；不同 model_id 的向量不可相互比较
+    ///
+    /// e5 模型的标识带 `+prefix`：加前缀后的向量与旧版本未加前缀写入的向量不可比较，
+    /// 旧向量会被重新嵌入。
+    pub fn model_id(&self) -> String {
+        let id = match &self.backend {
+            EmbeddingBackend::OpenAiCompatible { model, .. } => format!("api:{}", model),
+            EmbeddingBackend::Local => format!("local:{}", self.config.local_model.trim()),
+        };
+        if is_e5_model(self.model_name()) {
+            format!("{}+prefix", id)
+        } else {
+            id
+        }
+    }
+
+    /// 当前后端使用的模型名
+    fn model_name(&self) -> &str {
+        match &self.backend {
+            EmbeddingBackend::OpenAiCompatible { model, .. } => model,
+            EmbeddingBackend::Local => self.config.local_model.trim(),
+        }
+    }
+
+    /// 按模型要求给文本加角色前缀（目前只有 e5 系列需要）
+    fn with_role_prefix(&self, texts: &[String], role: EmbeddingRole) -> Vec<String> {
+        if !is_e5_model(self.model_name()) {
+            return texts.to_vec();
+        }
+        let prefix = match role {
+            EmbeddingRole::Query => "query: ",
+            EmbeddingRole::Passage => "passage: ",
+        };
+        texts.iter().map(|t| format!("{}{}", prefix, t)).collect()
+    }
+
+    /// 记录实际返回的向量维度
+    fn record_dim(&self, embeddings: &[Vec<f32>]) {
+        if let Some(first) = embeddings.first() {
+            let previous = self.embedding_dim.swap(first.len(), Ordering::SeqCst);
+            if previous != first.len() {
+                info!(
+                    "Embedding dimension detected: {} ({})",
+                    first.len(),
+                    self.model_id()
+                );
+            }
+        }
     }
 
     /// 获取后端名称
@@ -273,13 +542,15 @@ impl TextEmbedder {
                     format!("API ({})", endpoint)
                 }
             }
-            EmbeddingBackend::Local => "Local (MiniLM)".to_string(),
+            EmbeddingBackend::Local => format!("Local ({})", self.config.local_model.trim()),
         }
     }
 
-    /// 嵌入单个文本
-    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
-        let results = self.embed_batch(&[text.to_string()]).await?;
+    /// 嵌入检索词
+    pub async fn embed_query(&self, text: &str) -> Result<Vec<f32>> {
+        let results = self
+            .embed_with_role(&[text.to_string()], EmbeddingRole::Query)
+            .await?;
         results
             .into_iter()
             .next()
@@ -298,8 +569,11 @@ impl TextEmbedder {
                     .as_ref()
                     .ok_or_else(|| anyhow!("Local model not initialized"))?;
 
-                let truncated = Self::truncate_text(text, 512);
-                let embeddings = model.embed(vec![truncated], None)?;
+                let texts = self.with_role_prefix(&[text.to_string()], EmbeddingRole::Passage);
+                let truncated: Vec<String> =
+                    texts.iter().map(|t| Self::truncate_text(t, 512)).collect();
+                let embeddings = model.embed(truncated, None)?;
+                self.record_dim(&embeddings);
 
                 embeddings
                     .into_iter()
@@ -312,26 +586,43 @@ impl TextEmbedder {
         }
     }
 
-    /// 批量嵌入文本
+    /// 批量嵌入被检索的文本（trace 内容）
     pub async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.embed_with_role(texts, EmbeddingRole::Passage).await
+    }
+
+    /// 批量嵌入检索词（以及实体名这类短文本之间的对称比较）
+    pub async fn embed_queries(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.embed_with_role(texts, EmbeddingRole::Query).await
+    }
+
+    async fn embed_with_role(
+        &self,
+        texts: &[String],
+        role: EmbeddingRole,
+    ) -> Result<Vec<Vec<f32>>> {
         if texts.is_empty() {
             return Ok(Vec::new());
         }
+        let texts = self.with_role_prefix(texts, role);
+        let texts = texts.as_slice();
 
         // 更新最后使用时间
         *self.last_used.lock().unwrap() = Instant::now();
 
-        match &self.backend {
+        let embeddings = match &self.backend {
             EmbeddingBackend::OpenAiCompatible {
                 endpoint,
                 model,
                 api_key,
             } => {
                 self.embed_with_api(endpoint, model, api_key.as_deref(), texts)
-                    .await
+                    .await?
             }
-            EmbeddingBackend::Local => self.embed_with_local(texts),
-        }
+            EmbeddingBackend::Local => self.embed_with_local(texts)?,
+        };
+        self.record_dim(&embeddings);
+        Ok(embeddings)
     }
 
     /// 使用 API 嵌入
@@ -490,8 +781,12 @@ impl TextEmbedder {
     /// 获取内存使用估计（MB）
     pub fn estimated_memory_mb(&self) -> f64 {
         if matches!(self.backend, EmbeddingBackend::Local) && self.is_model_loaded() {
-            // MiniLM-L6 约 90MB
-            90.0
+            // 按维度粗略估计：small（384）约 100-500MB，base（768）约 1GB，large 约 2GB
+            match self.embedding_dim() {
+                0..=384 => 300.0,
+                385..=768 => 1000.0,
+                _ => 2000.0,
+            }
         } else {
             0.0
         }
@@ -574,6 +869,71 @@ mod tests {
         assert!(ollama.endpoint.unwrap().contains("11434"));
     }
 
+    #[test]
+    fn test_local_model_resolution_and_model_id() {
+        assert_eq!(
+            resolve_local_model("Multilingual-E5-Small"),
+            Some(fastembed::EmbeddingModel::MultilingualE5Small)
+        );
+        assert_eq!(
+            resolve_local_model("Xenova/bge-small-zh-v1.5"),
+            Some(fastembed::EmbeddingModel::BGESmallZHV15)
+        );
+        assert_eq!(resolve_local_model("bge-m3"), None);
+
+        // 旧配置缺少新字段时使用多语言默认模型
+        let config: EmbeddingConfig = serde_json::from_str(r#"{"model":"x"}"#).unwrap();
+        assert_eq!(config.local_model, "multilingual-e5-small");
+        assert!(!config.offline);
+
+        let local = TextEmbedder::with_config(EmbeddingConfig::local());
+        assert_eq!(local.model_id(), "local:multilingual-e5-small+prefix");
+        assert_eq!(local.embedding_dim(), 0);
+        let api = TextEmbedder::with_config(EmbeddingConfig::openai("sk"));
+        assert_eq!(api.model_id(), "api:text-embedding-3-small");
+        api.record_dim(&[vec![0.0; 1536]]);
+        assert_eq!(api.embedding_dim(), 1536);
+
+        // 未下载的模型在离线目录中视为未安装
+        let dir = std::env::temp_dir().join(format!("engram-models-{}", uuid::Uuid::new_v4()));
+        assert!(list_local_models(&dir).iter().all(|m| !m.installed));
+        assert!(custom_model_dir(&dir, "bge-m3").is_none());
+
+        // 自定义模型名不能解析到 models/ 之外
+        let models = dir.join("models");
+        std::fs::create_dir_all(dir.join("outside")).unwrap();
+        std::fs::write(dir.join("outside").join(CUSTOM_MODEL_FILE), b"").unwrap();
+        assert!(custom_model_dir(&models, "../outside").is_none());
+        assert!(custom_model_dir(&models, dir.join("outside").to_str().unwrap()).is_none());
+        std::fs::create_dir_all(models.join("mine")).unwrap();
+        std::fs::write(models.join("mine").join(CUSTOM_MODEL_FILE), b"").unwrap();
+        assert_eq!(
+            custom_model_dir(&models, " mine "),
+            Some(models.join("mine"))
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_e5_role_prefixes() {
+        assert!(is_e5_model("multilingual-e5-small"));
+        assert!(is_e5_model("intfloat/e5-base-v2"));
+        assert!(!is_e5_model("bge-small-zh-v1.5"));
+
+        let texts = vec!["数据库迁移".to_string()];
+        let e5 = TextEmbedder::with_config(EmbeddingConfig::local());
+        assert_eq!(
+            e5.with_role_prefix(&texts, EmbeddingRole::Query),
+            vec!["query: 数据库迁移"]
+        );
+        assert_eq!(
+            e5.with_role_prefix(&texts, EmbeddingRole::Passage),
+            vec!["passage: 数据库迁移"]
+        );
+        let api = TextEmbedder::with_config(EmbeddingConfig::openai("sk"));
+        assert_eq!(api.with_role_prefix(&texts, EmbeddingRole::Query), texts);
+    }
+
     #[test]
     fn test_cosine_similarity() {
         let a = vec![1.0, 0.0, 0.0];
@@ -592,6 +952,22 @@ mod tests {
         assert_eq!(embedding, restored);
     }
 
+    #[test]
+    fn test_embedding_queue_flushes_on_size() {
+        let mut queue = EmbeddingQueue::new(2).with_flush_interval(3600);
+        assert!(!queue.should_flush());
+
+        queue.enqueue("a".to_string(), 1);
+        assert!(!queue.should_flush());
+
+        queue.enqueue("b".to_string(), 2);
+        assert!(queue.should_flush());
+
+        let drained = queue.drain();
+        assert_eq!(drained, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+        assert!(queue.is_empty());
+    }
+
     #[test]
     fn test_l2_normalize() {
         let mut vec = vec![3.0, 4.0];
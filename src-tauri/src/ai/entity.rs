@@ -0,0 +1,440 @@
+//! 实体辅助函数
+//!
+//! VLM 对每条 trace 只返回实体名称，不带类型；这里按名称形态推断类型，
+//! 无法判断的记为 `other`，实体类型最终由各次提及的类型投票决定。
+//!
+//! 同一实体的不同写法（大小写、空白、URL 协议、路径分隔符）归一化后作为
+//! `entity_aliases` 的键；归一化无法覆盖的情况（`owner/repo` 与 `repo`、
+//! `engram repo` 与 `engram`、语义相近的名称）由 [`merge_suggestions`] 给出合并建议。
+//!
+//! 实体关系由共现推导：同一 trace 中出现计 1，同一会话中出现计 0.5，按发生时间以
+//! 30 天半衰期衰减后累加（[`RelationBuilder`]）。
+
+use crate::ai::embedding::TextEmbedder;
+use crate::db::models::Entity;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// 无法推断类型时使用的实体类型
+pub const UNKNOWN_ENTITY_TYPE: &str = "other";
+
+/// 常见文件扩展名（用于识别文件实体）
+const FILE_EXTENSIONS: &[&str] = &[
+    "rs", "ts", "tsx", "js", "jsx", "py", "go", "java", "kt", "swift", "c", "h", "cpp", "hpp",
+    "cs", "rb", "php", "sh", "md", "txt", "json", "toml", "yaml", "yml", "xml", "html", "css",
+    "sql", "csv", "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "png", "jpg", "jpeg", "svg",
+    "log", "lock",
+];
+
+/// 以 `.js` 结尾的常见框架 / 库名称（不是文件）
+const JS_LIBRARY_NAMES: &[&str] = &[
+    "node.js",
+    "vue.js",
+    "next.js",
+    "nuxt.js",
+    "nest.js",
+    "react.js",
+    "express.js",
+    "ember.js",
+    "backbone.js",
+    "alpine.js",
+    "solid.js",
+    "three.js",
+    "d3.js",
+    "chart.js",
+    "p5.js",
+    "deno.js",
+];
+
+/// 名称末尾的泛称（`engram repo` 与 `engram` 视为同一实体）
+const GENERIC_SUFFIXES: &[&str] = &[
+    "repo",
+    "repository",
+    "project",
+    "app",
+    "application",
+    "website",
+    "site",
+    "docs",
+    "项目",
+    "仓库",
+    "应用",
+    "官网",
+    "文档",
+];
+
+/// 引号、书名号等包裹字符
+const WRAPPING_CHARS: &[char] = &[
+    '"', '\'', '`', '“', '”', '‘', '’', '「', '」', '『', '』', '《', '》', '<', '>', '(', ')',
+    '[', ']',
+];
+
+/// 顶级域名（用于识别不带协议的网址）
+const URL_SUFFIXES: &[&str] = &[
+    ".com", ".org", ".net", ".io", ".dev", ".app", ".ai", ".cn", ".co", ".edu", ".gov",
+];
+
+/// 按名称形态推断实体类型（`url` / `file` / `other`）
+pub fn infer_entity_type(name: &str) -> &'static str {
+    let name = name.trim();
+    let lower = name.to_lowercase();
+
+    if lower.starts_with("http://") || lower.starts_with("https://") || lower.starts_with("www.") {
+        return "url";
+    }
+    if !name.contains(char::is_whitespace) {
+        let host = lower.split('/').next().unwrap_or_default();
+        if URL_SUFFIXES.iter().any(|s| host.ends_with(s)) && host.len() > 4 {
+            return "url";
+        }
+        let is_path = name.starts_with('/')
+            || name.starts_with("~/")
+            || name.starts_with("./")
+            || name.contains('\\');
+        let has_extension = lower
+            .rsplit_once('.')
+            .is_some_and(|(stem, ext)| !stem.is_empty() && FILE_EXTENSIONS.contains(&ext))
+            && !JS_LIBRARY_NAMES.contains(&lower.as_str());
+        if is_path || has_extension {
+            return "file";
+        }
+    }
+
+    UNKNOWN_ENTITY_TYPE
+}
+
+/// 归一化实体名称（别名表的键）
+///
+/// 去除包裹字符、合并空白、转小写；网址去掉协议、`www.`、查询参数、锚点、
+/// 末尾 `/` 与 `.git`；路径统一使用 `/` 并去掉 `./` 前缀与末尾 `/`。
+pub fn normalize_entity_name(name: &str) -> String {
+    let trimmed = name.trim().trim_matches(WRAPPING_CHARS).trim();
+    let mut key = trimmed
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    if infer_entity_type(trimmed) == "url" {
+        for scheme in ["https://", "http://"] {
+            if let Some(rest) = key.strip_prefix(scheme) {
+                key = rest.to_string();
+            }
+        }
+        if let Some(rest) = key.strip_prefix("www.") {
+            key = rest.to_string();
+        }
+        if let Some(index) = key.find(['?', '#']) {
+            key.truncate(index);
+        }
+        key = key.trim_end_matches('/').to_string();
+        if let Some(rest) = key.strip_suffix(".git") {
+            key = rest.to_string();
+        }
+    } else if key.contains('\\') || key.starts_with("./") || key.ends_with('/') {
+        key = key.replace('\\', "/");
+        if let Some(rest) = key.strip_prefix("./") {
+            key = rest.to_string();
+        }
+        if key.len() > 1 {
+            key = key.trim_end_matches('/').to_string();
+        }
+    }
+
+    key
+}
+
+/// 两个归一化名称在字面上是否指向同一实体，返回相似度与原因
+///
+/// - `path_suffix`：一个是另一个的最后一段路径（`tokenrollai/engram` 与 `engram`）
+/// - `generic_suffix`：只差一个泛称后缀（`engram repo` 与 `engram`）
+fn lexical_match(a: &str, b: &str) -> Option<(f32, &'static str)> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    if short.is_empty() || long == short {
+        return None;
+    }
+    if long.ends_with(&format!("/{}", short)) {
+        return Some((0.9, "path_suffix"));
+    }
+    if let Some(rest) = long.strip_prefix(short) {
+        if GENERIC_SUFFIXES.contains(&rest.trim()) {
+            return Some((0.9, "generic_suffix"));
+        }
+    }
+    None
+}
+
+/// 实体合并建议（`source` 合并到提及次数更多的 `target`）
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeSuggestion {
+    pub source: Entity,
+    pub target: Entity,
+    /// 相似度（0.0 - 1.0）
+    pub score: f32,
+    /// `path_suffix` / `generic_suffix` / `embedding`
+    pub reason: String,
+}
+
+/// 生成合并建议：字面规则优先，其次为名称嵌入的余弦相似度（不低于 `threshold`）
+///
+/// 两个实体都有具体类型且类型不同时不建议合并。`embeddings` 与 `entities` 一一对应，
+/// 嵌入模型不可用时传 `None`。结果按相似度降序。
+pub fn merge_suggestions(
+    entities: &[Entity],
+    embeddings: Option<&[Vec<f32>]>,
+    threshold: f32,
+) -> Vec<MergeSuggestion> {
+    let keys: Vec<String> = entities
+        .iter()
+        .map(|e| normalize_entity_name(&e.name))
+        .collect();
+    let mut suggestions = Vec::new();
+
+    for i in 0..entities.len() {
+        for j in (i + 1)..entities.len() {
+            let (a, b) = (&entities[i], &entities[j]);
+            if a.entity_type != b.entity_type
+                && a.entity_type != UNKNOWN_ENTITY_TYPE
+                && b.entity_type != UNKNOWN_ENTITY_TYPE
+            {
+                continue;
+            }
+
+            let matched = lexical_match(&keys[i], &keys[j]).or_else(|| {
+                let embeddings = embeddings?;
+                let score = TextEmbedder::cosine_similarity(&embeddings[i], &embeddings[j]);
+                (score >= threshold).then_some((score, "embedding"))
+            });
+            let Some((score, reason)) = matched else {
+                continue;
+            };
+
+            let (source, target) = if a.mention_count > b.mention_count {
+                (b, a)
+            } else {
+                (a, b)
+            };
+            suggestions.push(MergeSuggestion {
+                source: source.clone(),
+                target: target.clone(),
+                score,
+                reason: reason.to_string(),
+            });
+        }
+    }
+
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions
+}
+
+/// 关系权重半衰期（30 天）
+pub const RELATION_HALF_LIFE_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
+/// 同一 trace 中共现的权重
+const TRACE_COOCCURRENCE_WEIGHT: f64 = 1.0;
+
+/// 同一会话中共现的权重
+const SESSION_COOCCURRENCE_WEIGHT: f64 = 0.5;
+
+/// 单个会话参与共现计算的实体上限（避免长会话产生过多边）
+const MAX_SESSION_ENTITIES: usize = 50;
+
+/// 经过 `elapsed_ms` 后的权重衰减系数
+pub fn relation_decay(elapsed_ms: i64) -> f64 {
+    0.5f64.powf(elapsed_ms.max(0) as f64 / RELATION_HALF_LIFE_MS as f64)
+}
+
+/// 两个实体的共现统计（权重已衰减到构建时刻）
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoOccurrence {
+    pub weight: f64,
+    pub trace_count: u32,
+    pub session_count: u32,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+/// 按 trace 与会话累加实体共现
+pub struct RelationBuilder {
+    now: i64,
+    pairs: HashMap<(i64, i64), CoOccurrence>,
+}
+
+impl RelationBuilder {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now,
+            pairs: HashMap::new(),
+        }
+    }
+
+    /// 同一 trace 中出现的实体
+    pub fn add_trace(&mut self, entity_ids: &[i64], timestamp: i64) {
+        self.add(entity_ids, timestamp, TRACE_COOCCURRENCE_WEIGHT, true);
+    }
+
+    /// 同一会话中出现的实体（`timestamp` 取会话结束时间）
+    pub fn add_session(&mut self, entity_ids: &[i64], timestamp: i64) {
+        let mut seen = HashSet::new();
+        let ids: Vec<i64> = entity_ids
+            .iter()
+            .copied()
+            .filter(|id| seen.insert(*id))
+            .take(MAX_SESSION_ENTITIES)
+            .collect();
+        self.add(&ids, timestamp, SESSION_COOCCURRENCE_WEIGHT, false);
+    }
+
+    fn add(&mut self, entity_ids: &[i64], timestamp: i64, weight: f64, is_trace: bool) {
+        let mut ids = entity_ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+        let weight = weight * relation_decay(self.now - timestamp);
+
+        for (i, &a) in ids.iter().enumerate() {
+            for &b in &ids[i + 1..] {
+                let entry = self.pairs.entry((a, b)).or_insert(CoOccurrence {
+                    first_seen: timestamp,
+                    last_seen: timestamp,
+                    ..Default::default()
+                });
+                entry.weight += weight;
+                if is_trace {
+                    entry.trace_count += 1;
+                } else {
+                    entry.session_count += 1;
+                }
+                entry.first_seen = entry.first_seen.min(timestamp);
+                entry.last_seen = entry.last_seen.max(timestamp);
+            }
+        }
+    }
+
+    /// 共现的实体对（较小 ID 在前）
+    pub fn into_relations(self) -> HashMap<(i64, i64), CoOccurrence> {
+        self.pairs
+    }
+}
+
+/// 为 VLM 实体名称补上推断的类型（去除空白与重复项）
+pub fn typed_entities(names: &[String]) -> Vec<(String, String)> {
+    let mut result: Vec<(String, String)> = Vec::new();
+    for name in names {
+        let name = name.trim();
+        if name.is_empty() || result.iter().any(|(n, _)| n == name) {
+            continue;
+        }
+        result.push((name.to_string(), infer_entity_type(name).to_string()));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(id: i64, name: &str, entity_type: &str, mention_count: u32) -> Entity {
+        Entity {
+            id,
+            name: name.to_string(),
+            entity_type: entity_type.to_string(),
+            mention_count,
+            first_seen: 0,
+            last_seen: 0,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn test_normalize_entity_name() {
+        assert_eq!(normalize_entity_name("  Engram  "), "engram");
+        assert_eq!(
+            normalize_entity_name("Visual   Studio\tCode"),
+            "visual studio code"
+        );
+        assert_eq!(normalize_entity_name("《工作报告》"), "工作报告");
+        assert_eq!(
+            normalize_entity_name("https://www.GitHub.com/TokenRollAI/engram.git/"),
+            "github.com/tokenrollai/engram"
+        );
+        assert_eq!(
+            normalize_entity_name("https://example.com/page?utm=1#top"),
+            "example.com/page"
+        );
+        assert_eq!(normalize_entity_name("src\\db\\mod.rs"), "src/db/mod.rs");
+        assert_eq!(normalize_entity_name("./src/"), "src");
+    }
+
+    #[test]
+    fn test_merge_suggestions() {
+        let entities = vec![
+            entity(1, "engram", "project", 10),
+            entity(2, "TokenRollAI/engram", "other", 2),
+            entity(3, "engram repo", "project", 1),
+            entity(4, "Engram Notes", "person", 1),
+        ];
+
+        let suggestions = merge_suggestions(&entities, None, 0.9);
+        assert_eq!(suggestions.len(), 2);
+        assert!(suggestions.iter().all(|s| s.target.id == 1));
+
+        // 嵌入相似度达到阈值时也给出建议，类型冲突的实体除外
+        let embeddings = vec![
+            vec![1.0, 0.0],
+            vec![0.0, 1.0],
+            vec![0.0, 1.0],
+            vec![1.0, 0.0],
+        ];
+        let suggestions = merge_suggestions(&entities, Some(&embeddings), 0.95);
+        assert_eq!(suggestions.len(), 3);
+        assert!(suggestions
+            .iter()
+            .any(|s| s.reason == "embedding" && s.source.id == 3 && s.target.id == 2));
+    }
+
+    #[test]
+    fn test_relation_builder_decays_and_counts() {
+        let now = 100 * RELATION_HALF_LIFE_MS;
+        let mut builder = RelationBuilder::new(now);
+        builder.add_trace(&[2, 1, 3], now);
+        builder.add_trace(&[1, 2, 2], now - RELATION_HALF_LIFE_MS);
+        builder.add_session(&[1, 2], now);
+
+        let relations = builder.into_relations();
+        assert_eq!(relations.len(), 3);
+
+        let pair = &relations[&(1, 2)];
+        assert!((pair.weight - 2.0).abs() < 1e-9);
+        assert_eq!((pair.trace_count, pair.session_count), (2, 1));
+        assert_eq!(pair.first_seen, now - RELATION_HALF_LIFE_MS);
+        assert_eq!(relations[&(2, 3)].trace_count, 1);
+    }
+
+    #[test]
+    fn test_session_entities_deduped_before_cap() {
+        // 重复的 ID 不占用上限名额
+        let mut ids = vec![1; MAX_SESSION_ENTITIES];
+        ids.push(2);
+        let mut builder = RelationBuilder::new(0);
+        builder.add_session(&ids, 0);
+
+        let relations = builder.into_relations();
+        assert_eq!(relations[&(1, 2)].session_count, 1);
+    }
+
+    #[test]
+    fn test_infer_entity_type() {
+        assert_eq!(infer_entity_type("https://github.com/foo"), "url");
+        assert_eq!(infer_entity_type("crates.io/crates/tokio"), "url");
+        assert_eq!(infer_entity_type("src/main.rs"), "file");
+        assert_eq!(infer_entity_type("Cargo.toml"), "file");
+        assert_eq!(infer_entity_type("报告.pdf"), "file");
+        assert_eq!(infer_entity_type("~/projects/engram"), "file");
+        assert_eq!(infer_entity_type("Rust"), "other");
+        assert_eq!(infer_entity_type("Node.js"), "other");
+        assert_eq!(infer_entity_type("Vue.js"), "other");
+        assert_eq!(infer_entity_type("src/node.js"), "file");
+        assert_eq!(infer_entity_type("app.js"), "file");
+        assert_eq!(infer_entity_type("Visual Studio Code"), "other");
+    }
+}
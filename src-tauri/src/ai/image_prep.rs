@@ -0,0 +1,600 @@
+//! VLM 图像预处理
+//!
+//! 截图送入 VLM 前的处理流程：
+//! 1. 焦点裁剪：按截图时记录的焦点窗口范围裁剪（窗口太小或几乎占满整屏时不裁剪）
+//! 2. 概览图：等比缩放到长边不超过 `max_edge`，不拉伸超宽屏和竖屏
+//! 3. 文字密集分块：概览图缩小明显时，额外附带最多 `max_tiles` 张原分辨率的文字密集区域裁剪，
+//!    与变化区域相交的分块优先
+//!
+//!    分块取自已保存的帧，而帧在捕获时已下采样到约 1920x1080（`MAX_FRAME_PIXELS`），
+//!    所以分块的实际放大倍数只是帧长边与 `max_edge` 之比：默认 `max_edge = 1536` 时整屏约 1.25 倍，
+//!    焦点裁剪后通常不足 `MIN_TILE_UPSCALE`，不附带分块。需要更清晰的小字时应调低 `max_edge`
+//! 4. 编码：按服务端支持的格式和单图大小上限选择 JPEG/PNG/WebP，超限时逐步缩小
+
+use super::prompts::{PromptKind, PromptLibrary};
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use image::{DynamicImage, GrayImage, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// 截图像素坐标中的矩形 (x, y, width, height)
+pub type Rect = (u32, u32, u32, u32);
+
+/// 焦点窗口小于截图面积的该比例时不裁剪（避免只剩一个对话框）
+const MIN_FOCUS_AREA_RATIO: f64 = 0.15;
+/// 焦点窗口大于截图面积的该比例时不裁剪（几乎整屏，裁剪没有意义）
+const MAX_FOCUS_AREA_RATIO: f64 = 0.9;
+/// 焦点裁剪四周保留的边距（像素）
+const FOCUS_PADDING: u32 = 8;
+/// 原图长边超过概览长边的该倍数时才附带高清分块（帧长边 1920、默认 `max_edge` 1536 时恰为 1.25）
+const MIN_TILE_UPSCALE: f64 = 1.25;
+/// 相邻像素亮度差超过该值视为文字边缘
+const EDGE_THRESHOLD: u8 = 48;
+/// 边缘像素占比低于该值的分块不算文字密集
+const MIN_TEXT_DENSITY: f64 = 0.04;
+/// 单图大小超限时最多缩小的次数
+const MAX_SHRINK_STEPS: u32 = 4;
+
+/// 截图附带的区域信息（截图像素坐标）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameRegions {
+    /// 焦点窗口范围
+    pub focus: Option<Rect>,
+    /// 相对上一张截图的变化区域
+    pub changed: Option<Rect>,
+}
+
+/// 图像编码格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageEncoding {
+    /// 按服务端选择：概览图 JPEG，文字分块用无损 WebP（不支持时 PNG）
+    #[default]
+    Auto,
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl ImageEncoding {
+    fn mime(&self) -> &'static str {
+        match self {
+            ImageEncoding::Png => "image/png",
+            ImageEncoding::Webp => "image/webp",
+            _ => "image/jpeg",
+        }
+    }
+}
+
+/// 图像预处理配置（`[vlm.image]`）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImagePrepConfig {
+    /// 概览图长边上限（像素）
+    #[serde(default = "default_max_edge")]
+    pub max_edge: u32,
+    /// 按焦点窗口裁剪
+    #[serde(default = "default_focus_crop")]
+    pub focus_crop: bool,
+    /// 额外附带的文字密集分块数量（0 表示只发送概览图）
+    #[serde(default = "default_max_tiles")]
+    pub max_tiles: u32,
+    /// 分块边长（原图像素）
+    #[serde(default = "default_tile_size")]
+    pub tile_size: u32,
+    /// 编码格式
+    #[serde(default)]
+    pub encoding: ImageEncoding,
+    /// JPEG 质量（1-100）
+    #[serde(default = "default_jpeg_quality")]
+    pub jpeg_quality: u8,
+    /// 单图大小上限（字节，0 表示使用服务端默认上限）
+    #[serde(default)]
+    pub max_image_bytes: u64,
+}
+
+fn default_max_edge() -> u32 {
+    1536
+}
+
+fn default_focus_crop() -> bool {
+    true
+}
+
+fn default_max_tiles() -> u32 {
+    2
+}
+
+fn default_tile_size() -> u32 {
+    768
+}
+
+fn default_jpeg_quality() -> u8 {
+    85
+}
+
+impl Default for ImagePrepConfig {
+    fn default() -> Self {
+        Self {
+            max_edge: default_max_edge(),
+            focus_crop: default_focus_crop(),
+            max_tiles: default_max_tiles(),
+            tile_size: default_tile_size(),
+            encoding: ImageEncoding::default(),
+            jpeg_quality: default_jpeg_quality(),
+            max_image_bytes: 0,
+        }
+    }
+}
+
+/// 服务端的图像限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProviderLimits {
+    /// 单图大小上限（字节，0 表示不限制）
+    pub max_bytes: u64,
+    /// 是否接受 WebP
+    pub webp: bool,
+}
+
+impl ProviderLimits {
+    /// 按端点推断限制：Anthropic 单图 5MB，OpenAI 20MB；
+    /// Ollama 等基于 llama.cpp 的本地服务不解码 WebP
+    pub fn for_endpoint(endpoint: &str) -> Self {
+        let endpoint = endpoint.to_lowercase();
+        if endpoint.contains("anthropic.com") {
+            Self {
+                max_bytes: 5 * 1024 * 1024,
+                webp: true,
+            }
+        } else if endpoint.contains("openai.com") || endpoint.contains("openrouter.ai") {
+            Self {
+                max_bytes: 20 * 1024 * 1024,
+                webp: true,
+            }
+        } else if endpoint.contains(":11434") || endpoint.contains("ollama") {
+            Self {
+                max_bytes: 0,
+                webp: false,
+            }
+        } else {
+            Self {
+                max_bytes: 20 * 1024 * 1024,
+                webp: false,
+            }
+        }
+    }
+}
+
+/// 编码后的单张图片
+#[derive(Debug, Clone)]
+pub struct EncodedImage {
+    pub mime: &'static str,
+    pub base64: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl EncodedImage {
+    /// OpenAI 兼容 API 的 data URL
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime, self.base64)
+    }
+}
+
+/// 预处理结果
+#[derive(Debug, Clone)]
+pub struct PreparedFrame {
+    /// 概览图
+    pub overview: EncodedImage,
+    /// 文字密集分块（原分辨率），以及它们在概览图中的位置
+    pub tiles: Vec<(EncodedImage, Rect)>,
+    /// 是否按焦点窗口裁剪
+    pub cropped: bool,
+    /// 变化区域在概览图中的位置
+    pub changed: Option<Rect>,
+}
+
+impl PreparedFrame {
+    /// 按发送顺序列出所有图片
+    pub fn images(&self) -> impl Iterator<Item = &EncodedImage> {
+        std::iter::once(&self.overview).chain(self.tiles.iter().map(|(image, _)| image))
+    }
+
+    /// 编码后的总字节数（base64）
+    pub fn encoded_len(&self) -> usize {
+        self.images().map(|image| image.base64.len()).sum()
+    }
+
+    /// 图片布局说明（截图上下文中的 `{image_layout}`），按当前语言的模板渲染
+    pub fn layout_note(&self, prompts: &PromptLibrary) -> String {
+        let size = |v: u32| v.to_string();
+        let overview = if self.cropped {
+            PromptKind::ContextImageFocus
+        } else {
+            PromptKind::ContextImageOverview
+        };
+        let mut lines = vec![prompts.render(
+            overview,
+            &[
+                ("width", &size(self.overview.width)),
+                ("height", &size(self.overview.height)),
+            ],
+        )];
+        for (i, (_, (x, y, w, h))) in self.tiles.iter().enumerate() {
+            lines.push(prompts.render(
+                PromptKind::ContextImageTile,
+                &[
+                    ("index", &size(i as u32 + 2)),
+                    ("x", &size(*x)),
+                    ("y", &size(*y)),
+                    ("width", &size(*w)),
+                    ("height", &size(*h)),
+                ],
+            ));
+        }
+        if let Some((x, y, w, h)) = self.changed {
+            lines.push(prompts.render(
+                PromptKind::ContextImageChanged,
+                &[
+                    ("x", &size(x)),
+                    ("y", &size(y)),
+                    ("width", &size(w)),
+                    ("height", &size(h)),
+                ],
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// 预处理截图
+pub fn prepare(
+    image: &RgbImage,
+    regions: &FrameRegions,
+    config: &ImagePrepConfig,
+    limits: ProviderLimits,
+) -> Result<PreparedFrame> {
+    let (full_w, full_h) = image.dimensions();
+
+    // 1. 焦点裁剪
+    let crop = regions
+        .focus
+        .filter(|_| config.focus_crop)
+        .and_then(|focus| focus_crop_rect(focus, full_w, full_h))
+        .unwrap_or((0, 0, full_w, full_h));
+    let cropped = crop != (0, 0, full_w, full_h);
+    let source = if cropped {
+        image::imageops::crop_imm(image, crop.0, crop.1, crop.2, crop.3).to_image()
+    } else {
+        image.clone()
+    };
+
+    // 2. 等比缩放的概览图
+    let (ow, oh) = fit_within(source.width(), source.height(), config.max_edge);
+    let scale = source.width() as f64 / ow as f64;
+    let overview = if (ow, oh) == source.dimensions() {
+        source.clone()
+    } else {
+        image::imageops::resize(&source, ow, oh, image::imageops::FilterType::Triangle)
+    };
+
+    let max_bytes = match config.max_image_bytes {
+        0 => limits.max_bytes,
+        n => n,
+    };
+    let overview_encoding = match config.encoding {
+        ImageEncoding::Auto => ImageEncoding::Jpeg,
+        other => supported(other, limits),
+    };
+    let tile_encoding = match config.encoding {
+        ImageEncoding::Auto if limits.webp => ImageEncoding::Webp,
+        ImageEncoding::Auto => ImageEncoding::Png,
+        other => supported(other, limits),
+    };
+
+    // 变化区域映射到裁剪后的坐标
+    let changed = regions
+        .changed
+        .and_then(|rect| intersect(rect, crop))
+        .map(|(x, y, w, h)| (x - crop.0, y - crop.1, w, h));
+
+    // 3. 文字密集分块（只有概览图明显缩小时才有意义）
+    let mut tiles = Vec::new();
+    if config.max_tiles > 0 && scale >= MIN_TILE_UPSCALE {
+        let gray = DynamicImage::ImageRgb8(source.clone()).to_luma8();
+        for rect in dense_text_tiles(&gray, config.tile_size, config.max_tiles, changed) {
+            let tile =
+                image::imageops::crop_imm(&source, rect.0, rect.1, rect.2, rect.3).to_image();
+            let encoded = encode(&tile, tile_encoding, config.jpeg_quality, max_bytes)?;
+            tiles.push((encoded, scale_rect(rect, scale)));
+        }
+    }
+
+    Ok(PreparedFrame {
+        overview: encode(&overview, overview_encoding, config.jpeg_quality, max_bytes)?,
+        tiles,
+        cropped,
+        changed: changed.map(|rect| scale_rect(rect, scale)),
+    })
+}
+
+/// 服务端不支持的格式退回 JPEG
+fn supported(encoding: ImageEncoding, limits: ProviderLimits) -> ImageEncoding {
+    if encoding == ImageEncoding::Webp && !limits.webp {
+        ImageEncoding::Jpeg
+    } else {
+        encoding
+    }
+}
+
+/// 等比缩放到长边不超过 `max_edge`（只缩小）
+pub fn fit_within(width: u32, height: u32, max_edge: u32) -> (u32, u32) {
+    let long = width.max(height);
+    if max_edge == 0 || long <= max_edge {
+        return (width, height);
+    }
+    let ratio = max_edge as f64 / long as f64;
+    (
+        ((width as f64 * ratio).round() as u32).max(1),
+        ((height as f64 * ratio).round() as u32).max(1),
+    )
+}
+
+/// 焦点窗口加边距后的裁剪范围；窗口过小或几乎整屏时返回 None
+fn focus_crop_rect(focus: Rect, width: u32, height: u32) -> Option<Rect> {
+    let (x, y, w, h) = intersect(focus, (0, 0, width, height))?;
+    let ratio = (w as f64 * h as f64) / (width as f64 * height as f64);
+    if !(MIN_FOCUS_AREA_RATIO..=MAX_FOCUS_AREA_RATIO).contains(&ratio) {
+        return None;
+    }
+    let x0 = x.saturating_sub(FOCUS_PADDING);
+    let y0 = y.saturating_sub(FOCUS_PADDING);
+    let x1 = (x + w + FOCUS_PADDING).min(width);
+    let y1 = (y + h + FOCUS_PADDING).min(height);
+    Some((x0, y0, x1 - x0, y1 - y0))
+}
+
+fn intersect(a: Rect, b: Rect) -> Option<Rect> {
+    let x0 = a.0.max(b.0);
+    let y0 = a.1.max(b.1);
+    let x1 = (a.0 + a.2).min(b.0 + b.2);
+    let y1 = (a.1 + a.3).min(b.1 + b.3);
+    (x1 > x0 && y1 > y0).then(|| (x0, y0, x1 - x0, y1 - y0))
+}
+
+fn scale_rect((x, y, w, h): Rect, scale: f64) -> Rect {
+    let s = |v: u32| (v as f64 / scale).round() as u32;
+    (s(x), s(y), s(w).max(1), s(h).max(1))
+}
+
+/// 按边缘密度挑选文字密集的分块（与变化区域相交的优先）
+fn dense_text_tiles(
+    gray: &GrayImage,
+    tile_size: u32,
+    max_tiles: u32,
+    changed: Option<Rect>,
+) -> Vec<Rect> {
+    let (width, height) = gray.dimensions();
+    let tile_size = tile_size.max(64);
+    let starts = |len: u32| -> Vec<u32> {
+        if len <= tile_size {
+            return vec![0];
+        }
+        let mut v: Vec<u32> = (0..len - tile_size).step_by(tile_size as usize).collect();
+        // 最后一块贴齐边缘
+        v.push(len - tile_size);
+        v
+    };
+
+    let mut candidates: Vec<(f64, Rect)> = Vec::new();
+    for y in starts(height) {
+        for x in starts(width) {
+            let rect = (x, y, tile_size.min(width), tile_size.min(height));
+            let density = edge_density(gray, rect);
+            if density < MIN_TEXT_DENSITY {
+                continue;
+            }
+            let boost = if changed.and_then(|c| intersect(c, rect)).is_some() {
+                1.0
+            } else {
+                0.0
+            };
+            candidates.push((density + boost, rect));
+        }
+    }
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+    candidates
+        .into_iter()
+        .take(max_tiles as usize)
+        .map(|(_, rect)| rect)
+        .collect()
+}
+
+/// 分块内水平方向亮度突变的像素占比（隔行隔列采样）
+fn edge_density(gray: &GrayImage, (x, y, w, h): Rect) -> f64 {
+    let mut edges = 0u64;
+    let mut total = 0u64;
+    for py in (y..y + h).step_by(2) {
+        for px in (x..(x + w).saturating_sub(1)).step_by(2) {
+            let a = gray.get_pixel(px, py).0[0];
+            let b = gray.get_pixel(px + 1, py).0[0];
+            if a.abs_diff(b) > EDGE_THRESHOLD {
+                edges += 1;
+            }
+            total += 1;
+        }
+    }
+    if total == 0 {
+        0.0
+    } else {
+        edges as f64 / total as f64
+    }
+}
+
+/// 编码图片；超过 `max_bytes` 时逐步缩小
+fn encode(
+    image: &RgbImage,
+    encoding: ImageEncoding,
+    jpeg_quality: u8,
+    max_bytes: u64,
+) -> Result<EncodedImage> {
+    let mut current = image.clone();
+    let mut steps = 0;
+    loop {
+        let bytes = encode_bytes(&current, encoding, jpeg_quality)?;
+        // 服务端限制按解码后的图片字节计算
+        if max_bytes == 0 || bytes.len() as u64 <= max_bytes || steps >= MAX_SHRINK_STEPS {
+            return Ok(EncodedImage {
+                mime: encoding.mime(),
+                base64: BASE64.encode(&bytes),
+                width: current.width(),
+                height: current.height(),
+            });
+        }
+        let (w, h) = (current.width() * 3 / 4, current.height() * 3 / 4);
+        current = image::imageops::resize(
+            &current,
+            w.max(1),
+            h.max(1),
+            image::imageops::FilterType::Triangle,
+        );
+        steps += 1;
+    }
+}
+
+fn encode_bytes(image: &RgbImage, encoding: ImageEncoding, jpeg_quality: u8) -> Result<Vec<u8>> {
+    let mut buffer = Cursor::new(Vec::new());
+    match encoding {
+        ImageEncoding::Png => image.write_to(&mut buffer, image::ImageFormat::Png)?,
+        ImageEncoding::Webp => {
+            image.write_with_encoder(image::codecs::webp::WebPEncoder::new_lossless(&mut buffer))?
+        }
+        ImageEncoding::Auto | ImageEncoding::Jpeg => {
+            image.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(
+                &mut buffer,
+                jpeg_quality.clamp(1, 100),
+            ))?
+        }
+    }
+    Ok(buffer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 白底上画若干行"文字"（黑白交替的竖条）
+    fn screenshot(width: u32, height: u32, text_rows: &[(u32, u32, u32)]) -> RgbImage {
+        let mut image = RgbImage::from_pixel(width, height, image::Rgb([255, 255, 255]));
+        for &(x, y, len) in text_rows {
+            for py in y..y + 10 {
+                for px in (x..x + len).step_by(3) {
+                    image.put_pixel(px, py, image::Rgb([0, 0, 0]));
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_fit_within_preserves_aspect() {
+        assert_eq!(fit_within(3440, 1440, 1536), (1536, 643));
+        assert_eq!(fit_within(1080, 1920, 1536), (864, 1536));
+        assert_eq!(fit_within(800, 600, 1536), (800, 600));
+    }
+
+    #[test]
+    fn test_prepare_crops_focus_and_adds_text_tiles() {
+        let rows: Vec<_> = (0..20).map(|i| (200, 300 + i * 20, 600)).collect();
+        let image = screenshot(3000, 1600, &rows);
+        let regions = FrameRegions {
+            focus: Some((100, 100, 1500, 1000)),
+            changed: Some((200, 300, 600, 40)),
+        };
+        let config = ImagePrepConfig {
+            max_edge: 1000,
+            ..ImagePrepConfig::default()
+        };
+        let limits = ProviderLimits::for_endpoint("http://127.0.0.1:11434/v1");
+        let prepared = prepare(&image, &regions, &config, limits).unwrap();
+
+        assert!(prepared.cropped);
+        // 裁剪到焦点窗口（含边距）后等比缩放
+        assert_eq!(
+            (prepared.overview.width, prepared.overview.height),
+            (1000, 670)
+        );
+        assert_eq!(prepared.overview.mime, "image/jpeg");
+        // 文字区域作为原分辨率分块附带，Ollama 不支持 WebP 时用 PNG
+        assert!(!prepared.tiles.is_empty());
+        assert_eq!(prepared.tiles[0].0.mime, "image/png");
+        assert_eq!(prepared.tiles[0].0.width, config.tile_size);
+        assert!(prepared.changed.is_some());
+        let prompts = PromptLibrary::builtin(crate::config::Locale::En);
+        let note = prepared.layout_note(&prompts);
+        assert!(
+            note.starts_with("[Image Layout]\nImage 1: overview of the focused window, 1000x670.")
+        );
+        assert!(note.contains("Image 2: full-resolution crop"));
+        prompts.set_language(crate::config::Locale::Zh);
+        assert!(prepared.layout_note(&prompts).contains("图 2：概览图中"));
+
+        // 焦点窗口几乎整屏时不裁剪；不需要缩小时不附带分块
+        let regions = FrameRegions {
+            focus: Some((0, 0, 3000, 1600)),
+            changed: None,
+        };
+        let config = ImagePrepConfig {
+            max_edge: 4000,
+            ..ImagePrepConfig::default()
+        };
+        let prepared = prepare(&image, &regions, &config, limits).unwrap();
+        assert!(!prepared.cropped);
+        assert!(prepared.tiles.is_empty());
+        assert_eq!(
+            (prepared.overview.width, prepared.overview.height),
+            (3000, 1600)
+        );
+    }
+
+    #[test]
+    fn test_tile_gain_at_stored_frame_resolution() {
+        // 帧保存时已限制在约 1920x1080，分块只比默认概览图清晰 1.25 倍
+        let rows: Vec<_> = (0..30).map(|i| (100, 100 + i * 20, 1200)).collect();
+        let image = screenshot(1920, 1080, &rows);
+        let config = ImagePrepConfig::default();
+        let limits = ProviderLimits::for_endpoint("https://api.openai.com/v1");
+
+        let prepared = prepare(&image, &FrameRegions::default(), &config, limits).unwrap();
+        assert_eq!(
+            (prepared.overview.width, prepared.overview.height),
+            (1536, 864)
+        );
+        assert!(!prepared.tiles.is_empty());
+        let (tile, (_, _, w, _)) = &prepared.tiles[0];
+        assert!((tile.width as f64 / *w as f64 - MIN_TILE_UPSCALE).abs() < 0.01);
+
+        // 焦点裁剪后的窗口长边不足 1536 * 1.25，不附带分块
+        let regions = FrameRegions {
+            focus: Some((0, 0, 1400, 900)),
+            changed: None,
+        };
+        let prepared = prepare(&image, &regions, &config, limits).unwrap();
+        assert!(prepared.cropped);
+        assert!(prepared.tiles.is_empty());
+    }
+
+    #[test]
+    fn test_encoding_respects_provider_limits() {
+        let image = screenshot(400, 300, &[(10, 10, 300)]);
+        let limits = ProviderLimits::for_endpoint("http://localhost:11434/v1");
+        assert!(!limits.webp);
+        assert_eq!(supported(ImageEncoding::Webp, limits), ImageEncoding::Jpeg);
+        let webp = encode(&image, ImageEncoding::Webp, 85, 0).unwrap();
+        assert_eq!(webp.mime, "image/webp");
+
+        // 超过单图上限时缩小
+        let small = encode(&image, ImageEncoding::Png, 85, 1024).unwrap();
+        assert!(small.width < 400);
+    }
+}
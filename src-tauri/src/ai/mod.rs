@@ -4,11 +4,18 @@
 //! 所有功能都支持 OpenAI 兼容 API，并可回退到本地模型。
 
 pub mod embedding;
+pub mod entity;
+pub mod image_prep;
+pub mod prompts;
+pub mod structured;
 pub mod summarizer;
 pub mod vlm;
 
 pub use embedding::{EmbeddingConfig, EmbeddingQueue, TextEmbedder};
+pub use prompts::{PromptKind, PromptLibrary};
+pub use structured::{ParseFailure, ParseReport, ParseStatus, StructuredOutput};
 pub use summarizer::{
-    ExtractedEntity, GeneratedSummary, Summarizer, SummarizerConfig, SummaryType,
+    ExtractedEntity, GeneratedSummary, ModelTask, Summarizer, SummarizerConfig, SummaryType,
+    TaskModelConfig,
 };
 pub use vlm::{ScreenDescription, VlmConfig, VlmEngine};
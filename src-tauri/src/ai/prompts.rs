@@ -0,0 +1,711 @@
+//! Prompt 模板模块
+//!
+//! 每种 Prompt 按语言（zh/en）内置一份模板，模型输出语言由 `AppConfig::language` 决定。
+//! 用户可在配置目录下放置同名文件覆盖内置模板：
+//! `<config_dir>/prompts/<language>/<kind>.txt`，例如 `prompts/en/summary.txt`。
+//!
+//! 模板中的 `{name}` 占位符在渲染时替换为对应变量，每种 Prompt 可用的变量见 `PromptKind::variables`。
+//! 用户模板在加载时校验（未知变量或缺少必需变量的模板会被忽略并回退到内置模板），
+//! 目录变更后自动热加载。每次渲染附带版本号（语言 + 来源 + 内容哈希），
+//! 随 `vlm_raw_json` / `structured_data` 一起存储，便于对比不同模板的效果。
+
+use crate::config::Locale;
+use anyhow::Result;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use tracing::{debug, info, warn};
+
+/// 截图上下文的最大字符数
+const MAX_SCREEN_CONTEXT_CHARS: usize = 262_144; // 256K
+
+/// Prompt 类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PromptKind {
+    /// 截图分析（VLM），可选变量同 `ScreenContext`
+    ScreenAnalysis,
+    /// 截图分析的上下文块，变量：`{context}`、`{trace_meta}`、`{image_layout}`、`{active_sessions}`、`{recent_ocr}`
+    /// （`{changed_region}` 是 `{image_layout}` 的旧名，仍可使用）
+    ScreenContext,
+    /// 摘要生成，变量：`{period}`、`{context}`
+    Summary,
+    /// 汇总较短周期的摘要与会话（周/月/自定义范围），变量：`{period}`、`{context}`
+    SummaryRollup,
+    /// 时间段内无活动时的摘要内容
+    SummaryEmpty,
+    /// 手动生成摘要成功的提示，变量：`{summary_type}`
+    SummaryGenerated,
+    /// 实体提取，变量：`{text}`
+    EntityExtraction,
+    /// 实体关系标注，变量：`{entity}`、`{context}`
+    EntityRelations,
+    /// 会话关闭时的标题、描述与回顾，变量：`{context}`
+    SessionRecap,
+    /// 对话系统提示
+    ChatSystem,
+    /// 对话用户消息，变量：`{context}`、`{question}`
+    ChatUser,
+    /// 时间范围内无记录时的回复
+    ChatEmpty,
+    /// 输出无法解析为 JSON 时重新请求，变量：`{output}`、`{error}`、`{schema}`
+    JsonRepair,
+    /// 截图上下文：当前 trace 元信息，变量：`{app_name}`、`{window_title}`、`{time}`
+    ContextTraceMeta,
+    /// 截图上下文：活跃 Session 列表，变量：`{sessions}`
+    ContextActiveSessions,
+    /// 截图上下文：最近 traces 的 OCR 片段，变量：`{traces}`
+    ContextRecentOcr,
+    /// 截图上下文：用户纠正过的 Session 归类（few-shot 示例），变量：`{corrections}`
+    ContextRoutingCorrections,
+    /// 图片布局：整屏概览图，变量：`{width}`、`{height}`
+    ContextImageOverview,
+    /// 图片布局：焦点窗口概览图，变量：`{width}`、`{height}`
+    ContextImageFocus,
+    /// 图片布局：高清分块，变量：`{index}`、`{x}`、`{y}`、`{width}`、`{height}`
+    ContextImageTile,
+    /// 图片布局：变化区域，变量：`{x}`、`{y}`、`{width}`、`{height}`
+    ContextImageChanged,
+}
+
+impl PromptKind {
+    pub const ALL: [PromptKind; 21] = [
+        PromptKind::ScreenAnalysis,
+        PromptKind::ScreenContext,
+        PromptKind::Summary,
+        PromptKind::SummaryRollup,
+        PromptKind::SummaryEmpty,
+        PromptKind::SummaryGenerated,
+        PromptKind::EntityExtraction,
+        PromptKind::EntityRelations,
+        PromptKind::SessionRecap,
+        PromptKind::ChatSystem,
+        PromptKind::ChatUser,
+        PromptKind::ChatEmpty,
+        PromptKind::JsonRepair,
+        PromptKind::ContextTraceMeta,
+        PromptKind::ContextActiveSessions,
+        PromptKind::ContextRecentOcr,
+        PromptKind::ContextRoutingCorrections,
+        PromptKind::ContextImageOverview,
+        PromptKind::ContextImageFocus,
+        PromptKind::ContextImageTile,
+        PromptKind::ContextImageChanged,
+    ];
+
+    /// 模板文件名（不含扩展名）
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ScreenAnalysis => "screen_analysis",
+            Self::ScreenContext => "screen_context",
+            Self::Summary => "summary",
+            Self::SummaryRollup => "summary_rollup",
+            Self::SummaryEmpty => "summary_empty",
+            Self::SummaryGenerated => "summary_generated",
+            Self::EntityExtraction => "entity_extraction",
+            Self::EntityRelations => "entity_relations",
+            Self::SessionRecap => "session_recap",
+            Self::ChatSystem => "chat_system",
+            Self::ChatUser => "chat_user",
+            Self::ChatEmpty => "chat_empty",
+            Self::JsonRepair => "json_repair",
+            Self::ContextTraceMeta => "context_trace_meta",
+            Self::ContextActiveSessions => "context_active_sessions",
+            Self::ContextRecentOcr => "context_recent_ocr",
+            Self::ContextRoutingCorrections => "context_routing_corrections",
+            Self::ContextImageOverview => "context_image_overview",
+            Self::ContextImageFocus => "context_image_focus",
+            Self::ContextImageTile => "context_image_tile",
+            Self::ContextImageChanged => "context_image_changed",
+        }
+    }
+
+    /// 模板允许使用的变量
+    pub fn variables(&self) -> &'static [&'static str] {
+        match self {
+            Self::ScreenAnalysis | Self::ScreenContext => &[
+                "context",
+                "trace_meta",
+                "image_layout",
+                "changed_region",
+                "active_sessions",
+                "recent_ocr",
+            ],
+            Self::Summary | Self::SummaryRollup => &["period", "context"],
+            Self::EntityExtraction => &["text"],
+            Self::EntityRelations => &["entity", "context"],
+            Self::SessionRecap => &["context"],
+            Self::SummaryEmpty | Self::ChatSystem | Self::ChatEmpty => &[],
+            Self::SummaryGenerated => &["summary_type"],
+            Self::ChatUser => &["context", "question"],
+            Self::JsonRepair => &["output", "error", "schema"],
+            Self::ContextTraceMeta => &["app_name", "window_title", "time"],
+            Self::ContextActiveSessions => &["sessions"],
+            Self::ContextRecentOcr => &["traces"],
+            Self::ContextRoutingCorrections => &["corrections"],
+            Self::ContextImageOverview | Self::ContextImageFocus => &["width", "height"],
+            Self::ContextImageTile => &["index", "x", "y", "width", "height"],
+            Self::ContextImageChanged => &["x", "y", "width", "height"],
+        }
+    }
+
+    /// 模板必须包含的变量
+    pub fn required_variables(&self) -> &'static [&'static str] {
+        match self {
+            Self::Summary | Self::SummaryRollup => &["context"],
+            Self::EntityExtraction => &["text"],
+            Self::EntityRelations => &["entity", "context"],
+            Self::SessionRecap => &["context"],
+            Self::ChatUser => &["context", "question"],
+            Self::JsonRepair => &["output"],
+            Self::ContextActiveSessions => &["sessions"],
+            Self::ContextRecentOcr => &["traces"],
+            Self::ContextRoutingCorrections => &["corrections"],
+            _ => &[],
+        }
+    }
+
+    /// 从名称解析（与模板文件名一致）
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == name)
+    }
+
+    /// 内置模板
+    fn builtin(&self, language: Locale) -> &'static str {
+        match (language, self) {
+            (Locale::Zh, Self::ScreenAnalysis) => include_str!("prompts/zh/screen_analysis.txt"),
+            (Locale::Zh, Self::ScreenContext) => include_str!("prompts/zh/screen_context.txt"),
+            (Locale::Zh, Self::Summary) => include_str!("prompts/zh/summary.txt"),
+            (Locale::Zh, Self::SummaryRollup) => include_str!("prompts/zh/summary_rollup.txt"),
+            (Locale::Zh, Self::SummaryEmpty) => include_str!("prompts/zh/summary_empty.txt"),
+            (Locale::Zh, Self::SummaryGenerated) => {
+                include_str!("prompts/zh/summary_generated.txt")
+            }
+            (Locale::Zh, Self::EntityExtraction) => {
+                include_str!("prompts/zh/entity_extraction.txt")
+            }
+            (Locale::Zh, Self::EntityRelations) => {
+                include_str!("prompts/zh/entity_relations.txt")
+            }
+            (Locale::Zh, Self::SessionRecap) => include_str!("prompts/zh/session_recap.txt"),
+            (Locale::Zh, Self::ChatSystem) => include_str!("prompts/zh/chat_system.txt"),
+            (Locale::Zh, Self::ChatUser) => include_str!("prompts/zh/chat_user.txt"),
+            (Locale::Zh, Self::ChatEmpty) => include_str!("prompts/zh/chat_empty.txt"),
+            (Locale::Zh, Self::JsonRepair) => include_str!("prompts/zh/json_repair.txt"),
+            (Locale::Zh, Self::ContextTraceMeta) => {
+                include_str!("prompts/zh/context_trace_meta.txt")
+            }
+            (Locale::Zh, Self::ContextActiveSessions) => {
+                include_str!("prompts/zh/context_active_sessions.txt")
+            }
+            (Locale::Zh, Self::ContextRecentOcr) => {
+                include_str!("prompts/zh/context_recent_ocr.txt")
+            }
+            (Locale::Zh, Self::ContextRoutingCorrections) => {
+                include_str!("prompts/zh/context_routing_corrections.txt")
+            }
+            (Locale::Zh, Self::ContextImageOverview) => {
+                include_str!("prompts/zh/context_image_overview.txt")
+            }
+            (Locale::Zh, Self::ContextImageFocus) => {
+                include_str!("prompts/zh/context_image_focus.txt")
+            }
+            (Locale::Zh, Self::ContextImageTile) => {
+                include_str!("prompts/zh/context_image_tile.txt")
+            }
+            (Locale::Zh, Self::ContextImageChanged) => {
+                include_str!("prompts/zh/context_image_changed.txt")
+            }
+            (Locale::En, Self::ScreenAnalysis) => include_str!("prompts/en/screen_analysis.txt"),
+            (Locale::En, Self::ScreenContext) => include_str!("prompts/en/screen_context.txt"),
+            (Locale::En, Self::Summary) => include_str!("prompts/en/summary.txt"),
+            (Locale::En, Self::SummaryRollup) => include_str!("prompts/en/summary_rollup.txt"),
+            (Locale::En, Self::SummaryEmpty) => include_str!("prompts/en/summary_empty.txt"),
+            (Locale::En, Self::SummaryGenerated) => {
+                include_str!("prompts/en/summary_generated.txt")
+            }
+            (Locale::En, Self::EntityExtraction) => {
+                include_str!("prompts/en/entity_extraction.txt")
+            }
+            (Locale::En, Self::EntityRelations) => {
+                include_str!("prompts/en/entity_relations.txt")
+            }
+            (Locale::En, Self::SessionRecap) => include_str!("prompts/en/session_recap.txt"),
+            (Locale::En, Self::ChatSystem) => include_str!("prompts/en/chat_system.txt"),
+            (Locale::En, Self::ChatUser) => include_str!("prompts/en/chat_user.txt"),
+            (Locale::En, Self::ChatEmpty) => include_str!("prompts/en/chat_empty.txt"),
+            (Locale::En, Self::JsonRepair) => include_str!("prompts/en/json_repair.txt"),
+            (Locale::En, Self::ContextTraceMeta) => {
+                include_str!("prompts/en/context_trace_meta.txt")
+            }
+            (Locale::En, Self::ContextActiveSessions) => {
+                include_str!("prompts/en/context_active_sessions.txt")
+            }
+            (Locale::En, Self::ContextRecentOcr) => {
+                include_str!("prompts/en/context_recent_ocr.txt")
+            }
+            (Locale::En, Self::ContextRoutingCorrections) => {
+                include_str!("prompts/en/context_routing_corrections.txt")
+            }
+            (Locale::En, Self::ContextImageOverview) => {
+                include_str!("prompts/en/context_image_overview.txt")
+            }
+            (Locale::En, Self::ContextImageFocus) => {
+                include_str!("prompts/en/context_image_focus.txt")
+            }
+            (Locale::En, Self::ContextImageTile) => {
+                include_str!("prompts/en/context_image_tile.txt")
+            }
+            (Locale::En, Self::ContextImageChanged) => {
+                include_str!("prompts/en/context_image_changed.txt")
+            }
+        }
+    }
+}
+
+/// 语言目录名
+fn language_dir(language: Locale) -> &'static str {
+    match language {
+        Locale::Zh => "zh",
+        Locale::En => "en",
+    }
+}
+
+/// 提取模板中的 `{name}` 占位符（name 只含小写字母、数字和下划线，JSON 示例中的花括号不受影响）
+fn placeholders(template: &str) -> Vec<&str> {
+    let mut found = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let len = after
+            .find(|c: char| !(c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'))
+            .unwrap_or(after.len());
+        if len > 0 && after[len..].starts_with('}') {
+            found.push(&after[..len]);
+        }
+        rest = after;
+    }
+    found
+}
+
+/// 单遍替换模板中的 `{name}` 占位符
+///
+/// 变量值原样写入结果，不再扫描，因此值里出现的 `{context}` 等文本不会被再次替换；
+/// 未提供的占位符保留原文。
+fn substitute(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let len = after
+            .find(|c: char| !(c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'))
+            .unwrap_or(after.len());
+        let value = (len > 0 && after[len..].starts_with('}'))
+            .then(|| vars.iter().find(|(name, _)| *name == &after[..len]))
+            .flatten();
+        match value {
+            Some((_, value)) => {
+                rendered.push_str(value);
+                rest = &after[len + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// 校验模板：只允许使用该 Prompt 支持的变量，且必须包含必需变量
+pub fn validate_template(kind: PromptKind, template: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("template is empty".to_string());
+    }
+
+    let used = placeholders(template);
+    if let Some(unknown) = used.iter().find(|v| !kind.variables().contains(v)) {
+        return Err(format!(
+            "unknown variable {{{}}} (allowed: {})",
+            unknown,
+            kind.variables().join(", ")
+        ));
+    }
+    if let Some(missing) = kind.required_variables().iter().find(|v| !used.contains(v)) {
+        return Err(format!("missing required variable {{{}}}", missing));
+    }
+    Ok(())
+}
+
+/// 截图分析上下文（由 VlmTask 按 trace 构建）
+#[derive(Debug, Clone, Default)]
+pub struct ScreenPromptContext {
+    /// 当前 trace 的元信息（应用、窗口标题、时间）
+    pub trace_meta: String,
+    /// 图片布局说明：概览图、高清分块与变化区域的位置（由 VlmEngine 在图像预处理后填写）
+    pub image_layout: String,
+    /// 活跃 Session 列表
+    pub active_sessions: String,
+    /// 最近 traces 的 OCR 片段
+    pub recent_ocr: String,
+}
+
+impl ScreenPromptContext {
+    /// 拼接为完整上下文（`{context}`），超长时保留末尾
+    pub fn joined(&self) -> String {
+        let joined = [
+            &self.trace_meta,
+            &self.image_layout,
+            &self.active_sessions,
+            &self.recent_ocr,
+        ]
+        .into_iter()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+        let count = joined.chars().count();
+        if count > MAX_SCREEN_CONTEXT_CHARS {
+            joined
+                .chars()
+                .skip(count - MAX_SCREEN_CONTEXT_CHARS)
+                .collect()
+        } else {
+            joined
+        }
+    }
+}
+
+/// 渲染后的截图分析 Prompt
+#[derive(Debug, Clone)]
+pub struct RenderedPrompt {
+    /// 按顺序发送的文本块（上下文块在前，分析指令在后）
+    pub blocks: Vec<String>,
+    /// 模板版本号
+    pub version: String,
+}
+
+/// Prompt 模板库（内置模板 + 用户覆盖）
+pub struct PromptLibrary {
+    /// 模型输出语言
+    language: RwLock<Locale>,
+    /// 用户模板目录（None 表示只使用内置模板）
+    overrides_dir: Option<PathBuf>,
+    /// 用户覆盖模板
+    overrides: RwLock<HashMap<(Locale, PromptKind), String>>,
+    /// 最近一次加载时被拒绝的模板及原因
+    load_errors: RwLock<Vec<String>>,
+    /// 模板目录监听器（热加载）
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl PromptLibrary {
+    /// 只使用内置模板
+    pub fn builtin(language: Locale) -> Self {
+        Self {
+            language: RwLock::new(language),
+            overrides_dir: None,
+            overrides: RwLock::new(HashMap::new()),
+            load_errors: RwLock::new(Vec::new()),
+            watcher: Mutex::new(None),
+        }
+    }
+
+    /// 使用内置模板，并从目录加载用户覆盖模板
+    pub fn with_overrides_dir(language: Locale, dir: PathBuf) -> Self {
+        let library = Self {
+            overrides_dir: Some(dir),
+            ..Self::builtin(language)
+        };
+        library.reload();
+        library
+    }
+
+    /// 重新加载用户覆盖模板
+    pub fn reload(&self) {
+        let Some(dir) = &self.overrides_dir else {
+            return;
+        };
+
+        let mut overrides = HashMap::new();
+        let mut errors = Vec::new();
+        for language in [Locale::Zh, Locale::En] {
+            for kind in PromptKind::ALL {
+                let path = dir
+                    .join(language_dir(language))
+                    .join(format!("{}.txt", kind.as_str()));
+                if !path.exists() {
+                    continue;
+                }
+                let result = fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|content| validate_template(kind, &content).map(|_| content));
+                match result {
+                    Ok(content) => {
+                        debug!("Loaded prompt override: {}", path.display());
+                        overrides.insert((language, kind), content);
+                    }
+                    Err(e) => {
+                        warn!("Ignoring prompt override {}: {}", path.display(), e);
+                        errors.push(format!("{}: {}", path.display(), e));
+                    }
+                }
+            }
+        }
+
+        if !overrides.is_empty() {
+            info!(
+                "Loaded {} prompt override(s) from {}",
+                overrides.len(),
+                dir.display()
+            );
+        }
+        *self.overrides.write().unwrap() = overrides;
+        *self.load_errors.write().unwrap() = errors;
+    }
+
+    /// 监听模板目录，文件变更后自动重新加载
+    pub fn start_watching(self: &Arc<Self>) -> Result<()> {
+        let Some(dir) = &self.overrides_dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir)?;
+
+        let library: Weak<Self> = Arc::downgrade(self);
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) if !event.kind.is_access() => {
+                    if let Some(library) = library.upgrade() {
+                        info!("Prompt templates changed, reloading");
+                        library.reload();
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Prompt watcher error: {}", e),
+            })?;
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+
+        info!("Watching prompt templates in {}", dir.display());
+        *self.watcher.lock().unwrap() = Some(watcher);
+        Ok(())
+    }
+
+    /// 最近一次加载时被拒绝的用户模板
+    pub fn load_errors(&self) -> Vec<String> {
+        self.load_errors.read().unwrap().clone()
+    }
+
+    /// 当前模型输出语言
+    pub fn language(&self) -> Locale {
+        *self.language.read().unwrap()
+    }
+
+    /// 切换模型输出语言
+    pub fn set_language(&self, language: Locale) {
+        *self.language.write().unwrap() = language;
+    }
+
+    /// 获取当前语言下的模板原文（优先用户覆盖）
+    pub fn template(&self, kind: PromptKind) -> String {
+        let language = self.language();
+        self.overrides
+            .read()
+            .unwrap()
+            .get(&(language, kind))
+            .cloned()
+            .unwrap_or_else(|| kind.builtin(language).to_string())
+    }
+
+    /// 模板版本号：`<语言>-<builtin|user>-<内容哈希>`，多个模板合并计算
+    pub fn version(&self, kinds: &[PromptKind]) -> String {
+        let language = self.language();
+        let overrides = self.overrides.read().unwrap();
+
+        let mut hasher = Sha256::new();
+        let mut is_user = false;
+        for kind in kinds {
+            let template = match overrides.get(&(language, *kind)) {
+                Some(t) => {
+                    is_user = true;
+                    t.as_str()
+                }
+                None => kind.builtin(language),
+            };
+            hasher.update(kind.as_str().as_bytes());
+            hasher.update(template.as_bytes());
+        }
+        let digest = hasher.finalize();
+        let hash: String = digest
+            .iter()
+            .take(4)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        format!(
+            "{}-{}-{}",
+            language_dir(language),
+            if is_user { "user" } else { "builtin" },
+            hash
+        )
+    }
+
+    /// 渲染模板：把 `{name}` 替换为变量值
+    pub fn render(&self, kind: PromptKind, vars: &[(&str, &str)]) -> String {
+        substitute(&self.template(kind), vars)
+            .trim_end()
+            .to_string()
+    }
+
+    /// 渲染截图分析 Prompt（上下文块 + 分析指令）
+    pub fn render_screen_prompt(&self, context: Option<&ScreenPromptContext>) -> RenderedPrompt {
+        let version = self.version(&[PromptKind::ScreenContext, PromptKind::ScreenAnalysis]);
+        let joined = context.map(|c| c.joined()).unwrap_or_default();
+        let vars: Vec<(&str, &str)> = match context {
+            Some(c) => vec![
+                ("context", joined.as_str()),
+                ("trace_meta", c.trace_meta.as_str()),
+                ("image_layout", c.image_layout.as_str()),
+                ("changed_region", c.image_layout.as_str()),
+                ("active_sessions", c.active_sessions.as_str()),
+                ("recent_ocr", c.recent_ocr.as_str()),
+            ],
+            None => vec![
+                ("context", ""),
+                ("trace_meta", ""),
+                ("image_layout", ""),
+                ("changed_region", ""),
+                ("active_sessions", ""),
+                ("recent_ocr", ""),
+            ],
+        };
+
+        let mut blocks = Vec::with_capacity(2);
+        if !joined.is_empty() {
+            blocks.push(self.render(PromptKind::ScreenContext, &vars));
+        }
+        blocks.push(self.render(PromptKind::ScreenAnalysis, &vars));
+
+        RenderedPrompt { blocks, version }
+    }
+}
+
+impl Default for PromptLibrary {
+    fn default() -> Self {
+        Self::builtin(Locale::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_substitutes_variables_per_language() {
+        let library = PromptLibrary::builtin(Locale::En);
+        let prompt = library.render(
+            PromptKind::Summary,
+            &[("period", "15 minutes"), ("context", "[09:00] VSCode")],
+        );
+        assert!(prompt.contains("15 minutes"));
+        assert!(prompt.contains("[09:00] VSCode"));
+        assert!(prompt.contains("in English"));
+        assert!(!prompt.contains("{context}"));
+
+        library.set_language(Locale::Zh);
+        let prompt = library.render(PromptKind::ChatSystem, &[]);
+        assert!(prompt.contains("使用中文回复"));
+    }
+
+    #[test]
+    fn test_substitute_is_single_pass() {
+        // 变量值中的占位符（如 OCR 文本里的 `{context}`）不会被再次替换
+        let rendered = substitute(
+            "{period}: {context} {unknown} {\"json\": 1}",
+            &[("period", "{context}"), ("context", "a {period} b")],
+        );
+        assert_eq!(rendered, "{context}: a {period} b {unknown} {\"json\": 1}");
+    }
+
+    #[test]
+    fn test_builtin_templates_are_valid() {
+        for language in [Locale::Zh, Locale::En] {
+            for kind in PromptKind::ALL {
+                assert_eq!(
+                    validate_template(kind, kind.builtin(language)),
+                    Ok(()),
+                    "{:?}/{}",
+                    language,
+                    kind.as_str()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_validation_rejects_unknown_and_missing_variables() {
+        assert!(validate_template(PromptKind::Summary, "{period} {contxt}").is_err());
+        assert!(validate_template(PromptKind::Summary, "only {period}").is_err());
+        assert!(validate_template(PromptKind::Summary, "{period}\n{context}\n{\"a\": 1}").is_ok());
+        assert!(validate_template(
+            PromptKind::ScreenAnalysis,
+            "Sessions:\n{active_sessions}\nAnalyze."
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_changed_region_is_alias_of_image_layout() {
+        let dir = std::env::temp_dir().join(format!("engram-prompts-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("en")).unwrap();
+        fs::write(
+            dir.join("en").join("screen_context.txt"),
+            "Layout: {changed_region}\n{trace_meta}",
+        )
+        .unwrap();
+
+        let library = PromptLibrary::with_overrides_dir(Locale::En, dir.clone());
+        assert!(library.load_errors().is_empty());
+        let context = ScreenPromptContext {
+            trace_meta: "[Current Screenshot]".to_string(),
+            image_layout: "[Image Layout]".to_string(),
+            ..Default::default()
+        };
+        let prompt = library.render_screen_prompt(Some(&context));
+        assert!(prompt.blocks[0].starts_with("Layout: [Image Layout]"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_user_override_takes_precedence() {
+        let dir = std::env::temp_dir().join(format!("engram-prompts-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("en")).unwrap();
+        fs::write(dir.join("en").join("chat_empty.txt"), "Nothing here.\n").unwrap();
+
+        fs::write(dir.join("en").join("summary.txt"), "{period} {unknown}").unwrap();
+
+        let library = PromptLibrary::with_overrides_dir(Locale::En, dir.clone());
+        assert_eq!(library.render(PromptKind::ChatEmpty, &[]), "Nothing here.");
+        assert!(library
+            .version(&[PromptKind::ChatEmpty])
+            .starts_with("en-user-"));
+
+        // 校验失败的模板回退到内置模板
+        assert_eq!(library.load_errors().len(), 1);
+        assert!(library
+            .version(&[PromptKind::Summary])
+            .starts_with("en-builtin-"));
+
+        // 其他语言不受影响
+        library.set_language(Locale::Zh);
+        assert!(library
+            .render(PromptKind::ChatEmpty, &[])
+            .contains("没有找到"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,682 @@
+//! 结构化输出
+//!
+//! `ScreenDescription`、`GeneratedSummary`、会话回顾、实体提取与关系标注各有一份 JSON Schema：
+//! - 请求时按端点选择 `response_format`：已知支持的服务（OpenAI、OpenRouter）使用 `json_schema`
+//!   约束解码，其余使用 `json_object`（本地服务需要约束解码时显式配置 `json_schema`）；服务端拒绝
+//!   `json_schema` 时由调用方退回 `json_object`，再拒绝 `json_object` 时不发送 `response_format`
+//! - 解析时容忍代码块、前后多余文字、尾逗号与被截断的输出（补齐引号和括号，必要时回退到
+//!   最后一个完整字段），并按 Schema 纠正常见的类型偏差（字符串形式的数字、布尔值等）
+//! - 仍无法解析时把原输出和错误交给模型重新输出，次数由 `max_reasks` 限制
+//!
+//! 每次解析的结果记为 `ParseStatus`，截图分析的解析状态按 trace 存储在 `trace_parse_status`，
+//! 摘要的解析状态按周期存储在 `summary_parse_status`，其余文本任务按对象存储在 `model_parse_status`。
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::future::Future;
+
+/// 解析失败时保存的原始输出最大字符数
+pub const MAX_RAW_OUTPUT_CHARS: usize = 4000;
+
+/// 结构化输出方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StructuredOutput {
+    /// 按端点推断：已知支持的服务用 `json_schema`，其余用 `json_object`
+    #[default]
+    Auto,
+    /// 总是发送 `json_schema`
+    JsonSchema,
+    /// 只要求输出 JSON 对象（不约束结构）
+    JsonObject,
+    /// 不发送 `response_format`（服务端不支持该参数时使用）
+    Off,
+}
+
+impl StructuredOutput {
+    /// 该端点是否使用 `json_schema`
+    pub fn uses_schema(&self, endpoint: &str) -> bool {
+        match self {
+            StructuredOutput::JsonSchema => true,
+            StructuredOutput::Auto => supports_json_schema(endpoint),
+            StructuredOutput::JsonObject | StructuredOutput::Off => false,
+        }
+    }
+
+    /// 请求中的 `response_format` 字段；`schema_rejected` 表示服务端已拒绝过 `json_schema`
+    pub fn response_format(
+        &self,
+        endpoint: &str,
+        name: &str,
+        schema: &Value,
+        schema_rejected: bool,
+    ) -> Option<Value> {
+        if *self == StructuredOutput::Off {
+            return None;
+        }
+        if !schema_rejected && self.uses_schema(endpoint) {
+            return Some(json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": name,
+                    "strict": true,
+                    "schema": schema
+                }
+            }));
+        }
+        Some(json!({ "type": "json_object" }))
+    }
+}
+
+/// 已知支持 `response_format: json_schema` 的服务（按域名判断；本地服务的端口不代表实际部署的服务）
+fn supports_json_schema(endpoint: &str) -> bool {
+    let endpoint = endpoint.to_ascii_lowercase();
+    ["openai.com", "openrouter.ai"]
+        .iter()
+        .any(|host| endpoint.contains(host))
+}
+
+/// chat/completions 返回的错误状态
+#[derive(Debug)]
+pub struct ApiStatusError {
+    pub status: u16,
+    pub body: String,
+}
+
+impl std::fmt::Display for ApiStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "API error {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for ApiStatusError {}
+
+/// 服务端错误是否表示不支持 `json_schema`（此时退回 `json_object` 重试）
+pub fn is_schema_rejection(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ApiStatusError>().is_some_and(|e| {
+        let body = e.body.to_ascii_lowercase();
+        (e.status == 400 || e.status == 422)
+            && (body.contains("json_schema") || body.contains("response_format"))
+    })
+}
+
+/// 是否为 `json_schema` 形式的 `response_format`
+pub fn is_schema_format(format: Option<&Value>) -> bool {
+    format.is_some_and(|f| f["type"] == "json_schema")
+}
+
+/// 是否为 `json_object` 形式的 `response_format`
+pub fn is_object_format(format: Option<&Value>) -> bool {
+    format.is_some_and(|f| f["type"] == "json_object")
+}
+
+/// 带 `json_object` 的请求被拒绝（400/422）时不带 `response_format` 重发；
+/// 重发成功后调用方才记住服务端不支持该参数，避免其他 400 错误误判
+pub fn is_format_rejection(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<ApiStatusError>()
+        .is_some_and(|e| e.status == 400 || e.status == 422)
+}
+
+/// 重新请求后仍无法解析的结构化输出
+#[derive(Debug)]
+pub struct ParseFailure {
+    pub report: ParseReport,
+}
+
+impl std::fmt::Display for ParseFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unparseable structured output after {} attempts: {}",
+            self.report.attempts,
+            self.report.error.as_deref().unwrap_or("")
+        )
+    }
+}
+
+impl std::error::Error for ParseFailure {}
+
+/// 解析状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseStatus {
+    /// 直接解析成功
+    #[default]
+    Ok,
+    /// 修复后解析成功（截断、尾逗号、类型偏差等）
+    Repaired,
+    /// 重新请求后解析成功
+    Reasked,
+    /// 无法解析
+    Failed,
+}
+
+impl ParseStatus {
+    pub const ALL: [ParseStatus; 4] = [
+        ParseStatus::Ok,
+        ParseStatus::Repaired,
+        ParseStatus::Reasked,
+        ParseStatus::Failed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParseStatus::Ok => "ok",
+            ParseStatus::Repaired => "repaired",
+            ParseStatus::Reasked => "reasked",
+            ParseStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.as_str() == name)
+    }
+}
+
+/// 一次结构化输出的解析记录
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseReport {
+    pub status: ParseStatus,
+    /// 请求次数（含重新请求）
+    pub attempts: u32,
+    /// 最后一次解析错误
+    pub error: Option<String>,
+    /// 解析失败时的原始输出（截断到 `MAX_RAW_OUTPUT_CHARS`）
+    pub raw_output: Option<String>,
+}
+
+/// `ScreenDescription` 的 JSON Schema（strict 模式：所有字段必填，可空字段用 null）
+pub fn screen_description_schema() -> Value {
+    let nullable_string = json!({ "type": ["string", "null"] });
+    json!({
+        "type": "object",
+        "properties": {
+            "summary": { "type": "string" },
+            "text_content": nullable_string,
+            "detected_app": nullable_string,
+            "activity_type": nullable_string,
+            "entities": { "type": "array", "items": { "type": "string" } },
+            "is_key_action": { "type": "boolean" },
+            "action_description": nullable_string,
+            "confidence": { "type": "number" },
+            "session_title": nullable_string,
+            "session_description": nullable_string,
+            "existing_session_id": { "type": ["integer", "null"] }
+        },
+        "required": [
+            "summary", "text_content", "detected_app", "activity_type", "entities",
+            "is_key_action", "action_description", "confidence", "session_title",
+            "session_description", "existing_session_id"
+        ],
+        "additionalProperties": false
+    })
+}
+
+/// `GeneratedSummary` 中由模型输出部分的 JSON Schema
+pub fn generated_summary_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "content": { "type": "string" },
+            "topics": { "type": "array", "items": { "type": "string" } },
+            "entities": { "type": "array", "items": extracted_entity_schema() },
+            "links": { "type": "array", "items": { "type": "string" } }
+        },
+        "required": ["content", "topics", "entities", "links"],
+        "additionalProperties": false
+    })
+}
+
+/// 单个提取实体的 JSON Schema
+fn extracted_entity_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "name": { "type": "string" },
+            "type": { "type": "string" },
+            "confidence": { "type": "number" }
+        },
+        "required": ["name", "type", "confidence"],
+        "additionalProperties": false
+    })
+}
+
+/// 实体提取输出的 JSON Schema
+pub fn extracted_entities_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "entities": { "type": "array", "items": extracted_entity_schema() }
+        },
+        "required": ["entities"],
+        "additionalProperties": false
+    })
+}
+
+/// 实体关系标注输出的 JSON Schema
+pub fn relation_labels_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "relations": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "entity": { "type": "string" },
+                        "type": { "type": "string" }
+                    },
+                    "required": ["entity", "type"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["relations"],
+        "additionalProperties": false
+    })
+}
+
+/// `SessionRecap` 的 JSON Schema
+pub fn session_recap_schema() -> Value {
+    let nullable_string = json!({ "type": ["string", "null"] });
+    json!({
+        "type": "object",
+        "properties": {
+            "title": nullable_string,
+            "description": nullable_string,
+            "recap": { "type": "string" }
+        },
+        "required": ["title", "description", "recap"],
+        "additionalProperties": false
+    })
+}
+
+/// 解析模型输出：先直接解析，失败时修复后再按 Schema 纠正类型
+///
+/// 返回值与状态（`Ok` 或 `Repaired`）；错误信息用于重新请求。
+pub fn parse_json<T: DeserializeOwned>(
+    content: &str,
+    schema: &Value,
+) -> Result<(T, ParseStatus), String> {
+    let body = extract_json(content);
+    let first_error = match serde_json::from_str::<T>(body) {
+        Ok(value) => return Ok((value, ParseStatus::Ok)),
+        Err(e) => e.to_string(),
+    };
+
+    let mut value = repair_json(body).ok_or(first_error)?;
+    coerce_to_schema(&mut value, schema);
+    serde_json::from_value::<T>(value)
+        .map(|v| (v, ParseStatus::Repaired))
+        .map_err(|e| e.to_string())
+}
+
+/// 解析，失败时通过 `reask(原输出, 错误)` 重新请求，最多 `max_reasks` 次
+///
+/// 返回解析结果（失败时为 None）与解析记录。
+pub async fn parse_with_reask<T, F, Fut>(
+    content: &str,
+    schema: &Value,
+    max_reasks: u32,
+    mut reask: F,
+) -> (Option<T>, ParseReport)
+where
+    T: DeserializeOwned,
+    F: FnMut(String, String) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let mut report = ParseReport {
+        attempts: 1,
+        ..Default::default()
+    };
+    let mut error = match parse_json::<T>(content, schema) {
+        Ok((value, status)) => {
+            report.status = status;
+            return (Some(value), report);
+        }
+        Err(e) => e,
+    };
+
+    let mut output = content.to_string();
+    for _ in 0..max_reasks {
+        report.attempts += 1;
+        match reask(output.clone(), error.clone()).await {
+            Ok(next) => match parse_json::<T>(&next, schema) {
+                Ok((value, _)) => {
+                    report.status = ParseStatus::Reasked;
+                    return (Some(value), report);
+                }
+                Err(e) => {
+                    error = e;
+                    output = next;
+                }
+            },
+            Err(e) => {
+                error = format!("re-ask failed: {}", e);
+                break;
+            }
+        }
+    }
+
+    report.status = ParseStatus::Failed;
+    report.error = Some(error);
+    report.raw_output = Some(content.chars().take(MAX_RAW_OUTPUT_CHARS).collect());
+    (None, report)
+}
+
+/// 去掉代码块标记与 JSON 对象前后的多余文字
+fn extract_json(content: &str) -> &str {
+    let trimmed = content
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let Some(start) = trimmed.find('{') else {
+        return trimmed;
+    };
+    let body = &trimmed[start..];
+    // 输出完整时截到最后一个右花括号；被截断的输出保留全部内容交给修复
+    match body.rfind('}') {
+        Some(end) if serde_json::from_str::<Value>(&body[..=end]).is_ok() => &body[..=end],
+        _ => body,
+    }
+}
+
+/// 修复常见的 JSON 问题：尾逗号、未闭合的字符串与括号（输出被截断）
+///
+/// 先补齐后直接解析；仍失败时回退到最后一个完整值之后（丢弃被截断的字段）再补齐。
+fn repair_json(body: &str) -> Option<Value> {
+    let mut stack: Vec<char> = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    // 完整值之后的截断点（不含该位置）及当时的括号栈
+    let mut cut_points: Vec<(usize, Vec<char>)> = Vec::new();
+    let mut cleaned = String::with_capacity(body.len() + 8);
+
+    for c in body.chars() {
+        if in_string {
+            cleaned.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => stack.push('}'),
+            '[' => stack.push(']'),
+            '}' | ']' => {
+                // 尾逗号
+                let trimmed_len = cleaned.trim_end().len();
+                if cleaned[..trimmed_len].ends_with(',') {
+                    cleaned.truncate(trimmed_len - 1);
+                }
+                if stack.pop() != Some(c) {
+                    return None;
+                }
+                cleaned.push(c);
+                cut_points.push((cleaned.len(), stack.clone()));
+                if stack.is_empty() {
+                    break;
+                }
+                continue;
+            }
+            ',' => cut_points.push((cleaned.len(), stack.clone())),
+            _ => {}
+        }
+        cleaned.push(c);
+    }
+
+    let close = |text: &str, stack: &[char]| -> String {
+        let mut text = text.trim_end().trim_end_matches(',').to_string();
+        text.extend(stack.iter().rev());
+        text
+    };
+
+    // 1. 补齐未闭合的字符串与括号
+    let mut completed = cleaned.clone();
+    if in_string {
+        if escaped {
+            completed.pop();
+        }
+        completed.push('"');
+    }
+    if let Ok(value) = serde_json::from_str::<Value>(&close(&completed, &stack)) {
+        return Some(value);
+    }
+
+    // 2. 回退到最后一个完整值
+    cut_points
+        .iter()
+        .rev()
+        .find_map(|(len, stack)| {
+            serde_json::from_str::<Value>(&close(&cleaned[..*len], stack)).ok()
+        })
+        .filter(Value::is_object)
+}
+
+/// 按 Schema 纠正常见的类型偏差：字符串形式的数字 / 布尔值 / null、单个字符串代替数组等
+fn coerce_to_schema(value: &mut Value, schema: &Value) {
+    let types: Vec<&str> = match &schema["type"] {
+        Value::String(t) => vec![t.as_str()],
+        Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let allows = |t: &str| types.contains(&t);
+
+    if let Value::String(s) = value {
+        let text = s.trim();
+        if allows("null") && (text.is_empty() || text.eq_ignore_ascii_case("null")) {
+            *value = Value::Null;
+        } else if allows("boolean") && !allows("string") {
+            match text.to_ascii_lowercase().as_str() {
+                "true" | "yes" => *value = Value::Bool(true),
+                "false" | "no" => *value = Value::Bool(false),
+                _ => {}
+            }
+        } else if (allows("number") || allows("integer")) && !allows("string") {
+            if let Ok(n) = text.parse::<i64>() {
+                *value = json!(n);
+            } else if let Ok(n) = text.parse::<f64>() {
+                *value = json!(n);
+            }
+        } else if allows("array") {
+            *value = if text.is_empty() {
+                json!([])
+            } else {
+                json!(text
+                    .split([',', '，', '、'])
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>())
+            };
+        }
+    } else if allows("string") && !allows("array") {
+        match value {
+            Value::Number(_) | Value::Bool(_) => *value = Value::String(value.to_string()),
+            Value::Array(items) if items.iter().all(Value::is_string) => {
+                let joined = items
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                *value = Value::String(joined);
+            }
+            _ => {}
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::Object(properties)) = schema.get("properties") {
+                for (key, child_schema) in properties {
+                    if let Some(child) = map.get_mut(key) {
+                        coerce_to_schema(child, child_schema);
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for item in items {
+                    coerce_to_schema(item, item_schema);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Sample {
+        summary: String,
+        #[serde(default)]
+        entities: Vec<String>,
+        #[serde(default)]
+        confidence: f32,
+        #[serde(default)]
+        is_key_action: bool,
+    }
+
+    #[test]
+    fn test_parse_and_repair() {
+        let schema = screen_description_schema();
+
+        let (sample, status) = parse_json::<Sample>(
+            "```json\n{\"summary\": \"coding\", \"entities\": [\"rust\"], \"confidence\": 0.9}\n```",
+            &schema,
+        )
+        .unwrap();
+        assert_eq!(status, ParseStatus::Ok);
+        assert_eq!(sample.entities, vec!["rust"]);
+
+        // 前后多余文字、尾逗号、字符串形式的数字与布尔值
+        let (sample, status) = parse_json::<Sample>(
+            "Here you go: {\"summary\": \"x\", \"confidence\": \"0.8\", \"is_key_action\": \"true\", \"entities\": \"a, b\",} hope it helps",
+            &schema,
+        )
+        .unwrap();
+        assert_eq!(status, ParseStatus::Repaired);
+        assert_eq!(sample.confidence, 0.8);
+        assert!(sample.is_key_action);
+        assert_eq!(sample.entities, vec!["a", "b"]);
+
+        // 在字符串中间被截断
+        let (sample, status) = parse_json::<Sample>(
+            "{\"summary\": \"editing main.rs\", \"entities\": [\"ma",
+            &schema,
+        )
+        .unwrap();
+        assert_eq!(status, ParseStatus::Repaired);
+        assert_eq!(sample.summary, "editing main.rs");
+        assert_eq!(sample.entities, vec!["ma"]);
+
+        // 在键或数字中间被截断：丢弃不完整的字段
+        let (sample, _) = parse_json::<Sample>(
+            "{\"summary\": \"reading\", \"confidence\": 0.7, \"sess",
+            &schema,
+        )
+        .unwrap();
+        assert_eq!(sample.confidence, 0.7);
+
+        // 不是 JSON
+        assert!(parse_json::<Sample>("I cannot see the screen.", &schema).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reask_is_bounded() {
+        let schema = screen_description_schema();
+        let mut calls = 0;
+        let (sample, report) = parse_with_reask::<Sample, _, _>("not json", &schema, 2, |_, _| {
+            calls += 1;
+            let reply = if calls == 2 {
+                "{\"summary\": \"fixed\"}".to_string()
+            } else {
+                "still not json".to_string()
+            };
+            async move { Ok(reply) }
+        })
+        .await;
+        assert_eq!(sample.unwrap().summary, "fixed");
+        assert_eq!(report.status, ParseStatus::Reasked);
+        assert_eq!(report.attempts, 3);
+
+        let (sample, report) =
+            parse_with_reask::<Sample, _, _>("not json", &schema, 1, |_, _| async {
+                Ok("nope".to_string())
+            })
+            .await;
+        assert!(sample.is_none());
+        assert_eq!(report.status, ParseStatus::Failed);
+        assert_eq!(report.attempts, 2);
+        assert_eq!(report.raw_output.as_deref(), Some("not json"));
+    }
+
+    #[test]
+    fn test_response_format() {
+        let schema = generated_summary_schema();
+        let format = StructuredOutput::Auto.response_format(
+            "https://api.openai.com/v1",
+            "summary",
+            &schema,
+            false,
+        );
+        assert_eq!(format.unwrap()["type"], "json_schema");
+        let format = StructuredOutput::Auto.response_format(
+            "https://api.anthropic.com/v1",
+            "summary",
+            &schema,
+            false,
+        );
+        assert_eq!(format.unwrap()["type"], "json_object");
+        let format = StructuredOutput::Auto.response_format(
+            "http://127.0.0.1:11434/v1",
+            "summary",
+            &schema,
+            false,
+        );
+        assert_eq!(format.unwrap()["type"], "json_object");
+        // 本地服务按端口无法判断，需要显式配置
+        let format = StructuredOutput::JsonSchema.response_format(
+            "http://127.0.0.1:8000/v1",
+            "summary",
+            &schema,
+            false,
+        );
+        assert_eq!(format.unwrap()["type"], "json_schema");
+        let format = StructuredOutput::JsonSchema.response_format(
+            "http://127.0.0.1:8000/v1",
+            "summary",
+            &schema,
+            true,
+        );
+        assert_eq!(format.unwrap()["type"], "json_object");
+        assert!(StructuredOutput::Off
+            .response_format("http://127.0.0.1:11434/v1", "summary", &schema, false)
+            .is_none());
+        assert!(is_schema_rejection(&anyhow::Error::new(ApiStatusError {
+            status: 400,
+            body: "Unknown parameter: response_format.json_schema".to_string(),
+        })));
+        assert!(is_format_rejection(&anyhow::Error::new(ApiStatusError {
+            status: 400,
+            body: "Bad request".to_string(),
+        })));
+        assert!(!is_format_rejection(&anyhow::Error::new(ApiStatusError {
+            status: 500,
+            body: "response_format".to_string(),
+        })));
+    }
+}
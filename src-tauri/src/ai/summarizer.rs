@@ -2,16 +2,53 @@
 //!
 //! 使用 OpenAI 兼容 API 生成屏幕活动摘要和提取实体。
 //! 支持本地服务（Ollama、vLLM）和远程服务（OpenAI、Together AI 等）。
+//!
+//! 摘要上下文由活动会话聚合而成（标题、描述、关键行为、实体与停留时长），
+//! 按 token 预算优先保留停留时间长的会话；活动类型分布由 trace 停留时长直接统计，
+//! 不交给 LLM 估计。
+//!
+//! 摘要、对话与实体关系标注各自可以配置模型（`[summarizer]`、`[chat]`、`[entities]`），
+//! 由 `TaskModelConfig::resolve` 合成为 `SummarizerConfig`，未填写的字段回退到 `[vlm]`。
+//!
+//! 摘要按 `GeneratedSummary` 的 JSON Schema 请求结构化输出（见 `structured`），结构化输出方式
+//! 与重新请求次数沿用 `[vlm]` 的设置。
 
 use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, info, warn};
 
-use crate::db::models::Trace;
+use super::prompts::{PromptKind, PromptLibrary};
+use super::structured::{self, ApiStatusError, ParseFailure, ParseReport, StructuredOutput};
+use super::vlm::{default_max_reasks, VlmConfig};
+use crate::config::Locale;
+use crate::db::models::{ActivitySession, TraceDuration};
+
+/// 汇总摘要每块上下文的字符预算
+const ROLLUP_CHUNK_CHARS: usize = 12_000;
+
+/// 汇总摘要最多合并轮数（超过后每个条目按比例截断，合成一块）
+const MAX_ROLLUP_ROUNDS: usize = 4;
+
+/// 汇总摘要合并后保留的链接数量上限
+const MAX_ROLLUP_LINKS: usize = 20;
+
+/// 上下文中每个会话保留的关键行为数
+const CONTEXT_KEY_ACTIONS: usize = 5;
+
+/// 上下文中每个会话保留的实体数
+const CONTEXT_ENTITIES: usize = 8;
+
+/// 上下文中会话描述的最大字符数
+const CONTEXT_DESCRIPTION_CHARS: usize = 300;
 
 /// 摘要生成配置
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SummarizerConfig {
     /// API 端点
     pub endpoint: String,
@@ -26,6 +63,15 @@ pub struct SummarizerConfig {
     /// 温度参数
     #[serde(default = "default_temperature")]
     pub temperature: f32,
+    /// 摘要上下文的 token 预算
+    #[serde(default = "default_context_tokens")]
+    pub context_tokens: u32,
+    /// 结构化输出方式（`response_format`）
+    #[serde(default)]
+    pub structured_output: StructuredOutput,
+    /// 输出无法解析时重新请求的最大次数
+    #[serde(default = "default_max_reasks")]
+    pub max_reasks: u32,
 }
 
 fn default_max_tokens() -> u32 {
@@ -36,6 +82,10 @@ fn default_temperature() -> f32 {
     0.3
 }
 
+fn default_context_tokens() -> u32 {
+    6000
+}
+
 impl Default for SummarizerConfig {
     fn default() -> Self {
         Self {
@@ -44,6 +94,9 @@ impl Default for SummarizerConfig {
             api_key: None,
             max_tokens: default_max_tokens(),
             temperature: default_temperature(),
+            context_tokens: default_context_tokens(),
+            structured_output: StructuredOutput::default(),
+            max_reasks: default_max_reasks(),
         }
     }
 }
@@ -80,19 +133,146 @@ impl SummarizerConfig {
     }
 }
 
+/// 使用文本模型的任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelTask {
+    /// 周期摘要与会话回顾
+    Summarizer,
+    /// 与记忆对话
+    Chat,
+    /// 实体提取与关系标注
+    Entities,
+}
+
+impl ModelTask {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModelTask::Summarizer => "summarizer",
+            ModelTask::Chat => "chat",
+            ModelTask::Entities => "entities",
+        }
+    }
+
+    /// 任务的 (max_tokens, temperature, context_tokens) 默认值
+    fn defaults(&self) -> (u32, f32, u32) {
+        match self {
+            ModelTask::Summarizer => (
+                default_max_tokens(),
+                default_temperature(),
+                default_context_tokens(),
+            ),
+            ModelTask::Chat => (1024, 0.7, 8000),
+            ModelTask::Entities => (1024, 0.3, 4000),
+        }
+    }
+}
+
+/// 单个文本任务的模型配置（`[summarizer]`、`[chat]`、`[entities]`）
+///
+/// 所有字段可选：endpoint、model 留空时使用 `[vlm]` 的值；api_key 留空时仅在沿用 VLM 端点时
+/// 继承 VLM 的密钥，避免把密钥发给另一家服务；max_tokens、temperature、context_tokens
+/// 留空时使用任务自己的默认值。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaskModelConfig {
+    /// API 端点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// 模型名称
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// API 密钥
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// 最大输出 tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// 温度参数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// 上下文的 token 预算
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_tokens: Option<u32>,
+}
+
+impl TaskModelConfig {
+    /// 合成任务实际使用的模型配置
+    pub fn resolve(&self, task: ModelTask, vlm: &VlmConfig) -> SummarizerConfig {
+        let endpoint = self
+            .endpoint
+            .as_deref()
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .map(|e| e.trim_end_matches('/').to_string());
+        let same_endpoint = endpoint
+            .as_deref()
+            .is_none_or(|e| e == vlm.endpoint.trim_end_matches('/'));
+        let api_key = match &self.api_key {
+            Some(key) if !key.is_empty() => Some(key.clone()),
+            _ if same_endpoint => vlm.api_key.clone(),
+            _ => None,
+        };
+        let (max_tokens, temperature, context_tokens) = task.defaults();
+
+        SummarizerConfig {
+            endpoint: endpoint.unwrap_or_else(|| vlm.endpoint.clone()),
+            model: self
+                .model
+                .clone()
+                .filter(|m| !m.trim().is_empty())
+                .unwrap_or_else(|| vlm.model.clone()),
+            api_key,
+            max_tokens: self.max_tokens.unwrap_or(max_tokens),
+            temperature: self.temperature.unwrap_or(temperature),
+            context_tokens: self.context_tokens.unwrap_or(context_tokens),
+            structured_output: vlm.structured_output,
+            max_reasks: vlm.max_reasks,
+        }
+    }
+}
+
 /// 生成的摘要
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GeneratedSummary {
     /// 摘要内容
     pub content: String,
     /// 主题列表
+    #[serde(default)]
     pub topics: Vec<String>,
     /// 提取的实体
+    #[serde(default)]
     pub entities: Vec<ExtractedEntity>,
     /// 相关链接/文件
+    #[serde(default)]
     pub links: Vec<String>,
-    /// 活动类型分布
+    /// 活动类型分布（由 trace 停留时长统计）
+    #[serde(default)]
     pub activity_breakdown: Vec<ActivityCount>,
+    /// 生成本摘要所用的 Prompt 模板版本
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_version: Option<String>,
+    /// 模型输出的解析记录（未请求模型时 `attempts` 为 0）
+    #[serde(skip)]
+    pub parse: ParseReport,
+}
+
+/// LLM 标注的实体关系
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelationLabel {
+    /// 相关实体名称
+    pub entity: String,
+    /// 关系类型（works_with/uses/part_of/depends_on/discusses/related_to）
+    #[serde(rename = "type")]
+    pub relation_type: String,
+}
+
+/// 会话关闭时生成的标题、描述与回顾
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecap {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub recap: String,
 }
 
 /// 提取的实体
@@ -117,8 +297,11 @@ fn default_confidence() -> f32 {
 pub struct ActivityCount {
     /// 活动类型
     pub activity_type: String,
-    /// 出现次数
+    /// trace 数量
     pub count: u32,
+    /// 停留时长（毫秒）
+    #[serde(default)]
+    pub duration_ms: i64,
 }
 
 /// 摘要类型
@@ -128,6 +311,12 @@ pub enum SummaryType {
     Short,
     /// 每日摘要
     Daily,
+    /// 每周摘要（由每日摘要与会话汇总）
+    Weekly,
+    /// 每月摘要（由每日摘要与会话汇总）
+    Monthly,
+    /// 自定义时间范围摘要（Unix 毫秒）
+    Custom { start: i64, end: i64 },
 }
 
 impl SummaryType {
@@ -135,15 +324,110 @@ impl SummaryType {
         match self {
             Self::Short => "short",
             Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Monthly => "monthly",
+            Self::Custom { .. } => "custom",
+        }
+    }
+
+    /// 从名称解析（`custom` 需要时间范围，不在此处理）
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "short" => Some(Self::Short),
+            "daily" => Some(Self::Daily),
+            "weekly" => Some(Self::Weekly),
+            "monthly" => Some(Self::Monthly),
+            _ => None,
+        }
+    }
+
+    /// 是否由较低层级的摘要汇总生成
+    pub fn is_rollup(&self) -> bool {
+        matches!(self, Self::Weekly | Self::Monthly | Self::Custom { .. })
+    }
+
+    /// 界面提示中使用的摘要类型名称
+    pub fn display_label(&self, language: Locale) -> &'static str {
+        match (language, self) {
+            (Locale::Zh, Self::Short) => "15分钟",
+            (Locale::Zh, Self::Daily) => "每日",
+            (Locale::Zh, Self::Weekly) => "每周",
+            (Locale::Zh, Self::Monthly) => "每月",
+            (Locale::Zh, Self::Custom { .. }) => "自定义时间段",
+            (Locale::En, Self::Short) => "15-minute",
+            (Locale::En, Self::Daily) => "Daily",
+            (Locale::En, Self::Weekly) => "Weekly",
+            (Locale::En, Self::Monthly) => "Monthly",
+            (Locale::En, Self::Custom { .. }) => "Custom range",
+        }
+    }
+
+    /// Prompt 中使用的时间段描述
+    pub fn period_label(&self, language: Locale) -> &'static str {
+        match (language, self) {
+            (Locale::Zh, Self::Short) => "15 分钟",
+            (Locale::Zh, Self::Daily) => "一天",
+            (Locale::Zh, Self::Weekly) => "一周",
+            (Locale::Zh, Self::Monthly) => "一个月",
+            (Locale::Zh, Self::Custom { .. }) => "所选时间段",
+            (Locale::En, Self::Short) => "the last 15 minutes",
+            (Locale::En, Self::Daily) => "the whole day",
+            (Locale::En, Self::Weekly) => "the whole week",
+            (Locale::En, Self::Monthly) => "the whole month",
+            (Locale::En, Self::Custom { .. }) => "the selected period",
         }
     }
 }
 
-/// 摘要生成器
+/// 按字符预算把条目分块，块内条目以换行分隔（单个超长条目会被截断）
+fn chunk_items(items: &[String], budget: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_chars = 0;
+
+    for item in items {
+        let item: String = item.chars().take(budget).collect();
+        let item_chars = item.chars().count();
+        if current_chars > 0 && current_chars + 1 + item_chars > budget {
+            chunks.push(std::mem::take(&mut current));
+            current_chars = 0;
+        }
+        if current_chars > 0 {
+            current.push('\n');
+            current_chars += 1;
+        }
+        current.push_str(&item);
+        current_chars += item_chars;
+    }
+
+    if current_chars > 0 {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// 把所有条目合成一块：每个条目按预算均分截断，保证每个条目都有内容进入上下文
+fn fit_items(items: &[String], budget: usize) -> String {
+    let share = (budget / items.len().max(1)).saturating_sub(1).max(1);
+    let joined = items
+        .iter()
+        .map(|item| item.chars().take(share).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n");
+    joined.chars().take(budget).collect()
+}
+
+/// 摘要生成器（OpenAI 兼容的纯文本 LLM 客户端，对话与实体标注也使用它）
+#[derive(Clone)]
 pub struct Summarizer {
     config: SummarizerConfig,
     client: reqwest::Client,
     is_ready: bool,
+    prompts: Arc<PromptLibrary>,
+    /// 服务端拒绝过 `json_schema`，之后改用 `json_object`
+    schema_rejected: Arc<AtomicBool>,
+    /// 服务端拒绝过 `json_object`，之后不发送 `response_format`
+    format_rejected: Arc<AtomicBool>,
 }
 
 impl Summarizer {
@@ -156,9 +440,18 @@ impl Summarizer {
                 .build()
                 .unwrap(),
             is_ready: false,
+            prompts: Arc::new(PromptLibrary::default()),
+            schema_rejected: Arc::new(AtomicBool::new(false)),
+            format_rejected: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// 使用共享的 Prompt 模板库
+    pub fn with_prompts(mut self, prompts: Arc<PromptLibrary>) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
     /// 自动检测可用的本地服务
     pub async fn auto_detect() -> Result<Self> {
         let endpoints = [
@@ -242,152 +535,359 @@ impl Summarizer {
         self.is_ready
     }
 
-    /// 生成摘要
+    /// 由活动会话与 trace 停留时长生成摘要
     pub async fn generate_summary(
         &self,
-        traces: &[Trace],
+        sessions: &[ActivitySession],
+        durations: &[TraceDuration],
         summary_type: SummaryType,
     ) -> Result<GeneratedSummary> {
         if !self.is_ready {
             return Err(anyhow!("Summarizer not initialized"));
         }
 
-        if traces.is_empty() {
-            return Ok(GeneratedSummary {
-                content: "No activity recorded in this period.".to_string(),
-                topics: Vec::new(),
-                entities: Vec::new(),
-                links: Vec::new(),
-                activity_breakdown: Vec::new(),
-            });
+        let breakdown = activity_breakdown(durations);
+        if sessions.is_empty() && breakdown.is_empty() {
+            return Ok(self.empty_summary());
         }
 
-        let context = self.build_context(traces);
+        let context =
+            build_session_context(sessions, durations, self.config.context_tokens as usize);
+        // 版本号在请求前记录，避免等待回复期间模板热加载导致版本不符
+        let prompt_version = self.prompts.version(&[PromptKind::Summary]);
         let prompt = self.build_summary_prompt(&context, summary_type);
 
-        let result = self.call_api(&prompt).await?;
-        self.parse_summary_response(&result)
+        let mut summary = self.request_summary(&prompt).await?;
+        summary.activity_breakdown = breakdown;
+        summary.prompt_version = Some(prompt_version);
+        Ok(summary)
+    }
+
+    /// 汇总较短周期的摘要与会话（map-reduce）
+    ///
+    /// 条目按字符预算分块，每块生成一份中间摘要；中间摘要仍超过一块时继续合并，
+    /// 直到只剩一块。达到最大轮数时每个条目按比例截断后合成一块，不丢弃任何条目。
+    /// 实体与链接取各轮结果的并集，避免合并时丢失。
+    pub async fn generate_rollup(
+        &self,
+        items: &[String],
+        summary_type: SummaryType,
+    ) -> Result<GeneratedSummary> {
+        if !self.is_ready {
+            return Err(anyhow!("Summarizer not initialized"));
+        }
+
+        let prompt_version = self.prompts.version(&[PromptKind::SummaryRollup]);
+        let mut entities: Vec<ExtractedEntity> = Vec::new();
+        let mut links: Vec<String> = Vec::new();
+        let mut level = items.to_vec();
+
+        for round in 0.. {
+            let chunks = chunk_items(&level, ROLLUP_CHUNK_CHARS);
+            if chunks.len() <= 1 || round + 1 >= MAX_ROLLUP_ROUNDS {
+                let chunk = match chunks.len() {
+                    0 => break,
+                    1 => chunks.into_iter().next().unwrap_or_default(),
+                    n => {
+                        warn!(
+                            "Rollup still has {} chunks after {} rounds, truncating items",
+                            n,
+                            round + 1
+                        );
+                        fit_items(&level, ROLLUP_CHUNK_CHARS)
+                    }
+                };
+                let mut summary = self.summarize_rollup_chunk(&chunk, summary_type).await?;
+                merge_entities(&mut summary.entities, entities);
+                merge_links(&mut summary.links, links);
+                summary.prompt_version = Some(prompt_version);
+                return Ok(summary);
+            }
+
+            debug!(
+                "Rollup round {}: {} items in {} chunks",
+                round,
+                level.len(),
+                chunks.len()
+            );
+            let mut partials = Vec::with_capacity(chunks.len());
+            for chunk in &chunks {
+                let partial = self.summarize_rollup_chunk(chunk, summary_type).await?;
+                merge_entities(&mut entities, partial.entities);
+                merge_links(&mut links, partial.links);
+                partials.push(if partial.topics.is_empty() {
+                    partial.content
+                } else {
+                    format!("{} [{}]", partial.content, partial.topics.join(", "))
+                });
+            }
+            level = partials;
+        }
+
+        Ok(self.empty_summary())
     }
 
-    /// 从摘要中提取实体
-    pub async fn extract_entities(&self, text: &str) -> Result<Vec<ExtractedEntity>> {
+    /// 时间段内没有任何活动时的摘要（按输出语言取模板）
+    fn empty_summary(&self) -> GeneratedSummary {
+        GeneratedSummary {
+            content: self.prompts.render(PromptKind::SummaryEmpty, &[]),
+            topics: Vec::new(),
+            entities: Vec::new(),
+            links: Vec::new(),
+            activity_breakdown: Vec::new(),
+            prompt_version: None,
+            parse: ParseReport::default(),
+        }
+    }
+
+    /// 对一块汇总上下文调用 LLM
+    async fn summarize_rollup_chunk(
+        &self,
+        context: &str,
+        summary_type: SummaryType,
+    ) -> Result<GeneratedSummary> {
+        let period = summary_type.period_label(self.prompts.language());
+        let prompt = self.prompts.render(
+            PromptKind::SummaryRollup,
+            &[("period", period), ("context", context)],
+        );
+        self.request_summary(&prompt).await
+    }
+
+    /// 从摘要中提取实体（应在 `[entities]` 模型的实例上调用，见 `SummarizerTask::extract_entities`）
+    ///
+    /// 输出无法解析时返回 `ParseFailure`，不当作"没有实体"。
+    pub async fn extract_entities(
+        &self,
+        text: &str,
+    ) -> Result<(Vec<ExtractedEntity>, ParseReport)> {
         if !self.is_ready {
             return Err(anyhow!("Summarizer not initialized"));
         }
 
+        #[derive(Deserialize)]
+        struct EntityResponse {
+            entities: Vec<ExtractedEntity>,
+        }
+
         let prompt = self.build_entity_prompt(text);
-        let result = self.call_api(&prompt).await?;
-        self.parse_entity_response(&result)
+        let schema = structured::extracted_entities_schema();
+        let (response, report) = self
+            .request_structured::<EntityResponse>(&prompt, "extracted_entities", &schema)
+            .await?;
+        Ok((response.entities, report))
     }
 
-    /// 构建上下文
-    fn build_context(&self, traces: &[Trace]) -> String {
-        let mut context = String::new();
+    /// 标注实体与相邻实体的关系类型，`context` 为相邻实体及共同出现的会话
+    ///
+    /// 输出无法解析时返回 `ParseFailure`，不当作"没有关系"。
+    pub async fn label_relations(
+        &self,
+        entity: &str,
+        context: &str,
+    ) -> Result<(Vec<RelationLabel>, ParseReport)> {
+        if !self.is_ready {
+            return Err(anyhow!("Summarizer not initialized"));
+        }
 
-        for trace in traces.iter().take(50) {
-            // 限制上下文大小
-            let time = chrono::DateTime::from_timestamp_millis(trace.timestamp)
-                .map(|t| {
-                    t.with_timezone(&chrono::Local)
-                        .format("%H:%M:%S")
-                        .to_string()
-                })
-                .unwrap_or_default();
+        #[derive(Deserialize)]
+        struct RelationResponse {
+            relations: Vec<RelationLabel>,
+        }
 
-            let app = trace.app_name.as_deref().unwrap_or("Unknown");
-            let title = trace.window_title.as_deref().unwrap_or("");
-            let ocr = trace
-                .ocr_text
-                .as_ref()
-                .map(|t| t.chars().take(200).collect::<String>())
-                .unwrap_or_default();
+        let prompt = self.prompts.render(
+            PromptKind::EntityRelations,
+            &[("entity", entity), ("context", context)],
+        );
+        let schema = structured::relation_labels_schema();
+        let (response, report) = self
+            .request_structured::<RelationResponse>(&prompt, "relation_labels", &schema)
+            .await?;
+        Ok((response.relations, report))
+    }
 
-            context.push_str(&format!(
-                "[{}] {} - {}\n{}\n---\n",
-                time,
-                app,
-                title.chars().take(100).collect::<String>(),
-                ocr
-            ));
+    /// 为已关闭的会话生成最终标题、描述与回顾
+    ///
+    /// 输出无法解析时返回 `ParseFailure`，调用方不应据此关闭会话。
+    pub async fn generate_session_recap(
+        &self,
+        session: &ActivitySession,
+    ) -> Result<(SessionRecap, ParseReport)> {
+        if !self.is_ready {
+            return Err(anyhow!("Summarizer not initialized"));
         }
 
-        context
+        let context = build_recap_context(session, self.config.context_tokens as usize);
+        let prompt = self
+            .prompts
+            .render(PromptKind::SessionRecap, &[("context", &context)]);
+        let schema = structured::session_recap_schema();
+        self.request_structured::<SessionRecap>(&prompt, "session_recap", &schema)
+            .await
     }
 
     /// 构建摘要 Prompt
     fn build_summary_prompt(&self, context: &str, summary_type: SummaryType) -> String {
-        let period = match summary_type {
-            SummaryType::Short => "15 分钟",
-            SummaryType::Daily => "一天",
-        };
-
-        format!(
-            r#"你是一个工作记录助手。根据以下屏幕活动记录，生成{period}的工作摘要。
-
-活动记录：
-{context}
-
-请输出以下 JSON 格式（不要输出其他内容）：
-```json
-{{
-  "content": "简洁的工作摘要（100-200字）",
-  "topics": ["主题1", "主题2"],
-  "entities": [
-    {{"name": "实体名", "type": "person/project/technology/url/file", "confidence": 0.9}}
-  ],
-  "links": ["相关链接或文件路径"],
-  "activity_breakdown": [
-    {{"activity_type": "coding/browsing/reading/writing/communication/media/other", "count": 10}}
-  ]
-}}
-```"#,
-            period = period,
-            context = context
+        let period = summary_type.period_label(self.prompts.language());
+        self.prompts.render(
+            PromptKind::Summary,
+            &[("period", period), ("context", context)],
         )
     }
 
     /// 构建实体提取 Prompt
     fn build_entity_prompt(&self, text: &str) -> String {
-        format!(
-            r#"从以下文本中提取命名实体。
-
-文本：
-{text}
-
-请输出以下 JSON 格式（不要输出其他内容）：
-```json
-{{
-  "entities": [
-    {{"name": "实体名称", "type": "person/project/technology/url/file", "confidence": 0.9}}
-  ]
-}}
-```
-
-实体类型说明：
-- person: 人名
-- project: 项目名称、仓库名
-- technology: 编程语言、框架、工具
-- url: 网址
-- file: 文件名或路径"#,
-            text = text
+        self.prompts
+            .render(PromptKind::EntityExtraction, &[("text", text)])
+    }
+
+    /// 带系统提示的单轮对话
+    pub async fn chat(&self, system_prompt: &str, user_message: &str) -> Result<String> {
+        if !self.is_ready {
+            return Err(anyhow!("Chat model not initialized"));
+        }
+
+        self.send(
+            serde_json::json!([
+                { "role": "system", "content": system_prompt },
+                { "role": "user", "content": user_message }
+            ]),
+            None,
+        )
+        .await
+    }
+
+    /// 按 `GeneratedSummary` 的 Schema 请求摘要
+    ///
+    /// 仍无法解析时返回 `ParseFailure`（附带解析记录），不把原始输出当作摘要内容。
+    async fn request_summary(&self, prompt: &str) -> Result<GeneratedSummary> {
+        let schema = structured::generated_summary_schema();
+        let (mut summary, report) = self
+            .request_structured::<GeneratedSummary>(prompt, "generated_summary", &schema)
+            .await?;
+        summary.parse = report;
+        Ok(summary)
+    }
+
+    /// 按 Schema 请求结构化输出，无法解析时重新请求
+    ///
+    /// 仍无法解析时返回 `ParseFailure`（附带解析记录），调用方不应退回原始输出。
+    async fn request_structured<T: DeserializeOwned>(
+        &self,
+        prompt: &str,
+        name: &str,
+        schema: &Value,
+    ) -> Result<(T, ParseReport)> {
+        let output = self.complete_structured(prompt, name, schema).await?;
+
+        let (parsed, report) = structured::parse_with_reask::<T, _, _>(
+            &output,
+            schema,
+            self.config.max_reasks,
+            |output, error| {
+                let schema_text = serde_json::to_string_pretty(schema).unwrap_or_default();
+                let prompt = self.prompts.render(
+                    PromptKind::JsonRepair,
+                    &[
+                        ("schema", &schema_text),
+                        ("error", &error),
+                        ("output", &output),
+                    ],
+                );
+                info!("Re-asking summarizer for valid JSON ({}): {}", name, error);
+                async move { self.complete_structured(&prompt, name, schema).await }
+            },
         )
+        .await;
+        debug!(
+            "{} parse status: {} ({} attempts)",
+            name,
+            report.status.as_str(),
+            report.attempts
+        );
+
+        match parsed {
+            Some(value) => Ok((value, report)),
+            None => {
+                warn!(
+                    "Failed to parse {} response: {}",
+                    name,
+                    report.error.as_deref().unwrap_or("")
+                );
+                debug!("Raw content: {}", output);
+                Err(ParseFailure { report }.into())
+            }
+        }
+    }
+
+    /// 发送一次结构化输出请求；服务端拒绝 `json_schema` 时改用 `json_object` 重发，
+    /// 再拒绝 `json_object` 时不带 `response_format` 重发
+    async fn complete_structured(
+        &self,
+        prompt: &str,
+        name: &str,
+        schema: &Value,
+    ) -> Result<String> {
+        let messages = serde_json::json!([{ "role": "user", "content": prompt }]);
+        let mut format = if self.format_rejected.load(Ordering::Relaxed) {
+            None
+        } else {
+            self.config.structured_output.response_format(
+                &self.config.endpoint,
+                name,
+                schema,
+                self.schema_rejected.load(Ordering::Relaxed),
+            )
+        };
+        let mut format_dropped = false;
+        loop {
+            match self.send(messages.clone(), format.as_ref()).await {
+                Err(e)
+                    if structured::is_schema_format(format.as_ref())
+                        && structured::is_schema_rejection(&e) =>
+                {
+                    warn!("Summarizer endpoint rejected json_schema, falling back to json_object");
+                    self.schema_rejected.store(true, Ordering::Relaxed);
+                    format = Some(serde_json::json!({ "type": "json_object" }));
+                }
+                Err(e)
+                    if structured::is_object_format(format.as_ref())
+                        && structured::is_format_rejection(&e) =>
+                {
+                    warn!(
+                        "Summarizer endpoint rejected json_object, retrying without response_format"
+                    );
+                    format = None;
+                    format_dropped = true;
+                }
+                result => {
+                    if format_dropped && result.is_ok() {
+                        self.format_rejected.store(true, Ordering::Relaxed);
+                    }
+                    return result;
+                }
+            }
+        }
     }
 
-    /// 调用 API
-    async fn call_api(&self, prompt: &str) -> Result<String> {
-        let request = serde_json::json!({
+    /// 发送 chat/completions 请求，返回回复内容
+    async fn send(&self, messages: Value, response_format: Option<&Value>) -> Result<String> {
+        let mut request = serde_json::json!({
             "model": self.config.model,
-            "messages": [{
-                "role": "user",
-                "content": prompt
-            }],
+            "messages": messages,
             "max_tokens": self.config.max_tokens,
             "temperature": self.config.temperature
         });
+        if let Some(format) = response_format {
+            request["response_format"] = format.clone();
+        }
 
         let url = format!("{}/chat/completions", self.config.endpoint);
 
-        debug!("Summarizer API request to: {}", url);
+        debug!(
+            "Summarizer API request to: {} (model: {})",
+            url, self.config.model
+        );
 
         let start = std::time::Instant::now();
 
@@ -410,7 +910,11 @@ impl Summarizer {
         if !status.is_success() {
             let error = response.text().await.unwrap_or_default();
             warn!("Summarizer API error: {}", error);
-            return Err(anyhow!("API error {}: {}", status, error));
+            return Err(ApiStatusError {
+                status: status.as_u16(),
+                body: error,
+            }
+            .into());
         }
 
         let result: serde_json::Value = response.json().await?;
@@ -424,56 +928,6 @@ impl Summarizer {
         Ok(content)
     }
 
-    /// 解析摘要响应
-    fn parse_summary_response(&self, content: &str) -> Result<GeneratedSummary> {
-        let json_str = content
-            .trim()
-            .trim_start_matches("```json")
-            .trim_start_matches("```")
-            .trim_end_matches("```")
-            .trim();
-
-        match serde_json::from_str::<GeneratedSummary>(json_str) {
-            Ok(summary) => Ok(summary),
-            Err(e) => {
-                warn!("Failed to parse summary response: {}", e);
-                debug!("Raw content: {}", content);
-
-                // 降级：返回原始内容作为摘要
-                Ok(GeneratedSummary {
-                    content: content.chars().take(500).collect(),
-                    topics: Vec::new(),
-                    entities: Vec::new(),
-                    links: Vec::new(),
-                    activity_breakdown: Vec::new(),
-                })
-            }
-        }
-    }
-
-    /// 解析实体响应
-    fn parse_entity_response(&self, content: &str) -> Result<Vec<ExtractedEntity>> {
-        let json_str = content
-            .trim()
-            .trim_start_matches("```json")
-            .trim_start_matches("```")
-            .trim_end_matches("```")
-            .trim();
-
-        #[derive(Deserialize)]
-        struct EntityResponse {
-            entities: Vec<ExtractedEntity>,
-        }
-
-        match serde_json::from_str::<EntityResponse>(json_str) {
-            Ok(resp) => Ok(resp.entities),
-            Err(e) => {
-                warn!("Failed to parse entity response: {}", e);
-                Ok(Vec::new())
-            }
-        }
-    }
-
     /// 获取配置
     pub fn config(&self) -> &SummarizerConfig {
         &self.config
@@ -493,6 +947,389 @@ impl Summarizer {
     }
 }
 
+/// 由 trace 停留时长统计活动类型分布（按时长降序）
+pub fn activity_breakdown(durations: &[TraceDuration]) -> Vec<ActivityCount> {
+    merge_breakdowns(durations.iter().map(|d| ActivityCount {
+        activity_type: d.activity_type.clone(),
+        count: d.trace_count,
+        duration_ms: d.duration_ms,
+    }))
+}
+
+/// 合并活动类型分布（同类型累加，按时长降序）
+pub fn merge_breakdowns(parts: impl IntoIterator<Item = ActivityCount>) -> Vec<ActivityCount> {
+    let mut merged: Vec<ActivityCount> = Vec::new();
+    for part in parts {
+        match merged
+            .iter_mut()
+            .find(|a| a.activity_type == part.activity_type)
+        {
+            Some(existing) => {
+                existing.count += part.count;
+                existing.duration_ms += part.duration_ms;
+            }
+            None => merged.push(part),
+        }
+    }
+    merged.sort_by(|a, b| {
+        b.duration_ms
+            .cmp(&a.duration_ms)
+            .then(b.count.cmp(&a.count))
+            .then_with(|| a.activity_type.cmp(&b.activity_type))
+    });
+    merged
+}
+
+/// 粗略估算 token 数：CJK 字符约 1 个 token，其余约 4 个字符 1 个 token
+fn estimate_tokens(text: &str) -> usize {
+    let (cjk, other) = text.chars().fold((0usize, 0usize), |(cjk, other), c| {
+        if matches!(c as u32, 0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF)
+        {
+            (cjk + 1, other)
+        } else {
+            (cjk, other + 1)
+        }
+    });
+    cjk + other.div_ceil(4)
+}
+
+/// 按 token 预算截断上下文，保留开头的完整行；截断时以 `…` 结尾
+pub fn truncate_to_tokens(text: &str, budget_tokens: usize) -> String {
+    if estimate_tokens(text) <= budget_tokens {
+        return text.to_string();
+    }
+    let mut used = 0;
+    let mut kept = Vec::new();
+    for line in text.lines() {
+        let cost = estimate_tokens(line) + 1;
+        if used + cost > budget_tokens {
+            break;
+        }
+        used += cost;
+        kept.push(line);
+    }
+    kept.push("…");
+    kept.join("\n")
+}
+
+/// 时长的简短表示（如 `1h05m`、`12m`）
+fn format_duration(ms: i64) -> String {
+    let minutes = (ms + 30_000) / 60_000;
+    match minutes {
+        0 => "<1m".to_string(),
+        m if m < 60 => format!("{}m", m),
+        m => format!("{}h{:02}m", m / 60, m % 60),
+    }
+}
+
+/// 本地时间 `HH:MM`
+fn format_clock(ts: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(ts)
+        .map(|t| t.with_timezone(&chrono::Local).format("%H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// 上下文中的一个会话条目（完整与单行两种详细程度）
+struct ContextEntry {
+    start_time: i64,
+    duration_ms: i64,
+    full: String,
+    compact: String,
+}
+
+/// 未归入会话的 trace 按应用汇总的停留时长
+struct AppTime {
+    app: String,
+    duration_ms: i64,
+    /// (窗口标题, 停留时长)
+    titles: Vec<(String, i64)>,
+}
+
+/// 会话条目：标题行 + 描述、关键行为与实体
+fn session_entry(session: &ActivitySession, duration_ms: i64) -> ContextEntry {
+    let title = session
+        .title
+        .as_deref()
+        .map(str::trim)
+        .filter(|t| !t.is_empty());
+    let mut compact = format!(
+        "[{}-{} · {}] {}",
+        format_clock(session.start_time),
+        format_clock(session.end_time),
+        format_duration(duration_ms),
+        session.app_name
+    );
+    if let Some(title) = title {
+        compact.push_str(&format!(" · {}", title));
+    }
+
+    // 已关闭的会话优先使用关闭时生成的回顾
+    let mut full = compact.clone();
+    if let Some(description) = session
+        .recap
+        .as_deref()
+        .or(session.description.as_deref())
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+    {
+        let description: String = description
+            .chars()
+            .take(CONTEXT_DESCRIPTION_CHARS)
+            .collect();
+        full.push_str(&format!("\n  {}", description));
+    }
+
+    let key_actions: Vec<String> = session
+        .key_actions_json
+        .as_deref()
+        .and_then(|json| serde_json::from_str::<Vec<serde_json::Value>>(json).ok())
+        .unwrap_or_default()
+        .iter()
+        .rev()
+        .take(CONTEXT_KEY_ACTIONS)
+        .rev()
+        .filter_map(|action| {
+            action
+                .get("action_description")
+                .and_then(|v| v.as_str())
+                .or_else(|| action.get("summary").and_then(|v| v.as_str()))
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+                .map(str::to_string)
+        })
+        .collect();
+    if !key_actions.is_empty() {
+        full.push_str(&format!("\n  key actions: {}", key_actions.join("; ")));
+    }
+
+    // entities_json 为 {name: 出现次数}
+    let mut entities: Vec<(String, i64)> = session
+        .entities_json
+        .as_deref()
+        .and_then(|json| serde_json::from_str::<HashMap<String, serde_json::Value>>(json).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, count)| (name, count.as_i64().unwrap_or(1)))
+        .collect();
+    entities.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    if !entities.is_empty() {
+        let names: Vec<&str> = entities
+            .iter()
+            .take(CONTEXT_ENTITIES)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        full.push_str(&format!("\n  entities: {}", names.join(", ")));
+    }
+
+    ContextEntry {
+        start_time: session.start_time,
+        duration_ms,
+        full,
+        compact,
+    }
+}
+
+/// 构建会话回顾的上下文：会话条目 + context_text 中的逐条记录
+///
+/// 记录超出预算时保留最早一条与尽可能多的最近记录。
+fn build_recap_context(session: &ActivitySession, budget_tokens: usize) -> String {
+    let entry = session_entry(session, session.end_time - session.start_time);
+    let mut budget = budget_tokens.saturating_sub(estimate_tokens(&entry.full));
+
+    let lines: Vec<&str> = session
+        .context_text
+        .as_deref()
+        .unwrap_or("")
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let mut kept: Vec<&str> = Vec::new();
+    for line in lines.iter().rev() {
+        let cost = estimate_tokens(line) + 1;
+        if cost > budget {
+            break;
+        }
+        budget -= cost;
+        kept.push(line);
+    }
+    kept.reverse();
+    if kept.len() < lines.len() {
+        kept.insert(0, "…");
+        kept.insert(0, lines[0]);
+    }
+
+    if kept.is_empty() {
+        entry.full
+    } else {
+        format!("{}\n\n{}", entry.full, kept.join("\n"))
+    }
+}
+
+/// 构建摘要上下文
+///
+/// 首行为总时长与活动类型分布；随后按时间顺序列出会话。预算不足时按停留时长
+/// 从长到短分配：放不下完整条目的会话降级为单行，单行也放不下的省略并汇总为一行。
+/// 尚未归入会话的 trace（如未启用 VLM）按应用汇总，附停留最久的窗口标题。
+fn build_session_context(
+    sessions: &[ActivitySession],
+    durations: &[TraceDuration],
+    budget_tokens: usize,
+) -> String {
+    let breakdown = activity_breakdown(durations);
+    let total_ms: i64 = breakdown.iter().map(|a| a.duration_ms).sum();
+    let mut header = format!("Active time: {}", format_duration(total_ms));
+    if total_ms > 0 {
+        let parts: Vec<String> = breakdown
+            .iter()
+            .map(|a| {
+                format!(
+                    "{} {} ({}%)",
+                    a.activity_type,
+                    format_duration(a.duration_ms),
+                    a.duration_ms * 100 / total_ms
+                )
+            })
+            .collect();
+        header.push_str(&format!(" · {}", parts.join(", ")));
+    }
+
+    let mut session_ms: HashMap<i64, i64> = HashMap::new();
+    let mut unsessioned: Vec<AppTime> = Vec::new();
+    for d in durations {
+        match d.session_id {
+            Some(id) => *session_ms.entry(id).or_default() += d.duration_ms,
+            None => {
+                let app = d.app_name.clone().unwrap_or_else(|| "Unknown".to_string());
+                let index = match unsessioned.iter().position(|a| a.app == app) {
+                    Some(index) => index,
+                    None => {
+                        unsessioned.push(AppTime {
+                            app,
+                            duration_ms: 0,
+                            titles: Vec::new(),
+                        });
+                        unsessioned.len() - 1
+                    }
+                };
+                let entry = &mut unsessioned[index];
+                entry.duration_ms += d.duration_ms;
+                if let Some(title) = d.window_title.as_deref().filter(|t| !t.trim().is_empty()) {
+                    match entry.titles.iter_mut().find(|(t, _)| t == title) {
+                        Some(existing) => existing.1 += d.duration_ms,
+                        None => entry.titles.push((title.to_string(), d.duration_ms)),
+                    }
+                }
+            }
+        }
+    }
+
+    let mut entries: Vec<ContextEntry> = sessions
+        .iter()
+        .map(|session| {
+            let duration_ms = session_ms
+                .get(&session.id)
+                .copied()
+                .unwrap_or(session.end_time - session.start_time);
+            session_entry(session, duration_ms)
+        })
+        .collect();
+    entries.sort_by_key(|e| e.start_time);
+    for mut app_time in unsessioned {
+        app_time
+            .titles
+            .sort_by_key(|(_, ms)| std::cmp::Reverse(*ms));
+        let compact = format!(
+            "[no session · {}] {}",
+            format_duration(app_time.duration_ms),
+            app_time.app
+        );
+        let titles: Vec<String> = app_time
+            .titles
+            .into_iter()
+            .take(3)
+            .map(|(t, _)| t.chars().take(100).collect())
+            .collect();
+        let full = if titles.is_empty() {
+            compact.clone()
+        } else {
+            format!("{}: {}", compact, titles.join(" | "))
+        };
+        entries.push(ContextEntry {
+            start_time: i64::MAX,
+            duration_ms: app_time.duration_ms,
+            full,
+            compact,
+        });
+    }
+
+    // 按停留时长分配预算
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by(|&a, &b| entries[b].duration_ms.cmp(&entries[a].duration_ms));
+
+    let mut used = estimate_tokens(&header);
+    let mut detail: Vec<Option<bool>> = vec![None; entries.len()];
+    let mut omitted = 0;
+    let mut omitted_ms = 0;
+    for index in order {
+        let entry = &entries[index];
+        let full = estimate_tokens(&entry.full) + 1;
+        let compact = estimate_tokens(&entry.compact) + 1;
+        if used + full <= budget_tokens {
+            used += full;
+            detail[index] = Some(true);
+        } else if used + compact <= budget_tokens {
+            used += compact;
+            detail[index] = Some(false);
+        } else {
+            omitted += 1;
+            omitted_ms += entry.duration_ms;
+        }
+    }
+
+    let mut context = header;
+    for (entry, detail) in entries.iter().zip(detail) {
+        match detail {
+            Some(true) => context.push_str(&format!("\n{}", entry.full)),
+            Some(false) => context.push_str(&format!("\n{}", entry.compact)),
+            None => {}
+        }
+    }
+    if omitted > 0 {
+        context.push_str(&format!(
+            "\n... {} shorter sessions omitted ({})",
+            omitted,
+            format_duration(omitted_ms)
+        ));
+    }
+    context
+}
+
+/// 合并实体（按名称去重，保留较高置信度）
+fn merge_entities(into: &mut Vec<ExtractedEntity>, from: Vec<ExtractedEntity>) {
+    for entity in from {
+        match into
+            .iter_mut()
+            .find(|e| e.name.eq_ignore_ascii_case(&entity.name))
+        {
+            Some(existing) => existing.confidence = existing.confidence.max(entity.confidence),
+            None => into.push(entity),
+        }
+    }
+}
+
+/// 合并链接（去重并限制数量）
+fn merge_links(into: &mut Vec<String>, from: Vec<String>) {
+    for link in from {
+        if into.len() >= MAX_ROLLUP_LINKS {
+            break;
+        }
+        if !into.contains(&link) {
+            into.push(link);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -508,10 +1345,189 @@ mod tests {
         assert!(openai.api_key.is_some());
     }
 
+    #[test]
+    fn test_task_model_fallback() {
+        let vlm = VlmConfig::custom("https://api.openai.com/v1/", "gpt-4o", Some("sk-vlm"));
+
+        // 全部留空：沿用 VLM 的端点、模型与密钥，生成参数取任务默认值
+        let chat = TaskModelConfig::default().resolve(ModelTask::Chat, &vlm);
+        assert_eq!(chat.endpoint, vlm.endpoint);
+        assert_eq!(chat.model, "gpt-4o");
+        assert_eq!(chat.api_key.as_deref(), Some("sk-vlm"));
+        assert_eq!((chat.max_tokens, chat.temperature), (1024, 0.7));
+
+        // 同一端点换模型：继承密钥
+        let summarizer = TaskModelConfig {
+            endpoint: Some("https://api.openai.com/v1".to_string()),
+            model: Some("gpt-4o-mini".to_string()),
+            context_tokens: Some(12_000),
+            ..Default::default()
+        }
+        .resolve(ModelTask::Summarizer, &vlm);
+        assert_eq!(summarizer.model, "gpt-4o-mini");
+        assert_eq!(summarizer.api_key.as_deref(), Some("sk-vlm"));
+        assert_eq!(summarizer.context_tokens, 12_000);
+
+        // 换成本地端点：不把 VLM 密钥发过去
+        let entities = TaskModelConfig {
+            endpoint: Some("http://127.0.0.1:11434/v1".to_string()),
+            model: Some("qwen2.5:7b".to_string()),
+            ..Default::default()
+        }
+        .resolve(ModelTask::Entities, &vlm);
+        assert_eq!(entities.endpoint, "http://127.0.0.1:11434/v1");
+        assert!(entities.api_key.is_none());
+    }
+
+    #[test]
+    fn test_truncate_to_tokens() {
+        let text = (0..50)
+            .map(|i| format!("line {i}: some context"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(truncate_to_tokens(&text, 10_000), text);
+        let truncated = truncate_to_tokens(&text, 40);
+        assert!(truncated.starts_with("line 0:"));
+        assert!(truncated.ends_with('…'));
+        assert!(estimate_tokens(&truncated) <= 42);
+    }
+
     #[test]
     fn test_summary_type() {
         assert_eq!(SummaryType::Short.as_str(), "short");
         assert_eq!(SummaryType::Daily.as_str(), "daily");
+        assert_eq!(SummaryType::parse("weekly"), Some(SummaryType::Weekly));
+        assert_eq!(SummaryType::parse("custom"), None);
+        assert!(SummaryType::Custom { start: 0, end: 1 }.is_rollup());
+        assert!(!SummaryType::Daily.is_rollup());
+    }
+
+    #[test]
+    fn test_chunk_items_respects_budget() {
+        let items: Vec<String> = (0..10).map(|i| format!("{:0>9}", i)).collect();
+        let chunks = chunk_items(&items, 30);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|c| c.chars().count() <= 30));
+        assert_eq!(chunks.iter().map(|c| c.lines().count()).sum::<usize>(), 10);
+
+        // 超长条目被截断到预算内（含分隔符）
+        let chunks = chunk_items(&["x".repeat(100)], 30);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].chars().count(), 30);
+    }
+
+    #[test]
+    fn test_fit_items_keeps_every_item() {
+        let items: Vec<String> = (0..6).map(|i| format!("{}{}", i, "x".repeat(50))).collect();
+        let fitted = fit_items(&items, 60);
+        assert!(fitted.chars().count() <= 60);
+        assert_eq!(fitted.lines().count(), 6);
+        assert!(fitted
+            .lines()
+            .enumerate()
+            .all(|(i, l)| l.starts_with(&i.to_string())));
+    }
+
+    fn duration(session_id: Option<i64>, activity_type: &str, duration_ms: i64) -> TraceDuration {
+        TraceDuration {
+            session_id,
+            app_name: Some("Code".to_string()),
+            activity_type: activity_type.to_string(),
+            window_title: session_id.is_none().then(|| "main.rs".to_string()),
+            trace_count: 3,
+            duration_ms,
+        }
+    }
+
+    fn session(id: i64, start_time: i64, description: &str) -> ActivitySession {
+        ActivitySession {
+            id,
+            app_name: "Code".to_string(),
+            title: Some(format!("Session {}", id)),
+            description: Some(description.to_string()),
+            start_time,
+            end_time: start_time + 60_000,
+            start_trace_id: None,
+            end_trace_id: None,
+            trace_count: 3,
+            context_text: None,
+            entities_json: Some(r#"{"Rust": 3, "engram": 1}"#.to_string()),
+            key_actions_json: None,
+            created_at: start_time,
+            updated_at: start_time,
+            state: "closed".to_string(),
+            closed_at: None,
+            recap: None,
+        }
+    }
+
+    #[test]
+    fn test_activity_breakdown_from_durations() {
+        let breakdown = activity_breakdown(&[
+            duration(Some(1), "coding", 120_000),
+            duration(Some(2), "browsing", 300_000),
+            duration(None, "coding", 240_000),
+        ]);
+
+        assert_eq!(breakdown.len(), 2);
+        assert_eq!(breakdown[0].activity_type, "coding");
+        assert_eq!(breakdown[0].duration_ms, 360_000);
+        assert_eq!(breakdown[0].count, 6);
+        assert_eq!(breakdown[1].activity_type, "browsing");
+    }
+
+    #[test]
+    fn test_session_context_prioritizes_long_sessions() {
+        let sessions = vec![
+            session(1, 0, &"short session detail ".repeat(20)),
+            session(2, 100_000, &"long session detail ".repeat(20)),
+        ];
+        let durations = vec![
+            duration(Some(1), "coding", 60_000),
+            duration(Some(2), "coding", 600_000),
+        ];
+
+        let context = build_session_context(&sessions, &durations, 10_000);
+        assert!(context.starts_with("Active time: 11m"));
+        assert!(context.contains("short session detail"));
+        assert!(context.contains("entities: Rust, engram"));
+        // 按时间顺序输出
+        assert!(context.find("Session 1").unwrap() < context.find("Session 2").unwrap());
+
+        // 预算不足时较短的会话先降级为单行
+        let context = build_session_context(&sessions, &durations, 150);
+        assert!(context.contains("long session detail"));
+        assert!(context.contains("Session 1"));
+        assert!(!context.contains("short session detail"));
+        assert!(estimate_tokens(&context) <= 150);
+
+        // 单行也放不下时省略
+        let context = build_session_context(&sessions, &durations, 1);
+        assert!(context.contains("2 shorter sessions omitted"));
+    }
+
+    #[test]
+    fn test_recap_context_keeps_first_and_latest_lines() {
+        let mut s = session(1, 0, "editing the parser");
+        s.context_text = Some(
+            (0..200)
+                .map(|i| format!("[01-01 10:{:02}] step {}", i % 60, i))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+
+        let context = build_recap_context(&s, 10_000);
+        assert!(context.contains("Code · Session 1\n  editing the parser"));
+        assert!(context.contains("step 0\n[01-01 10:01] step 1"));
+
+        let context = build_recap_context(&s, 200);
+        assert!(context.contains("step 0\n…\n"));
+        assert!(context.ends_with("step 199"));
+        assert!(!context.contains("step 100\n"));
+
+        // 关闭后的回顾优先于描述
+        s.recap = Some("Refactored the parser".to_string());
+        assert!(build_recap_context(&s, 200).contains("\n  Refactored the parser"));
     }
 
     #[test]
@@ -528,12 +1544,25 @@ mod tests {
             ]
         }"#;
 
-        let summarizer = Summarizer::new(SummarizerConfig::default());
-        let result = summarizer.parse_summary_response(json).unwrap();
+        let schema = structured::generated_summary_schema();
+        let (result, _) = structured::parse_json::<GeneratedSummary>(json, &schema).unwrap();
 
         assert_eq!(result.content, "用户在编写 Rust 代码");
         assert_eq!(result.topics.len(), 2);
         assert_eq!(result.entities.len(), 1);
         assert_eq!(result.entities[0].entity_type, "technology");
     }
+
+    #[test]
+    fn test_parse_session_recap() {
+        let schema = structured::session_recap_schema();
+        let output = "```json\n{\"title\": \"修复登录\", \"description\": null, \"recap\": \"排查并修复了登录超时\"}\n```";
+        let (recap, _) = structured::parse_json::<SessionRecap>(output, &schema).unwrap();
+        assert_eq!(recap.title.as_deref(), Some("修复登录"));
+        assert!(recap.description.is_none());
+
+        // 缺少 recap 的输出不能当作回顾
+        assert!(structured::parse_json::<SessionRecap>("好的，这是回顾：", &schema).is_err());
+        assert!(structured::parse_json::<SessionRecap>("{\"title\": \"x\"}", &schema).is_err());
+    }
 }
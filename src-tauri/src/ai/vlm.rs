@@ -4,15 +4,18 @@
 //! 支持本地服务（Ollama、vLLM、LM Studio）和远程服务（OpenAI、Together AI、OpenRouter 等）。
 
 use anyhow::{anyhow, Result};
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use image::RgbImage;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::io::Cursor;
-use std::sync::Mutex;
-use std::time::{Duration, Instant};
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::{debug, info, warn};
 
+use super::image_prep::{self, FrameRegions, ImagePrepConfig, PreparedFrame, ProviderLimits};
+use super::prompts::{PromptKind, PromptLibrary, ScreenPromptContext};
+use super::structured::{self, ApiStatusError, ParseReport, ParseStatus, StructuredOutput};
+
 /// 屏幕描述结果
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScreenDescription {
@@ -25,6 +28,7 @@ pub struct ScreenDescription {
     /// 主要活动类型
     pub activity_type: Option<String>,
     /// 关键实体（人名、项目名、URL 等）
+    #[serde(default)]
     pub entities: Vec<String>,
     /// 是否关键行为（用于聚合到 Session 的 key_actions）
     #[serde(default)]
@@ -33,6 +37,7 @@ pub struct ScreenDescription {
     #[serde(default)]
     pub action_description: Option<String>,
     /// 置信度 (0.0 - 1.0)
+    #[serde(default)]
     pub confidence: f32,
 
     /// 建议的 Session 标题（可选；如果不需要更新请为 null）
@@ -45,10 +50,49 @@ pub struct ScreenDescription {
     /// 多线程 Session 路由：如果上下文给出了候选 Session 列表，可返回其中一个 id；否则为 null
     #[serde(default)]
     pub existing_session_id: Option<i64>,
+
+    /// 生成本结果所用的 Prompt 模板版本
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_version: Option<String>,
+
+    /// 生成本结果的模型层级（见 `VlmTaskConfig::escalation_tiers`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_tier: Option<String>,
+
+    /// 结构化输出的解析记录（仅运行时使用，不序列化）
+    #[serde(skip)]
+    pub parse: ParseReport,
+}
+
+impl ScreenDescription {
+    /// 模型输出无法解析时的空结果（不写入摘要，由 VlmTask 记为解析失败）
+    pub fn unparsed(parse: ParseReport) -> Self {
+        Self {
+            summary: String::new(),
+            text_content: None,
+            detected_app: None,
+            activity_type: None,
+            entities: Vec::new(),
+            is_key_action: false,
+            action_description: None,
+            confidence: 0.0,
+            session_title: None,
+            session_description: None,
+            existing_session_id: None,
+            prompt_version: None,
+            model_tier: None,
+            parse,
+        }
+    }
+
+    /// 模型输出是否无法解析
+    pub fn parse_failed(&self) -> bool {
+        self.parse.status == ParseStatus::Failed
+    }
 }
 
 /// VLM 引擎配置
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VlmConfig {
     /// API 端点（如 http://localhost:11434/v1 或 https://api.openai.com/v1）
     pub endpoint: String,
@@ -63,12 +107,25 @@ pub struct VlmConfig {
     /// 温度参数
     #[serde(default = "default_temperature")]
     pub temperature: f32,
+    /// 截图预处理（等比缩放、焦点裁剪、文字分块、编码格式）
+    #[serde(default)]
+    pub image: ImagePrepConfig,
+    /// 结构化输出方式（`response_format`）
+    #[serde(default)]
+    pub structured_output: StructuredOutput,
+    /// 输出无法解析时重新请求的最大次数（0 表示不重新请求）
+    #[serde(default = "default_max_reasks")]
+    pub max_reasks: u32,
 }
 
 fn default_max_tokens() -> u32 {
     512
 }
 
+pub(crate) fn default_max_reasks() -> u32 {
+    1
+}
+
 fn default_temperature() -> f32 {
     0.3
 }
@@ -81,6 +138,9 @@ impl Default for VlmConfig {
             api_key: None,
             max_tokens: default_max_tokens(),
             temperature: default_temperature(),
+            image: ImagePrepConfig::default(),
+            structured_output: StructuredOutput::default(),
+            max_reasks: default_max_reasks(),
         }
     }
 }
@@ -117,27 +177,17 @@ impl VlmConfig {
     }
 }
 
-/// VLM 缓存条目
-#[derive(Clone)]
-struct CacheEntry {
-    result: ScreenDescription,
-    timestamp: Instant,
-}
-
-/// VLM 缓存配置
-const CACHE_MAX_SIZE: usize = 100;
-const CACHE_TTL_SECS: u64 = 300; // 5 分钟
-
 /// VLM 引擎
 pub struct VlmEngine {
     config: VlmConfig,
     client: reqwest::Client,
     is_ready: bool,
-    /// 结果缓存（基于图像哈希）
-    cache: Mutex<HashMap<u64, CacheEntry>>,
-    /// 缓存统计
-    cache_hits: Mutex<u64>,
-    cache_misses: Mutex<u64>,
+    /// Prompt 模板
+    prompts: Arc<PromptLibrary>,
+    /// 服务端拒绝过 `json_schema`，之后改用 `json_object`
+    schema_rejected: AtomicBool,
+    /// 服务端拒绝过 `json_object`，之后不发送 `response_format`
+    format_rejected: AtomicBool,
 }
 
 impl VlmEngine {
@@ -150,12 +200,18 @@ impl VlmEngine {
                 .build()
                 .unwrap(),
             is_ready: false,
-            cache: Mutex::new(HashMap::new()),
-            cache_hits: Mutex::new(0),
-            cache_misses: Mutex::new(0),
+            prompts: Arc::new(PromptLibrary::default()),
+            schema_rejected: AtomicBool::new(false),
+            format_rejected: AtomicBool::new(false),
         }
     }
 
+    /// 使用共享的 Prompt 模板库
+    pub fn with_prompts(mut self, prompts: Arc<PromptLibrary>) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
     /// 自动检测可用的本地服务
     pub async fn auto_detect() -> Result<Self> {
         // 常见的本地服务端点
@@ -242,290 +298,185 @@ impl VlmEngine {
     }
 
     /// 分析屏幕截图
+    ///
+    /// 近重复截图的结果复用由 VlmTask 基于数据库中的 pHash 完成（见 `Database::find_near_duplicate_analysis`）。
     pub async fn analyze_screen(&self, image: &RgbImage) -> Result<ScreenDescription> {
-        if !self.is_ready {
-            return Err(anyhow!("VLM engine not initialized"));
-        }
-
-        // 计算图像哈希用于缓存
-        let image_hash = self.compute_image_hash(image);
-
-        // 检查缓存
-        if let Some(cached) = self.get_cached(image_hash) {
-            *self.cache_hits.lock().unwrap() += 1;
-            debug!("VLM cache hit for hash {}", image_hash);
-            return Ok(cached);
-        }
-
-        *self.cache_misses.lock().unwrap() += 1;
-
-        let image_base64 = self.encode_image(image)?;
-        let result = self.call_api(&image_base64, None).await?;
-
-        // 存入缓存
-        self.put_cached(image_hash, result.clone());
-
-        Ok(result)
+        self.analyze_screen_with_context(image, None, &FrameRegions::default())
+            .await
     }
 
     /// 分析屏幕截图（带外部上下文：来自活动 Session + 最近 traces）
     ///
-    /// 注意：context 会显著影响输出，为避免缓存污染，默认跳过缓存。
+    /// `regions` 是截图时记录的焦点窗口与变化区域，用于焦点裁剪和挑选高清分块。
     pub async fn analyze_screen_with_context(
         &self,
         image: &RgbImage,
-        context: Option<&str>,
+        context: Option<&ScreenPromptContext>,
+        regions: &FrameRegions,
     ) -> Result<ScreenDescription> {
         if !self.is_ready {
             return Err(anyhow!("VLM engine not initialized"));
         }
 
-        let image_base64 = self.encode_image(image)?;
-        self.call_api(&image_base64, context).await
+        let prepared = self.prepare_image(image, regions)?;
+        let mut context = context.cloned().unwrap_or_default();
+        context.image_layout = prepared.layout_note(&self.prompts);
+        self.call_api(&prepared, &context).await
     }
 
-    /// 分析屏幕截图（带 phash，可利用已有哈希）
-    pub async fn analyze_screen_with_hash(
+    /// 调用 OpenAI 兼容 API
+    async fn call_api(
         &self,
-        image: &RgbImage,
-        phash: u64,
+        prepared: &PreparedFrame,
+        context: &ScreenPromptContext,
     ) -> Result<ScreenDescription> {
-        if !self.is_ready {
-            return Err(anyhow!("VLM engine not initialized"));
-        }
+        let prompt = self.prompts.render_screen_prompt(Some(context));
 
-        // 检查缓存
-        if let Some(cached) = self.get_cached(phash) {
-            *self.cache_hits.lock().unwrap() += 1;
-            debug!("VLM cache hit for phash {}", phash);
-            return Ok(cached);
-        }
-
-        *self.cache_misses.lock().unwrap() += 1;
-
-        let image_base64 = self.encode_image(image)?;
-        let result = self.call_api(&image_base64, None).await?;
-
-        // 存入缓存
-        self.put_cached(phash, result.clone());
-
-        Ok(result)
-    }
-
-    /// 计算图像哈希（简化的 dHash）
-    fn compute_image_hash(&self, image: &RgbImage) -> u64 {
-        // 缩小到 9x8
-        let small = image::imageops::resize(image, 9, 8, image::imageops::FilterType::Nearest);
-        let gray = image::DynamicImage::ImageRgb8(small).to_luma8();
-
-        let mut hash: u64 = 0;
-        for y in 0..8 {
-            for x in 0..8 {
-                let left = gray.get_pixel(x, y)[0];
-                let right = gray.get_pixel(x + 1, y)[0];
-                if left > right {
-                    hash |= 1 << (y * 8 + x);
-                }
-            }
-        }
-        hash
-    }
-
-    /// 从缓存获取结果
-    fn get_cached(&self, hash: u64) -> Option<ScreenDescription> {
-        let cache = self.cache.lock().unwrap();
-        if let Some(entry) = cache.get(&hash) {
-            // 检查 TTL
-            if entry.timestamp.elapsed().as_secs() < CACHE_TTL_SECS {
-                return Some(entry.result.clone());
-            }
-        }
-        None
-    }
-
-    /// 存入缓存
-    fn put_cached(&self, hash: u64, result: ScreenDescription) {
-        let mut cache = self.cache.lock().unwrap();
-
-        // 如果缓存满了，移除最旧的条目
-        if cache.len() >= CACHE_MAX_SIZE {
-            // 找到最旧的条目
-            if let Some(oldest_key) = cache
-                .iter()
-                .min_by_key(|(_, v)| v.timestamp)
-                .map(|(k, _)| *k)
-            {
-                cache.remove(&oldest_key);
-            }
-        }
-
-        cache.insert(
-            hash,
-            CacheEntry {
-                result,
-                timestamp: Instant::now(),
-            },
-        );
-    }
-
-    /// 清除过期缓存
-    pub fn cleanup_cache(&self) {
-        let mut cache = self.cache.lock().unwrap();
-        let before = cache.len();
-        cache.retain(|_, v| v.timestamp.elapsed().as_secs() < CACHE_TTL_SECS);
-        let after = cache.len();
-        if before != after {
-            debug!("VLM cache cleanup: {} -> {} entries", before, after);
-        }
-    }
-
-    /// 获取缓存统计
-    pub fn cache_stats(&self) -> (u64, u64, usize) {
-        let hits = *self.cache_hits.lock().unwrap();
-        let misses = *self.cache_misses.lock().unwrap();
-        let size = self.cache.lock().unwrap().len();
-        (hits, misses, size)
-    }
-
-    /// 清空缓存
-    pub fn clear_cache(&self) {
-        self.cache.lock().unwrap().clear();
-        *self.cache_hits.lock().unwrap() = 0;
-        *self.cache_misses.lock().unwrap() = 0;
-        info!("VLM cache cleared");
-    }
-
-    /// 纯文本对话（不带图像）
-    pub async fn chat(&self, system_prompt: &str, user_message: &str) -> Result<String> {
-        if !self.is_ready {
-            return Err(anyhow!("VLM engine not initialized"));
-        }
-
-        let request = serde_json::json!({
-            "model": self.config.model,
-            "messages": [
-                {
-                    "role": "system",
-                    "content": system_prompt
-                },
-                {
-                    "role": "user",
-                    "content": user_message
+        let mut content: Vec<serde_json::Value> = prompt
+            .blocks
+            .iter()
+            .map(|text| {
+                serde_json::json!({
+                    "type": "text",
+                    "text": text
+                })
+            })
+            .collect();
+
+        content.extend(prepared.images().map(|image| {
+            serde_json::json!({
+                "type": "image_url",
+                "image_url": {
+                    "url": image.data_url()
                 }
-            ],
-            "max_tokens": 1024,
-            "temperature": 0.7
-        });
-
-        let url = format!("{}/chat/completions", self.config.endpoint);
+            })
+        }));
 
+        // 记录请求日志
         info!(
-            "VLM Chat Request: endpoint={}, model={}, message_len={}",
+            "VLM API Request: endpoint={}, model={}, max_tokens={}, temperature={}, images={}, image_size={}KB",
             self.config.endpoint,
             self.config.model,
-            user_message.len()
-        );
-
-        let start_time = std::time::Instant::now();
-
-        let mut req = self.client.post(&url).json(&request);
-
-        if let Some(ref key) = self.config.api_key {
-            req = req.header("Authorization", format!("Bearer {}", key));
-        }
-
-        let response = req.send().await?;
-        let status = response.status();
-        let elapsed = start_time.elapsed();
-
-        info!(
-            "VLM Chat Response: status={}, elapsed={:.2}s",
-            status,
-            elapsed.as_secs_f64()
+            self.config.max_tokens,
+            self.config.temperature,
+            1 + prepared.tiles.len(),
+            prepared.encoded_len() / 1024
         );
 
-        if !status.is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow!("Chat API error {}: {}", status, error_text));
-        }
-
-        let response_json: serde_json::Value = response.json().await?;
-
-        let content = response_json["choices"][0]["message"]["content"]
-            .as_str()
-            .ok_or_else(|| anyhow!("Invalid chat response format"))?
-            .to_string();
+        let schema = structured::screen_description_schema();
+        let messages = serde_json::json!([{
+            "role": "user",
+            "content": content
+        }]);
+        let output = self.complete(messages, &schema).await?;
+        debug!("VLM API Response content length: {} chars", output.len());
+
+        let (parsed, report) = structured::parse_with_reask(
+            &output,
+            &schema,
+            self.config.max_reasks,
+            |output, error| {
+                let schema_text = serde_json::to_string_pretty(&schema).unwrap_or_default();
+                let prompt = self.prompts.render(
+                    PromptKind::JsonRepair,
+                    &[
+                        ("schema", &schema_text),
+                        ("error", &error),
+                        ("output", &output),
+                    ],
+                );
+                info!("Re-asking VLM for valid JSON: {}", error);
+                let messages = serde_json::json!([{
+                    "role": "user",
+                    "content": prompt
+                }]);
+                let schema = &schema;
+                async move { self.complete(messages, schema).await }
+            },
+        )
+        .await;
 
-        Ok(content)
+        let mut description = match parsed {
+            Some(description) => ScreenDescription {
+                parse: report,
+                ..description
+            },
+            None => {
+                warn!(
+                    "Failed to parse VLM response after {} attempts: {}",
+                    report.attempts,
+                    report.error.as_deref().unwrap_or("")
+                );
+                debug!("Raw: {}", output);
+                ScreenDescription::unparsed(report)
+            }
+        };
+        description.prompt_version = Some(prompt.version);
+        Ok(description)
     }
 
-    /// 调用 OpenAI 兼容 API
-    async fn call_api(
-        &self,
-        image_base64: &str,
-        context: Option<&str>,
-    ) -> Result<ScreenDescription> {
-        let context_block = context
-            .map(str::trim)
-            .filter(|s| !s.is_empty())
-            .map(|s| {
-                format!(
-                    "以下是同一段用户活动的已知上下文（可能不完整，供参考）：\n{}\n\n请结合上下文与图片进行分析。",
-                    s
-                )
+    /// 发送一次结构化输出请求，返回模型输出文本
+    ///
+    /// 服务端拒绝 `json_schema` 时记住该结果，并改用 `json_object` 重发；
+    /// 再拒绝 `json_object` 时不带 `response_format` 重发。
+    async fn complete(&self, messages: Value, schema: &Value) -> Result<String> {
+        let mut format = if self.format_rejected.load(Ordering::Relaxed) {
+            None
+        } else {
+            self.config.structured_output.response_format(
+                &self.config.endpoint,
+                "screen_description",
+                schema,
+                self.schema_rejected.load(Ordering::Relaxed),
+            )
+        };
+        let mut format_dropped = false;
+        loop {
+            let mut request = serde_json::json!({
+                "model": self.config.model,
+                "messages": messages,
+                "max_tokens": self.config.max_tokens,
+                "temperature": self.config.temperature
             });
-
-        let mut content = vec![serde_json::json!({
-            "type": "text",
-            "text": Self::build_prompt()
-        })];
-
-        if let Some(ctx) = context_block {
-            content.insert(
-                0,
-                serde_json::json!({
-                    "type": "text",
-                    "text": ctx
-                }),
-            );
-        }
-
-        content.push(serde_json::json!({
-            "type": "image_url",
-            "image_url": {
-                "url": format!("data:image/jpeg;base64,{}", image_base64)
+            if let Some(ref format) = format {
+                request["response_format"] = format.clone();
             }
-        }));
 
-        let request = serde_json::json!({
-            "model": self.config.model,
-            "messages": [{
-                "role": "user",
-                "content": content
-            }],
-            "max_tokens": self.config.max_tokens,
-            "temperature": self.config.temperature,
-            "response_format": {
-                "type": "json_object"
+            match self.send(&request).await {
+                Err(e)
+                    if structured::is_schema_format(format.as_ref())
+                        && structured::is_schema_rejection(&e) =>
+                {
+                    warn!("VLM endpoint rejected json_schema, falling back to json_object");
+                    self.schema_rejected.store(true, Ordering::Relaxed);
+                    format = Some(serde_json::json!({ "type": "json_object" }));
+                }
+                Err(e)
+                    if structured::is_object_format(format.as_ref())
+                        && structured::is_format_rejection(&e) =>
+                {
+                    warn!("VLM endpoint rejected json_object, retrying without response_format");
+                    format = None;
+                    format_dropped = true;
+                }
+                result => {
+                    if format_dropped && result.is_ok() {
+                        self.format_rejected.store(true, Ordering::Relaxed);
+                    }
+                    return result;
+                }
             }
-        });
+        }
+    }
 
+    /// 调用 chat/completions 接口
+    async fn send(&self, request: &Value) -> Result<String> {
         let url = format!("{}/chat/completions", self.config.endpoint);
-
-        // 记录请求日志
-        info!(
-            "VLM API Request: endpoint={}, model={}, max_tokens={}, temperature={}, image_size={}KB",
-            self.config.endpoint,
-            self.config.model,
-            self.config.max_tokens,
-            self.config.temperature,
-            image_base64.len() / 1024
-        );
         debug!("VLM API URL: {}", url);
 
         let start_time = std::time::Instant::now();
 
-        let mut req = self.client.post(&url).json(&request);
+        let mut req = self.client.post(&url).json(request);
 
         if let Some(ref key) = self.config.api_key {
             req = req.header("Authorization", format!("Bearer {}", key));
@@ -546,10 +497,14 @@ impl VlmEngine {
         if !status.is_success() {
             let error = response.text().await.unwrap_or_default();
             warn!("VLM API Error: status={}, body={}", status, error);
-            return Err(anyhow!("API error {}: {}", status, error));
+            return Err(ApiStatusError {
+                status: status.as_u16(),
+                body: error,
+            }
+            .into());
         }
 
-        let result: serde_json::Value = response.json().await?;
+        let result: Value = response.json().await?;
 
         // 记录使用情况（如果有）
         if let Some(usage) = result.get("usage") {
@@ -570,98 +525,25 @@ impl VlmEngine {
             );
         }
 
-        let content = result["choices"][0]["message"]["content"]
+        Ok(result["choices"][0]["message"]["content"]
             .as_str()
-            .unwrap_or("");
-
-        debug!("VLM API Response content length: {} chars", content.len());
-
-        Self::parse_response(content)
-    }
-
-    /// 编码图片为 base64 JPEG
-    fn encode_image(&self, image: &RgbImage) -> Result<String> {
-        let mut buffer = Cursor::new(Vec::new());
-
-        // 缩放大图
-        let image = if image.width() > 1280 || image.height() > 720 {
-            let resized =
-                image::imageops::resize(image, 1280, 720, image::imageops::FilterType::Triangle);
-            image::DynamicImage::ImageRgb8(resized)
-        } else {
-            image::DynamicImage::ImageRgb8(image.clone())
-        };
-
-        image.write_to(&mut buffer, image::ImageFormat::Jpeg)?;
-        Ok(BASE64.encode(buffer.into_inner()))
-    }
-
-    /// 构建分析 Prompt
-    fn build_prompt() -> &'static str {
-        r#"请分析这个屏幕截图，并结合（如果提供的）活动 Session 上下文，输出以下 JSON 格式（不要输出其他内容）：
-
-Session 归类规则（严格分离原则）：
-- 不同的活动类型必须分到不同 Session（如：编程 vs 娱乐、工作 vs 休闲）
-- 不同的项目/仓库/工作任务必须分到不同 Session
-- 不同的沟通对象/会议必须分到不同 Session
-- 娱乐/游戏/视频/社交媒体浏览与工作内容必须严格分开
-- 走神/休息/非生产性活动应该新建 Session，不要归入工作 Session
-- 只有当内容"高度相关且属于同一任务"时，才选择 existing_session_id
-- 如果不确定是否相关，宁可新建 Session（输出 null），也不要错误归类
-- 示例：在 VSCode 写 engram 项目 和 在 VSCode 写 other-project 应该是不同 Session
-
-关键行为标注规则：
-- 只有当"这条行为不重复，且缺少它会让 Session 的结论不完整"时才标记为关键行为
-- 如果它与上下文里已有关键行为（action_description/summary）高度重复，则必须输出 is_key_action=false
-- 如果上下文里已经有很多关键行为，请更保守：每个 Session 总关键行为目标不超过 30 条
-```json
-{
-  "summary": "简短描述用户正在做什么（50字以内）",
-  "text_content": "屏幕上的重要文本内容",
-  "detected_app": "检测到的应用或网站名称",
-  "activity_type": "活动类型：coding/browsing/reading/writing/communication/media/other",
-  "entities": ["提取的关键实体：人名、项目名、URL、文件名等"],
-  "is_key_action": true,
-  "action_description": "如果 is_key_action=true，用一句话客观描述"发生了什么"（不超过80字）；否则为 null",
-  "confidence": 0.95,
-  "session_title": "可选：如果你认为需要更新 Session 标题则给出（20字以内），否则为 null",
-  "session_description": "可选：如果你认为需要更新 Session 描述则给出（100字以内），否则为 null",
-  "existing_session_id": "只有当内容与某个 Active Session 高度相关时才填写其 session_id，否则必须为 null"
-}
-```"#
+            .unwrap_or("")
+            .to_string())
     }
 
-    /// 解析 VLM 响应
-    fn parse_response(content: &str) -> Result<ScreenDescription> {
-        let json_str = content
-            .trim()
-            .trim_start_matches("```json")
-            .trim_start_matches("```")
-            .trim_end_matches("```")
-            .trim();
-
-        match serde_json::from_str::<ScreenDescription>(json_str) {
-            Ok(desc) => Ok(desc),
-            Err(e) => {
-                warn!("Failed to parse response: {}", e);
-                debug!("Raw: {}", content);
-
-                // 降级：返回原始内容
-                Ok(ScreenDescription {
-                    summary: content.chars().take(200).collect(),
-                    text_content: Some(content.to_string()),
-                    detected_app: None,
-                    activity_type: Some("other".to_string()),
-                    entities: Vec::new(),
-                    is_key_action: false,
-                    action_description: None,
-                    confidence: 0.5,
-                    session_title: None,
-                    session_description: None,
-                    existing_session_id: None,
-                })
-            }
-        }
+    /// 按配置与服务端限制预处理截图（见 `image_prep`）
+    fn prepare_image(&self, image: &RgbImage, regions: &FrameRegions) -> Result<PreparedFrame> {
+        let limits = ProviderLimits::for_endpoint(&self.config.endpoint);
+        let prepared = image_prep::prepare(image, regions, &self.config.image, limits)?;
+        debug!(
+            "Prepared VLM image: overview={}x{} ({}), cropped={}, tiles={}",
+            prepared.overview.width,
+            prepared.overview.height,
+            prepared.overview.mime,
+            prepared.cropped,
+            prepared.tiles.len()
+        );
+        Ok(prepared)
     }
 
     /// 获取用于嵌入的文本
@@ -694,6 +576,11 @@ Session 归类规则（严格分离原则）：
         }
     }
 
+    /// 获取 Prompt 模板（升级层级的引擎共用同一套模板）
+    pub fn prompts(&self) -> Arc<PromptLibrary> {
+        self.prompts.clone()
+    }
+
     /// 获取配置
     pub fn config(&self) -> &VlmConfig {
         &self.config
@@ -715,9 +602,32 @@ mod tests {
             "confidence": 0.95
         }"#;
 
-        let result = VlmEngine::parse_response(json).unwrap();
+        let schema = structured::screen_description_schema();
+        let (result, status) = structured::parse_json::<ScreenDescription>(json, &schema).unwrap();
         assert_eq!(result.activity_type, Some("coding".to_string()));
         assert_eq!(result.detected_app, Some("Visual Studio Code".to_string()));
+        assert_eq!(status, ParseStatus::Ok);
+
+        // 被截断的输出修复后仍可用
+        let truncated = r#"```json
+{"summary": "阅读文档", "entities": ["Rust",], "confidence": "0.8", "detected_app": "Fire"#;
+        let (result, status) =
+            structured::parse_json::<ScreenDescription>(truncated, &schema).unwrap();
+        assert_eq!(status, ParseStatus::Repaired);
+        assert_eq!(result.summary, "阅读文档");
+        assert!((result.confidence - 0.8).abs() < 1e-6);
+
+        // 无法解析时不把原始文本当作摘要
+        assert!(
+            structured::parse_json::<ScreenDescription>("抱歉，我无法识别这张截图。", &schema)
+                .is_err()
+        );
+        let result = ScreenDescription::unparsed(ParseReport {
+            status: ParseStatus::Failed,
+            ..Default::default()
+        });
+        assert!(result.parse_failed());
+        assert!(result.summary.is_empty());
     }
 
     #[test]
@@ -2,35 +2,44 @@
 //!
 //! 提供前端调用的 API 接口。
 
-use crate::ai::{EmbeddingConfig, VlmConfig};
-use crate::daemon::{DaemonStatus, VlmTaskConfig};
+use crate::ai::embedding::LocalEmbeddingModel;
+use crate::ai::entity::MergeSuggestion;
+use crate::ai::summarizer::truncate_to_tokens;
+use crate::ai::{EmbeddingConfig, ParseStatus, PromptKind, TaskModelConfig, VlmConfig};
+use crate::config::Locale;
+use crate::daemon::routing_eval::{self, RoutingReport};
+use crate::daemon::{DaemonStatus, VlmTask, VlmTaskConfig};
 use crate::db::models::{
-    ActivitySession, ChatMessage, Entity, SearchResult, Settings, StorageStats, Summary, Trace,
+    ActivitySession, ChatMessage, Entity, EntityAlias, EntityGraph, EntityNeighbor,
+    EntityTimelineEntry, ParseStatusStats, SearchResult, Settings, StorageStats, Summary, Trace,
+    TraceParseStatus, VectorBenchmark, VlmReuseStats, VlmTierStats,
 };
+use crate::error::{CommandResult, EngramError};
 use crate::AppState;
 use serde::Serialize;
 use std::path::Path;
+use std::sync::Arc;
 use tauri::State;
 use tracing::{debug, info, warn};
 
 /// 获取截图状态
 #[tauri::command]
-pub async fn get_capture_status(state: State<'_, AppState>) -> Result<DaemonStatus, String> {
+pub async fn get_capture_status(state: State<'_, AppState>) -> CommandResult<DaemonStatus> {
     let daemon = state.daemon.read().await;
     Ok(daemon.status())
 }
 
 /// 启动守护进程
 #[tauri::command]
-pub async fn start_daemon(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn start_daemon(state: State<'_, AppState>) -> CommandResult<()> {
     info!("Starting daemon...");
     let mut daemon = state.daemon.write().await;
-    daemon.start().map_err(|e| e.to_string())
+    daemon.start().map_err(EngramError::from)
 }
 
 /// 停止守护进程
 #[tauri::command]
-pub async fn stop_daemon(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn stop_daemon(state: State<'_, AppState>) -> CommandResult<()> {
     info!("Stopping daemon...");
     let mut daemon = state.daemon.write().await;
     daemon.stop();
@@ -39,7 +48,7 @@ pub async fn stop_daemon(state: State<'_, AppState>) -> Result<(), String> {
 
 /// 切换暂停/恢复
 #[tauri::command]
-pub async fn toggle_capture(state: State<'_, AppState>, paused: bool) -> Result<(), String> {
+pub async fn toggle_capture(state: State<'_, AppState>, paused: bool) -> CommandResult<()> {
     info!("Toggle capture: paused={}", paused);
     let daemon = state.daemon.read().await;
     daemon.set_paused(paused);
@@ -48,10 +57,10 @@ pub async fn toggle_capture(state: State<'_, AppState>, paused: bool) -> Result<
 
 /// 立即截图
 #[tauri::command]
-pub async fn capture_now(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn capture_now(state: State<'_, AppState>) -> CommandResult<()> {
     info!("Manual capture requested");
     let daemon = state.daemon.read().await;
-    daemon.capture_now().map_err(|e| e.to_string())?;
+    daemon.capture_now().map_err(EngramError::from)?;
     Ok(())
 }
 
@@ -63,21 +72,19 @@ pub async fn get_traces(
     end_time: i64,
     limit: Option<u32>,
     offset: Option<u32>,
-) -> Result<Vec<Trace>, String> {
+) -> CommandResult<Vec<Trace>> {
     debug!(
         "get_traces: start={}, end={}, limit={:?}, offset={:?}",
         start_time, end_time, limit, offset
     );
 
+    let limit = limit.unwrap_or(100);
+    let offset = offset.unwrap_or(0);
     state
         .db
-        .get_traces(
-            start_time,
-            end_time,
-            limit.unwrap_or(100),
-            offset.unwrap_or(0),
-        )
-        .map_err(|e| e.to_string())
+        .call(move |db| db.get_traces(start_time, end_time, limit, offset))
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 获取活动会话列表（对外主入口）
@@ -89,22 +96,21 @@ pub async fn get_activity_sessions(
     limit: Option<u32>,
     offset: Option<u32>,
     app_filter: Option<Vec<String>>,
-) -> Result<Vec<ActivitySession>, String> {
+) -> CommandResult<Vec<ActivitySession>> {
     debug!(
         "get_activity_sessions: start={}, end={}, limit={:?}, offset={:?}, apps={:?}",
         start_time, end_time, limit, offset, app_filter
     );
 
+    let limit = limit.unwrap_or(100);
+    let offset = offset.unwrap_or(0);
     state
         .db
-        .get_activity_sessions(
-            start_time,
-            end_time,
-            app_filter.as_ref(),
-            limit.unwrap_or(100),
-            offset.unwrap_or(0),
-        )
-        .map_err(|e| e.to_string())
+        .call(move |db| {
+            db.get_activity_sessions(start_time, end_time, app_filter.as_ref(), limit, offset)
+        })
+        .await
+        .map_err(EngramError::from)
 }
 
 #[tauri::command]
@@ -113,7 +119,7 @@ pub async fn get_activity_session_traces(
     session_id: i64,
     limit: Option<u32>,
     offset: Option<u32>,
-) -> Result<Vec<Trace>, String> {
+) -> CommandResult<Vec<Trace>> {
     debug!(
         "get_activity_session_traces: session_id={}, limit={:?}, offset={:?}",
         session_id, limit, offset
@@ -121,8 +127,205 @@ pub async fn get_activity_session_traces(
 
     state
         .db
-        .get_traces_by_activity_session(session_id, limit.unwrap_or(200), offset.unwrap_or(0))
-        .map_err(|e| e.to_string())
+        .call(move |db| {
+            db.get_traces_by_activity_session(session_id, limit.unwrap_or(200), offset.unwrap_or(0))
+        })
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 合并会话：把 `source_ids` 的 trace 全部归入 `target_id`
+#[tauri::command]
+pub async fn merge_activity_sessions(
+    state: State<'_, AppState>,
+    target_id: i64,
+    source_ids: Vec<i64>,
+) -> CommandResult<ActivitySession> {
+    info!(
+        "merge_activity_sessions: target_id={}, source_ids={:?}",
+        target_id, source_ids
+    );
+    if source_ids.is_empty() {
+        return Err(EngramError::invalid_input("source_ids", "cannot be empty"));
+    }
+
+    let session = state
+        .db
+        .call(move |db| {
+            if !db.merge_activity_sessions(target_id, &source_ids)? {
+                return Ok(None);
+            }
+            db.get_activity_session_by_id(target_id)
+        })
+        .await
+        .map_err(EngramError::from)?;
+
+    let session = session.ok_or_else(|| EngramError::not_found("activity_session", target_id))?;
+    adapt_session_threshold(&state).await;
+    Ok(session)
+}
+
+/// 从指定 trace 处拆分会话，返回拆出的新会话
+#[tauri::command]
+pub async fn split_activity_session(
+    state: State<'_, AppState>,
+    session_id: i64,
+    trace_id: i64,
+) -> CommandResult<ActivitySession> {
+    info!(
+        "split_activity_session: session_id={}, trace_id={}",
+        session_id, trace_id
+    );
+    let session = state
+        .db
+        .call(move |db| {
+            let Some(new_id) = db.split_activity_session(session_id, trace_id)? else {
+                return Ok(None);
+            };
+            db.get_activity_session_by_id(new_id)
+        })
+        .await
+        .map_err(EngramError::from)?;
+
+    let session = session.ok_or_else(|| {
+        EngramError::invalid_input(
+            "trace_id",
+            "trace does not belong to the session or is its first trace",
+        )
+    })?;
+    adapt_session_threshold(&state).await;
+    Ok(session)
+}
+
+/// 把 trace 移到另一个会话；`session_id` 为空时新建会话
+#[tauri::command]
+pub async fn move_traces_to_session(
+    state: State<'_, AppState>,
+    trace_ids: Vec<i64>,
+    session_id: Option<i64>,
+) -> CommandResult<ActivitySession> {
+    info!(
+        "move_traces_to_session: trace_ids={:?}, session_id={:?}",
+        trace_ids, session_id
+    );
+    if trace_ids.is_empty() {
+        return Err(EngramError::invalid_input("trace_ids", "cannot be empty"));
+    }
+
+    let session = state
+        .db
+        .call(move |db| {
+            let Some(target_id) = db.move_traces_to_session(&trace_ids, session_id)? else {
+                return Ok(None);
+            };
+            db.get_activity_session_by_id(target_id)
+        })
+        .await
+        .map_err(EngramError::from)?;
+
+    let session = session.ok_or_else(|| match session_id {
+        Some(id) => EngramError::not_found("activity_session", id),
+        None => EngramError::invalid_input("trace_ids", "no such traces"),
+    })?;
+    adapt_session_threshold(&state).await;
+    Ok(session)
+}
+
+/// 根据最近的会话纠正调整 Session 相似度阈值（失败只记录日志，不影响纠正本身）
+async fn adapt_session_threshold(state: &AppState) {
+    let session_config = state.config.read().await.session.clone();
+    let base_threshold = session_config.similarity_threshold;
+    let model_id = state.embedder.read().await.model_id();
+    let now = chrono::Utc::now().timestamp_millis();
+    let adapted = state
+        .db
+        .call(move |db| routing_eval::adapted_threshold(db, &session_config, &model_id, now))
+        .await;
+
+    match adapted {
+        Ok(Some(threshold)) => {
+            let result = state
+                .update_config(|config| {
+                    // 回放期间阈值被修改或关闭了自适应时放弃本次调整
+                    if !config.session.adaptive_threshold
+                        || config.session.similarity_threshold != base_threshold
+                    {
+                        return Ok(());
+                    }
+                    info!(
+                        "Adapting session similarity threshold: {:.2} -> {:.2}",
+                        config.session.similarity_threshold, threshold
+                    );
+                    config.session.similarity_threshold = threshold;
+                    Ok(())
+                })
+                .await;
+            if let Err(e) = result {
+                warn!("Failed to save adapted session threshold: {}", e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to adapt session threshold: {}", e),
+    }
+}
+
+/// 评估 Session 路由准确率
+///
+/// 以当前的 Session 归属（含用户纠正）为标注回放 embedding 路由，
+/// 默认评估最近 14 天，同时给出回放最优的阈值。只回放当前嵌入模型生成的向量。
+#[tauri::command]
+pub async fn evaluate_session_routing(
+    state: State<'_, AppState>,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+) -> CommandResult<RoutingReport> {
+    let end = end_time.unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+    let start =
+        start_time.unwrap_or(end - routing_eval::ROUTING_HISTORY_DAYS * 24 * 60 * 60 * 1000);
+    info!("evaluate_session_routing: start={}, end={}", start, end);
+    if start >= end {
+        return Err(EngramError::invalid_input(
+            "start_time",
+            "must be earlier than end_time",
+        ));
+    }
+
+    let session_config = state.config.read().await.session.clone();
+    let model_id = state.embedder.read().await.model_id();
+    state
+        .db
+        .call(move |db| routing_eval::evaluate_history(db, &session_config, &model_id, start, end))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 修改会话标题与描述；修改后 VLM 不再覆盖
+#[tauri::command]
+pub async fn update_activity_session(
+    state: State<'_, AppState>,
+    session_id: i64,
+    title: Option<String>,
+    description: Option<String>,
+) -> CommandResult<ActivitySession> {
+    info!("update_activity_session: session_id={}", session_id);
+    let title = title.map(|t| t.trim().to_string());
+    if title.as_deref() == Some("") {
+        return Err(EngramError::invalid_input("title", "cannot be empty"));
+    }
+    let description = description.map(|d| d.trim().to_string());
+
+    let session = state
+        .db
+        .call(move |db| {
+            if !db.rename_activity_session(session_id, title.as_deref(), description.as_deref())? {
+                return Ok(None);
+            }
+            db.get_activity_session_by_id(session_id)
+        })
+        .await
+        .map_err(EngramError::from)?;
+
+    session.ok_or_else(|| EngramError::not_found("activity_session", session_id))
 }
 
 /// 获取图片完整路径
@@ -131,7 +334,7 @@ pub async fn get_activity_session_traces(
 pub async fn get_image_path(
     state: State<'_, AppState>,
     relative_path: String,
-) -> Result<String, String> {
+) -> CommandResult<String> {
     Ok(state.db.get_full_path_string(&relative_path))
 }
 
@@ -161,10 +364,17 @@ fn infer_mime_from_path(path: &Path) -> &'static str {
 pub async fn get_image_data(
     state: State<'_, AppState>,
     relative_path: String,
-) -> Result<ImageData, String> {
+) -> CommandResult<ImageData> {
+    // 超过热数据保留期的 trace 截图已被清理，image_path 置空
+    if relative_path.is_empty() {
+        return Err(EngramError::not_found("image", &relative_path));
+    }
     let full_path = state.db.get_full_path(&relative_path);
     let mime = infer_mime_from_path(&full_path).to_string();
-    let bytes = std::fs::read(&full_path).map_err(|e| e.to_string())?;
+    let bytes = std::fs::read(&full_path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => EngramError::not_found("image", &relative_path),
+        _ => EngramError::from(e),
+    })?;
     Ok(ImageData { mime, bytes })
 }
 
@@ -196,7 +406,7 @@ pub async fn search_traces(
     end_time: Option<i64>,
     app_filter: Option<Vec<String>>,
     limit: Option<u32>,
-) -> Result<Vec<SearchResult>, String> {
+) -> CommandResult<Vec<SearchResult>> {
     debug!(
         "search_traces: query='{}', mode={:?}, limit={:?}",
         query, mode, limit
@@ -215,18 +425,30 @@ pub async fn search_traces(
 
         if is_initialized {
             // 生成查询向量（使用异步方法支持 API 后端）
-            let embed_result = {
+            let (embed_result, model_id) = {
                 let embedder = state.embedder.read().await;
-                embedder.embed(&query).await
+                (embedder.embed_query(&query).await, embedder.model_id())
             };
 
             match embed_result {
                 Ok(query_embedding) => {
                     // 混合搜索
+                    let fts_query = query.clone();
+                    let time_range = (start_time.is_some() || end_time.is_some())
+                        .then(|| (start_time.unwrap_or(i64::MIN), end_time.unwrap_or(i64::MAX)));
                     let hybrid_results = state
                         .db
-                        .hybrid_search(&query, Some(&query_embedding), limit)
-                        .map_err(|e| e.to_string())?;
+                        .call(move |db| {
+                            db.hybrid_search(
+                                &fts_query,
+                                Some(&query_embedding),
+                                &model_id,
+                                time_range,
+                                limit,
+                            )
+                        })
+                        .await
+                        .map_err(EngramError::from)?;
 
                     let filtered = apply_trace_filters(
                         hybrid_results,
@@ -253,7 +475,8 @@ pub async fn search_traces(
                         start_time,
                         end_time,
                         app_filter.as_ref(),
-                    )?
+                    )
+                    .await?
                 }
             }
         } else {
@@ -265,7 +488,8 @@ pub async fn search_traces(
                 start_time,
                 end_time,
                 app_filter.as_ref(),
-            )?
+            )
+            .await?
         }
     } else {
         // 关键词搜索模式
@@ -276,22 +500,47 @@ pub async fn search_traces(
             start_time,
             end_time,
             app_filter.as_ref(),
-        )?
+        )
+        .await?
     };
 
     Ok(results)
 }
 
+/// 按标题、描述与回顾搜索活动会话
+#[tauri::command]
+pub async fn search_activity_sessions(
+    state: State<'_, AppState>,
+    query: String,
+    limit: Option<u32>,
+) -> CommandResult<Vec<ActivitySession>> {
+    debug!(
+        "search_activity_sessions: query='{}', limit={:?}",
+        query, limit
+    );
+
+    let limit = limit.unwrap_or(10);
+    state
+        .db
+        .call(move |db| db.search_sessions_text(&query, limit))
+        .await
+        .map_err(EngramError::from)
+}
+
 /// FTS 回退搜索
-fn fallback_fts_search(
-    db: &crate::db::Database,
+async fn fallback_fts_search(
+    db: &Arc<crate::db::Database>,
     query: &str,
     limit: u32,
     start_time: Option<i64>,
     end_time: Option<i64>,
     app_filter: Option<&Vec<String>>,
-) -> Result<Vec<SearchResult>, String> {
-    let traces = db.search_text(query, limit).map_err(|e| e.to_string())?;
+) -> CommandResult<Vec<SearchResult>> {
+    let query = query.to_string();
+    let traces = db
+        .call(move |db| db.search_text(&query, limit))
+        .await
+        .map_err(EngramError::from)?;
 
     let filtered = apply_trace_filters(
         traces.into_iter().map(|t| (t, 1.0)).collect(),
@@ -347,7 +596,7 @@ fn apply_trace_filters(
 
 /// 获取设置
 #[tauri::command]
-pub async fn get_settings(state: State<'_, AppState>) -> Result<Settings, String> {
+pub async fn get_settings(state: State<'_, AppState>) -> CommandResult<Settings> {
     debug!("get_settings");
 
     let config = state.config.read().await;
@@ -355,49 +604,69 @@ pub async fn get_settings(state: State<'_, AppState>) -> Result<Settings, String
         capture_interval_ms: config.capture.interval_ms,
         idle_threshold_ms: config.capture.idle_threshold_ms,
         similarity_threshold: config.capture.similarity_threshold,
+        region_detection: config.capture.region_detection,
+        min_change_score: config.capture.min_change_score,
         hot_data_days: config.storage.hot_data_days,
         warm_data_days: config.storage.warm_data_days,
+        retention_enabled: config.storage.retention_enabled,
         summary_interval_min: config.summary.interval_min,
         session_active_window_ms: config.session.active_window_ms,
         session_max_active_sessions: config.session.max_active_sessions,
         session_similarity_threshold: config.session.similarity_threshold,
         session_gap_threshold_ms: config.session.gap_threshold_ms,
+        session_adaptive_threshold: config.session.adaptive_threshold,
+        locale: config.locale,
+        language: config.language,
     })
 }
 
 /// 更新设置
 #[tauri::command]
-pub async fn update_settings(state: State<'_, AppState>, settings: Settings) -> Result<(), String> {
+pub async fn update_settings(state: State<'_, AppState>, settings: Settings) -> CommandResult<()> {
     info!("update_settings: {:?}", settings);
 
-    let mut config = state.config.write().await;
-    config.capture.interval_ms = settings.capture_interval_ms;
-    config.capture.idle_threshold_ms = settings.idle_threshold_ms;
-    config.capture.similarity_threshold = settings.similarity_threshold;
-    config.storage.hot_data_days = settings.hot_data_days;
-    config.storage.warm_data_days = settings.warm_data_days;
-    config.summary.interval_min = settings.summary_interval_min;
-    config.session.active_window_ms = settings.session_active_window_ms;
-    config.session.max_active_sessions = settings.session_max_active_sessions;
-    config.session.similarity_threshold = settings.session_similarity_threshold;
-    config.session.gap_threshold_ms = settings.session_gap_threshold_ms;
-
-    config.save().map_err(|e| e.to_string())?;
-    Ok(())
+    // 保存并广播，截图循环与后台任务即时生效
+    state
+        .update_config(|config| {
+            config.capture.interval_ms = settings.capture_interval_ms;
+            config.capture.idle_threshold_ms = settings.idle_threshold_ms;
+            config.capture.similarity_threshold = settings.similarity_threshold;
+            config.capture.region_detection = settings.region_detection;
+            config.capture.min_change_score = settings.min_change_score;
+            config.storage.hot_data_days = settings.hot_data_days;
+            config.storage.warm_data_days = settings.warm_data_days;
+            config.storage.retention_enabled = settings.retention_enabled;
+            config.summary.interval_min = settings.summary_interval_min;
+            config.session.active_window_ms = settings.session_active_window_ms;
+            config.session.max_active_sessions = settings.session_max_active_sessions;
+            config.session.similarity_threshold = settings.session_similarity_threshold;
+            config.session.gap_threshold_ms = settings.session_gap_threshold_ms;
+            config.session.adaptive_threshold = settings.session_adaptive_threshold;
+            config.locale = settings.locale;
+            config.language = settings.language;
+            config
+                .validate()
+                .map_err(|e| EngramError::invalid_input("settings", e.to_string()))
+        })
+        .await
 }
 
 /// 获取存储统计
 #[tauri::command]
-pub async fn get_storage_stats(state: State<'_, AppState>) -> Result<StorageStats, String> {
+pub async fn get_storage_stats(state: State<'_, AppState>) -> CommandResult<StorageStats> {
     debug!("get_storage_stats");
-    state.db.get_storage_stats().map_err(|e| e.to_string())
+    state
+        .db
+        .call(|db| db.get_storage_stats())
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 初始化 AI 模块
 #[tauri::command]
-pub async fn initialize_ai(state: State<'_, AppState>) -> Result<bool, String> {
+pub async fn initialize_ai(state: State<'_, AppState>) -> CommandResult<bool> {
     info!("Initializing AI modules...");
-    state.initialize_ai().await.map_err(|e| e.to_string())?;
+    state.initialize_ai().await.map_err(EngramError::from)?;
 
     let vlm_ready = state.is_vlm_ready().await;
     let embedder = state.embedder.read().await;
@@ -411,25 +680,150 @@ pub async fn initialize_ai(state: State<'_, AppState>) -> Result<bool, String> {
     Ok(vlm_ready || embedder_ready)
 }
 
+/// 获取近重复截图复用 VLM 结果的统计（默认最近 24 小时）
+#[tauri::command]
+pub async fn get_vlm_reuse_stats(
+    state: State<'_, AppState>,
+    since_hours: Option<u32>,
+) -> CommandResult<VlmReuseStats> {
+    let hours = since_hours.unwrap_or(24).max(1) as i64;
+    let since = chrono::Utc::now().timestamp_millis() - hours * 60 * 60 * 1000;
+    state
+        .db
+        .call(move |db| db.get_vlm_reuse_stats(since))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 获取 VLM 各层级的使用统计（默认最近 24 小时）
+#[tauri::command]
+pub async fn get_vlm_tier_stats(
+    state: State<'_, AppState>,
+    since_hours: Option<u32>,
+) -> CommandResult<Vec<VlmTierStats>> {
+    let hours = since_hours.unwrap_or(24).max(1) as i64;
+    let since = chrono::Utc::now().timestamp_millis() - hours * 60 * 60 * 1000;
+    state
+        .db
+        .call(move |db| db.get_vlm_tier_stats(since))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 获取 VLM 输出解析状态统计（默认最近 24 小时）
+#[tauri::command]
+pub async fn get_parse_status_stats(
+    state: State<'_, AppState>,
+    since_hours: Option<u32>,
+) -> CommandResult<ParseStatusStats> {
+    let hours = since_hours.unwrap_or(24).max(1) as i64;
+    let since = chrono::Utc::now().timestamp_millis() - hours * 60 * 60 * 1000;
+    state
+        .db
+        .call(move |db| db.get_parse_status_stats(since))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 获取摘要输出解析状态统计（默认最近 7 天结束的周期）
+#[tauri::command]
+pub async fn get_summary_parse_status_stats(
+    state: State<'_, AppState>,
+    since_hours: Option<u32>,
+) -> CommandResult<ParseStatusStats> {
+    let hours = since_hours.unwrap_or(24 * 7).max(1) as i64;
+    let since = chrono::Utc::now().timestamp_millis() - hours * 60 * 60 * 1000;
+    state
+        .db
+        .call(move |db| db.get_summary_parse_status_stats(since))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 获取会话回顾（session_recap）、实体提取（entity_extraction）或关系标注（entity_relations）
+/// 的输出解析状态统计（默认最近 7 天）
+#[tauri::command]
+pub async fn get_model_parse_status_stats(
+    state: State<'_, AppState>,
+    task: String,
+    since_hours: Option<u32>,
+) -> CommandResult<ParseStatusStats> {
+    if !["session_recap", "entity_extraction", "entity_relations"].contains(&task.as_str()) {
+        return Err(EngramError::invalid_input(
+            "task",
+            "must be one of session_recap, entity_extraction, entity_relations",
+        ));
+    }
+    let hours = since_hours.unwrap_or(24 * 7).max(1) as i64;
+    let since = chrono::Utc::now().timestamp_millis() - hours * 60 * 60 * 1000;
+    state
+        .db
+        .call(move |db| db.get_model_parse_status_stats(&task, since))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 按解析状态（ok / repaired / reasked / failed）列出 traces
+#[tauri::command]
+pub async fn get_traces_by_parse_status(
+    state: State<'_, AppState>,
+    status: String,
+    limit: Option<u32>,
+) -> CommandResult<Vec<TraceParseStatus>> {
+    let status = ParseStatus::parse(&status).ok_or_else(|| {
+        EngramError::invalid_input("status", "must be one of ok, repaired, reasked, failed")
+    })?;
+    let limit = limit.unwrap_or(50);
+    state
+        .db
+        .call(move |db| db.get_traces_by_parse_status(status.as_str(), limit))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 把解析失败的 traces 放回 VLM 分析队列，返回数量
+#[tauri::command]
+pub async fn reprocess_failed_analyses(
+    state: State<'_, AppState>,
+    limit: Option<u32>,
+) -> CommandResult<usize> {
+    let limit = limit.unwrap_or(500);
+    let count = state
+        .db
+        .call(move |db| db.reset_failed_analyses(limit))
+        .await
+        .map_err(EngramError::from)?;
+    info!("Queued {} failed analyses for reprocessing", count);
+    Ok(count)
+}
+
 /// 获取 AI 状态
 #[tauri::command]
-pub async fn get_ai_status(state: State<'_, AppState>) -> Result<AiStatus, String> {
+pub async fn get_ai_status(state: State<'_, AppState>) -> CommandResult<AiStatus> {
     let vlm_ready = state.is_vlm_ready().await;
-    let embedder = state.embedder.read().await;
+    let (embedder_ready, model_id) = {
+        let embedder = state.embedder.read().await;
+        (embedder.is_initialized(), embedder.model_id())
+    };
+
+    let (pending_analysis_count, pending_embedding_count) = state
+        .db
+        .call(move |db| {
+            let analysis = db.get_traces_pending_ocr(1).map(|v| v.len()).unwrap_or(0);
+            let embedding = db
+                .get_traces_pending_embedding(1, Some(&model_id))
+                .map(|v| v.len())
+                .unwrap_or(0);
+            Ok((analysis as u64, embedding as u64))
+        })
+        .await
+        .map_err(EngramError::from)?;
 
     Ok(AiStatus {
         vlm_ready,
-        embedder_ready: embedder.is_initialized(),
-        pending_analysis_count: state
-            .db
-            .get_traces_pending_ocr(1)
-            .map(|v| v.len())
-            .unwrap_or(0) as u64,
-        pending_embedding_count: state
-            .db
-            .get_traces_pending_embedding(1)
-            .map(|v| v.len())
-            .unwrap_or(0) as u64,
+        embedder_ready,
+        pending_analysis_count,
+        pending_embedding_count,
     })
 }
 
@@ -448,11 +842,18 @@ pub struct AiConfig {
     pub vlm: VlmConfig,
     pub embedding: EmbeddingConfig,
     pub vlm_task: VlmTaskConfig,
+    /// 文本任务模型（留空的字段回退到 `vlm`）
+    #[serde(default)]
+    pub summarizer: TaskModelConfig,
+    #[serde(default)]
+    pub chat: TaskModelConfig,
+    #[serde(default)]
+    pub entities: TaskModelConfig,
 }
 
 /// 获取 AI 配置
 #[tauri::command]
-pub async fn get_ai_config(state: State<'_, AppState>) -> Result<AiConfig, String> {
+pub async fn get_ai_config(state: State<'_, AppState>) -> CommandResult<AiConfig> {
     debug!("get_ai_config");
 
     let config = state.config.read().await;
@@ -460,25 +861,68 @@ pub async fn get_ai_config(state: State<'_, AppState>) -> Result<AiConfig, Strin
         vlm: config.vlm.clone(),
         embedding: config.embedding.clone(),
         vlm_task: config.vlm_task.clone(),
+        summarizer: config.summarizer.clone(),
+        chat: config.chat.clone(),
+        entities: config.entities.clone(),
     })
 }
 
+/// 列出可选的本地嵌入模型（含是否已下载到 models/ 目录）
+#[tauri::command]
+pub async fn list_local_embedding_models(
+    state: State<'_, AppState>,
+) -> CommandResult<Vec<LocalEmbeddingModel>> {
+    debug!("list_local_embedding_models");
+
+    Ok(crate::ai::embedding::list_local_models(
+        &state.db.get_models_dir(),
+    ))
+}
+
+/// 向量检索基准测试：对比当前向量索引（分区 / 量化）与精确 KNN 的延迟和召回率
+#[tauri::command]
+pub async fn benchmark_vector_search(
+    state: State<'_, AppState>,
+    queries: Option<u32>,
+    limit: Option<u32>,
+) -> CommandResult<VectorBenchmark> {
+    let queries = queries.unwrap_or(20).clamp(1, 200);
+    let limit = limit.unwrap_or(10).clamp(1, 100);
+    let model_id = state.embedder.read().await.model_id();
+    debug!(
+        "benchmark_vector_search: model={}, queries={}, limit={}",
+        model_id, queries, limit
+    );
+
+    state
+        .db
+        .call(move |db| db.benchmark_vector_search(&model_id, queries, limit))
+        .await
+        .map_err(EngramError::from)
+}
+
 /// 更新 AI 配置
 #[tauri::command]
-pub async fn update_ai_config(state: State<'_, AppState>, config: AiConfig) -> Result<(), String> {
+pub async fn update_ai_config(state: State<'_, AppState>, config: AiConfig) -> CommandResult<()> {
     info!(
         "update_ai_config: vlm.endpoint={}, embedding.endpoint={:?}, vlm_task.concurrency={}",
         config.vlm.endpoint, config.embedding.endpoint, config.vlm_task.concurrency
     );
 
     // 更新配置并保存到文件
-    {
-        let mut app_config = state.config.write().await;
-        app_config.vlm = config.vlm.clone();
-        app_config.embedding = config.embedding.clone();
-        app_config.vlm_task = config.vlm_task.clone();
-        app_config.save().map_err(|e| e.to_string())?;
-    }
+    state
+        .update_config(|app_config| {
+            app_config.vlm = config.vlm.clone();
+            app_config.embedding = config.embedding.clone();
+            app_config.vlm_task = config.vlm_task.clone();
+            app_config.summarizer = config.summarizer.clone();
+            app_config.chat = config.chat.clone();
+            app_config.entities = config.entities.clone();
+            app_config
+                .validate()
+                .map_err(|e| EngramError::invalid_input("ai_config", e.to_string()))
+        })
+        .await?;
 
     // 重新初始化 AI 模块
     reinitialize_ai(&state, &config).await?;
@@ -487,13 +931,13 @@ pub async fn update_ai_config(state: State<'_, AppState>, config: AiConfig) -> R
 }
 
 /// 重新初始化 AI 模块
-async fn reinitialize_ai(state: &State<'_, AppState>, config: &AiConfig) -> Result<(), String> {
+async fn reinitialize_ai(state: &State<'_, AppState>, config: &AiConfig) -> CommandResult<()> {
     let mut vlm_initialized = false;
 
     // 重新初始化 VLM
     {
         let vlm_config = config.vlm.clone();
-        let mut engine = crate::ai::VlmEngine::new(vlm_config);
+        let mut engine = crate::ai::VlmEngine::new(vlm_config).with_prompts(state.prompts.clone());
         match engine.initialize().await {
             Ok(_) => {
                 info!("VLM re-initialized with new config");
@@ -510,7 +954,8 @@ async fn reinitialize_ai(state: &State<'_, AppState>, config: &AiConfig) -> Resu
     // 重新初始化 Embedding
     {
         let embedding_config = config.embedding.clone();
-        let mut embedder = crate::ai::TextEmbedder::with_config(embedding_config);
+        let mut embedder = crate::ai::TextEmbedder::with_config(embedding_config)
+            .with_models_dir(state.db.get_models_dir());
         match embedder.initialize().await {
             Ok(_) => {
                 info!("Embedder re-initialized with new config");
@@ -522,6 +967,11 @@ async fn reinitialize_ai(state: &State<'_, AppState>, config: &AiConfig) -> Resu
         }
     }
 
+    // 摘要任务按新的 `summarizer` / `entities` 模型重建（对话模型在下次对话时按需重建）
+    if let Err(e) = state.restart_summarizer_task().await {
+        warn!("Failed to restart summarizer task: {}", e);
+    }
+
     // 使用新配置重启 VLM 分析任务
     if vlm_initialized {
         if let Err(e) = state.restart_vlm_task(config.vlm_task.clone()).await {
@@ -537,6 +987,56 @@ async fn reinitialize_ai(state: &State<'_, AppState>, config: &AiConfig) -> Resu
     Ok(())
 }
 
+// ==================== Prompt Commands ====================
+
+/// Prompt 预览结果
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PromptPreview {
+    pub trace_id: i64,
+    /// 模型输出语言
+    pub language: Locale,
+    /// 模板版本号（与 vlm_raw_json 中的 prompt_version 一致）
+    pub version: String,
+    /// 渲染后的完整 Prompt（按发送顺序拼接）
+    pub prompt: String,
+    /// 被拒绝的用户模板及原因
+    pub template_errors: Vec<String>,
+}
+
+/// 预览指定 trace 的截图分析 Prompt（使用当前模板与上下文渲染，不调用模型）
+#[tauri::command]
+pub async fn preview_prompt(
+    state: State<'_, AppState>,
+    trace_id: i64,
+) -> CommandResult<PromptPreview> {
+    debug!("preview_prompt: trace_id={}", trace_id);
+
+    let session_config = state.config.read().await.session.clone();
+    let prompts = state.prompts.clone();
+    let context = state
+        .db
+        .call(move |db| {
+            let Some(trace) = db.get_trace_by_id(trace_id)? else {
+                return Ok(None);
+            };
+            let (context, _) =
+                VlmTask::build_prompt_context(db, &prompts, &session_config, &trace)?;
+            Ok(Some(context))
+        })
+        .await
+        .map_err(EngramError::from)?
+        .ok_or_else(|| EngramError::not_found("trace", trace_id))?;
+    let rendered = state.prompts.render_screen_prompt(Some(&context));
+
+    Ok(PromptPreview {
+        trace_id,
+        language: state.prompts.language(),
+        version: rendered.version,
+        prompt: rendered.blocks.join("\n\n"),
+        template_errors: state.prompts.load_errors(),
+    })
+}
+
 // ==================== Summary Commands ====================
 
 /// 获取摘要列表
@@ -547,7 +1047,7 @@ pub async fn get_summaries(
     end_time: i64,
     summary_type: Option<String>,
     limit: Option<u32>,
-) -> Result<Vec<Summary>, String> {
+) -> CommandResult<Vec<Summary>> {
     debug!(
         "get_summaries: start={}, end={}, type={:?}, limit={:?}",
         start_time, end_time, summary_type, limit
@@ -555,13 +1055,16 @@ pub async fn get_summaries(
 
     state
         .db
-        .get_summaries(
-            start_time,
-            end_time,
-            summary_type.as_deref(),
-            limit.unwrap_or(50),
-        )
-        .map_err(|e| e.to_string())
+        .call(move |db| {
+            db.get_summaries(
+                start_time,
+                end_time,
+                summary_type.as_deref(),
+                limit.unwrap_or(50),
+            )
+        })
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 获取单个摘要
@@ -569,9 +1072,13 @@ pub async fn get_summaries(
 pub async fn get_summary_by_id(
     state: State<'_, AppState>,
     id: i64,
-) -> Result<Option<Summary>, String> {
+) -> CommandResult<Option<Summary>> {
     debug!("get_summary_by_id: id={}", id);
-    state.db.get_summary_by_id(id).map_err(|e| e.to_string())
+    state
+        .db
+        .call(move |db| db.get_summary_by_id(id))
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 获取最近的摘要
@@ -579,19 +1086,38 @@ pub async fn get_summary_by_id(
 pub async fn get_latest_summary(
     state: State<'_, AppState>,
     summary_type: String,
-) -> Result<Option<Summary>, String> {
+) -> CommandResult<Option<Summary>> {
     debug!("get_latest_summary: type={}", summary_type);
     state
         .db
-        .get_latest_summary(&summary_type)
-        .map_err(|e| e.to_string())
+        .call(move |db| db.get_latest_summary(&summary_type))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 获取汇总摘要（每周/每月/自定义）的下级摘要
+#[tauri::command]
+pub async fn get_summary_children(
+    state: State<'_, AppState>,
+    summary_id: i64,
+) -> CommandResult<Vec<Summary>> {
+    debug!("get_summary_children: summary_id={}", summary_id);
+    state
+        .db
+        .call(move |db| db.get_child_summaries(summary_id))
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 删除摘要
 #[tauri::command]
-pub async fn delete_summary(state: State<'_, AppState>, id: i64) -> Result<bool, String> {
+pub async fn delete_summary(state: State<'_, AppState>, id: i64) -> CommandResult<bool> {
     info!("delete_summary: id={}", id);
-    state.db.delete_summary(id).map_err(|e| e.to_string())
+    state
+        .db
+        .call(move |db| db.delete_summary(id))
+        .await
+        .map_err(EngramError::from)
 }
 
 // ==================== Entity Commands ====================
@@ -603,7 +1129,7 @@ pub async fn get_entities(
     entity_type: Option<String>,
     limit: Option<u32>,
     order_by_mentions: Option<bool>,
-) -> Result<Vec<Entity>, String> {
+) -> CommandResult<Vec<Entity>> {
     debug!(
         "get_entities: type={:?}, limit={:?}, order_by_mentions={:?}",
         entity_type, limit, order_by_mentions
@@ -611,12 +1137,15 @@ pub async fn get_entities(
 
     state
         .db
-        .get_entities(
-            entity_type.as_deref(),
-            limit.unwrap_or(100),
-            order_by_mentions.unwrap_or(true),
-        )
-        .map_err(|e| e.to_string())
+        .call(move |db| {
+            db.get_entities(
+                entity_type.as_deref(),
+                limit.unwrap_or(100),
+                order_by_mentions.unwrap_or(true),
+            )
+        })
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 按名称获取实体
@@ -624,12 +1153,13 @@ pub async fn get_entities(
 pub async fn get_entity_by_name(
     state: State<'_, AppState>,
     name: String,
-) -> Result<Option<Entity>, String> {
+) -> CommandResult<Option<Entity>> {
     debug!("get_entity_by_name: name={}", name);
     state
         .db
-        .get_entity_by_name(&name)
-        .map_err(|e| e.to_string())
+        .call(move |db| db.get_entity_by_name(&name))
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 获取实体关联的痕迹
@@ -638,15 +1168,34 @@ pub async fn get_traces_by_entity(
     state: State<'_, AppState>,
     entity_id: i64,
     limit: Option<u32>,
-) -> Result<Vec<Trace>, String> {
+) -> CommandResult<Vec<Trace>> {
     debug!(
         "get_traces_by_entity: entity_id={}, limit={:?}",
         entity_id, limit
     );
     state
         .db
-        .get_traces_by_entity(entity_id, limit.unwrap_or(50))
-        .map_err(|e| e.to_string())
+        .call(move |db| db.get_traces_by_entity(entity_id, limit.unwrap_or(50)))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 获取实体关联的活动会话
+#[tauri::command]
+pub async fn get_sessions_by_entity(
+    state: State<'_, AppState>,
+    entity_id: i64,
+    limit: Option<u32>,
+) -> CommandResult<Vec<ActivitySession>> {
+    debug!(
+        "get_sessions_by_entity: entity_id={}, limit={:?}",
+        entity_id, limit
+    );
+    state
+        .db
+        .call(move |db| db.get_sessions_by_entity(entity_id, limit.unwrap_or(50)))
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 搜索实体
@@ -655,19 +1204,291 @@ pub async fn search_entities(
     state: State<'_, AppState>,
     query: String,
     limit: Option<u32>,
-) -> Result<Vec<Entity>, String> {
+) -> CommandResult<Vec<Entity>> {
     debug!("search_entities: query='{}', limit={:?}", query, limit);
     state
         .db
-        .search_entities(&query, limit.unwrap_or(20))
-        .map_err(|e| e.to_string())
+        .call(move |db| db.search_entities(&query, limit.unwrap_or(20)))
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 删除实体
 #[tauri::command]
-pub async fn delete_entity(state: State<'_, AppState>, id: i64) -> Result<bool, String> {
+pub async fn delete_entity(state: State<'_, AppState>, id: i64) -> CommandResult<bool> {
     info!("delete_entity: id={}", id);
-    state.db.delete_entity(id).map_err(|e| e.to_string())
+    state
+        .db
+        .call(move |db| db.delete_entity(id))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 获取实体的别名
+#[tauri::command]
+pub async fn get_entity_aliases(
+    state: State<'_, AppState>,
+    entity_id: i64,
+) -> CommandResult<Vec<EntityAlias>> {
+    debug!("get_entity_aliases: entity_id={}", entity_id);
+    state
+        .db
+        .call(move |db| db.get_entity_aliases(entity_id))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 将 source 实体合并到 target 实体
+#[tauri::command]
+pub async fn merge_entities(
+    state: State<'_, AppState>,
+    source_id: i64,
+    target_id: i64,
+) -> CommandResult<()> {
+    info!("merge_entities: source={}, target={}", source_id, target_id);
+    if source_id == target_id {
+        return Err(EngramError::invalid_input(
+            "target_id",
+            "cannot merge an entity into itself",
+        ));
+    }
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let merged = state
+        .db
+        .call(move |db| {
+            let merged = db.merge_entities(source_id, target_id)?;
+            if merged {
+                db.rebuild_entity_relations_for(&[source_id, target_id], now)?;
+            }
+            Ok(merged)
+        })
+        .await
+        .map_err(EngramError::from)?;
+    if !merged {
+        return Err(EngramError::not_found(
+            "entity",
+            format!("{} or {}", source_id, target_id),
+        ));
+    }
+    Ok(())
+}
+
+/// 把别名拆分为独立实体，返回新实体
+#[tauri::command]
+pub async fn split_entity(
+    state: State<'_, AppState>,
+    entity_id: i64,
+    alias: String,
+) -> CommandResult<Entity> {
+    info!("split_entity: entity_id={}, alias='{}'", entity_id, alias);
+    let now = chrono::Utc::now().timestamp_millis();
+    let entity = state
+        .db
+        .call(move |db| {
+            let Some(new_id) = db.split_entity(entity_id, &alias)? else {
+                return Ok(None);
+            };
+            db.rebuild_entity_relations_for(&[entity_id, new_id], now)?;
+            db.get_entity_by_id(new_id)
+        })
+        .await
+        .map_err(EngramError::from)?;
+
+    entity.ok_or_else(|| {
+        EngramError::invalid_input(
+            "alias",
+            "alias does not belong to the entity or is its current name",
+        )
+    })
+}
+
+/// 重命名实体
+#[tauri::command]
+pub async fn rename_entity(
+    state: State<'_, AppState>,
+    entity_id: i64,
+    name: String,
+) -> CommandResult<()> {
+    info!("rename_entity: entity_id={}, name='{}'", entity_id, name);
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(EngramError::invalid_input("name", "cannot be empty"));
+    }
+
+    let renamed = state
+        .db
+        .call(move |db| db.rename_entity(entity_id, &name))
+        .await
+        .map_err(EngramError::from)?;
+    if !renamed {
+        return Err(EngramError::invalid_input(
+            "name",
+            "already used by another entity; merge them instead",
+        ));
+    }
+    Ok(())
+}
+
+/// 获取实体合并建议
+///
+/// 取提及次数最多的实体，按名称形态匹配；嵌入模型可用时再按名称嵌入相似度匹配。
+#[tauri::command]
+pub async fn get_entity_merge_suggestions(
+    state: State<'_, AppState>,
+    limit: Option<u32>,
+    threshold: Option<f32>,
+) -> CommandResult<Vec<MergeSuggestion>> {
+    debug!(
+        "get_entity_merge_suggestions: limit={:?}, threshold={:?}",
+        limit, threshold
+    );
+    let entities = state
+        .db
+        .call(|db| db.get_entities(None, MERGE_SUGGESTION_CANDIDATES, true))
+        .await
+        .map_err(EngramError::from)?;
+
+    let embeddings = {
+        let embedder = state.embedder.read().await;
+        if embedder.is_initialized() {
+            let names: Vec<String> = entities.iter().map(|e| e.name.clone()).collect();
+            match embedder.embed_queries(&names).await {
+                Ok(embeddings) => Some(embeddings),
+                Err(e) => {
+                    warn!("Failed to embed entity names: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    };
+
+    let mut suggestions = crate::ai::entity::merge_suggestions(
+        &entities,
+        embeddings.as_deref(),
+        threshold.unwrap_or(0.9),
+    );
+    suggestions.truncate(limit.unwrap_or(20) as usize);
+    Ok(suggestions)
+}
+
+/// 参与合并建议的实体数（按提及次数取前 N 个）
+const MERGE_SUGGESTION_CANDIDATES: u32 = 300;
+
+/// 获取实体的相邻实体（共现权重降序，可按类型过滤）
+#[tauri::command]
+pub async fn get_entity_neighbors(
+    state: State<'_, AppState>,
+    entity_id: i64,
+    entity_type: Option<String>,
+    limit: Option<u32>,
+) -> CommandResult<Vec<EntityNeighbor>> {
+    debug!(
+        "get_entity_neighbors: entity_id={}, type={:?}, limit={:?}",
+        entity_id, entity_type, limit
+    );
+    let now = chrono::Utc::now().timestamp_millis();
+    state
+        .db
+        .call(move |db| {
+            db.get_entity_neighbors(entity_id, entity_type.as_deref(), limit.unwrap_or(20), now)
+        })
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 获取以实体为中心的关系图：中心实体、权重最高的相邻实体及它们之间的边
+#[tauri::command]
+pub async fn get_entity_graph(
+    state: State<'_, AppState>,
+    entity_id: i64,
+    limit: Option<u32>,
+) -> CommandResult<EntityGraph> {
+    debug!(
+        "get_entity_graph: entity_id={}, limit={:?}",
+        entity_id, limit
+    );
+    let now = chrono::Utc::now().timestamp_millis();
+    let limit = limit.unwrap_or(20);
+    let graph = state
+        .db
+        .call(move |db| {
+            let Some(center) = db.get_entity_by_id(entity_id)? else {
+                return Ok(None);
+            };
+            let neighbors = db.get_entity_neighbors(entity_id, None, limit, now)?;
+            let mut nodes = vec![center];
+            nodes.extend(neighbors.into_iter().map(|n| n.entity));
+            let ids: Vec<i64> = nodes.iter().map(|n| n.id).collect();
+            let edges = db.get_entity_relations_among(&ids, now)?;
+            Ok(Some(EntityGraph { nodes, edges }))
+        })
+        .await
+        .map_err(EngramError::from)?;
+
+    graph.ok_or_else(|| EngramError::not_found("entity", entity_id))
+}
+
+/// 获取实体时间线（实体出现过的会话，可限定与另一实体共同出现）
+#[tauri::command]
+pub async fn get_entity_timeline(
+    state: State<'_, AppState>,
+    entity_id: i64,
+    related_id: Option<i64>,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    limit: Option<u32>,
+) -> CommandResult<Vec<EntityTimelineEntry>> {
+    debug!(
+        "get_entity_timeline: entity_id={}, related_id={:?}, start={:?}, end={:?}",
+        entity_id, related_id, start_time, end_time
+    );
+    state
+        .db
+        .call(move |db| {
+            db.get_entity_timeline(
+                entity_id,
+                related_id,
+                start_time.unwrap_or(0),
+                end_time.unwrap_or(i64::MAX),
+                limit.unwrap_or(200),
+            )
+        })
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 重建实体共现关系，返回关系数
+#[tauri::command]
+pub async fn rebuild_entity_relations(state: State<'_, AppState>) -> CommandResult<usize> {
+    info!("rebuild_entity_relations");
+    let now = chrono::Utc::now().timestamp_millis();
+    state
+        .db
+        .call(move |db| db.rebuild_entity_relations(now))
+        .await
+        .map_err(EngramError::from)
+}
+
+/// 用 LLM 标注实体与相邻实体的关系类型，返回标注数
+#[tauri::command]
+pub async fn label_entity_relations(
+    state: State<'_, AppState>,
+    entity_id: i64,
+    limit: Option<u32>,
+) -> CommandResult<usize> {
+    info!(
+        "label_entity_relations: entity_id={}, limit={:?}",
+        entity_id, limit
+    );
+
+    // 标注使用 `[entities]` 模型，客户端按当前配置按需初始化
+    let task = state.summarizer_task.read().await;
+    task.label_entity_relations(entity_id, limit.unwrap_or(10))
+        .await
+        .map_err(|e| EngramError::ai_request("label_relations", e))
 }
 
 // ==================== Chat Commands ====================
@@ -707,7 +1528,7 @@ pub struct ChatResponse {
 pub async fn chat_with_memory(
     state: State<'_, AppState>,
     request: ChatRequest,
-) -> Result<ChatResponse, String> {
+) -> CommandResult<ChatResponse> {
     info!(
         "chat_with_memory: message='{}', start={:?}, end={:?}, apps={:?}",
         request.message.chars().take(50).collect::<String>(),
@@ -726,66 +1547,63 @@ pub async fn chat_with_memory(
         (None, None) => (now - day_ms, now), // 默认最近24小时
     };
 
-    // 获取时间范围内的活动 sessions（对外主视图）
-    let sessions = state
-        .db
-        .get_activity_sessions(start_time, end_time, request.app_filter.as_ref(), 30, 0)
-        .map_err(|e| e.to_string())?;
-
+    // 获取时间范围内的活动 sessions（对外主视图），
     // 同时取最近 1-2 条 trace 作为细节补充（可选）
-    let recent_traces = state
+    let app_filter = request.app_filter.clone();
+    let (sessions, recent_traces) = state
         .db
-        .get_traces_filtered(start_time, end_time, request.app_filter.as_ref(), 2)
-        .map_err(|e| e.to_string())?;
+        .call(move |db| {
+            let sessions =
+                db.get_activity_sessions(start_time, end_time, app_filter.as_ref(), 30, 0)?;
+            let traces = db.get_traces_filtered(start_time, end_time, app_filter.as_ref(), 2)?;
+            Ok((sessions, traces))
+        })
+        .await
+        .map_err(EngramError::from)?;
 
     if sessions.is_empty() && recent_traces.is_empty() {
         return Ok(ChatResponse {
-            content: "在指定的时间范围内没有找到相关的屏幕记录。请尝试扩大时间范围或选择其他应用。"
-                .to_string(),
+            content: state.prompts.render(PromptKind::ChatEmpty, &[]),
             context_count: 0,
             time_range: Some(format_time_range(start_time, end_time)),
             thread_id: request.thread_id.unwrap_or(0),
         });
     }
 
-    // 构建上下文：Session（聚合）+ 最近 1-2 条 Trace（细节）
-    let context = build_chat_context_from_sessions(&sessions, &recent_traces);
-    let context_count = (sessions.len() + recent_traces.len()) as u32;
+    // 对话模型（`[chat]`，未配置时回退到 VLM）
+    let chat = state
+        .chat_model()
+        .await
+        .map_err(|e| EngramError::ai_request("chat_init", e))?;
 
-    // 获取 VLM 引擎进行对话
-    let vlm_guard = state.vlm.read().await;
-    let vlm = vlm_guard
-        .as_ref()
-        .ok_or("VLM 未初始化。请先在设置中配置 AI 模型。")?;
+    // 构建上下文：Session（聚合）+ 最近 1-2 条 Trace（细节），按对话模型的上下文预算截断
+    let context = truncate_to_tokens(
+        &build_chat_context_from_sessions(&sessions, &recent_traces),
+        chat.config().context_tokens as usize,
+    );
+    let context_count = (sessions.len() + recent_traces.len()) as u32;
 
     // 构建 prompt
-    let system_prompt = r#"你是 Engram 智能助手，帮助用户回忆和理解他们的屏幕活动记录。
-用户会提供一段时间内的屏幕活动摘要，你需要基于这些信息回答用户的问题。
-
-注意：
-- 只基于提供的上下文回答，不要编造信息
-- 如果信息不足，诚实告知用户
-- 回答要简洁、有帮助
-- 使用中文回复"#;
-
-    let user_prompt = format!(
-        "以下是用户的屏幕活动记录：\n\n{}\n\n用户问题：{}",
-        context, request.message
+    let system_prompt = state.prompts.render(PromptKind::ChatSystem, &[]);
+    let user_prompt = state.prompts.render(
+        PromptKind::ChatUser,
+        &[("context", &context), ("question", &request.message)],
     );
 
     // 调用 LLM
-    let response = vlm
+    let response = chat
         .chat(&system_prompt, &user_prompt)
         .await
-        .map_err(|e| format!("Chat 失败: {}", e))?;
+        .map_err(|e| EngramError::ai_request("chat", e))?;
 
     // 持久化对话历史（thread）
     let thread_id = match request.thread_id {
         Some(id) if id > 0 => id,
         _ => state
             .db
-            .create_chat_thread(Some("与记忆对话"))
-            .map_err(|e| e.to_string())?,
+            .call(|db| db.create_chat_thread(Some("与记忆对话")))
+            .await
+            .map_err(EngramError::from)?,
     };
 
     let context_json = serde_json::json!({
@@ -795,12 +1613,15 @@ pub async fn chat_with_memory(
     })
     .to_string();
 
+    let message = request.message;
+    let reply = response.clone();
     let _ = state
         .db
-        .append_chat_message(thread_id, "user", &request.message, Some(&context_json));
-    let _ = state
-        .db
-        .append_chat_message(thread_id, "assistant", &response, Some(&context_json));
+        .call(move |db| {
+            db.append_chat_message(thread_id, "user", &message, Some(&context_json))?;
+            db.append_chat_message(thread_id, "assistant", &reply, Some(&context_json))
+        })
+        .await;
 
     Ok(ChatResponse {
         content: response,
@@ -817,11 +1638,12 @@ pub async fn get_chat_messages(
     thread_id: i64,
     limit: Option<u32>,
     offset: Option<u32>,
-) -> Result<Vec<ChatMessage>, String> {
+) -> CommandResult<Vec<ChatMessage>> {
     state
         .db
-        .get_chat_messages(thread_id, limit.unwrap_or(200), offset.unwrap_or(0))
-        .map_err(|e| e.to_string())
+        .call(move |db| db.get_chat_messages(thread_id, limit.unwrap_or(200), offset.unwrap_or(0)))
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 获取可用的应用列表（用于过滤）
@@ -830,7 +1652,7 @@ pub async fn get_available_apps(
     state: State<'_, AppState>,
     start_time: Option<i64>,
     end_time: Option<i64>,
-) -> Result<Vec<String>, String> {
+) -> CommandResult<Vec<String>> {
     let now = chrono::Utc::now().timestamp_millis();
     let week_ms = 7 * 24 * 3600 * 1000i64;
     let start = start_time.unwrap_or(now - week_ms); // 默认最近7天
@@ -838,8 +1660,9 @@ pub async fn get_available_apps(
 
     state
         .db
-        .get_distinct_apps(start, end)
-        .map_err(|e| e.to_string())
+        .call(move |db| db.get_distinct_apps(start, end))
+        .await
+        .map_err(EngramError::from)
 }
 
 /// 构建 chat 上下文
@@ -1004,47 +1827,55 @@ fn format_time_range(start: i64, end: i64) -> String {
 }
 
 /// 手动触发摘要生成
+///
+/// `summary_type` 为 `custom` 时需要同时提供 `start_time` 与 `end_time`（Unix 毫秒）。
 #[tauri::command]
 pub async fn trigger_summary(
     state: State<'_, AppState>,
     summary_type: String,
-) -> Result<String, String> {
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+) -> CommandResult<String> {
     use crate::ai::summarizer::SummaryType;
 
-    let stype = match summary_type.as_str() {
-        "short" => SummaryType::Short,
-        "daily" => SummaryType::Daily,
-        _ => return Err("无效的摘要类型，请使用 'short' 或 'daily'".to_string()),
+    let stype = match (summary_type.as_str(), start_time, end_time) {
+        ("custom", Some(start), Some(end)) if start < end => SummaryType::Custom { start, end },
+        ("custom", Some(_), Some(_)) => {
+            return Err(EngramError::invalid_input(
+                "end_time",
+                "end_time must be greater than start_time",
+            ))
+        }
+        ("custom", _, _) => {
+            return Err(EngramError::invalid_input(
+                "start_time",
+                "custom summaries require both start_time and end_time",
+            ))
+        }
+        (name, _, _) => SummaryType::parse(name).ok_or_else(|| {
+            EngramError::invalid_input(
+                "summary_type",
+                format!(
+                    "unknown summary type '{}', expected 'short', 'daily', 'weekly', 'monthly' or 'custom'",
+                    summary_type
+                ),
+            )
+        })?,
     };
 
-    // 确保使用用户配置的 VLM 模型
-    // 先获取 VLM 配置，然后用它初始化 SummarizerTask
-    let vlm_guard = state.vlm.read().await;
-    if let Some(vlm) = vlm_guard.as_ref() {
-        let vlm_config = vlm.config().clone();
-        drop(vlm_guard); // 释放锁
-
-        // 使用 VLM 配置重新初始化 SummarizerTask
-        state
-            .start_summarizer_task_with_vlm_config(vlm_config)
-            .await
-            .map_err(|e| format!("初始化摘要任务失败: {}", e))?;
-    } else {
-        drop(vlm_guard);
-        return Err("VLM 未初始化。请先在设置中配置 AI 模型。".to_string());
-    }
+    // 按当前配置重建摘要任务（使用 `[summarizer]` 模型，未配置时回退到 VLM）
+    state
+        .restart_summarizer_task()
+        .await
+        .map_err(|e| EngramError::ai_request("summarizer_init", e))?;
 
     let task = state.summarizer_task.read().await;
     task.trigger_summary(stype)
         .await
-        .map_err(|e| format!("生成摘要失败: {}", e))?;
+        .map_err(|e| EngramError::ai_request("summary", e))?;
 
-    Ok(format!(
-        "{}摘要生成成功",
-        if summary_type == "daily" {
-            "每日"
-        } else {
-            "15分钟"
-        }
-    ))
+    let label = stype.display_label(state.prompts.language());
+    Ok(state
+        .prompts
+        .render(PromptKind::SummaryGenerated, &[("summary_type", label)]))
 }
@@ -0,0 +1,150 @@
+//! 运行时配置广播
+//!
+//! `LiveConfig` 持有最新的 `AppConfig`，后台任务通过 `subscribe()` 获取
+//! `watch::Receiver`，在循环中读取最新值或等待变更。配置来源有两个：
+//! - 设置页调用 `update_settings` / `update_ai_config` 后 `publish`
+//! - 用户手动编辑 `config.toml`，文件监听器解析并校验后 `publish`
+
+use super::AppConfig;
+use anyhow::Result;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+/// 运行时配置（变更广播 + 配置文件热重载）
+pub struct LiveConfig {
+    tx: watch::Sender<AppConfig>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl LiveConfig {
+    /// 使用初始配置创建
+    pub fn new(initial: AppConfig) -> Self {
+        let (tx, _) = watch::channel(initial);
+        Self {
+            tx,
+            watcher: Mutex::new(None),
+        }
+    }
+
+    /// 订阅配置变更
+    pub fn subscribe(&self) -> watch::Receiver<AppConfig> {
+        self.tx.subscribe()
+    }
+
+    /// 当前配置快照
+    pub fn current(&self) -> AppConfig {
+        self.tx.borrow().clone()
+    }
+
+    /// 广播新配置；与当前配置内容相同时不通知订阅者
+    ///
+    /// 返回是否发生了变更。
+    pub fn publish(&self, config: AppConfig) -> bool {
+        let new_repr = toml::to_string(&config).ok();
+        self.tx.send_if_modified(|current| {
+            if new_repr.is_some() && toml::to_string(current).ok() == new_repr {
+                return false;
+            }
+            *current = config;
+            true
+        })
+    }
+
+    /// 监听配置文件，手动编辑后校验并广播
+    ///
+    /// 监听父目录而不是文件本身：编辑器保存时常以“写临时文件再重命名”的方式替换文件。
+    pub fn watch_file(self: &Arc<Self>, path: PathBuf) -> Result<()> {
+        let Some(dir) = path.parent().map(|p| p.to_path_buf()) else {
+            return Ok(());
+        };
+        fs::create_dir_all(&dir)?;
+
+        let live: Weak<Self> = Arc::downgrade(self);
+        let file_name = path.file_name().map(|n| n.to_os_string());
+        let config_path = path.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) if !event.kind.is_access() => {
+                    let touches_config = event
+                        .paths
+                        .iter()
+                        .any(|p| p.file_name().map(|n| n.to_os_string()) == file_name);
+                    if !touches_config {
+                        return;
+                    }
+                    if let Some(live) = live.upgrade() {
+                        live.reload_from(&config_path);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Config watcher error: {}", e),
+            })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        info!("Watching config file {}", path.display());
+        *self.watcher.lock().unwrap() = Some(watcher);
+        Ok(())
+    }
+
+    /// 从文件重新加载；解析或校验失败时保留当前配置
+    fn reload_from(&self, path: &Path) {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                debug!("Config file not readable yet: {}", e);
+                return;
+            }
+        };
+
+        match AppConfig::parse(&content) {
+            Ok(config) => {
+                if self.publish(config) {
+                    info!("Config file changed, applied new settings");
+                }
+            }
+            Err(e) => warn!("Ignoring invalid config file change: {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_notifies_only_on_change() {
+        let live = LiveConfig::new(AppConfig::default());
+        let mut rx = live.subscribe();
+
+        assert!(!live.publish(AppConfig::default()));
+        assert!(!rx.has_changed().unwrap());
+
+        let mut config = AppConfig::default();
+        config.capture.interval_ms = 5000;
+        assert!(live.publish(config));
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(rx.borrow_and_update().capture.interval_ms, 5000);
+    }
+
+    #[test]
+    fn test_invalid_file_keeps_current_config() {
+        let dir = std::env::temp_dir().join(format!("engram-config-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let live = LiveConfig::new(AppConfig::default());
+
+        fs::write(&path, "[capture]\ninterval_ms = 0\n").unwrap();
+        live.reload_from(&path);
+        assert_eq!(live.current().capture.interval_ms, 2000);
+
+        fs::write(&path, "[capture]\ninterval_ms = 3000\n").unwrap();
+        live.reload_from(&path);
+        assert_eq!(live.current().capture.interval_ms, 3000);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
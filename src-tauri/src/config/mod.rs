@@ -12,10 +12,16 @@ use std::fs;
 use std::path::PathBuf;
 use tracing::{debug, info, warn};
 
+mod live;
+
+pub use live::LiveConfig;
+
 // 重新导出 AI 相关配置（保持兼容性）
 pub use crate::ai::embedding::EmbeddingConfig;
+pub use crate::ai::summarizer::{ModelTask, SummarizerConfig, TaskModelConfig};
 pub use crate::ai::vlm::VlmConfig;
 pub use crate::daemon::vlm_task::VlmTaskConfig;
+pub use crate::db::{VectorIndexConfig, VectorQuantization};
 
 /// 截图捕获模式
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
@@ -30,8 +36,29 @@ pub enum CaptureMode {
     ActiveWindow,
 }
 
+/// 界面语言（命令错误消息等面向用户的文本）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    /// 简体中文（默认）
+    #[default]
+    Zh = 0,
+    /// 英文
+    En = 1,
+}
+
+impl Locale {
+    /// 从数值还原（未知值回退为默认语言）
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => Locale::En,
+            _ => Locale::Zh,
+        }
+    }
+}
+
 /// 截图捕获配置
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CaptureConfig {
     /// 截图间隔（毫秒）
     #[serde(default = "default_capture_interval")]
@@ -39,12 +66,30 @@ pub struct CaptureConfig {
     /// 闲置检测阈值（毫秒）
     #[serde(default = "default_idle_threshold")]
     pub idle_threshold_ms: u64,
-    /// 相似度阈值（pHash 汉明距离）
+    /// 相似度阈值（pHash 汉明距离，仅在关闭分块检测时用于跳过相似帧）
     #[serde(default = "default_similarity_threshold")]
     pub similarity_threshold: u32,
     /// 截图捕获模式
     #[serde(default)]
     pub mode: CaptureMode,
+    /// 分块变化检测（关闭时退回全局 dHash 比较）
+    #[serde(default = "default_region_detection")]
+    pub region_detection: bool,
+    /// 分块网格列数
+    #[serde(default = "default_tile_columns")]
+    pub tile_columns: u32,
+    /// 分块网格行数
+    #[serde(default = "default_tile_rows")]
+    pub tile_rows: u32,
+    /// 像素亮度差超过该值视为变化像素（0-255）；块内变化像素达到一定数量即为变化块
+    #[serde(default = "default_tile_diff_threshold")]
+    pub tile_diff_threshold: u32,
+    /// 加权变化显著度达到该值才保存截图（焦点窗口内一个变化块计 1）
+    #[serde(default = "default_min_change_score")]
+    pub min_change_score: f32,
+    /// 焦点窗口外变化块的权重（0-1）
+    #[serde(default = "default_background_weight")]
+    pub background_weight: f32,
 }
 
 fn default_capture_interval() -> u64 {
@@ -56,6 +101,24 @@ fn default_idle_threshold() -> u64 {
 fn default_similarity_threshold() -> u32 {
     5
 }
+fn default_region_detection() -> bool {
+    true
+}
+fn default_tile_columns() -> u32 {
+    32
+}
+fn default_tile_rows() -> u32 {
+    18
+}
+fn default_tile_diff_threshold() -> u32 {
+    12
+}
+fn default_min_change_score() -> f32 {
+    2.0
+}
+fn default_background_weight() -> f32 {
+    0.25
+}
 
 impl Default for CaptureConfig {
     fn default() -> Self {
@@ -64,13 +127,18 @@ impl Default for CaptureConfig {
             idle_threshold_ms: default_idle_threshold(),
             similarity_threshold: default_similarity_threshold(),
             mode: CaptureMode::default(),
+            region_detection: default_region_detection(),
+            tile_columns: default_tile_columns(),
+            tile_rows: default_tile_rows(),
+            tile_diff_threshold: default_tile_diff_threshold(),
+            min_change_score: default_min_change_score(),
+            background_weight: default_background_weight(),
         }
     }
 }
 
-
 /// 数据存储配置
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct StorageConfig {
     /// 热数据保留天数
     #[serde(default = "default_hot_data_days")]
@@ -78,6 +146,9 @@ pub struct StorageConfig {
     /// 温数据保留天数
     #[serde(default = "default_warm_data_days")]
     pub warm_data_days: u32,
+    /// 是否按保留天数自动清理截图与 trace（默认关闭，需用户在设置中确认开启）
+    #[serde(default)]
+    pub retention_enabled: bool,
 }
 
 fn default_hot_data_days() -> u32 {
@@ -92,12 +163,13 @@ impl Default for StorageConfig {
         Self {
             hot_data_days: default_hot_data_days(),
             warm_data_days: default_warm_data_days(),
+            retention_enabled: false,
         }
     }
 }
 
 /// 会话管理配置
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SessionConfig {
     /// 活跃线程窗口（毫秒）- 用于多线程 Session 路由/聚类
     #[serde(default = "default_session_active_window_ms")]
@@ -114,6 +186,14 @@ pub struct SessionConfig {
     /// 会话分割阈值（毫秒）- 超过此时间间隔则偏向开启新会话
     #[serde(default = "default_session_gap")]
     pub gap_threshold_ms: u64,
+
+    /// 根据用户对会话的纠正自动调整 `similarity_threshold`（默认关闭）
+    #[serde(default)]
+    pub adaptive_threshold: bool,
+
+    /// 作为路由示例放进截图分析上下文的最近纠正条数（0 表示不使用）
+    #[serde(default = "default_session_correction_examples")]
+    pub correction_examples: u32,
 }
 
 fn default_session_active_window_ms() -> u64 {
@@ -132,6 +212,10 @@ fn default_session_gap() -> u64 {
     300000 // 5 分钟
 }
 
+fn default_session_correction_examples() -> u32 {
+    3
+}
+
 impl Default for SessionConfig {
     fn default() -> Self {
         Self {
@@ -139,16 +223,21 @@ impl Default for SessionConfig {
             max_active_sessions: default_session_max_active(),
             similarity_threshold: default_session_similarity_threshold(),
             gap_threshold_ms: default_session_gap(),
+            adaptive_threshold: false,
+            correction_examples: default_session_correction_examples(),
         }
     }
 }
 
 /// 摘要生成配置
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SummaryConfig {
     /// 自动摘要生成间隔（分钟）
     #[serde(default = "default_summary_interval")]
     pub interval_min: u32,
+    /// 摘要周期对齐使用的时区（IANA 名称，如 "Asia/Shanghai"；留空使用系统时区）
+    #[serde(default)]
+    pub timezone: String,
 }
 
 fn default_summary_interval() -> u32 {
@@ -159,6 +248,7 @@ impl Default for SummaryConfig {
     fn default() -> Self {
         Self {
             interval_min: default_summary_interval(),
+            timezone: String::new(),
         }
     }
 }
@@ -166,6 +256,12 @@ impl Default for SummaryConfig {
 /// 应用配置（顶层结构）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
+    /// 界面语言
+    #[serde(default)]
+    pub locale: Locale,
+    /// 模型输出语言（选择对应语言的 Prompt 模板）
+    #[serde(default)]
+    pub language: Locale,
     /// 截图捕获配置
     #[serde(default)]
     pub capture: CaptureConfig,
@@ -181,28 +277,59 @@ pub struct AppConfig {
     /// VLM 视觉模型配置
     #[serde(default)]
     pub vlm: VlmConfig,
+    /// 摘要模型（留空的字段回退到 `vlm`）
+    #[serde(default)]
+    pub summarizer: TaskModelConfig,
+    /// 对话模型（留空的字段回退到 `vlm`）
+    #[serde(default)]
+    pub chat: TaskModelConfig,
+    /// 实体关系标注模型（留空的字段回退到 `vlm`）
+    #[serde(default)]
+    pub entities: TaskModelConfig,
     /// 文本嵌入配置
     #[serde(default)]
     pub embedding: EmbeddingConfig,
     /// VLM 后台任务配置
     #[serde(default)]
     pub vlm_task: VlmTaskConfig,
+    /// 向量索引配置（量化与时间分区）
+    #[serde(default)]
+    pub vector_index: VectorIndexConfig,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            locale: Locale::default(),
+            language: Locale::default(),
             capture: CaptureConfig::default(),
             storage: StorageConfig::default(),
             session: SessionConfig::default(),
             summary: SummaryConfig::default(),
             vlm: VlmConfig::default(),
+            summarizer: TaskModelConfig::default(),
+            chat: TaskModelConfig::default(),
+            entities: TaskModelConfig::default(),
             embedding: EmbeddingConfig::default(),
             vlm_task: VlmTaskConfig::default(),
+            vector_index: VectorIndexConfig::default(),
         }
     }
 }
 
+/// 配置校验错误：每项为（配置段，原因）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidConfig(pub Vec<(&'static str, String)>);
+
+impl std::fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<&str> = self.0.iter().map(|(_, message)| message.as_str()).collect();
+        f.write_str(&messages.join("; "))
+    }
+}
+
+impl std::error::Error for InvalidConfig {}
+
 impl AppConfig {
     /// 获取配置目录路径
     pub fn config_dir() -> Result<PathBuf> {
@@ -215,6 +342,21 @@ impl AppConfig {
         }
     }
 
+    /// 文本任务实际使用的模型配置（未配置的字段回退到 `vlm`）
+    pub fn task_model(&self, task: ModelTask) -> SummarizerConfig {
+        let config = match task {
+            ModelTask::Summarizer => &self.summarizer,
+            ModelTask::Chat => &self.chat,
+            ModelTask::Entities => &self.entities,
+        };
+        config.resolve(task, &self.vlm)
+    }
+
+    /// 获取用户 Prompt 模板目录
+    pub fn prompts_dir() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("prompts"))
+    }
+
     /// 获取配置文件完整路径
     pub fn config_path() -> Result<PathBuf> {
         Ok(Self::config_dir()?.join("config.toml"))
@@ -234,7 +376,7 @@ impl AppConfig {
                 e
             })?;
             info!("Config loaded from: {}", path.display());
-            Ok(config)
+            Ok(config.with_invalid_sections_reset())
         } else {
             info!(
                 "Config file not found, creating default at: {}",
@@ -246,6 +388,211 @@ impl AppConfig {
         }
     }
 
+    /// 解析 TOML 配置并校验取值
+    pub fn parse(content: &str) -> Result<Self> {
+        let config: Self = toml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 启动时的校验：取值非法的段回退到默认值并记录日志，不因单个取值拒绝整个配置
+    pub fn with_invalid_sections_reset(mut self) -> Self {
+        let Err(InvalidConfig(issues)) = self.validate() else {
+            return self;
+        };
+        for (section, message) in &issues {
+            warn!(
+                "Invalid config ({}), resetting [{}] to defaults",
+                message, section
+            );
+            self.reset_section(section);
+        }
+        // 重置后仍然非法（取值依赖其他段）时整体回退
+        if let Err(e) = self.validate() {
+            warn!("Invalid config ({}), using defaults", e);
+            return Self::default();
+        }
+        self
+    }
+
+    /// 把一段配置恢复为默认值
+    fn reset_section(&mut self, section: &str) {
+        let defaults = Self::default();
+        match section {
+            "capture" => self.capture = defaults.capture,
+            "storage" => self.storage = defaults.storage,
+            "session" => self.session = defaults.session,
+            "summary" => self.summary = defaults.summary,
+            "vlm" => self.vlm = defaults.vlm,
+            "summarizer" => self.summarizer = defaults.summarizer,
+            "chat" => self.chat = defaults.chat,
+            "entities" => self.entities = defaults.entities,
+            "embedding" => self.embedding = defaults.embedding,
+            "vlm_task" => self.vlm_task = defaults.vlm_task,
+            "vector_index" => self.vector_index = defaults.vector_index,
+            _ => warn!("Unknown config section [{}]", section),
+        }
+    }
+
+    /// 校验配置取值范围（热更新时拒绝非法配置，保留旧值）
+    ///
+    /// 返回全部非法取值及其所属的配置段，启动时据此只重置出错的段。
+    pub fn validate(&self) -> std::result::Result<(), InvalidConfig> {
+        let mut issues = Vec::new();
+        let mut invalid = |section: &'static str, message: String| issues.push((section, message));
+        if self.capture.interval_ms < 100 {
+            invalid("capture", "capture.interval_ms must be >= 100".to_string());
+        }
+        if self.capture.idle_threshold_ms == 0 {
+            invalid(
+                "capture",
+                "capture.idle_threshold_ms must be > 0".to_string(),
+            );
+        }
+        if self.capture.similarity_threshold > 64 {
+            invalid(
+                "capture",
+                "capture.similarity_threshold must be <= 64".to_string(),
+            );
+        }
+        if !(1..=128).contains(&self.capture.tile_columns)
+            || !(1..=128).contains(&self.capture.tile_rows)
+        {
+            invalid(
+                "capture",
+                "capture.tile_columns/tile_rows must be within 1..=128".to_string(),
+            );
+        }
+        if self.capture.tile_diff_threshold > 255 {
+            invalid(
+                "capture",
+                "capture.tile_diff_threshold must be <= 255".to_string(),
+            );
+        }
+        if !self.capture.min_change_score.is_finite() || self.capture.min_change_score < 0.0 {
+            invalid(
+                "capture",
+                "capture.min_change_score must be >= 0".to_string(),
+            );
+        }
+        if !(0.0..=1.0).contains(&self.capture.background_weight) {
+            invalid(
+                "capture",
+                "capture.background_weight must be within 0..=1".to_string(),
+            );
+        }
+        if self.storage.hot_data_days == 0 {
+            invalid("storage", "storage.hot_data_days must be > 0".to_string());
+        }
+        if self.storage.warm_data_days < self.storage.hot_data_days {
+            invalid(
+                "storage",
+                "storage.warm_data_days must be >= storage.hot_data_days".to_string(),
+            );
+        }
+        if self.summary.interval_min == 0 || self.summary.interval_min > 24 * 60 {
+            invalid(
+                "summary",
+                "summary.interval_min must be within 1..=1440".to_string(),
+            );
+        }
+        if !self.summary.timezone.is_empty()
+            && self.summary.timezone.parse::<chrono_tz::Tz>().is_err()
+        {
+            invalid(
+                "summary",
+                format!(
+                    "summary.timezone '{}' is not a valid IANA time zone",
+                    self.summary.timezone
+                ),
+            );
+        }
+        if !(0.0..=1.0).contains(&self.session.similarity_threshold) {
+            invalid(
+                "session",
+                "session.similarity_threshold must be within 0..=1".to_string(),
+            );
+        }
+        if self.session.max_active_sessions == 0 {
+            invalid(
+                "session",
+                "session.max_active_sessions must be > 0".to_string(),
+            );
+        }
+        if self.vlm.image.max_edge < 256 {
+            invalid("vlm", "vlm.image.max_edge must be >= 256".to_string());
+        }
+        if self.vlm.image.tile_size < 64 {
+            invalid("vlm", "vlm.image.tile_size must be >= 64".to_string());
+        }
+        if !(1..=100).contains(&self.vlm.image.jpeg_quality) {
+            invalid(
+                "vlm",
+                "vlm.image.jpeg_quality must be within 1..=100".to_string(),
+            );
+        }
+        if self.vlm.max_reasks > 3 {
+            invalid("vlm", "vlm.max_reasks must be <= 3".to_string());
+        }
+        for task in [ModelTask::Summarizer, ModelTask::Chat, ModelTask::Entities] {
+            let model = self.task_model(task);
+            let section = task.as_str();
+            if model.max_tokens == 0 {
+                invalid(section, format!("{section}.max_tokens must be > 0"));
+            }
+            if !(0.0..=2.0).contains(&model.temperature) {
+                invalid(
+                    section,
+                    format!("{section}.temperature must be within 0..=2"),
+                );
+            }
+            if model.context_tokens < 256 {
+                invalid(section, format!("{section}.context_tokens must be >= 256"));
+            }
+        }
+        if self.vlm_task.reuse_max_distance > 64 {
+            invalid(
+                "vlm_task",
+                "vlm_task.reuse_max_distance must be <= 64".to_string(),
+            );
+        }
+        if !(0.0..=1.0).contains(&self.vlm_task.escalation_confidence) {
+            invalid(
+                "vlm_task",
+                "vlm_task.escalation_confidence must be within 0..=1".to_string(),
+            );
+        }
+        let mut tier_names = std::collections::HashSet::new();
+        for tier in &self.vlm_task.escalation_tiers {
+            let name = tier.name.trim();
+            if name.is_empty() || name == "primary" || !tier_names.insert(name) {
+                invalid(
+                    "vlm_task",
+                    "vlm_task.escalation_tiers names must be non-empty, unique and not 'primary'"
+                        .to_string(),
+                );
+                break;
+            }
+        }
+        if self.vector_index.rescore_factor == 0 || self.vector_index.rescore_factor > 100 {
+            invalid(
+                "vector_index",
+                "vector_index.rescore_factor must be within 1..=100".to_string(),
+            );
+        }
+        if self.vector_index.partition_days > 3650 {
+            invalid(
+                "vector_index",
+                "vector_index.partition_days must be <= 3650".to_string(),
+            );
+        }
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidConfig(issues))
+        }
+    }
+
     /// 保存配置到文件
     pub fn save(&self) -> Result<()> {
         let path = Self::config_path()?;
@@ -301,4 +648,67 @@ mod tests {
         let parsed: AppConfig = toml::from_str(&toml_str).unwrap();
         assert_eq!(parsed.capture.interval_ms, config.capture.interval_ms);
     }
+
+    #[test]
+    fn test_parse_rejects_invalid_values() {
+        assert!(AppConfig::parse("[capture]\ninterval_ms = 5000\n").is_ok());
+        assert!(AppConfig::parse("[capture]\ninterval_ms = 0\n").is_err());
+        assert!(AppConfig::parse("[storage]\nhot_data_days = 40\nwarm_data_days = 30\n").is_err());
+        assert!(AppConfig::parse("[capture\n").is_err());
+    }
+
+    #[test]
+    fn test_invalid_sections_reset_on_load() {
+        let config: AppConfig = toml::from_str(
+            "[capture]\ninterval_ms = 0\n[storage]\nhot_data_days = 3\nwarm_data_days = 10\n",
+        )
+        .unwrap();
+        let invalid = config.validate().unwrap_err();
+        assert_eq!(
+            invalid
+                .0
+                .iter()
+                .map(|(section, _)| *section)
+                .collect::<Vec<_>>(),
+            vec!["capture"]
+        );
+        let config = config.with_invalid_sections_reset();
+        assert_eq!(
+            config.capture.interval_ms,
+            CaptureConfig::default().interval_ms
+        );
+        assert_eq!(config.storage.hot_data_days, 3);
+
+        // 每个出错的段各自重置，其他段保留
+        let config: AppConfig = toml::from_str(
+            "[capture]\ninterval_ms = 0\n[entities]\ntemperature = 3.0\n[storage]\nhot_data_days = 3\n",
+        )
+        .unwrap();
+        let sections: Vec<&str> = config
+            .validate()
+            .unwrap_err()
+            .0
+            .iter()
+            .map(|(s, _)| *s)
+            .collect();
+        assert_eq!(sections, vec!["capture", "entities"]);
+        let config = config.with_invalid_sections_reset();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.storage.hot_data_days, 3);
+    }
+
+    #[test]
+    fn test_task_models_fall_back_to_vlm() {
+        let config = AppConfig::parse(
+            "[vlm]\nendpoint = \"http://127.0.0.1:8000/v1\"\nmodel = \"qwen3-vl-8b\"\n\n[chat]\nmodel = \"qwen3-32b\"\ntemperature = 0.5\n",
+        )
+        .unwrap();
+        let chat = config.task_model(ModelTask::Chat);
+        assert_eq!(chat.endpoint, "http://127.0.0.1:8000/v1");
+        assert_eq!(chat.model, "qwen3-32b");
+        assert_eq!(chat.temperature, 0.5);
+        let summarizer = config.task_model(ModelTask::Summarizer);
+        assert_eq!(summarizer.model, "qwen3-vl-8b");
+        assert!(AppConfig::parse("[entities]\ntemperature = 3.0\n").is_err());
+    }
 }
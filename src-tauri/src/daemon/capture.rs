@@ -23,14 +23,17 @@ pub struct CapturedFrame {
     pub height: u32,
     /// 捕获时间戳（Unix 毫秒）
     pub timestamp: i64,
+    /// 帧覆盖的屏幕区域（x, y, width, height），用于把焦点窗口映射到帧坐标
+    pub source_rect: Option<(i32, i32, u32, u32)>,
 }
 
+/// 保存帧的最大像素数（约 1920x1080）；超出时等比下采样，超宽屏与竖屏不变形
+const MAX_FRAME_PIXELS: u64 = 1920 * 1080;
+
 /// 屏幕捕获器
 pub struct ScreenCapture {
-    /// 目标宽度（下采样）
-    target_width: u32,
-    /// 目标高度（下采样）
-    target_height: u32,
+    /// 下采样后的最大像素数
+    max_pixels: u64,
     /// 捕获模式
     mode: CaptureMode,
 }
@@ -40,8 +43,7 @@ impl ScreenCapture {
     pub fn new(mode: CaptureMode) -> Result<Self> {
         debug!("Initializing ScreenCapture with mode: {:?}", mode);
         Ok(Self {
-            target_width: 1920,
-            target_height: 1080,
+            max_pixels: MAX_FRAME_PIXELS,
             mode,
         })
     }
@@ -77,7 +79,7 @@ impl ScreenCapture {
             image.height()
         );
 
-        self.process_image(image, timestamp)
+        self.process_image(image, timestamp, monitor_rect(monitor))
     }
 
     /// 捕获活动窗口所在的显示器
@@ -103,7 +105,7 @@ impl ScreenCapture {
                         image.width(),
                         image.height()
                     );
-                    return self.process_image(image, timestamp);
+                    return self.process_image(image, timestamp, monitor_rect(&monitor));
                 }
                 Err(e) => {
                     warn!(
@@ -157,7 +159,7 @@ impl ScreenCapture {
                             image.width(),
                             image.height()
                         );
-                        return self.process_image(image, timestamp);
+                        return self.process_image(image, timestamp, window_rect(&window));
                     }
                 }
             }
@@ -173,7 +175,7 @@ impl ScreenCapture {
                             image.width(),
                             image.height()
                         );
-                        return self.process_image(image, timestamp);
+                        return self.process_image(image, timestamp, window_rect(&window));
                     }
                 }
             }
@@ -186,35 +188,58 @@ impl ScreenCapture {
         self.capture_primary_monitor()
     }
 
-    /// 处理图像（下采样等）
+    /// 处理图像（按像素数等比下采样）
     fn process_image(
         &self,
         image: image::RgbaImage,
         timestamp: i64,
+        source_rect: Option<(i32, i32, u32, u32)>,
     ) -> Result<CapturedFrame> {
         let (width, height) = (image.width(), image.height());
 
         // 如果需要下采样
-        let (final_pixels, final_width, final_height) =
-            if width > self.target_width || height > self.target_height {
-                let resized = image::imageops::resize(
-                    &image,
-                    self.target_width,
-                    self.target_height,
-                    image::imageops::FilterType::Triangle,
-                );
-                let w = resized.width();
-                let h = resized.height();
-                (resized.into_raw(), w, h)
-            } else {
-                (image.into_raw(), width, height)
-            };
+        let pixels = width as u64 * height as u64;
+        let (final_pixels, final_width, final_height) = if pixels > self.max_pixels {
+            let ratio = (self.max_pixels as f64 / pixels as f64).sqrt();
+            let resized = image::imageops::resize(
+                &image,
+                ((width as f64 * ratio) as u32).max(1),
+                ((height as f64 * ratio) as u32).max(1),
+                image::imageops::FilterType::Triangle,
+            );
+            let w = resized.width();
+            let h = resized.height();
+            (resized.into_raw(), w, h)
+        } else {
+            (image.into_raw(), width, height)
+        };
 
         Ok(CapturedFrame {
             pixels: final_pixels,
             width: final_width,
             height: final_height,
             timestamp,
+            source_rect,
         })
     }
 }
+
+/// 显示器在屏幕坐标中的范围
+fn monitor_rect(monitor: &xcap::Monitor) -> Option<(i32, i32, u32, u32)> {
+    Some((
+        monitor.x().ok()?,
+        monitor.y().ok()?,
+        monitor.width().ok()?,
+        monitor.height().ok()?,
+    ))
+}
+
+/// 窗口在屏幕坐标中的范围
+fn window_rect(window: &xcap::Window) -> Option<(i32, i32, u32, u32)> {
+    Some((
+        window.x().ok()?,
+        window.y().ok()?,
+        window.width().ok()?,
+        window.height().ok()?,
+    ))
+}
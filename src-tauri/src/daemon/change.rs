@@ -0,0 +1,254 @@
+//! 分块变化检测
+//!
+//! 把帧划分为 `tile_columns x tile_rows` 的网格，与上一张已保存的帧逐像素比较亮度：
+//! 亮度差超过 `tile_diff_threshold` 的像素为变化像素，一块中变化像素不少于
+//! [`MIN_CHANGED_PIXELS`] 时视为变化块。按像素计数而不是比较块平均亮度，输入几个字符
+//! 这种只占块面积百分之一左右的变化也能被发现。变化显著度是变化块的加权数量，
+//! 与焦点窗口相交的块权重为 1，其余为 `background_weight`。这样在编辑器里打一段字会被记录，
+//! 而焦点窗口外闪烁的广告不会触发截图。
+
+use crate::config::CaptureConfig;
+use crate::db::ChangeRegion;
+
+/// 一块中至少有这么多变化像素才视为变化块（过滤个别像素的抗锯齿抖动）
+pub const MIN_CHANGED_PIXELS: u32 = 4;
+
+/// 帧的分块亮度签名
+#[derive(Debug, Clone)]
+pub struct TileGrid {
+    columns: u32,
+    rows: u32,
+    width: u32,
+    height: u32,
+    /// 每个像素的亮度（行优先）
+    luma: Vec<u8>,
+}
+
+impl TileGrid {
+    /// 计算 RGBA 帧的亮度图（帧已限制在 MAX_FRAME_PIXELS 以内，逐像素保存）
+    pub fn compute(pixels: &[u8], width: u32, height: u32, columns: u32, rows: u32) -> Self {
+        let columns = columns.clamp(1, width.max(1));
+        let rows = rows.clamp(1, height.max(1));
+        let luma = pixels
+            .chunks_exact(4)
+            .take((width * height) as usize)
+            .map(|px| ((px[0] as u32 * 299 + px[1] as u32 * 587 + px[2] as u32 * 114) / 1000) as u8)
+            .collect();
+
+        Self {
+            columns,
+            rows,
+            width,
+            height,
+            luma,
+        }
+    }
+
+    /// 网格与帧尺寸一致时才能逐块比较
+    pub fn compatible(&self, other: &TileGrid) -> bool {
+        self.columns == other.columns
+            && self.rows == other.rows
+            && self.width == other.width
+            && self.height == other.height
+            && self.luma.len() == other.luma.len()
+    }
+
+    /// 块在帧中的像素范围 (x0, y0, x1, y1)，右下不含
+    fn tile_rect(&self, col: u32, row: u32) -> (u32, u32, u32, u32) {
+        let x0 = (col as u64 * self.width as u64 / self.columns as u64) as u32;
+        let x1 = ((col + 1) as u64 * self.width as u64 / self.columns as u64) as u32;
+        let y0 = (row as u64 * self.height as u64 / self.rows as u64) as u32;
+        let y1 = ((row + 1) as u64 * self.height as u64 / self.rows as u64) as u32;
+        (x0, y0, x1, y1)
+    }
+
+    /// 每块的变化像素数（行优先）
+    fn changed_pixels(&self, reference: &TileGrid, threshold: u32) -> Vec<u32> {
+        let mut counts = vec![0u32; (self.columns * self.rows) as usize];
+        if self.width == 0 {
+            return counts;
+        }
+        for (i, (a, b)) in self.luma.iter().zip(&reference.luma).enumerate() {
+            if a.abs_diff(*b) as u32 <= threshold {
+                continue;
+            }
+            let x = i as u64 % self.width as u64;
+            let y = i as u64 / self.width as u64;
+            let col = (x * self.columns as u64 / self.width as u64) as u32;
+            let row = (y * self.rows as u64 / self.height as u64) as u32;
+            counts[(row * self.columns + col) as usize] += 1;
+        }
+        counts
+    }
+
+    /// 与参考帧逐块比较，返回变化块的外接矩形与加权显著度；没有变化块时返回 None
+    ///
+    /// `focus` 是焦点窗口在帧中的范围（x, y, width, height）；为 None 时所有块权重为 1。
+    pub fn diff(
+        &self,
+        reference: &TileGrid,
+        config: &CaptureConfig,
+        focus: Option<(u32, u32, u32, u32)>,
+    ) -> Option<ChangeRegion> {
+        if !self.compatible(reference) {
+            return None;
+        }
+
+        let counts = self.changed_pixels(reference, config.tile_diff_threshold);
+        let mut changed_tiles = 0u32;
+        let mut score = 0.0f64;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0u32, 0u32);
+
+        for row in 0..self.rows {
+            for col in 0..self.columns {
+                if counts[(row * self.columns + col) as usize] < MIN_CHANGED_PIXELS {
+                    continue;
+                }
+                let (x0, y0, x1, y1) = self.tile_rect(col, row);
+                let in_focus = focus.is_none_or(|(fx, fy, fw, fh)| {
+                    x0 < fx + fw && fx < x1 && y0 < fy + fh && fy < y1
+                });
+                changed_tiles += 1;
+                score += if in_focus {
+                    1.0
+                } else {
+                    config.background_weight as f64
+                };
+                min_x = min_x.min(x0);
+                min_y = min_y.min(y0);
+                max_x = max_x.max(x1);
+                max_y = max_y.max(y1);
+            }
+        }
+
+        (changed_tiles > 0).then(|| ChangeRegion {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x,
+            height: max_y - min_y,
+            changed_tiles,
+            total_tiles: self.columns * self.rows,
+            score,
+        })
+    }
+}
+
+/// 把焦点窗口的屏幕坐标映射到帧像素坐标，并裁剪到帧内；不相交时返回 None
+///
+/// `source` 是帧所覆盖的屏幕区域（x, y, width, height）。
+pub fn focus_rect_in_frame(
+    bounds: (i32, i32, u32, u32),
+    source: (i32, i32, u32, u32),
+    frame_width: u32,
+    frame_height: u32,
+) -> Option<(u32, u32, u32, u32)> {
+    let (bx, by, bw, bh) = bounds;
+    let (sx, sy, sw, sh) = source;
+    if sw == 0 || sh == 0 {
+        return None;
+    }
+    let scale_x = frame_width as f64 / sw as f64;
+    let scale_y = frame_height as f64 / sh as f64;
+
+    let x0 = ((bx - sx) as f64 * scale_x).clamp(0.0, frame_width as f64);
+    let y0 = ((by - sy) as f64 * scale_y).clamp(0.0, frame_height as f64);
+    let x1 = ((bx - sx + bw as i32) as f64 * scale_x).clamp(0.0, frame_width as f64);
+    let y1 = ((by - sy + bh as i32) as f64 * scale_y).clamp(0.0, frame_height as f64);
+    if x1 <= x0 || y1 <= y0 {
+        return None;
+    }
+    Some((
+        x0 as u32,
+        y0 as u32,
+        (x1 - x0).round() as u32,
+        (y1 - y0).round() as u32,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, fill: u8) -> Vec<u8> {
+        vec![fill; (width * height * 4) as usize]
+    }
+
+    fn paint(pixels: &mut [u8], width: u32, rect: (u32, u32, u32, u32), value: u8) {
+        let (x, y, w, h) = rect;
+        for py in y..y + h {
+            for px in x..x + w {
+                let idx = ((py * width + px) * 4) as usize;
+                pixels[idx..idx + 3].fill(value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_focus_weighted_tile_changes() {
+        let config = CaptureConfig::default();
+        let (w, h) = (320, 180);
+        let base = frame(w, h, 255);
+        let reference = TileGrid::compute(&base, w, h, config.tile_columns, config.tile_rows);
+
+        // 焦点窗口占左半屏；右上角一小块（"广告"）变化
+        let focus = Some((0, 0, 160, 180));
+        let mut ad = base.clone();
+        paint(&mut ad, w, (290, 0, 30, 20), 0);
+        let grid = TileGrid::compute(&ad, w, h, config.tile_columns, config.tile_rows);
+        let region = grid.diff(&reference, &config, focus).unwrap();
+        assert!(region.x >= 280 && region.y == 0);
+        assert!(region.score < config.min_change_score as f64);
+
+        // 无变化
+        assert!(reference.diff(&reference, &config, focus).is_none());
+    }
+
+    /// 在 (x, y) 处画一个 6x10 的细笔画字形（类似“E”），只占块面积的百分之一左右
+    fn glyph(pixels: &mut [u8], width: u32, x: u32, y: u32) {
+        paint(pixels, width, (x, y, 1, 10), 40);
+        paint(pixels, width, (x, y, 6, 1), 40);
+        paint(pixels, width, (x, y + 5, 5, 1), 40);
+        paint(pixels, width, (x, y + 9, 6, 1), 40);
+    }
+
+    #[test]
+    fn test_typed_glyphs_are_detected() {
+        let config = CaptureConfig::default();
+        // 1920x1080 下每块 60x60 像素
+        let (w, h) = (1920, 1080);
+        let base = frame(w, h, 250);
+        let reference = TileGrid::compute(&base, w, h, config.tile_columns, config.tile_rows);
+        let focus = Some((0, 0, 960, 1080));
+
+        // 焦点窗口内输入两个字符，分别落在相邻两块：整块平均亮度只变化约 1
+        let mut typed = base.clone();
+        glyph(&mut typed, w, 50, 300);
+        glyph(&mut typed, w, 62, 300);
+        let grid = TileGrid::compute(&typed, w, h, config.tile_columns, config.tile_rows);
+        let region = grid.diff(&reference, &config, focus).unwrap();
+        assert_eq!(region.changed_tiles, 2);
+        assert!(region.score >= config.min_change_score as f64);
+        assert!(region.x <= 50 && region.x + region.width >= 68);
+
+        // 全屏轻微的亮度抖动（低于阈值）不算变化
+        let dimmed = frame(w, h, 250 - config.tile_diff_threshold as u8);
+        let grid = TileGrid::compute(&dimmed, w, h, config.tile_columns, config.tile_rows);
+        assert!(grid.diff(&reference, &config, focus).is_none());
+
+        // 个别像素的变化不足以构成变化块
+        let mut speck = base.clone();
+        paint(&mut speck, w, (100, 100, 1, 2), 0);
+        let grid = TileGrid::compute(&speck, w, h, config.tile_columns, config.tile_rows);
+        assert!(grid.diff(&reference, &config, focus).is_none());
+    }
+
+    #[test]
+    fn test_focus_rect_mapping() {
+        // 3840x2160 的显示器下采样到 1920x1080
+        let rect = focus_rect_in_frame((100, 200, 1000, 800), (0, 0, 3840, 2160), 1920, 1080);
+        assert_eq!(rect, Some((50, 100, 500, 400)));
+        // 窗口在另一块显示器上
+        let rect = focus_rect_in_frame((4000, 0, 500, 500), (0, 0, 3840, 2160), 1920, 1080);
+        assert_eq!(rect, None);
+    }
+}
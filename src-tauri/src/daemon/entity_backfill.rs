@@ -0,0 +1,248 @@
+//! 实体关联回填
+//!
+//! 早期版本只把 VLM 实体写入 `traces.vlm_entities_json`，没有建立 `entity_traces`
+//! 与 `entity_sessions` 关联。启动时按 trace ID 分批回填，进度记录在 settings 中，
+//! 中断后从记录处继续；关联写入是幂等的，重复处理不会增加提及次数。
+//!
+//! 回填 trace 之前先为没有别名的实体补建别名，归一化名称相同的实体就此合并；
+//! 回填完成后重建实体共现关系。
+
+use crate::ai::entity::typed_entities;
+use crate::db::Database;
+use anyhow::Result;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// 回填进度（最后处理的 trace ID）
+const CURSOR_KEY: &str = "entity_backfill_cursor";
+
+/// 每批处理的 trace 数
+const BATCH_SIZE: u32 = 500;
+
+/// 分批回填直到没有剩余 trace
+pub async fn run_entity_backfill(db: Arc<Database>) {
+    match db.call(|db| db.backfill_entity_aliases()).await {
+        Ok(0) => {}
+        Ok(merged) => info!("Merged {} duplicate entities by normalized name", merged),
+        Err(e) => warn!("Entity alias backfill failed: {}", e),
+    }
+
+    let mut total = 0;
+    loop {
+        match db.call(backfill_batch).await {
+            Ok((0, _)) => break,
+            Ok((processed, linked)) => {
+                total += linked;
+                if processed < BATCH_SIZE as usize {
+                    break;
+                }
+            }
+            Err(e) => {
+                warn!("Entity backfill failed: {}", e);
+                return;
+            }
+        }
+    }
+    if total > 0 {
+        info!("Entity backfill linked {} entity mentions", total);
+    }
+
+    let now = chrono::Utc::now().timestamp_millis();
+    match db.call(move |db| db.rebuild_entity_relations(now)).await {
+        Ok(relations) => info!("Rebuilt {} entity relations", relations),
+        Err(e) => warn!("Entity relation rebuild failed: {}", e),
+    }
+}
+
+/// 处理一批 trace，返回（处理的 trace 数, 新建的关联数）
+fn backfill_batch(db: &Database) -> Result<(usize, usize)> {
+    let cursor = db
+        .get_setting(CURSOR_KEY)?
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(0);
+    let traces = db.get_traces_with_entities_after(cursor, BATCH_SIZE)?;
+    let Some(last_id) = traces.last().map(|(id, _)| *id) else {
+        return Ok((0, 0));
+    };
+
+    let mut linked = 0;
+    for (trace_id, entities_json) in &traces {
+        let names: Vec<String> = serde_json::from_str(entities_json).unwrap_or_default();
+        linked += db.record_trace_entities(*trace_id, &typed_entities(&names))?;
+    }
+
+    db.set_setting(CURSOR_KEY, &last_id.to_string())?;
+    Ok((traces.len(), linked))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::NewTrace;
+
+    fn insert_trace(db: &Database, timestamp: i64, entities: &[&str]) -> i64 {
+        let (id, _) = db
+            .insert_trace(&NewTrace {
+                timestamp,
+                image_path: String::new(),
+                app_name: Some("Code".to_string()),
+                window_title: None,
+                is_fullscreen: false,
+                is_idle: false,
+                ocr_text: None,
+                phash: None,
+            })
+            .unwrap();
+        let entities: Vec<String> = entities.iter().map(|e| e.to_string()).collect();
+        db.update_trace_vlm_analysis(id, None, None, None, None, &entities, None, false)
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn test_backfill_links_entities_once() {
+        let dir = std::env::temp_dir().join(format!("engram-entities-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let first = insert_trace(&db, 2_000, &["Rust", "src/main.rs"]);
+        insert_trace(&db, 1_000, &["Rust"]);
+        insert_trace(&db, 3_000, &["Rust"]);
+
+        assert_eq!(backfill_batch(&db).unwrap(), (3, 4));
+        // 再次处理（例如游标丢失）不会重复计数
+        db.set_setting(CURSOR_KEY, "0").unwrap();
+        assert_eq!(backfill_batch(&db).unwrap(), (3, 0));
+
+        let rust = db.get_entity_by_name("Rust").unwrap().unwrap();
+        assert_eq!(rust.mention_count, 3);
+        assert_eq!(rust.first_seen, 1_000);
+        assert_eq!(rust.last_seen, 3_000);
+        assert_eq!(db.get_traces_by_entity(rust.id, 10).unwrap().len(), 3);
+
+        let file = db.get_entity_by_name("src/main.rs").unwrap().unwrap();
+        assert_eq!(file.entity_type, "file");
+        assert_eq!(db.get_traces_by_entity(file.id, 10).unwrap()[0].id, first);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_aliases_resolve_and_split() {
+        let dir = std::env::temp_dir().join(format!("engram-entities-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        insert_trace(&db, 1_000, &["Engram"]);
+        insert_trace(&db, 2_000, &["engram"]);
+        let repo = insert_trace(&db, 3_000, &["TokenRollAI/engram"]);
+        backfill_batch(&db).unwrap();
+
+        // 大小写不同的写法解析到同一实体
+        let engram = db.get_entity_by_name("ENGRAM").unwrap().unwrap();
+        assert_eq!(engram.name, "Engram");
+        assert_eq!(engram.mention_count, 2);
+
+        let other = db
+            .get_entity_by_name("tokenrollai/engram")
+            .unwrap()
+            .unwrap();
+        assert!(db.merge_entities(other.id, engram.id).unwrap());
+        let merged = db.get_entity_by_name("Engram").unwrap().unwrap();
+        assert_eq!(merged.mention_count, 3);
+        assert_eq!(merged.last_seen, 3_000);
+        assert_eq!(db.get_entity_aliases(engram.id).unwrap().len(), 2);
+
+        // 合并后再拆分，trace 关联随别名迁回
+        let split = db
+            .split_entity(engram.id, "tokenrollai/engram")
+            .unwrap()
+            .unwrap();
+        assert_eq!(db.get_traces_by_entity(split, 10).unwrap()[0].id, repo);
+        assert_eq!(db.get_traces_by_entity(engram.id, 10).unwrap().len(), 2);
+        assert!(db.split_entity(engram.id, "engram").unwrap().is_none());
+
+        // 类型按票数决定
+        db.upsert_entity(&crate::db::models::NewEntity {
+            name: "engram".to_string(),
+            entity_type: "project".to_string(),
+            first_seen: 1_000,
+            last_seen: 1_000,
+            metadata: None,
+        })
+        .unwrap();
+        assert_eq!(
+            db.get_entity_by_name("Engram")
+                .unwrap()
+                .unwrap()
+                .entity_type,
+            "project"
+        );
+
+        assert!(!db.rename_entity(engram.id, "tokenrollai/engram").unwrap());
+        assert!(db.rename_entity(engram.id, "Engram App").unwrap());
+        assert_eq!(
+            db.get_entity_by_name("engram").unwrap().unwrap().id,
+            engram.id
+        );
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_relations_from_cooccurrence() {
+        let dir = std::env::temp_dir().join(format!("engram-entities-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        insert_trace(&db, 1_000, &["engram", "Rust", "Alice"]);
+        insert_trace(&db, 2_000, &["engram", "Rust"]);
+        insert_trace(&db, 3_000, &["Rust", "Cargo.toml"]);
+        backfill_batch(&db).unwrap();
+        assert_eq!(db.rebuild_entity_relations(3_000).unwrap(), 4);
+
+        let engram = db.get_entity_by_name("engram").unwrap().unwrap();
+        let rust = db.get_entity_by_name("Rust").unwrap().unwrap();
+        let neighbors = db.get_entity_neighbors(engram.id, None, 10, 3_000).unwrap();
+        assert_eq!(neighbors.len(), 2);
+        assert_eq!(neighbors[0].entity.id, rust.id);
+        assert_eq!(neighbors[0].relation.trace_count, 2);
+
+        // 标注的关系类型在重建后保留
+        assert!(db
+            .set_entity_relation_type(rust.id, engram.id, Some("uses"))
+            .unwrap());
+        db.rebuild_entity_relations(4_000).unwrap();
+        let neighbors = db
+            .get_entity_neighbors(rust.id, Some("file"), 10, 4_000)
+            .unwrap();
+        assert_eq!(neighbors.len(), 1);
+        let edges = db
+            .get_entity_relations_among(&[engram.id, rust.id], 4_000)
+            .unwrap();
+        assert_eq!(edges[0].relation_type.as_deref(), Some("uses"));
+
+        // 合并后只重算涉及的实体，结果与全量重建一致
+        let alice = db.get_entity_by_name("Alice").unwrap().unwrap();
+        assert!(db.merge_entities(alice.id, engram.id).unwrap());
+        db.rebuild_entity_relations_for(&[alice.id, engram.id], 5_000)
+            .unwrap();
+        let scoped = db.get_entity_neighbors(engram.id, None, 10, 5_000).unwrap();
+        assert_eq!(scoped.len(), 1);
+        assert_eq!(scoped[0].entity.id, rust.id);
+        assert_eq!(scoped[0].relation.relation_type.as_deref(), Some("uses"));
+        db.rebuild_entity_relations(5_000).unwrap();
+        let full = db.get_entity_neighbors(engram.id, None, 10, 5_000).unwrap();
+        assert_eq!(
+            scoped
+                .iter()
+                .map(|n| (n.entity.id, n.relation.trace_count))
+                .collect::<Vec<_>>(),
+            full.iter()
+                .map(|n| (n.entity.id, n.relation.trace_count))
+                .collect::<Vec<_>>()
+        );
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
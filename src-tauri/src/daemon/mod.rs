@@ -3,37 +3,37 @@
 //! 负责定时截图、上下文感知、图像处理和摘要生成。
 
 mod capture;
+mod change;
 mod context;
+mod entity_backfill;
 mod hasher;
 mod idle;
+pub mod retention_task;
+pub mod routing_eval;
 pub mod summarizer_task;
+mod summary_schedule;
 pub mod vlm_task;
 
 pub use capture::ScreenCapture;
+pub use change::TileGrid;
 pub use context::{FocusContext, WindowWatcher};
+pub use entity_backfill::run_entity_backfill;
 pub use hasher::PerceptualHasher;
 pub use idle::IdleDetector;
+pub use retention_task::RetentionTask;
 pub use summarizer_task::{SummarizerTask, SummarizerTaskConfig};
+pub use summary_schedule::{ScheduleTimezone, SummarySchedule};
 pub use vlm_task::{VlmTask, VlmTaskConfig, VlmTaskStatus};
 
-use crate::config::CaptureMode;
-use crate::db::Database;
+use crate::config::AppConfig;
+use crate::db::{ChangeRegion, Database};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
-use tokio::time::interval;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{interval, interval_at, Instant};
 use tracing::{debug, error, info, warn};
 
-/// 截图间隔（毫秒）
-const DEFAULT_CAPTURE_INTERVAL_MS: u64 = 2000;
-
-/// 闲置阈值（毫秒）
-const DEFAULT_IDLE_THRESHOLD_MS: u64 = 30000;
-
-/// 相似度阈值（汉明距离）
-const DEFAULT_SIMILARITY_THRESHOLD: u32 = 5;
-
 /// 守护进程状态
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct DaemonStatus {
@@ -52,34 +52,23 @@ pub struct EngramDaemon {
     is_paused: Arc<AtomicBool>,
     is_idle: Arc<AtomicBool>,
     idle_time_ms: Arc<AtomicU64>,
-    capture_interval_ms: u64,
-    idle_threshold_ms: u64,
-    similarity_threshold: u32,
-    capture_mode: CaptureMode,
+    config_rx: watch::Receiver<AppConfig>,
     shutdown_tx: Option<mpsc::Sender<()>>,
     last_capture_time: Arc<AtomicU64>,
     total_captures_today: Arc<AtomicU64>,
 }
 
 impl EngramDaemon {
-    /// 创建新的守护进程
+    /// 创建新的守护进程（使用默认配置）
     pub fn new(db: Arc<Database>) -> anyhow::Result<Self> {
-        Self::new_with_config(
-            db,
-            DEFAULT_CAPTURE_INTERVAL_MS,
-            DEFAULT_IDLE_THRESHOLD_MS,
-            DEFAULT_SIMILARITY_THRESHOLD,
-            CaptureMode::default(),
-        )
+        let (_, config_rx) = watch::channel(AppConfig::default());
+        Self::new_with_config(db, config_rx)
     }
 
-    /// 使用指定配置创建守护进程
+    /// 使用配置广播创建守护进程，截图循环会在配置变更时即时生效
     pub fn new_with_config(
         db: Arc<Database>,
-        capture_interval_ms: u64,
-        idle_threshold_ms: u64,
-        similarity_threshold: u32,
-        capture_mode: CaptureMode,
+        config_rx: watch::Receiver<AppConfig>,
     ) -> anyhow::Result<Self> {
         Ok(Self {
             db,
@@ -87,10 +76,7 @@ impl EngramDaemon {
             is_paused: Arc::new(AtomicBool::new(false)),
             is_idle: Arc::new(AtomicBool::new(false)),
             idle_time_ms: Arc::new(AtomicU64::new(0)),
-            capture_interval_ms,
-            idle_threshold_ms,
-            similarity_threshold,
-            capture_mode,
+            config_rx,
             shutdown_tx: None,
             last_capture_time: Arc::new(AtomicU64::new(0)),
             total_captures_today: Arc::new(AtomicU64::new(0)),
@@ -116,17 +102,15 @@ impl EngramDaemon {
         let last_capture_time = self.last_capture_time.clone();
         let total_captures_today = self.total_captures_today.clone();
         let db = self.db.clone();
-        let interval_ms = self.capture_interval_ms;
-        let idle_threshold_ms = self.idle_threshold_ms;
-        let similarity_threshold = self.similarity_threshold;
-        let capture_mode = self.capture_mode;
+        let mut config_rx = self.config_rx.clone();
+        let mut capture_config = config_rx.borrow_and_update().capture.clone();
 
         is_running.store(true, Ordering::SeqCst);
 
         // 启动截图循环
         tokio::spawn(async move {
-            let mut ticker = interval(Duration::from_millis(interval_ms));
-            let mut screen_capture = match ScreenCapture::new(capture_mode) {
+            let mut ticker = interval(Duration::from_millis(capture_config.interval_ms));
+            let mut screen_capture = match ScreenCapture::new(capture_config.mode) {
                 Ok(sc) => sc,
                 Err(e) => {
                     error!("Failed to initialize screen capture: {}", e);
@@ -134,12 +118,14 @@ impl EngramDaemon {
                 }
             };
             let hasher = PerceptualHasher::new();
-            let idle_detector = IdleDetector::new(idle_threshold_ms);
+            let mut idle_detector = IdleDetector::new(capture_config.idle_threshold_ms);
             let mut last_hash: Option<[u8; 8]> = None;
+            // 上一张已保存帧的分块签名（变化检测的参考帧）
+            let mut last_grid: Option<TileGrid> = None;
 
             info!(
-                "Daemon capture loop started (idle threshold: {}ms)",
-                idle_threshold_ms
+                "Daemon capture loop started (interval: {}ms, idle threshold: {}ms)",
+                capture_config.interval_ms, capture_config.idle_threshold_ms
             );
 
             loop {
@@ -148,6 +134,32 @@ impl EngramDaemon {
                         info!("Daemon received shutdown signal");
                         break;
                     }
+                    Ok(()) = config_rx.changed() => {
+                        let new_config = config_rx.borrow_and_update().capture.clone();
+                        if new_config == capture_config {
+                            continue;
+                        }
+                        if new_config.interval_ms != capture_config.interval_ms {
+                            let period = Duration::from_millis(new_config.interval_ms);
+                            ticker = interval_at(Instant::now() + period, period);
+                        }
+                        if new_config.mode != capture_config.mode {
+                            match ScreenCapture::new(new_config.mode) {
+                                Ok(sc) => screen_capture = sc,
+                                Err(e) => warn!("Failed to switch capture mode: {}", e),
+                            }
+                        }
+                        idle_detector.set_threshold(new_config.idle_threshold_ms);
+                        info!(
+                            "Capture config applied (interval: {}ms, idle threshold: {}ms, similarity: {}, region detection: {}, mode: {:?})",
+                            new_config.interval_ms,
+                            new_config.idle_threshold_ms,
+                            new_config.similarity_threshold,
+                            new_config.region_detection,
+                            new_config.mode
+                        );
+                        capture_config = new_config;
+                    }
                     _ = ticker.tick() => {
                         // 检查是否暂停
                         if is_paused.load(Ordering::SeqCst) {
@@ -173,13 +185,36 @@ impl EngramDaemon {
                         // 执行截图（传入上下文）
                         match screen_capture.capture(&context) {
                             Ok(frame) => {
-                                // 计算感知哈希
+                                // 计算感知哈希（用于近重复复用）
                                 let current_hash = hasher.compute(&frame.pixels, frame.width, frame.height);
 
-                                // 检查是否与上一帧相似
-                                if let Some(prev_hash) = last_hash {
+                                // 焦点窗口在帧中的范围（变化加权与 VLM 焦点裁剪）
+                                let focus = context.bounds.zip(frame.source_rect).and_then(|(bounds, source)| {
+                                    change::focus_rect_in_frame(bounds, source, frame.width, frame.height)
+                                });
+
+                                // 检查与上一张已保存帧相比是否有足够变化
+                                let mut region = None;
+                                if capture_config.region_detection {
+                                    let grid = TileGrid::compute(
+                                        &frame.pixels,
+                                        frame.width,
+                                        frame.height,
+                                        capture_config.tile_columns,
+                                        capture_config.tile_rows,
+                                    );
+                                    if let Some(prev_grid) = last_grid.as_ref().filter(|g| g.compatible(&grid)) {
+                                        region = grid.diff(prev_grid, &capture_config, focus);
+                                        let score = region.map_or(0.0, |r| r.score);
+                                        if score < capture_config.min_change_score as f64 {
+                                            debug!("Frame change not significant (score={:.2}), skipping", score);
+                                            continue;
+                                        }
+                                    }
+                                    last_grid = Some(grid);
+                                } else if let Some(prev_hash) = last_hash {
                                     let distance = hasher.hamming_distance(&prev_hash, &current_hash);
-                                    if distance < similarity_threshold {
+                                    if distance < capture_config.similarity_threshold {
                                         debug!("Frame too similar (distance={}), skipping", distance);
                                         continue;
                                     }
@@ -187,9 +222,10 @@ impl EngramDaemon {
                                 last_hash = Some(current_hash);
 
                                 // 保存到数据库
-                                match Self::save_frame(&db, &frame, &context, &current_hash).await {
+                                let frame_timestamp = frame.timestamp;
+                                match Self::save_frame(&db, frame, context, current_hash, focus, region).await {
                                     Ok(_) => {
-                                        last_capture_time.store(frame.timestamp as u64, Ordering::SeqCst);
+                                        last_capture_time.store(frame_timestamp as u64, Ordering::SeqCst);
                                         total_captures_today.fetch_add(1, Ordering::SeqCst);
                                     }
                                     Err(e) => {
@@ -253,33 +289,26 @@ impl EngramDaemon {
         Ok(())
     }
 
-    /// 更新配置
-    pub fn update_config(
-        &mut self,
-        capture_interval_ms: Option<u64>,
-        idle_threshold_ms: Option<u64>,
-        similarity_threshold: Option<u32>,
-    ) {
-        if let Some(interval) = capture_interval_ms {
-            self.capture_interval_ms = interval;
-            info!("Updated capture interval: {}ms", interval);
-        }
-        if let Some(threshold) = idle_threshold_ms {
-            self.idle_threshold_ms = threshold;
-            info!("Updated idle threshold: {}ms", threshold);
-        }
-        if let Some(threshold) = similarity_threshold {
-            self.similarity_threshold = threshold;
-            info!("Updated similarity threshold: {}", threshold);
-        }
+    /// 保存帧到数据库（WebP 编码与写库在阻塞线程池执行，不阻塞截图循环）
+    async fn save_frame(
+        db: &Arc<Database>,
+        frame: capture::CapturedFrame,
+        context: FocusContext,
+        phash: [u8; 8],
+        focus: Option<(u32, u32, u32, u32)>,
+        region: Option<ChangeRegion>,
+    ) -> anyhow::Result<()> {
+        db.call(move |db| Self::write_frame(db, &frame, &context, &phash, focus, region.as_ref()))
+            .await
     }
 
-    /// 保存帧到数据库
-    async fn save_frame(
+    fn write_frame(
         db: &Database,
         frame: &capture::CapturedFrame,
         context: &FocusContext,
         phash: &[u8; 8],
+        focus: Option<(u32, u32, u32, u32)>,
+        region: Option<&ChangeRegion>,
     ) -> anyhow::Result<()> {
         use crate::db::models::NewTrace;
 
@@ -305,6 +334,13 @@ impl EngramDaemon {
         };
 
         let (trace_id, session_id) = db.insert_trace(&trace)?;
+        // 首帧或网格变化后没有参考帧，不记录变化区域（视为整屏）
+        if let Some(region) = region {
+            db.save_change_region(trace_id, region)?;
+        }
+        if let Some(focus) = focus {
+            db.save_focus_region(trace_id, focus)?;
+        }
         debug!("Frame saved: {}", trace.image_path);
         debug!(
             "Trace inserted: id={}, session_id={:?}",
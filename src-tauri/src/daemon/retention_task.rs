@@ -0,0 +1,134 @@
+//! 数据保留任务
+//!
+//! 按 `storage` 配置分层清理数据：
+//! - 超过 `hot_data_days` 的 trace 删除截图文件，只保留 OCR、向量与元数据
+//! - 超过 `warm_data_days` 的 trace 整条删除，只保留摘要、实体与会话
+//!
+//! 清理会删除历史数据，默认关闭：只有 `storage.retention_enabled` 为 true 时才执行。
+//! 开启或修改保留天数后立即执行一次清理。
+
+use crate::config::{AppConfig, StorageConfig};
+use crate::db::Database;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::time::interval;
+use tracing::{error, info, warn};
+
+/// 清理间隔（毫秒）- 1 小时
+const RETENTION_INTERVAL_MS: u64 = 60 * 60 * 1000;
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// 数据保留后台任务
+pub struct RetentionTask {
+    db: Arc<Database>,
+    config_rx: watch::Receiver<AppConfig>,
+    is_running: Arc<AtomicBool>,
+    shutdown_tx: Option<mpsc::Sender<()>>,
+}
+
+impl RetentionTask {
+    /// 创建新的数据保留任务
+    pub fn new(db: Arc<Database>, config_rx: watch::Receiver<AppConfig>) -> Self {
+        Self {
+            db,
+            config_rx,
+            is_running: Arc::new(AtomicBool::new(false)),
+            shutdown_tx: None,
+        }
+    }
+
+    /// 启动数据保留任务
+    pub fn start(&mut self) {
+        if self.is_running.load(Ordering::SeqCst) {
+            warn!("Retention task is already running");
+            return;
+        }
+
+        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+        self.shutdown_tx = Some(shutdown_tx);
+
+        let is_running = self.is_running.clone();
+        let db = self.db.clone();
+        let mut config_rx = self.config_rx.clone();
+        let mut storage = config_rx.borrow_and_update().storage.clone();
+
+        is_running.store(true, Ordering::SeqCst);
+
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_millis(RETENTION_INTERVAL_MS));
+
+            info!(
+                "Retention task started (enabled: {}, hot: {}d, warm: {}d)",
+                storage.retention_enabled, storage.hot_data_days, storage.warm_data_days
+            );
+
+            loop {
+                tokio::select! {
+                    _ = shutdown_rx.recv() => {
+                        info!("Retention task received shutdown signal");
+                        break;
+                    }
+                    Ok(()) = config_rx.changed() => {
+                        let new_storage = config_rx.borrow_and_update().storage.clone();
+                        if new_storage == storage {
+                            continue;
+                        }
+                        info!(
+                            "Retention config changed (enabled: {}, hot: {}d, warm: {}d)",
+                            new_storage.retention_enabled,
+                            new_storage.hot_data_days,
+                            new_storage.warm_data_days
+                        );
+                        storage = new_storage;
+                        Self::run_once(&db, &storage).await;
+                    }
+                    _ = ticker.tick() => {
+                        Self::run_once(&db, &storage).await;
+                    }
+                }
+            }
+
+            is_running.store(false, Ordering::SeqCst);
+            info!("Retention task stopped");
+        });
+    }
+
+    /// 停止数据保留任务
+    pub fn stop(&mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.try_send(());
+        }
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+
+    /// 执行一次分层清理（未开启时跳过）
+    async fn run_once(db: &Arc<Database>, storage: &StorageConfig) {
+        if !storage.retention_enabled {
+            return;
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let hot_cutoff = now - storage.hot_data_days as i64 * DAY_MS;
+        let warm_cutoff = now - storage.warm_data_days as i64 * DAY_MS;
+
+        let result = db
+            .call(move |db| {
+                let deleted = db.delete_traces_before(warm_cutoff)?;
+                let pruned = db.prune_screenshots_before(hot_cutoff)?;
+                Ok((deleted, pruned))
+            })
+            .await;
+
+        match result {
+            Ok((0, 0)) => {}
+            Ok((deleted, pruned)) => info!(
+                "Retention: deleted {} traces, pruned {} screenshots",
+                deleted, pruned
+            ),
+            Err(e) => error!("Retention cleanup failed: {}", e),
+        }
+    }
+}
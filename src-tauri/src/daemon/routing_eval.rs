@@ -0,0 +1,371 @@
+//! Session 路由评估与阈值自适应
+//!
+//! 以当前的 Session 归属（包含用户合并、拆分、移动后的结果）作为标注，按时间顺序回放
+//! embedding 路由：每条 trace 的候选是活跃窗口内各 Session 的质心（已归入 trace 向量的
+//! 均值），与线上 `route_trace_by_embedding` 一致。模型直接选中 Session 的路径不参与回放。
+//!
+//! 阈值自适应在若干候选阈值上计算回放准确率（用户纠正过的 trace 加权），
+//! 再让当前阈值向最优区间的中点小步靠拢。
+
+use super::vlm_task::VlmTask;
+use crate::config::SessionConfig;
+use crate::db::Database;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// 评估使用的历史范围（天）
+pub const ROUTING_HISTORY_DAYS: i64 = 14;
+
+/// 单次回放最多使用的 trace 数
+const MAX_HISTORY_TRACES: u32 = 20_000;
+
+/// 用户纠正过的 trace 在阈值搜索中的权重
+const CORRECTED_WEIGHT: u32 = 3;
+
+/// 至少有这么多条纠正样本才调整阈值
+const MIN_CORRECTED_SAMPLES: u32 = 3;
+
+/// 阈值搜索范围与步长
+const THRESHOLD_MIN: f32 = 0.5;
+const THRESHOLD_MAX: f32 = 0.95;
+const THRESHOLD_STEP: f32 = 0.01;
+
+/// 单次自适应最多调整的幅度
+const MAX_ADJUSTMENT: f32 = 0.05;
+
+/// 一条参与回放的 trace
+#[derive(Debug, Clone)]
+pub struct RoutingTrace {
+    pub trace_id: i64,
+    pub timestamp: i64,
+    pub session_id: i64,
+    pub embedding: Vec<f32>,
+}
+
+/// 回放得到的样本：最相似的候选 Session 与标注结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingSample {
+    /// 相似度最高的候选（Session ID, 相似度）
+    pub best: Option<(i64, f32)>,
+    /// 正确的归属：候选中的 Session，None 表示应新建
+    pub truth: Option<i64>,
+    /// 是否被用户纠正过
+    pub corrected: bool,
+}
+
+impl RoutingSample {
+    /// 给定阈值下的路由结果
+    fn predict(&self, threshold: f32) -> Option<i64> {
+        self.best
+            .filter(|(_, sim)| *sim >= threshold)
+            .map(|(id, _)| id)
+    }
+}
+
+/// 某个阈值下的路由评估结果
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RoutingEvaluation {
+    pub threshold: f32,
+    pub total: u32,
+    pub correct: u32,
+    pub accuracy: f32,
+    /// 应新建却归入了既有 Session
+    pub false_joins: u32,
+    /// 应归入既有 Session 却新建
+    pub missed_joins: u32,
+    /// 归入了错误的既有 Session
+    pub wrong_sessions: u32,
+    /// 用户纠正过的样本数及其中路由正确的数量
+    pub corrected_total: u32,
+    pub corrected_correct: u32,
+}
+
+/// 路由评估报告
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutingReport {
+    pub start_time: i64,
+    pub end_time: i64,
+    /// 当前阈值下的评估
+    pub current: RoutingEvaluation,
+    /// 回放最优的阈值及其评估（样本不足时为空）
+    pub suggested: Option<RoutingEvaluation>,
+}
+
+/// 按时间顺序回放 traces，生成路由样本
+///
+/// `traces` 需按 (timestamp, trace_id) 升序。
+pub fn build_samples(
+    traces: &[RoutingTrace],
+    corrected: &HashSet<i64>,
+    active_window_ms: i64,
+    max_active: usize,
+) -> Vec<RoutingSample> {
+    // Session -> (最后一条 trace 的时间, 向量累加和)；余弦相似度与缩放无关，累加和即可代表质心
+    let mut last_seen: HashMap<i64, (i64, Vec<f32>)> = HashMap::new();
+    let mut samples = Vec::with_capacity(traces.len());
+
+    for trace in traces {
+        let since = trace.timestamp - active_window_ms;
+        // 与线上一致：只取最近活跃的 max_active 个 Session
+        let mut candidates: Vec<(i64, i64, &[f32])> = last_seen
+            .iter()
+            .filter(|(_, (ts, _))| *ts >= since)
+            .map(|(sid, (ts, sum))| (*ts, *sid, sum.as_slice()))
+            .collect();
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        candidates.truncate(max_active);
+
+        let mut best: Option<(i64, f32)> = None;
+        for (_, sid, centroid) in &candidates {
+            if centroid.len() != trace.embedding.len() || centroid.is_empty() {
+                continue;
+            }
+            let sim = VlmTask::cosine_similarity(&trace.embedding, centroid);
+            if best.map(|(_, b)| sim > b).unwrap_or(true) {
+                best = Some((*sid, sim));
+            }
+        }
+
+        let truth = candidates
+            .iter()
+            .any(|(_, sid, _)| *sid == trace.session_id)
+            .then_some(trace.session_id);
+        samples.push(RoutingSample {
+            best,
+            truth,
+            corrected: corrected.contains(&trace.trace_id),
+        });
+        let entry = last_seen
+            .entry(trace.session_id)
+            .or_insert_with(|| (trace.timestamp, vec![0.0; trace.embedding.len()]));
+        entry.0 = trace.timestamp;
+        if entry.1.len() == trace.embedding.len() {
+            for (acc, v) in entry.1.iter_mut().zip(&trace.embedding) {
+                *acc += v;
+            }
+        }
+    }
+
+    samples
+}
+
+/// 计算给定阈值下的路由准确率
+pub fn evaluate(samples: &[RoutingSample], threshold: f32) -> RoutingEvaluation {
+    let mut eval = RoutingEvaluation {
+        threshold,
+        ..Default::default()
+    };
+    for sample in samples {
+        let predicted = sample.predict(threshold);
+        let ok = predicted == sample.truth;
+        eval.total += 1;
+        if ok {
+            eval.correct += 1;
+        } else {
+            match (predicted, sample.truth) {
+                (Some(_), None) => eval.false_joins += 1,
+                (None, Some(_)) => eval.missed_joins += 1,
+                _ => eval.wrong_sessions += 1,
+            }
+        }
+        if sample.corrected {
+            eval.corrected_total += 1;
+            if ok {
+                eval.corrected_correct += 1;
+            }
+        }
+    }
+    if eval.total > 0 {
+        eval.accuracy = eval.correct as f32 / eval.total as f32;
+    }
+    eval
+}
+
+/// 搜索回放准确率最高的阈值（纠正样本加权），取最优区间的中点
+///
+/// 纠正样本不足 `MIN_CORRECTED_SAMPLES` 时返回 None。
+pub fn suggest_threshold(samples: &[RoutingSample]) -> Option<f32> {
+    let corrected = samples.iter().filter(|s| s.corrected).count() as u32;
+    if corrected < MIN_CORRECTED_SAMPLES {
+        return None;
+    }
+
+    let steps = ((THRESHOLD_MAX - THRESHOLD_MIN) / THRESHOLD_STEP).round() as u32;
+    // (得分, 区间起点, 区间终点)；得分相同的连续阈值合并为一个区间，取第一个最优区间
+    let mut best: Option<(u32, f32, f32)> = None;
+    let mut extending = false;
+    for step in 0..=steps {
+        let threshold = THRESHOLD_MIN + step as f32 * THRESHOLD_STEP;
+        let score: u32 = samples
+            .iter()
+            .filter(|s| s.predict(threshold) == s.truth)
+            .map(|s| if s.corrected { CORRECTED_WEIGHT } else { 1 })
+            .sum();
+        match best {
+            Some((best_score, start, _)) if score == best_score && extending => {
+                best = Some((best_score, start, threshold));
+            }
+            Some((best_score, _, _)) if score <= best_score => extending = false,
+            _ => {
+                best = Some((score, threshold, threshold));
+                extending = true;
+            }
+        }
+    }
+
+    best.map(|(_, start, end)| round_threshold((start + end) / 2.0))
+}
+
+/// 当前阈值向建议值小步靠拢
+pub fn adapt_threshold(current: f32, suggested: f32) -> f32 {
+    let delta = ((suggested - current) / 2.0).clamp(-MAX_ADJUSTMENT, MAX_ADJUSTMENT);
+    round_threshold((current + delta).clamp(THRESHOLD_MIN, THRESHOLD_MAX))
+}
+
+fn round_threshold(value: f32) -> f32 {
+    (value * 100.0).round() / 100.0
+}
+
+/// 从数据库读取 [start, end] 的标注历史并回放（只使用嵌入模型 `model` 的向量）
+pub fn load_samples(
+    db: &Database,
+    session_config: &SessionConfig,
+    model: &str,
+    start: i64,
+    end: i64,
+) -> anyhow::Result<Vec<RoutingSample>> {
+    // 超过空闲间隔的 Session 在线上已被关闭，不再参与路由
+    let window =
+        (session_config.active_window_ms as i64).min(session_config.gap_threshold_ms as i64);
+    // 往前多取一个活跃窗口，范围起点附近的 trace 也有候选
+    let traces: Vec<RoutingTrace> = db
+        .get_routing_history(start - window, end, model, MAX_HISTORY_TRACES)?
+        .into_iter()
+        .filter_map(|t| {
+            Some(RoutingTrace {
+                trace_id: t.trace_id,
+                timestamp: t.timestamp,
+                session_id: t.session_id,
+                embedding: VlmTask::deserialize_embedding(&t.embedding)?,
+            })
+        })
+        .collect();
+    let corrected = db.get_corrected_trace_ids(start - window)?;
+
+    let samples = build_samples(
+        &traces,
+        &corrected,
+        window,
+        session_config.max_active_sessions as usize,
+    );
+    // 只评估范围内的 trace
+    Ok(traces
+        .iter()
+        .zip(samples)
+        .filter(|(t, _)| t.timestamp >= start)
+        .map(|(_, s)| s)
+        .collect())
+}
+
+/// 生成路由评估报告
+pub fn evaluate_history(
+    db: &Database,
+    session_config: &SessionConfig,
+    model: &str,
+    start: i64,
+    end: i64,
+) -> anyhow::Result<RoutingReport> {
+    let samples = load_samples(db, session_config, model, start, end)?;
+    Ok(RoutingReport {
+        start_time: start,
+        end_time: end,
+        current: evaluate(&samples, session_config.similarity_threshold),
+        suggested: suggest_threshold(&samples).map(|t| evaluate(&samples, t)),
+    })
+}
+
+/// 根据最近的纠正回放结果计算新的 Session 相似度阈值
+///
+/// 未开启自适应、纠正样本不足或阈值无需变化时返回 None。
+pub fn adapted_threshold(
+    db: &Database,
+    session_config: &SessionConfig,
+    model: &str,
+    now: i64,
+) -> anyhow::Result<Option<f32>> {
+    if !session_config.adaptive_threshold {
+        return Ok(None);
+    }
+    let start = now - ROUTING_HISTORY_DAYS * 24 * 60 * 60 * 1000;
+    let samples = load_samples(db, session_config, model, start, now)?;
+    let Some(suggested) = suggest_threshold(&samples) else {
+        return Ok(None);
+    };
+
+    let current = session_config.similarity_threshold;
+    let adapted = adapt_threshold(current, suggested);
+    Ok(((adapted - current).abs() >= THRESHOLD_STEP / 2.0).then_some(adapted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(trace_id: i64, timestamp: i64, session_id: i64, embedding: [f32; 2]) -> RoutingTrace {
+        RoutingTrace {
+            trace_id,
+            timestamp,
+            session_id,
+            embedding: embedding.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_replay_and_threshold_search() {
+        // 两个交替的线程：A 的向量接近 x 轴，B 接近 y 轴；trace 5 被用户从 A 移到 B
+        let traces = vec![
+            trace(1, 1_000, 1, [1.0, 0.0]),
+            trace(2, 2_000, 2, [0.0, 1.0]),
+            trace(3, 3_000, 1, [1.0, 0.1]),
+            trace(4, 4_000, 2, [0.1, 1.0]),
+            trace(5, 5_000, 2, [0.6, 0.8]),
+            trace(6, 600_000, 3, [1.0, 0.0]),
+        ];
+        let corrected: HashSet<i64> = [5].into_iter().collect();
+        let samples = build_samples(&traces, &corrected, 60_000, 8);
+
+        assert_eq!(
+            samples[0],
+            RoutingSample {
+                best: None,
+                truth: None,
+                corrected: false
+            }
+        );
+        assert_eq!(samples[2].truth, Some(1));
+        assert_eq!(samples[4].truth, Some(2));
+        // 超出活跃窗口：没有候选，应新建
+        assert_eq!(samples[5].best, None);
+
+        let eval = evaluate(&samples, 0.9);
+        assert_eq!(eval.total, 6);
+        // trace 5 与 B 质心的相似度约 0.83，低于 0.9 → 漏归
+        assert_eq!(eval.missed_joins, 1);
+        assert_eq!((eval.corrected_total, eval.corrected_correct), (1, 0));
+
+        // trace 2 与 A 正交，阈值为 0 时被错误归入 A
+        let eval = evaluate(&samples, 0.0);
+        assert_eq!(eval.false_joins, 1);
+
+        // 纠正样本不足时不给建议；足够时落在全部正确的区间 (0, 0.83] 内
+        assert_eq!(suggest_threshold(&samples), None);
+        let corrected: HashSet<i64> = [3, 4, 5].into_iter().collect();
+        let samples = build_samples(&traces, &corrected, 60_000, 8);
+        let suggested = suggest_threshold(&samples).unwrap();
+        assert!((0.6..0.7).contains(&suggested), "{}", suggested);
+        assert_eq!(evaluate(&samples, suggested).accuracy, 1.0);
+
+        assert_eq!(adapt_threshold(0.78, 0.6), 0.73);
+        assert_eq!(adapt_threshold(0.78, 0.8), 0.79);
+    }
+}
@@ -1,23 +1,73 @@
 //! 定时摘要任务
 //!
-//! 定期生成屏幕活动摘要并存储到数据库。
-
-use crate::ai::summarizer::{GeneratedSummary, Summarizer, SummarizerConfig, SummaryType};
-use crate::db::models::{NewEntity, NewSummary};
+//! 按本地时间对齐的周期生成屏幕活动摘要并存储到数据库（周期划分见 `summary_schedule`）。
+//! 每个已结束的周期处理后记录到 `summary_periods`，启动时从记录处补齐错过的
+//! 短周期、每日、每周与每月摘要（回溯范围有上限）。
+//!
+//! 每周、每月与自定义范围摘要不直接读取 trace，而是汇总下级摘要与活动会话，
+//! 并在 `summary_links` 中记录层级关系。
+//!
+//! 每日摘要生成后重建实体共现关系；关系类型由 LLM 按需标注（使用 `[entities]` 模型）。
+//!
+//! 活动会话关闭后（见 `vlm_task`），由 LLM 生成最终标题、描述与回顾，
+//! 并把 `context_text` 压缩为回顾加最近的若干条记录。
+
+use crate::ai::prompts::PromptLibrary;
+use crate::ai::summarizer::{
+    activity_breakdown, merge_breakdowns, truncate_to_tokens, ActivityCount, ExtractedEntity,
+    GeneratedSummary, Summarizer, SummarizerConfig, SummaryType,
+};
+use crate::ai::{ParseFailure, ParseReport};
+use crate::config::{AppConfig, ModelTask};
+use crate::daemon::summary_schedule::{ScheduleTimezone, SummarySchedule};
+use crate::db::models::{ActivitySession, NewEntity, NewSummary, Summary};
 use crate::db::Database;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
 use tokio::sync::Mutex;
+use tokio::sync::{mpsc, watch};
 use tokio::time::interval;
 use tracing::{debug, error, info, warn};
 
 /// 摘要间隔（毫秒）- 15 分钟
 const DEFAULT_SUMMARY_INTERVAL_MS: u64 = 15 * 60 * 1000;
 
-/// 每日摘要时间 (UTC 小时) - 默认 23:00
-const DAILY_SUMMARY_HOUR: u32 = 23;
+/// 调度检查间隔（毫秒）- 周期边界后一分钟内生成
+const SCHEDULER_TICK_MS: u64 = 60 * 1000;
+
+/// 短周期摘要最多回溯（毫秒）- 1 天
+const SHORT_BACKFILL_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// 每日摘要最多回溯（毫秒）- 7 天
+const DAILY_BACKFILL_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+/// 每周摘要最多回溯（毫秒）- 5 周
+const WEEKLY_BACKFILL_MS: i64 = 5 * 7 * 24 * 60 * 60 * 1000;
+
+/// 每月摘要最多回溯（毫秒）- 约 2 个月
+const MONTHLY_BACKFILL_MS: i64 = 62 * 24 * 60 * 60 * 1000;
+
+/// 汇总摘要最多读取的活动会话数
+const MAX_ROLLUP_SESSIONS: u32 = 2000;
+
+/// 短周期与每日摘要最多读取的活动会话数
+const MAX_PERIOD_SESSIONS: u32 = 500;
+
+/// 单条 trace 最长计入的停留时长（毫秒）- 5 分钟
+const MAX_TRACE_SPAN_MS: i64 = 5 * 60 * 1000;
+
+/// 关系标注时每个相邻实体附带的共同会话数
+const RELATION_CONTEXT_SESSIONS: u32 = 3;
+
+/// 每轮最多调用 LLM 的次数（补齐大量周期时分摊到后续轮次）
+const MAX_SUMMARIES_PER_RUN: usize = 4;
+
+/// 每轮最多生成的会话回顾数
+const MAX_RECAPS_PER_RUN: u32 = 4;
+
+/// 只为最近结束的会话生成回顾，更早关闭的会话（如首次升级时的历史会话）跳过
+const RECAP_LOOKBACK_MS: i64 = 2 * 24 * 60 * 60 * 1000;
 
 /// 摘要任务配置
 #[derive(Debug, Clone)]
@@ -26,6 +76,8 @@ pub struct SummarizerTaskConfig {
     pub interval_ms: u64,
     /// LLM 配置
     pub llm_config: SummarizerConfig,
+    /// 实体关系标注的 LLM 配置
+    pub entities_config: SummarizerConfig,
     /// 是否启用
     pub enabled: bool,
 }
@@ -35,6 +87,7 @@ impl Default for SummarizerTaskConfig {
         Self {
             interval_ms: DEFAULT_SUMMARY_INTERVAL_MS,
             llm_config: SummarizerConfig::default(),
+            entities_config: SummarizerConfig::default(),
             enabled: true,
         }
     }
@@ -44,7 +97,11 @@ impl Default for SummarizerTaskConfig {
 pub struct SummarizerTask {
     db: Arc<Database>,
     summarizer: Arc<Mutex<Option<Summarizer>>>,
+    /// `[entities]` 模型客户端：实体提取与关系标注（按需初始化，模型配置变化时重置）
+    labeler: Arc<Mutex<Option<Summarizer>>>,
     config: SummarizerTaskConfig,
+    prompts: Arc<PromptLibrary>,
+    config_rx: Option<watch::Receiver<AppConfig>>,
     is_running: Arc<AtomicBool>,
     shutdown_tx: Option<mpsc::Sender<()>>,
 }
@@ -55,12 +112,27 @@ impl SummarizerTask {
         Self {
             db,
             summarizer: Arc::new(Mutex::new(None)),
+            labeler: Arc::new(Mutex::new(None)),
             config,
+            prompts: Arc::new(PromptLibrary::default()),
+            config_rx: None,
             is_running: Arc::new(AtomicBool::new(false)),
             shutdown_tx: None,
         }
     }
 
+    /// 使用共享的 Prompt 模板库
+    pub fn with_prompts(mut self, prompts: Arc<PromptLibrary>) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
+    /// 订阅配置变更，`summary` 修改后即时调整摘要周期，`summarizer` 模型修改后重新初始化
+    pub fn with_config_updates(mut self, config_rx: watch::Receiver<AppConfig>) -> Self {
+        self.config_rx = Some(config_rx);
+        self
+    }
+
     /// 启动摘要任务
     pub async fn start(&mut self) -> anyhow::Result<()> {
         if !self.config.enabled {
@@ -76,7 +148,8 @@ impl SummarizerTask {
         info!("Starting summarizer task...");
 
         // 初始化 Summarizer
-        let mut summarizer = Summarizer::new(self.config.llm_config.clone());
+        let mut summarizer =
+            Summarizer::new(self.config.llm_config.clone()).with_prompts(self.prompts.clone());
         match summarizer.initialize().await {
             Ok(_) => {
                 info!("Summarizer initialized: {}", summarizer.backend_name());
@@ -97,16 +170,31 @@ impl SummarizerTask {
         let is_running = self.is_running.clone();
         let db = self.db.clone();
         let summarizer = self.summarizer.clone();
-        let interval_ms = self.config.interval_ms;
-        let llm_config = self.config.llm_config.clone();
+        // 订阅了配置时以 `summary` 配置为准；
+        // 否则使用一个发送端已关闭的通道，对应分支永远不会触发
+        let mut schedule = self.schedule();
+        let mut config_rx = match &self.config_rx {
+            Some(rx) => {
+                let mut rx = rx.clone();
+                rx.mark_unchanged();
+                rx
+            }
+            None => watch::channel(AppConfig::default()).1,
+        };
+        let mut llm_config = self.config.llm_config.clone();
+        let labeler = self.labeler.clone();
+        let mut entities_config = self.entities_config();
+        let prompts = self.prompts.clone();
 
         is_running.store(true, Ordering::SeqCst);
 
         tokio::spawn(async move {
-            let mut ticker = interval(Duration::from_millis(interval_ms));
-            let mut last_daily_summary_day: Option<u32> = None;
+            let mut ticker = interval(Duration::from_millis(SCHEDULER_TICK_MS));
 
-            info!("Summarizer task loop started (interval: {}ms)", interval_ms);
+            info!(
+                "Summarizer task loop started (period: {}ms)",
+                schedule.interval_ms()
+            );
 
             loop {
                 tokio::select! {
@@ -114,12 +202,40 @@ impl SummarizerTask {
                         info!("Summarizer task received shutdown signal");
                         break;
                     }
+                    Ok(()) = config_rx.changed() => {
+                        let (new_schedule, new_llm_config, new_entities_config) = {
+                            let config = config_rx.borrow_and_update();
+                            (
+                                SummarySchedule::from_config(&config.summary),
+                                config.task_model(ModelTask::Summarizer),
+                                config.task_model(ModelTask::Entities),
+                            )
+                        };
+                        if new_llm_config != llm_config {
+                            // 下一轮按新模型重新初始化
+                            info!("Summarizer model changed: {}", new_llm_config.model);
+                            llm_config = new_llm_config;
+                            *summarizer.lock().await = None;
+                        }
+                        if new_entities_config != entities_config {
+                            // 下次使用时按新模型重新初始化
+                            info!("Entities model changed: {}", new_entities_config.model);
+                            entities_config = new_entities_config;
+                            *labeler.lock().await = None;
+                        }
+                        if new_schedule != schedule {
+                            // 已完成的周期保持不变，新周期从记录处按新配置继续划分
+                            schedule = new_schedule;
+                            info!("Summary schedule changed (period: {}ms)", schedule.interval_ms());
+                        }
+                    }
                     _ = ticker.tick() => {
                         // 尝试重新初始化 summarizer（如果之前失败）
                         {
                             let mut guard = summarizer.lock().await;
                             if guard.is_none() {
-                                let mut new_summarizer = Summarizer::new(llm_config.clone());
+                                let mut new_summarizer = Summarizer::new(llm_config.clone())
+                                    .with_prompts(prompts.clone());
                                 if new_summarizer.initialize().await.is_ok() {
                                     info!("Summarizer re-initialized successfully");
                                     *guard = Some(new_summarizer);
@@ -130,41 +246,11 @@ impl SummarizerTask {
                             }
                         }
 
-                        // 生成短周期摘要
-                        let now = chrono::Utc::now();
-                        let end_time = now.timestamp_millis();
-                        let start_time = end_time - interval_ms as i64;
-
-                        if let Err(e) = Self::generate_short_summary(
-                            &db,
-                            &summarizer,
-                            start_time,
-                            end_time,
-                        ).await {
-                            error!("Failed to generate short summary: {}", e);
+                        if let Err(e) = Self::run_due_periods(&db, &summarizer, &schedule).await {
+                            error!("Failed to generate scheduled summaries: {}", e);
                         }
-
-                        // 检查是否需要生成每日摘要
-                        let current_hour = now.hour();
-                        let current_day = now.day();
-
-                        if current_hour == DAILY_SUMMARY_HOUR {
-                            let should_generate = match last_daily_summary_day {
-                                Some(day) => day != current_day,
-                                None => true,
-                            };
-
-                            if should_generate {
-                                info!("Generating daily summary...");
-                                if let Err(e) = Self::generate_daily_summary(
-                                    &db,
-                                    &summarizer,
-                                ).await {
-                                    error!("Failed to generate daily summary: {}", e);
-                                } else {
-                                    last_daily_summary_day = Some(current_day);
-                                }
-                            }
+                        if let Err(e) = Self::run_session_recaps(&db, &summarizer).await {
+                            error!("Failed to generate session recaps: {}", e);
                         }
                     }
                 }
@@ -191,111 +277,394 @@ impl SummarizerTask {
         self.is_running.load(Ordering::SeqCst)
     }
 
-    /// 生成短周期摘要（15分钟）
-    async fn generate_short_summary(
+    /// 补齐所有已结束但尚未处理的周期：先短周期，再每日（当天短周期全部完成后）
+    async fn run_due_periods(
         db: &Arc<Database>,
         summarizer: &Arc<Mutex<Option<Summarizer>>>,
-        start_time: i64,
-        end_time: i64,
+        schedule: &SummarySchedule,
     ) -> anyhow::Result<()> {
-        // 获取时间范围内的 traces
-        let traces = db.get_traces(start_time, end_time, 100, 0)?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut generated = 0;
+
+        let lookback = schedule.short_period_containing(now - SHORT_BACKFILL_MS).0;
+        let from = db
+            .get_summary_watermark("short")?
+            .map_or(lookback, |w| w.max(lookback));
+        for (start, end) in schedule.completed_short_periods(from, now) {
+            if generated >= MAX_SUMMARIES_PER_RUN {
+                return Ok(());
+            }
+            let summary_id = Self::skip_unparsed(
+                Self::generate_session_summary(db, summarizer, SummaryType::Short, start, end)
+                    .await,
+            )?;
+            db.mark_summary_period("short", start, end, summary_id)?;
+            if summary_id.is_some() {
+                generated += 1;
+            }
+        }
 
-        if traces.is_empty() {
-            debug!("No traces in time range, skipping summary");
+        let short_done = db.get_summary_watermark("short")?.unwrap_or(0).min(now);
+        let mut days_done = 0;
+        let lookback = schedule.day_containing(now - DAILY_BACKFILL_MS).0;
+        let from = db
+            .get_summary_watermark("daily")?
+            .map_or(lookback, |w| w.max(lookback));
+        for (start, end) in schedule.completed_days(from, short_done) {
+            if generated >= MAX_SUMMARIES_PER_RUN {
+                return Ok(());
+            }
+            info!("Generating daily summary...");
+            let summary_id = Self::skip_unparsed(
+                Self::generate_session_summary(db, summarizer, SummaryType::Daily, start, end)
+                    .await,
+            )?;
+            db.mark_summary_period("daily", start, end, summary_id)?;
+            days_done += 1;
+            if summary_id.is_some() {
+                generated += 1;
+            }
+        }
+        if days_done > 0 {
+            let relations = db.rebuild_entity_relations(now)?;
+            debug!("Rebuilt {} entity relations", relations);
+        }
+
+        // 每周、每月摘要在覆盖的每日摘要全部完成后再汇总
+        let daily_done = db.get_summary_watermark("daily")?.unwrap_or(0).min(now);
+        let rollups = [
+            (
+                SummaryType::Weekly,
+                schedule.week_containing(now - WEEKLY_BACKFILL_MS).0,
+            ),
+            (
+                SummaryType::Monthly,
+                schedule.month_containing(now - MONTHLY_BACKFILL_MS).0,
+            ),
+        ];
+        for (summary_type, lookback) in rollups {
+            let name = summary_type.as_str();
+            let from = db
+                .get_summary_watermark(name)?
+                .map_or(lookback, |w| w.max(lookback));
+            let periods = match summary_type {
+                SummaryType::Weekly => schedule.completed_weeks(from, daily_done),
+                _ => schedule.completed_months(from, daily_done),
+            };
+            for (start, end) in periods {
+                if generated >= MAX_SUMMARIES_PER_RUN {
+                    return Ok(());
+                }
+                info!("Generating {} summary...", name);
+                let summary_id = Self::skip_unparsed(
+                    Self::generate_rollup_summary(db, summarizer, summary_type, start, end).await,
+                )?;
+                db.mark_summary_period(name, start, end, summary_id)?;
+                if summary_id.is_some() {
+                    generated += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 为最近关闭的会话生成回顾并压缩 context_text
+    async fn run_session_recaps(
+        db: &Arc<Database>,
+        summarizer: &Arc<Mutex<Option<Summarizer>>>,
+    ) -> anyhow::Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let sessions =
+            db.get_sessions_pending_recap(now - RECAP_LOOKBACK_MS, MAX_RECAPS_PER_RUN)?;
+        if sessions.is_empty() {
             return Ok(());
         }
 
+        let guard = summarizer.lock().await;
+        let summarizer_ref = guard
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Summarizer not initialized"))?;
+        for session in sessions {
+            // 回顾无法解析时不关闭会话，保留完整的 context_text，下一轮重试
+            let result = Self::record_model_parse_status(
+                db,
+                "session_recap",
+                &session.id.to_string(),
+                summarizer_ref.generate_session_recap(&session).await,
+            );
+            let recap = match result {
+                Err(e) if e.is::<ParseFailure>() => {
+                    warn!("Session {} recap not saved: {}", session.id, e);
+                    continue;
+                }
+                result => result?,
+            };
+            let recap_text = recap.recap.trim();
+            if recap_text.is_empty() {
+                warn!("Empty recap for session {}, skipping", session.id);
+                continue;
+            }
+            db.finalize_activity_session(
+                session.id,
+                recap.title.as_deref(),
+                recap.description.as_deref(),
+                recap_text,
+            )?;
+            info!("Session {} closed with recap", session.id);
+        }
+        Ok(())
+    }
+
+    /// 由周期内的活动会话生成短周期或每日摘要，返回摘要 ID（周期内无 trace 时返回 `None`）
+    async fn generate_session_summary(
+        db: &Arc<Database>,
+        summarizer: &Arc<Mutex<Option<Summarizer>>>,
+        summary_type: SummaryType,
+        start_time: i64,
+        end_time: i64,
+    ) -> anyhow::Result<Option<i64>> {
+        // 周期为左闭右开区间
+        let durations = db.get_trace_durations(start_time, end_time, MAX_TRACE_SPAN_MS)?;
+        if durations.is_empty() {
+            debug!("No traces in time range, skipping summary");
+            return Ok(None);
+        }
+
+        let mut sessions =
+            db.get_activity_sessions(start_time, end_time - 1, None, MAX_PERIOD_SESSIONS, 0)?;
+        sessions.reverse();
+        let trace_count: u32 = durations.iter().map(|d| d.trace_count).sum();
+
         info!(
-            "Generating short summary for {} traces ({} to {})",
-            traces.len(),
+            "Generating {} summary from {} sessions, {} traces ({} to {})",
+            summary_type.as_str(),
+            sessions.len(),
+            trace_count,
             start_time,
             end_time
         );
 
-        // 生成摘要
         let guard = summarizer.lock().await;
         let summarizer_ref = guard
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Summarizer not initialized"))?;
 
-        let summary = summarizer_ref
-            .generate_summary(&traces, SummaryType::Short)
-            .await?;
+        let summary = Self::record_parse_status(
+            db,
+            summary_type,
+            start_time,
+            end_time,
+            summarizer_ref
+                .generate_summary(&sessions, &durations, summary_type)
+                .await,
+        )?;
 
-        // 保存摘要
-        Self::save_summary(
+        let summary_id = Self::save_summary(
             db,
             start_time,
             end_time,
-            "short",
+            summary_type.as_str(),
             &summary,
-            traces.len() as u32,
+            trace_count,
         )?;
 
-        // 保存实体
-        Self::save_entities(db, &summary.entities, end_time)?;
+        Self::save_entities(db, &summary.entities, start_time, end_time)?;
 
-        info!("Short summary saved successfully");
-        Ok(())
+        info!("{} summary saved successfully", summary_type.as_str());
+        Ok(Some(summary_id))
     }
 
-    /// 生成每日摘要
-    async fn generate_daily_summary(
+    /// 汇总生成每周、每月或自定义范围摘要，返回摘要 ID（范围内既无下级摘要也无会话时返回 `None`）
+    async fn generate_rollup_summary(
         db: &Arc<Database>,
         summarizer: &Arc<Mutex<Option<Summarizer>>>,
-    ) -> anyhow::Result<()> {
-        let now = chrono::Utc::now();
-        let today_start = now
-            .date_naive()
-            .and_hms_opt(0, 0, 0)
-            .unwrap()
-            .and_utc()
-            .timestamp_millis();
-        let today_end = now.timestamp_millis();
-
-        // 获取今日所有短摘要
-        let short_summaries = db.get_summaries(today_start, today_end, Some("short"), 100)?;
-
-        if short_summaries.is_empty() {
-            info!("No short summaries for today, skipping daily summary");
-            return Ok(());
+        summary_type: SummaryType,
+        start_time: i64,
+        end_time: i64,
+    ) -> anyhow::Result<Option<i64>> {
+        // 范围为左闭右开区间；下级摘要已覆盖的时段不再重复列出会话
+        let children = Self::collect_child_summaries(db, summary_type, start_time, end_time)?;
+        let mut sessions: Vec<ActivitySession> = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        let spans = uncovered_spans(
+            start_time,
+            end_time,
+            children.iter().map(|c| (c.start_time, c.end_time)),
+        );
+        for (span_start, span_end) in spans {
+            for session in
+                db.get_activity_sessions(span_start, span_end - 1, None, MAX_ROLLUP_SESSIONS, 0)?
+            {
+                if seen.insert(session.id) {
+                    sessions.push(session);
+                }
+            }
+        }
+        // 超出上限时保留最近的会话
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.end_time));
+        sessions.truncate(MAX_ROLLUP_SESSIONS as usize);
+        sessions.reverse();
+
+        if children.is_empty() && sessions.is_empty() {
+            debug!(
+                "No summaries or sessions in range, skipping {} summary",
+                summary_type.as_str()
+            );
+            return Ok(None);
         }
 
-        // 获取今日 traces 数量
-        let traces = db.get_traces(today_start, today_end, 1000, 0)?;
+        let mut items: Vec<String> = children.iter().map(format_child_summary).collect();
+        items.extend(sessions.iter().map(|session| {
+            let mut line = format!(
+                "[session {}-{}] {}",
+                format_time(session.start_time, "%m-%d %H:%M"),
+                format_time(session.end_time, "%H:%M"),
+                session.app_name
+            );
+            if let Some(title) = session.title.as_deref().filter(|t| !t.is_empty()) {
+                line.push_str(&format!(" · {}", title));
+            }
+            if let Some(description) = session.description.as_deref().filter(|d| !d.is_empty()) {
+                line.push_str(&format!(" — {}", description));
+            }
+            line
+        }));
 
         info!(
-            "Generating daily summary from {} short summaries, {} traces",
-            short_summaries.len(),
-            traces.len()
+            "Generating {} summary from {} summaries, {} sessions",
+            summary_type.as_str(),
+            children.len(),
+            sessions.len()
         );
 
-        // 使用 LLM 生成每日摘要
         let guard = summarizer.lock().await;
         let summarizer_ref = guard
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Summarizer not initialized"))?;
 
-        let summary = summarizer_ref
-            .generate_summary(&traces, SummaryType::Daily)
-            .await?;
+        let mut summary = Self::record_parse_status(
+            db,
+            summary_type,
+            start_time,
+            end_time,
+            summarizer_ref.generate_rollup(&items, summary_type).await,
+        )?;
 
-        // 保存每日摘要
-        Self::save_summary(
+        // 活动类型分布优先由 trace 停留时长统计；trace 已按保留策略清理时合并下级摘要的分布
+        let durations = db.get_trace_durations(start_time, end_time, MAX_TRACE_SPAN_MS)?;
+        let trace_count = if durations.is_empty() {
+            summary.activity_breakdown =
+                merge_breakdowns(children.iter().flat_map(child_breakdown));
+            // 下级摘要互不重叠，会话只来自未覆盖的时段，两者相加不重复计数
+            children
+                .iter()
+                .map(|c| c.trace_count.unwrap_or(0))
+                .sum::<u32>()
+                + sessions.iter().map(|s| s.trace_count).sum::<u32>()
+        } else {
+            summary.activity_breakdown = activity_breakdown(&durations);
+            durations.iter().map(|d| d.trace_count).sum()
+        };
+        let summary_id = Self::save_summary(
             db,
-            today_start,
-            today_end,
-            "daily",
+            start_time,
+            end_time,
+            summary_type.as_str(),
             &summary,
-            traces.len() as u32,
+            trace_count,
         )?;
 
-        // 保存实体
-        Self::save_entities(db, &summary.entities, today_end)?;
+        let child_ids: Vec<i64> = children.iter().map(|c| c.id).collect();
+        db.link_summary_children(summary_id, &child_ids)?;
 
-        info!("Daily summary saved successfully");
-        Ok(())
+        Self::save_entities(db, &summary.entities, start_time, end_time)?;
+
+        info!("{} summary saved successfully", summary_type.as_str());
+        Ok(Some(summary_id))
+    }
+
+    /// 记录摘要输出的解析状态（未请求模型的空摘要不记录），原样返回结果
+    fn record_parse_status(
+        db: &Arc<Database>,
+        summary_type: SummaryType,
+        start_time: i64,
+        end_time: i64,
+        result: anyhow::Result<GeneratedSummary>,
+    ) -> anyhow::Result<GeneratedSummary> {
+        let report = match &result {
+            Ok(summary) if summary.parse.attempts > 0 => Some(&summary.parse),
+            Err(e) => e.downcast_ref::<ParseFailure>().map(|f| &f.report),
+            _ => None,
+        };
+        if let Some(report) = report {
+            if let Err(e) =
+                db.save_summary_parse_status(summary_type.as_str(), start_time, end_time, report)
+            {
+                warn!("Failed to save summary parse status: {}", e);
+            }
+        }
+        result
+    }
+
+    /// 记录会话回顾、实体提取与关系标注的输出解析状态，返回解析结果
+    fn record_model_parse_status<T>(
+        db: &Database,
+        task: &str,
+        subject: &str,
+        result: anyhow::Result<(T, ParseReport)>,
+    ) -> anyhow::Result<T> {
+        let report = match &result {
+            Ok((_, report)) => Some(report),
+            Err(e) => e.downcast_ref::<ParseFailure>().map(|f| &f.report),
+        };
+        if let Some(report) = report {
+            if let Err(e) = db.save_model_parse_status(task, subject, report) {
+                warn!("Failed to save {} parse status: {}", task, e);
+            }
+        }
+        result.map(|(value, _)| value)
+    }
+
+    /// 摘要输出无法解析时放弃该周期（状态已记为 failed），不保存原始输出，也不在每轮重复请求
+    fn skip_unparsed(result: anyhow::Result<Option<i64>>) -> anyhow::Result<Option<i64>> {
+        match result {
+            Err(e) if e.is::<ParseFailure>() => {
+                warn!("Skipping summary period: {}", e);
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    /// 选取覆盖范围的下级摘要：优先使用较高层级，缺口处再用较低层级补齐，互不重叠
+    fn collect_child_summaries(
+        db: &Arc<Database>,
+        summary_type: SummaryType,
+        start_time: i64,
+        end_time: i64,
+    ) -> anyhow::Result<Vec<Summary>> {
+        let levels: &[&str] = match summary_type {
+            SummaryType::Custom { .. } => &["monthly", "weekly", "daily", "short"],
+            _ => &["daily", "short"],
+        };
+
+        let mut selected: Vec<Summary> = Vec::new();
+        for level in levels {
+            let mut candidates = db.get_summaries(start_time, end_time, Some(level), 10_000)?;
+            candidates.reverse();
+            for candidate in candidates {
+                let overlaps = selected.iter().any(|s| {
+                    s.start_time < candidate.end_time && candidate.start_time < s.end_time
+                });
+                if !overlaps {
+                    selected.push(candidate);
+                }
+            }
+        }
+
+        selected.sort_by_key(|s| s.start_time);
+        Ok(selected)
     }
 
     /// 保存摘要到数据库
@@ -312,6 +681,7 @@ impl SummarizerTask {
             "links": summary.links,
             "activity_breakdown": summary.activity_breakdown,
             "entities": summary.entities,
+            "prompt_version": summary.prompt_version,
         }))?;
 
         let new_summary = NewSummary {
@@ -326,24 +696,28 @@ impl SummarizerTask {
         db.insert_summary(&new_summary)
     }
 
-    /// 保存实体到数据库
+    /// 保存摘要中的实体，首末出现时间取摘要时间范围内 VLM 已关联的 trace
     fn save_entities(
         db: &Arc<Database>,
-        entities: &[crate::ai::summarizer::ExtractedEntity],
-        timestamp: i64,
+        entities: &[ExtractedEntity],
+        start_time: i64,
+        end_time: i64,
     ) -> anyhow::Result<()> {
         for entity in entities {
             let new_entity = NewEntity {
-                name: entity.name.clone(),
+                name: entity.name.trim().to_string(),
                 entity_type: entity.entity_type.clone(),
-                first_seen: timestamp,
-                last_seen: timestamp,
+                first_seen: end_time,
+                last_seen: end_time,
                 metadata: Some(serde_json::to_string(&serde_json::json!({
                     "confidence": entity.confidence,
                 }))?),
             };
+            if new_entity.name.is_empty() {
+                continue;
+            }
 
-            if let Err(e) = db.upsert_entity(&new_entity) {
+            if let Err(e) = db.record_summary_entity(&new_entity, start_time, end_time) {
                 warn!("Failed to save entity '{}': {}", entity.name, e);
             }
         }
@@ -353,29 +727,282 @@ impl SummarizerTask {
 
     /// 手动触发摘要生成
     pub async fn trigger_summary(&self, summary_type: SummaryType) -> anyhow::Result<()> {
-        // 确保 Summarizer 已初始化
-        {
-            let mut guard = self.summarizer.lock().await;
-            if guard.is_none() {
-                info!("Initializing summarizer for manual trigger...");
-                let mut new_summarizer = Summarizer::new(self.config.llm_config.clone());
-                new_summarizer.initialize().await?;
-                info!("Summarizer initialized: {}", new_summarizer.backend_name());
-                *guard = Some(new_summarizer);
-            }
-        }
+        self.ensure_summarizer().await?;
 
-        let now = chrono::Utc::now();
+        // 手动触发覆盖截至当前的区间，不记录为已完成周期，定时任务仍会生成完整周期
+        let now = chrono::Utc::now().timestamp_millis();
+        let schedule = self.schedule();
 
         match summary_type {
             SummaryType::Short => {
-                let end_time = now.timestamp_millis();
-                let start_time = end_time - self.config.interval_ms as i64;
-                Self::generate_short_summary(&self.db, &self.summarizer, start_time, end_time).await
+                let start_time = now - schedule.interval_ms();
+                Self::generate_session_summary(
+                    &self.db,
+                    &self.summarizer,
+                    summary_type,
+                    start_time,
+                    now,
+                )
+                .await?;
+            }
+            SummaryType::Daily => {
+                let (day_start, _) = schedule.day_containing(now);
+                Self::generate_session_summary(
+                    &self.db,
+                    &self.summarizer,
+                    summary_type,
+                    day_start,
+                    now,
+                )
+                .await?;
+            }
+            SummaryType::Weekly | SummaryType::Monthly => {
+                let (period_start, _) = if summary_type == SummaryType::Weekly {
+                    schedule.week_containing(now)
+                } else {
+                    schedule.month_containing(now)
+                };
+                Self::generate_rollup_summary(
+                    &self.db,
+                    &self.summarizer,
+                    summary_type,
+                    period_start,
+                    now,
+                )
+                .await?;
+            }
+            SummaryType::Custom { start, end } => {
+                Self::generate_rollup_summary(&self.db, &self.summarizer, summary_type, start, end)
+                    .await?;
             }
-            SummaryType::Daily => Self::generate_daily_summary(&self.db, &self.summarizer).await,
         }
+        Ok(())
     }
+
+    /// 用 LLM 标注实体与权重最高的 `limit` 个相邻实体的关系类型，返回标注数
+    pub async fn label_entity_relations(
+        &self,
+        entity_id: i64,
+        limit: u32,
+    ) -> anyhow::Result<usize> {
+        use crate::ai::entity::normalize_entity_name;
+
+        let entity = self
+            .db
+            .get_entity_by_id(entity_id)?
+            .ok_or_else(|| anyhow::anyhow!("Entity {} not found", entity_id))?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let neighbors = self.db.get_entity_neighbors(entity_id, None, limit, now)?;
+        if neighbors.is_empty() {
+            return Ok(0);
+        }
+
+        let mut context = String::new();
+        for neighbor in &neighbors {
+            let titles = self.db.get_shared_session_titles(
+                entity_id,
+                neighbor.entity.id,
+                RELATION_CONTEXT_SESSIONS,
+            )?;
+            context.push_str(&format!(
+                "- {} [{}] traces={}, sessions={}",
+                neighbor.entity.name,
+                neighbor.entity.entity_type,
+                neighbor.relation.trace_count,
+                neighbor.relation.session_count
+            ));
+            if !titles.is_empty() {
+                context.push_str(&format!(": {}", titles.join("; ")));
+            }
+            context.push('\n');
+        }
+
+        let context = truncate_to_tokens(&context, self.entities_config().context_tokens as usize);
+        let labeler = self.ensure_labeler().await?;
+        let labels = Self::record_model_parse_status(
+            &self.db,
+            "entity_relations",
+            &entity_id.to_string(),
+            labeler.label_relations(&entity.name, &context).await,
+        )?;
+
+        let mut labelled = 0;
+        for label in labels {
+            let key = normalize_entity_name(&label.entity);
+            let relation_type = label.relation_type.trim().to_lowercase();
+            let Some(neighbor) = neighbors
+                .iter()
+                .find(|n| normalize_entity_name(&n.entity.name) == key)
+            else {
+                continue;
+            };
+            if relation_type.is_empty() {
+                continue;
+            }
+            if self.db.set_entity_relation_type(
+                entity_id,
+                neighbor.entity.id,
+                Some(&relation_type),
+            )? {
+                labelled += 1;
+            }
+        }
+        info!(
+            "Labelled {} relations of entity '{}'",
+            labelled, entity.name
+        );
+        Ok(labelled)
+    }
+
+    /// 确保 Summarizer 已初始化（手动触发时使用）
+    async fn ensure_summarizer(&self) -> anyhow::Result<()> {
+        let mut guard = self.summarizer.lock().await;
+        if guard.is_none() {
+            info!("Initializing summarizer for manual trigger...");
+            let mut new_summarizer =
+                Summarizer::new(self.config.llm_config.clone()).with_prompts(self.prompts.clone());
+            new_summarizer.initialize().await?;
+            info!("Summarizer initialized: {}", new_summarizer.backend_name());
+            *guard = Some(new_summarizer);
+        }
+        Ok(())
+    }
+
+    /// 用 `[entities]` 模型从文本中提取实体
+    pub async fn extract_entities(&self, text: &str) -> anyhow::Result<Vec<ExtractedEntity>> {
+        use sha2::{Digest, Sha256};
+
+        let labeler = self.ensure_labeler().await?;
+        // 按文本哈希记录解析状态
+        let subject: String = format!("{:x}", Sha256::digest(text.as_bytes()))
+            .chars()
+            .take(16)
+            .collect();
+        Self::record_model_parse_status(
+            &self.db,
+            "entity_extraction",
+            &subject,
+            labeler.extract_entities(text).await,
+        )
+    }
+
+    /// 确保 `[entities]` 模型客户端已按当前配置初始化（任务循环未运行时也不会沿用旧模型）
+    async fn ensure_labeler(&self) -> anyhow::Result<Summarizer> {
+        let config = self.entities_config();
+        let mut guard = self.labeler.lock().await;
+        if let Some(labeler) = guard.as_ref().filter(|l| *l.config() == config) {
+            return Ok(labeler.clone());
+        }
+        let model = config.model.clone();
+        let mut labeler = Summarizer::new(config).with_prompts(self.prompts.clone());
+        labeler.initialize().await?;
+        info!(
+            "Entity labeler initialized: {} ({})",
+            labeler.backend_name(),
+            model
+        );
+        *guard = Some(labeler.clone());
+        Ok(labeler)
+    }
+
+    /// 当前配置对应的 `[entities]` 模型
+    fn entities_config(&self) -> SummarizerConfig {
+        match &self.config_rx {
+            Some(rx) => rx.borrow().task_model(ModelTask::Entities),
+            None => self.config.entities_config.clone(),
+        }
+    }
+
+    /// 当前配置对应的周期表
+    fn schedule(&self) -> SummarySchedule {
+        match &self.config_rx {
+            Some(rx) => SummarySchedule::from_config(&rx.borrow().summary),
+            None => SummarySchedule::new(
+                (self.config.interval_ms / 60_000) as u32,
+                ScheduleTimezone::System,
+            ),
+        }
+    }
+}
+
+/// `[start, end)` 中未被 `covered`（按开始时间排序、互不重叠的左闭右开区间）覆盖的时段
+fn uncovered_spans(
+    start: i64,
+    end: i64,
+    covered: impl IntoIterator<Item = (i64, i64)>,
+) -> Vec<(i64, i64)> {
+    let mut spans = Vec::new();
+    let mut cursor = start;
+    for (covered_start, covered_end) in covered {
+        if covered_start > cursor {
+            spans.push((cursor, covered_start.min(end)));
+        }
+        cursor = cursor.max(covered_end);
+    }
+    if cursor < end {
+        spans.push((cursor, end));
+    }
+    spans
 }
 
-use chrono::{Datelike, Timelike};
+/// 格式化本地时间
+fn format_time(ts: i64, fmt: &str) -> String {
+    chrono::DateTime::from_timestamp_millis(ts)
+        .map(|t| t.with_timezone(&chrono::Local).format(fmt).to_string())
+        .unwrap_or_default()
+}
+
+/// 下级摘要记录的活动类型分布
+fn child_breakdown(summary: &Summary) -> Vec<ActivityCount> {
+    summary
+        .structured_data
+        .as_deref()
+        .and_then(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .and_then(|data| serde_json::from_value(data.get("activity_breakdown")?.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// 下级摘要在汇总上下文中的一行
+fn format_child_summary(summary: &Summary) -> String {
+    let topics = summary
+        .structured_data
+        .as_deref()
+        .and_then(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .and_then(|data| {
+            data.get("topics")?.as_array().map(|topics| {
+                topics
+                    .iter()
+                    .filter_map(|t| t.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+        })
+        .unwrap_or_default();
+
+    let mut line = format!(
+        "[summary:{} {}–{}] {}",
+        summary.summary_type,
+        format_time(summary.start_time, "%m-%d %H:%M"),
+        format_time(summary.end_time, "%m-%d %H:%M"),
+        summary.content.trim()
+    );
+    if !topics.is_empty() {
+        line.push_str(&format!(" [{}]", topics));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uncovered_spans() {
+        assert_eq!(uncovered_spans(0, 100, []), vec![(0, 100)]);
+        assert_eq!(
+            uncovered_spans(0, 100, [(10, 20), (20, 50), (70, 100)]),
+            vec![(0, 10), (50, 70)]
+        );
+        assert!(uncovered_spans(0, 100, [(0, 60), (60, 100)]).is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+//! 摘要周期计算
+//!
+//! 短周期摘要按本地时间从零点起每 `interval_min` 分钟对齐（15 分钟即 00:00、00:15 …），
+//! 最后一个周期在零点截断，不跨天；每日、每周（周一开始）、每月摘要按本地日历对齐。
+//! 时区取自 `summary.timezone`，留空使用系统时区。
+
+use crate::config::SummaryConfig;
+use chrono::{Datelike, Duration, Local, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use tracing::warn;
+
+/// 周期对齐使用的时区
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleTimezone {
+    /// 系统时区
+    System,
+    /// 指定的 IANA 时区
+    Named(Tz),
+}
+
+impl ScheduleTimezone {
+    /// 解析时区名称（空字符串或无法识别时使用系统时区）
+    pub fn parse(name: &str) -> Self {
+        let name = name.trim();
+        if name.is_empty() {
+            return Self::System;
+        }
+        match name.parse::<Tz>() {
+            Ok(tz) => Self::Named(tz),
+            Err(_) => {
+                warn!("Unknown time zone '{}', falling back to system", name);
+                Self::System
+            }
+        }
+    }
+
+    /// 时间戳所在的本地日期
+    fn local_date(&self, ts: i64) -> NaiveDate {
+        let utc = Utc
+            .timestamp_millis_opt(ts)
+            .single()
+            .unwrap_or_else(Utc::now);
+        match self {
+            Self::System => utc.with_timezone(&Local).date_naive(),
+            Self::Named(tz) => utc.with_timezone(tz).date_naive(),
+        }
+    }
+
+    /// 本地日期零点对应的时间戳
+    fn day_start(&self, date: NaiveDate) -> i64 {
+        match self {
+            Self::System => local_midnight(&Local, date),
+            Self::Named(tz) => local_midnight(tz, date),
+        }
+    }
+}
+
+/// 本地零点（夏令时跳变导致零点不存在时，取之后最早的有效整点）
+fn local_midnight<Z: TimeZone>(tz: &Z, date: NaiveDate) -> i64 {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("valid midnight");
+    (0..=3)
+        .find_map(|h| {
+            tz.from_local_datetime(&(midnight + Duration::hours(h)))
+                .earliest()
+        })
+        .map(|dt| dt.timestamp_millis())
+        .unwrap_or_else(|| midnight.and_utc().timestamp_millis())
+}
+
+/// 摘要周期表
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SummarySchedule {
+    interval_ms: i64,
+    timezone: ScheduleTimezone,
+}
+
+impl SummarySchedule {
+    /// 创建周期表
+    pub fn new(interval_min: u32, timezone: ScheduleTimezone) -> Self {
+        Self {
+            interval_ms: interval_min.max(1) as i64 * 60 * 1000,
+            timezone,
+        }
+    }
+
+    /// 从摘要配置创建
+    pub fn from_config(config: &SummaryConfig) -> Self {
+        Self::new(
+            config.interval_min,
+            ScheduleTimezone::parse(&config.timezone),
+        )
+    }
+
+    /// 短周期长度（毫秒）
+    pub fn interval_ms(&self) -> i64 {
+        self.interval_ms
+    }
+
+    /// 包含 `ts` 的本地自然日 `[start, end)`
+    pub fn day_containing(&self, ts: i64) -> (i64, i64) {
+        let date = self.timezone.local_date(ts);
+        let start = self.timezone.day_start(date);
+        let end = date
+            .succ_opt()
+            .map(|next| self.timezone.day_start(next))
+            .unwrap_or(start + 24 * 60 * 60 * 1000);
+        (start, end)
+    }
+
+    /// 包含 `ts` 的短周期 `[start, end)`（不跨越本地零点）
+    pub fn short_period_containing(&self, ts: i64) -> (i64, i64) {
+        let (day_start, day_end) = self.day_containing(ts);
+        let index = (ts - day_start) / self.interval_ms;
+        let start = day_start + index * self.interval_ms;
+        (start, (start + self.interval_ms).min(day_end))
+    }
+
+    /// `from` 之后在 `now` 之前已结束的短周期；`from` 落在周期中间时首个周期从 `from` 开始
+    pub fn completed_short_periods(&self, from: i64, now: i64) -> Vec<(i64, i64)> {
+        let mut periods = Vec::new();
+        let mut cursor = from;
+        loop {
+            let (_, end) = self.short_period_containing(cursor);
+            if end > now {
+                break;
+            }
+            periods.push((cursor, end));
+            cursor = end;
+        }
+        periods
+    }
+
+    /// 包含 `ts` 的本地自然周 `[start, end)`（周一开始）
+    pub fn week_containing(&self, ts: i64) -> (i64, i64) {
+        let date = self.timezone.local_date(ts);
+        let monday = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+        (
+            self.timezone.day_start(monday),
+            self.timezone.day_start(monday + Duration::days(7)),
+        )
+    }
+
+    /// 包含 `ts` 的本地自然月 `[start, end)`
+    pub fn month_containing(&self, ts: i64) -> (i64, i64) {
+        let date = self.timezone.local_date(ts);
+        let first = date.with_day(1).expect("valid first day");
+        let next = first
+            .checked_add_months(chrono::Months::new(1))
+            .expect("valid next month");
+        (
+            self.timezone.day_start(first),
+            self.timezone.day_start(next),
+        )
+    }
+
+    /// `from` 之后在 `now` 之前已结束的完整自然日
+    pub fn completed_days(&self, from: i64, now: i64) -> Vec<(i64, i64)> {
+        Self::completed(from, now, |ts| self.day_containing(ts))
+    }
+
+    /// `from` 之后在 `now` 之前已结束的完整自然周
+    pub fn completed_weeks(&self, from: i64, now: i64) -> Vec<(i64, i64)> {
+        Self::completed(from, now, |ts| self.week_containing(ts))
+    }
+
+    /// `from` 之后在 `now` 之前已结束的完整自然月
+    pub fn completed_months(&self, from: i64, now: i64) -> Vec<(i64, i64)> {
+        Self::completed(from, now, |ts| self.month_containing(ts))
+    }
+
+    /// 枚举完整周期：`from` 落在周期中间时从下一个周期开始
+    fn completed(from: i64, now: i64, containing: impl Fn(i64) -> (i64, i64)) -> Vec<(i64, i64)> {
+        let mut periods = Vec::new();
+        let (start, end) = containing(from);
+        let mut cursor = if start == from { start } else { end };
+        loop {
+            let (start, end) = containing(cursor);
+            if end > now {
+                break;
+            }
+            periods.push((start, end));
+            cursor = end;
+        }
+        periods
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(tz: Tz, y: i32, m: u32, d: u32, h: u32, min: u32) -> i64 {
+        tz.with_ymd_and_hms(y, m, d, h, min, 0)
+            .single()
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn test_short_periods_align_to_local_boundaries() {
+        let tz = chrono_tz::Asia::Shanghai;
+        let schedule = SummarySchedule::new(15, ScheduleTimezone::Named(tz));
+
+        let (start, end) = schedule.short_period_containing(ts(tz, 2025, 3, 1, 10, 7));
+        assert_eq!(start, ts(tz, 2025, 3, 1, 10, 0));
+        assert_eq!(end, ts(tz, 2025, 3, 1, 10, 15));
+
+        let (start, end) = schedule.day_containing(ts(tz, 2025, 3, 1, 0, 30));
+        assert_eq!(start, ts(tz, 2025, 3, 1, 0, 0));
+        assert_eq!(end, ts(tz, 2025, 3, 2, 0, 0));
+    }
+
+    #[test]
+    fn test_completed_periods_start_at_watermark() {
+        let tz = chrono_tz::Asia::Shanghai;
+        let schedule = SummarySchedule::new(15, ScheduleTimezone::Named(tz));
+
+        let periods =
+            schedule.completed_short_periods(ts(tz, 2025, 3, 1, 10, 5), ts(tz, 2025, 3, 1, 10, 40));
+        assert_eq!(
+            periods,
+            vec![
+                (ts(tz, 2025, 3, 1, 10, 5), ts(tz, 2025, 3, 1, 10, 15)),
+                (ts(tz, 2025, 3, 1, 10, 15), ts(tz, 2025, 3, 1, 10, 30)),
+            ]
+        );
+
+        let days = schedule.completed_days(ts(tz, 2025, 3, 1, 0, 0), ts(tz, 2025, 3, 3, 9, 0));
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[1].1, ts(tz, 2025, 3, 3, 0, 0));
+    }
+
+    #[test]
+    fn test_weeks_and_months_follow_calendar() {
+        let tz = chrono_tz::Asia::Shanghai;
+        let schedule = SummarySchedule::new(15, ScheduleTimezone::Named(tz));
+
+        // 2025-03-05 是周三，所在周为 03-03（周一）至 03-10
+        let (start, end) = schedule.week_containing(ts(tz, 2025, 3, 5, 18, 0));
+        assert_eq!(start, ts(tz, 2025, 3, 3, 0, 0));
+        assert_eq!(end, ts(tz, 2025, 3, 10, 0, 0));
+
+        let (start, end) = schedule.month_containing(ts(tz, 2025, 2, 14, 9, 0));
+        assert_eq!(start, ts(tz, 2025, 2, 1, 0, 0));
+        assert_eq!(end, ts(tz, 2025, 3, 1, 0, 0));
+
+        // 起点落在月中时从下个完整月开始
+        let months = schedule.completed_months(ts(tz, 2025, 1, 20, 0, 0), ts(tz, 2025, 4, 2, 0, 0));
+        assert_eq!(
+            months,
+            vec![
+                (ts(tz, 2025, 2, 1, 0, 0), ts(tz, 2025, 3, 1, 0, 0)),
+                (ts(tz, 2025, 3, 1, 0, 0), ts(tz, 2025, 4, 1, 0, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dst_day_is_shorter() {
+        let tz = chrono_tz::America::New_York;
+        let schedule = SummarySchedule::new(60, ScheduleTimezone::Named(tz));
+
+        // 2025-03-09 美国夏令时开始，当天只有 23 小时
+        let (start, end) = schedule.day_containing(ts(tz, 2025, 3, 9, 12, 0));
+        assert_eq!(end - start, 23 * 60 * 60 * 1000);
+
+        // 不能整除一天的间隔在零点截断
+        let schedule = SummarySchedule::new(50, ScheduleTimezone::Named(tz));
+        let (_, end) = schedule.short_period_containing(ts(tz, 2025, 3, 10, 23, 55));
+        assert_eq!(end, ts(tz, 2025, 3, 11, 0, 0));
+    }
+}
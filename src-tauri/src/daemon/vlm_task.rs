@@ -1,20 +1,35 @@
 //! VLM 分析后台任务
 //!
 //! 支持并发处理待分析的 traces，调用 VLM 进行屏幕理解，
-//! 写入 trace 的轻量 ocr_text 并生成文本嵌入，同时把 VLM 结论聚合到活动 Session。
-
-use crate::ai::embedding::TextEmbedder;
-use crate::ai::vlm::VlmEngine;
-use crate::config::SessionConfig;
-use crate::db::{Database, Trace};
+//! 写入 trace 的轻量 ocr_text，同时把 VLM 结论聚合到活动 Session。
+//! 同一窗口内 pHash 相近的截图直接复用已分析 trace 的结果（见 `VlmTaskConfig::reuse_enabled`）。
+//!
+//! 模型分层：`[vlm]`（通常是本地小模型）分析所有 trace，置信度低、判定为关键操作或输出无法解析的
+//! trace 依次交给 `escalation_tiers` 中更强的模型重新分析；每次调用记录在 `vlm_tier_calls`，
+//! 最终采用的层级写入 vlm_raw_json 的 `model_tier`。
+//!
+//! 文本嵌入是独立的阶段：VLM 阶段只负责把待嵌入文本放入 `EmbeddingQueue`，
+//! 嵌入阶段按批大小或时间刷新，通过 `embed_batch` 一次请求处理多条 trace，
+//! 在单个事务中写回向量，并对模型未选中 Session 的 trace 做相似度路由。
+//!
+//! 路由候选是活跃窗口内 open 会话的质心（会话内 trace 向量的均值）。会话在
+//! `gap_threshold_ms` 内没有新 trace 后关闭，不再接收新 trace；关闭时的回顾由摘要任务生成。
+
+use crate::ai::embedding::{EmbeddingQueue, TextEmbedder};
+use crate::ai::entity::typed_entities;
+use crate::ai::image_prep::FrameRegions;
+use crate::ai::prompts::{PromptKind, PromptLibrary, ScreenPromptContext};
+use crate::ai::vlm::{ScreenDescription, VlmConfig, VlmEngine};
+use crate::config::{AppConfig, SessionConfig};
+use crate::db::{ActivitySession, Database, RoutingCorrection, Trace, VlmTierCall};
 use futures::stream::{self, StreamExt};
 use image::RgbImage;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tokio::sync::Semaphore;
+use tokio::sync::{mpsc, watch};
 use tokio::time::interval;
 use tracing::{debug, error, info, warn};
 
@@ -27,6 +42,46 @@ const DEFAULT_BATCH_SIZE: u32 = 10;
 /// 默认并发数
 const DEFAULT_CONCURRENCY: u32 = 3;
 
+/// 默认嵌入批大小
+const DEFAULT_EMBEDDING_BATCH_SIZE: u32 = 16;
+
+/// 默认嵌入强制刷新间隔（秒）
+const DEFAULT_EMBEDDING_FLUSH_SECS: u64 = 10;
+
+/// 默认复用 VLM 结果的最大 pHash 汉明距离（64 位哈希）
+const DEFAULT_REUSE_MAX_DISTANCE: u32 = 6;
+
+/// 默认复用 VLM 结果的时间窗口（毫秒）- 1 小时
+const DEFAULT_REUSE_WINDOW_MS: u64 = 60 * 60 * 1000;
+
+/// 嵌入队列积压上限（超过后丢弃，由后台扫描从数据库恢复）
+const MAX_EMBEDDING_BACKLOG: usize = 1000;
+
+/// 队列空闲时扫描待（重新）嵌入 traces 的间隔（秒）
+const REEMBED_SCAN_SECS: u64 = 10;
+
+/// 每次后台扫描放入队列的最大 trace 数
+const REEMBED_SCAN_LIMIT: usize = 100;
+
+/// 默认升级置信度阈值
+const DEFAULT_ESCALATION_CONFIDENCE: f32 = 0.6;
+
+/// 第一层（`[vlm]`）的层级名称
+const PRIMARY_TIER_NAME: &str = "primary";
+
+/// 升级层级连接失败后的重试间隔
+const TIER_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// VLM 升级层级
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct VlmTierConfig {
+    /// 层级名称（写入 vlm_raw_json 的 `model_tier` 与层级统计）
+    pub name: String,
+    /// 模型配置（字段同 `[vlm]`）
+    #[serde(flatten)]
+    pub vlm: VlmConfig,
+}
+
 /// VLM 分析任务配置
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct VlmTaskConfig {
@@ -38,6 +93,65 @@ pub struct VlmTaskConfig {
     pub concurrency: u32,
     /// 是否启用
     pub enabled: bool,
+    /// 嵌入批大小（累积到该数量即刷新）
+    #[serde(default = "default_embedding_batch_size")]
+    pub embedding_batch_size: u32,
+    /// 嵌入强制刷新间隔（秒）
+    #[serde(default = "default_embedding_flush_secs")]
+    pub embedding_flush_interval_secs: u64,
+    /// 近重复截图（同一应用与窗口、pHash 相近）直接复用已分析 trace 的结果，不调用 VLM
+    #[serde(default = "default_reuse_enabled")]
+    pub reuse_enabled: bool,
+    /// 复用判定的最大 pHash 汉明距离
+    #[serde(default = "default_reuse_max_distance")]
+    pub reuse_max_distance: u32,
+    /// 复用来源与当前 trace 的最大时间间隔（毫秒）
+    #[serde(default = "default_reuse_window_ms")]
+    pub reuse_window_ms: u64,
+    /// 升级层级（按顺序，由弱到强）；为空时只使用 `[vlm]`
+    #[serde(default)]
+    pub escalation_tiers: Vec<VlmTierConfig>,
+    /// 置信度低于该值时交给下一层
+    #[serde(default = "default_escalation_confidence")]
+    pub escalation_confidence: f32,
+    /// 第一层判定为关键操作的 trace 交给下一层复核
+    #[serde(default = "default_escalate_key_actions")]
+    pub escalate_key_actions: bool,
+    /// 输出无法解析为 JSON 时交给下一层
+    #[serde(default = "default_escalate_parse_failures")]
+    pub escalate_parse_failures: bool,
+}
+
+fn default_embedding_batch_size() -> u32 {
+    DEFAULT_EMBEDDING_BATCH_SIZE
+}
+
+fn default_embedding_flush_secs() -> u64 {
+    DEFAULT_EMBEDDING_FLUSH_SECS
+}
+
+fn default_reuse_enabled() -> bool {
+    true
+}
+
+fn default_reuse_max_distance() -> u32 {
+    DEFAULT_REUSE_MAX_DISTANCE
+}
+
+fn default_reuse_window_ms() -> u64 {
+    DEFAULT_REUSE_WINDOW_MS
+}
+
+fn default_escalation_confidence() -> f32 {
+    DEFAULT_ESCALATION_CONFIDENCE
+}
+
+fn default_escalate_key_actions() -> bool {
+    true
+}
+
+fn default_escalate_parse_failures() -> bool {
+    true
 }
 
 impl Default for VlmTaskConfig {
@@ -47,10 +161,173 @@ impl Default for VlmTaskConfig {
             batch_size: DEFAULT_BATCH_SIZE,
             concurrency: DEFAULT_CONCURRENCY,
             enabled: true,
+            embedding_batch_size: DEFAULT_EMBEDDING_BATCH_SIZE,
+            embedding_flush_interval_secs: DEFAULT_EMBEDDING_FLUSH_SECS,
+            reuse_enabled: true,
+            reuse_max_distance: DEFAULT_REUSE_MAX_DISTANCE,
+            reuse_window_ms: DEFAULT_REUSE_WINDOW_MS,
+            escalation_tiers: Vec::new(),
+            escalation_confidence: DEFAULT_ESCALATION_CONFIDENCE,
+            escalate_key_actions: true,
+            escalate_parse_failures: true,
+        }
+    }
+}
+
+impl VlmTaskConfig {
+    /// 近重复复用与模型升级策略
+    fn analysis_policy(&self) -> AnalysisPolicy {
+        AnalysisPolicy {
+            reuse: self.reuse_enabled.then_some(ReusePolicy {
+                max_distance: self.reuse_max_distance,
+                window_ms: self.reuse_window_ms as i64,
+            }),
+            escalation: EscalationPolicy {
+                min_confidence: self.escalation_confidence,
+                key_actions: self.escalate_key_actions,
+                parse_failures: self.escalate_parse_failures,
+            },
+        }
+    }
+}
+
+/// 单个 trace 的分析策略（每轮从配置读取）
+#[derive(Debug, Clone, Copy)]
+struct AnalysisPolicy {
+    /// 近重复复用；关闭时为 None
+    reuse: Option<ReusePolicy>,
+    escalation: EscalationPolicy,
+}
+
+/// 近重复截图复用 VLM 结果的判定条件
+#[derive(Debug, Clone, Copy)]
+struct ReusePolicy {
+    max_distance: u32,
+    window_ms: i64,
+}
+
+/// 交给下一层重新分析的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscalationReason {
+    LowConfidence,
+    KeyAction,
+    ParseFailed,
+    Error,
+}
+
+impl EscalationReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EscalationReason::LowConfidence => "low_confidence",
+            EscalationReason::KeyAction => "key_action",
+            EscalationReason::ParseFailed => "parse_failed",
+            EscalationReason::Error => "error",
         }
     }
 }
 
+/// 升级判定条件
+#[derive(Debug, Clone, Copy)]
+struct EscalationPolicy {
+    min_confidence: f32,
+    key_actions: bool,
+    parse_failures: bool,
+}
+
+impl EscalationPolicy {
+    /// 结果是否需要交给下一层；关键操作只在第一层之后复核一次
+    fn reason(
+        &self,
+        description: &ScreenDescription,
+        first_tier: bool,
+    ) -> Option<EscalationReason> {
+        if description.parse_failed() {
+            return self.parse_failures.then_some(EscalationReason::ParseFailed);
+        }
+        if description.confidence < self.min_confidence {
+            return Some(EscalationReason::LowConfidence);
+        }
+        if first_tier && self.key_actions && description.is_key_action {
+            return Some(EscalationReason::KeyAction);
+        }
+        None
+    }
+}
+
+/// 升级层级的引擎：按需连接，连接失败后间隔重试
+struct TierEngine {
+    config: VlmTierConfig,
+    engine: Option<Arc<VlmEngine>>,
+    last_attempt: Option<Instant>,
+}
+
+/// 升级层级引擎（随配置同步）
+#[derive(Default)]
+struct TierEngines {
+    tiers: Vec<TierEngine>,
+}
+
+impl TierEngines {
+    /// 同步层级配置：配置变化的层级丢弃旧引擎
+    fn sync(&mut self, configs: &[VlmTierConfig]) {
+        self.tiers.truncate(configs.len());
+        for (i, config) in configs.iter().enumerate() {
+            let fresh = TierEngine {
+                config: config.clone(),
+                engine: None,
+                last_attempt: None,
+            };
+            match self.tiers.get_mut(i) {
+                Some(tier) if tier.config == *config => {}
+                Some(tier) => *tier = fresh,
+                None => self.tiers.push(fresh),
+            }
+        }
+    }
+
+    /// 获取第 `index` 个升级层级的引擎（未连接时尝试初始化）
+    ///
+    /// 初始化期间不持有锁：其他 trace 在此期间跳过该层级，与连接失败后的重试间隔一致。
+    async fn engine(
+        engines: &tokio::sync::Mutex<Self>,
+        index: usize,
+        prompts: Arc<PromptLibrary>,
+    ) -> Option<(String, Arc<VlmEngine>)> {
+        let config = {
+            let mut guard = engines.lock().await;
+            let tier = guard.tiers.get_mut(index)?;
+            if let Some(engine) = &tier.engine {
+                return Some((tier.config.name.clone(), engine.clone()));
+            }
+            if tier
+                .last_attempt
+                .is_some_and(|t| t.elapsed() < TIER_RETRY_INTERVAL)
+            {
+                return None;
+            }
+            tier.last_attempt = Some(Instant::now());
+            tier.config.clone()
+        };
+
+        let mut engine = VlmEngine::new(config.vlm.clone()).with_prompts(prompts);
+        if let Err(e) = engine.initialize().await {
+            warn!("VLM tier '{}' unavailable: {}", config.name, e);
+            return None;
+        }
+        info!("VLM tier '{}' ready ({})", config.name, config.vlm.model);
+        let engine = Arc::new(engine);
+
+        // 初始化期间层级配置可能已变化，只在配置未变时保存
+        let mut guard = engines.lock().await;
+        if let Some(tier) = guard.tiers.get_mut(index) {
+            if tier.config == config {
+                tier.engine = Some(engine.clone());
+            }
+        }
+        Some((config.name, engine))
+    }
+}
+
 /// VLM 分析任务状态
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct VlmTaskStatus {
@@ -64,6 +341,22 @@ pub struct VlmTaskStatus {
     pub pending_count: u64,
     /// 当前并发数配置
     pub concurrency: u32,
+    /// 已写入向量的 traces 数量
+    pub embedded_count: u64,
+    /// 已执行的嵌入批次数
+    pub embedding_batch_count: u64,
+    /// 嵌入队列中等待的文本数量
+    pub embedding_queue_len: u64,
+    /// 嵌入吞吐（条/秒，按累计耗时计算）
+    pub embedding_throughput: f64,
+}
+
+/// 嵌入阶段统计
+#[derive(Default)]
+struct EmbeddingStats {
+    embedded_count: AtomicU64,
+    batch_count: AtomicU64,
+    busy_ms: AtomicU64,
 }
 
 /// VLM 分析后台任务
@@ -72,31 +365,41 @@ pub struct VlmTask {
     vlm: Arc<RwLock<Option<VlmEngine>>>,
     embedder: Arc<RwLock<TextEmbedder>>,
     config: VlmTaskConfig,
-    session_config: SessionConfig,
+    config_rx: watch::Receiver<AppConfig>,
     is_running: Arc<AtomicBool>,
     processed_count: Arc<AtomicU64>,
     failed_count: Arc<AtomicU64>,
+    embedding_queue: Arc<Mutex<EmbeddingQueue>>,
+    embedding_stats: Arc<EmbeddingStats>,
+    tier_engines: Arc<tokio::sync::Mutex<TierEngines>>,
     shutdown_tx: Option<mpsc::Sender<()>>,
 }
 
 impl VlmTask {
     /// 创建新的 VLM 分析任务
+    ///
+    /// Session 路由参数每轮从 `config_rx` 读取，设置修改后无需重启任务。
     pub fn new(
         db: Arc<Database>,
         vlm: Arc<RwLock<Option<VlmEngine>>>,
         embedder: Arc<RwLock<TextEmbedder>>,
         config: VlmTaskConfig,
-        session_config: SessionConfig,
+        config_rx: watch::Receiver<AppConfig>,
     ) -> Self {
+        let embedding_queue = EmbeddingQueue::new(config.embedding_batch_size.max(1) as usize)
+            .with_flush_interval(config.embedding_flush_interval_secs);
         Self {
             db,
             vlm,
             embedder,
             config,
-            session_config,
+            config_rx,
             is_running: Arc::new(AtomicBool::new(false)),
             processed_count: Arc::new(AtomicU64::new(0)),
             failed_count: Arc::new(AtomicU64::new(0)),
+            embedding_queue: Arc::new(Mutex::new(embedding_queue)),
+            embedding_stats: Arc::new(EmbeddingStats::default()),
+            tier_engines: Arc::new(tokio::sync::Mutex::new(TierEngines::default())),
             shutdown_tx: None,
         }
     }
@@ -118,17 +421,31 @@ impl VlmTask {
         let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
         self.shutdown_tx = Some(shutdown_tx);
 
+        // 恢复上次未完成嵌入的 traces（队列只在内存中）
+        self.recover_pending_embeddings();
+
         let is_running = self.is_running.clone();
         let processed_count = self.processed_count.clone();
         let failed_count = self.failed_count.clone();
         let db = self.db.clone();
         let vlm = self.vlm.clone();
-        let embedder = self.embedder.clone();
+        let embedding_queue = self.embedding_queue.clone();
+        let tier_engines = self.tier_engines.clone();
         let config = self.config.clone();
-        let session_config = self.session_config.clone();
+        let config_rx = self.config_rx.clone();
 
         is_running.store(true, Ordering::SeqCst);
 
+        // 嵌入阶段：独立循环，按批大小或时间刷新
+        tokio::spawn(Self::run_embedding_stage(
+            self.db.clone(),
+            self.embedder.clone(),
+            self.embedding_queue.clone(),
+            self.embedding_stats.clone(),
+            self.config_rx.clone(),
+            is_running.clone(),
+        ));
+
         tokio::spawn(async move {
             let mut ticker = interval(Duration::from_millis(config.interval_ms));
 
@@ -147,6 +464,11 @@ impl VlmTask {
                         break;
                     }
                     _ = ticker.tick() => {
+                        let app_config = config_rx.borrow().clone();
+                        if let Err(e) = Self::close_idle_sessions(&db, &app_config.session) {
+                            warn!("Failed to close idle sessions: {}", e);
+                        }
+
                         // 检查 VLM 是否可用
                         let vlm_ready = {
                             let vlm_guard = vlm.read().await;
@@ -157,13 +479,15 @@ impl VlmTask {
                             debug!("VLM not ready, skipping processing");
                             continue;
                         }
+                        tier_engines.lock().await.sync(&app_config.vlm_task.escalation_tiers);
 
-                        // 并发处理待分析的 traces
+                        // 并发处理待分析的 traces（每轮读取最新的 Session 与复用配置）
                         match Self::process_pending_traces_concurrent(
                             &db,
                             &vlm,
-                            &embedder,
-                            &session_config,
+                            &tier_engines,
+                            &embedding_queue,
+                            &app_config,
                             &semaphore,
                             config.batch_size,
                             &processed_count,
@@ -200,12 +524,22 @@ impl VlmTask {
 
     /// 获取任务状态
     pub fn status(&self, pending_count: u64) -> VlmTaskStatus {
+        let embedded_count = self.embedding_stats.embedded_count.load(Ordering::SeqCst);
+        let busy_ms = self.embedding_stats.busy_ms.load(Ordering::SeqCst);
         VlmTaskStatus {
             is_running: self.is_running.load(Ordering::SeqCst),
             processed_count: self.processed_count.load(Ordering::SeqCst),
             failed_count: self.failed_count.load(Ordering::SeqCst),
             pending_count,
             concurrency: self.config.concurrency,
+            embedded_count,
+            embedding_batch_count: self.embedding_stats.batch_count.load(Ordering::SeqCst),
+            embedding_queue_len: self.embedding_queue.lock().unwrap().len() as u64,
+            embedding_throughput: if busy_ms > 0 {
+                embedded_count as f64 * 1000.0 / busy_ms as f64
+            } else {
+                0.0
+            },
         }
     }
 
@@ -218,8 +552,9 @@ impl VlmTask {
     async fn process_pending_traces_concurrent(
         db: &Arc<Database>,
         vlm: &Arc<RwLock<Option<VlmEngine>>>,
-        embedder: &Arc<RwLock<TextEmbedder>>,
-        session_config: &SessionConfig,
+        tier_engines: &Arc<tokio::sync::Mutex<TierEngines>>,
+        embedding_queue: &Arc<Mutex<EmbeddingQueue>>,
+        app_config: &AppConfig,
         semaphore: &Arc<Semaphore>,
         batch_size: u32,
         processed_count: &Arc<AtomicU64>,
@@ -238,22 +573,33 @@ impl VlmTask {
             trace_count
         );
 
+        let policy = app_config.vlm_task.analysis_policy();
+
         // 并发处理所有 traces
         let results: Vec<Result<i64, (i64, String)>> = stream::iter(pending_traces)
             .map(|trace| {
                 let db = db.clone();
                 let vlm = vlm.clone();
-                let embedder = embedder.clone();
+                let tier_engines = tier_engines.clone();
+                let embedding_queue = embedding_queue.clone();
                 let semaphore = semaphore.clone();
-                let session_config = session_config.clone();
+                let session_config = app_config.session.clone();
 
                 async move {
                     // 获取信号量许可，控制并发数
                     let _permit = semaphore.acquire().await.unwrap();
 
                     let trace_id = trace.id;
-                    match Self::process_single_trace(&db, &vlm, &embedder, &session_config, &trace)
-                        .await
+                    match Self::process_single_trace(
+                        &db,
+                        &vlm,
+                        &tier_engines,
+                        &embedding_queue,
+                        &session_config,
+                        policy,
+                        &trace,
+                    )
+                    .await
                     {
                         Ok(_) => Ok(trace_id),
                         Err(e) => Err((trace_id, e.to_string())),
@@ -283,39 +629,32 @@ impl VlmTask {
         Ok(success_count)
     }
 
-    /// 处理单个 trace
-    async fn process_single_trace(
-        db: &Arc<Database>,
-        vlm: &Arc<RwLock<Option<VlmEngine>>>,
-        embedder: &Arc<RwLock<TextEmbedder>>,
+    /// 构建截图分析上下文：当前 trace 元信息、活跃 Session 列表和最近 OCR 片段
+    ///
+    /// 同时返回活跃 Session（用于校验模型选择的 existing_session_id）。
+    pub fn build_prompt_context(
+        db: &Database,
+        prompts: &PromptLibrary,
         session_config: &SessionConfig,
         trace: &Trace,
-    ) -> anyhow::Result<()> {
-        // 1. 获取图片路径
-        let image_path_str = trace
-            .image_path
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Trace {} has no image_path", trace.id))?;
-
-        // 2. 加载图片（同步操作，在 spawn_blocking 中执行）
-        let path = db.get_full_path(image_path_str);
-        let image = tokio::task::spawn_blocking(move || Self::load_image(&path)).await??;
-
-        // 3. 多线程 Session：提供活跃线程列表作为上下文，并让模型选择 existing_session_id
+    ) -> anyhow::Result<(ScreenPromptContext, Vec<ActivitySession>)> {
         let now_ts = trace.timestamp;
-        let mut parts: Vec<String> = Vec::new();
-
-        parts.push(format!(
-            "【Current Trace Meta】\napp_name: {}\nwindow_title: {}\ntime: {}",
-            trace.app_name.as_deref().unwrap_or("-"),
-            trace.window_title.as_deref().unwrap_or("-"),
-            chrono::DateTime::from_timestamp_millis(now_ts)
-                .map(|t| t
-                    .with_timezone(&chrono::Local)
+
+        let time = chrono::DateTime::from_timestamp_millis(now_ts)
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
                     .format("%m-%d %H:%M:%S")
-                    .to_string())
-                .unwrap_or_else(|| "?".to_string()),
-        ));
+                    .to_string()
+            })
+            .unwrap_or_else(|| "?".to_string());
+        let trace_meta = prompts.render(
+            PromptKind::ContextTraceMeta,
+            &[
+                ("app_name", trace.app_name.as_deref().unwrap_or("-")),
+                ("window_title", trace.window_title.as_deref().unwrap_or("-")),
+                ("time", &time),
+            ],
+        );
 
         let active_sessions = db.get_active_sessions_for_routing(
             now_ts,
@@ -323,8 +662,9 @@ impl VlmTask {
             session_config.max_active_sessions,
         )?;
 
+        let mut session_blocks: Vec<String> = Vec::new();
         if !active_sessions.is_empty() {
-            parts.push("【Active Sessions（Threads）】\n注意：只有当前截图与某个 Session 的任务/项目/内容高度相关时才选择其 session_id；不同类型活动（工作vs娱乐）、不同项目、不同沟通对象必须分到不同 Session。如果不确定，请输出 null 新建 Session。".to_string());
+            let mut session_lines: Vec<String> = Vec::new();
             for s in active_sessions.iter() {
                 let st = chrono::DateTime::from_timestamp_millis(s.start_time)
                     .map(|t| {
@@ -375,10 +715,28 @@ impl VlmTask {
                         block.push_str(&format!("\n{}", last3));
                     }
                 }
-                parts.push(block);
+                session_lines.push(block);
+            }
+            session_blocks.push(prompts.render(
+                PromptKind::ContextActiveSessions,
+                &[("sessions", &session_lines.join("\n\n"))],
+            ));
+
+            // 用户纠正过的归类作为 few-shot 示例
+            if session_config.correction_examples > 0 {
+                match db.get_recent_routing_corrections(session_config.correction_examples) {
+                    Ok(corrections) => {
+                        if let Some(block) = Self::format_routing_corrections(prompts, &corrections)
+                        {
+                            session_blocks.push(block);
+                        }
+                    }
+                    Err(e) => warn!("Failed to load routing corrections: {}", e),
+                }
             }
         }
 
+        let mut recent_ocr = String::new();
         if let Ok(recent) = db.get_recent_traces_before(trace.timestamp, 2) {
             let mut lines = Vec::new();
             for t in recent {
@@ -391,73 +749,313 @@ impl VlmTask {
                 }
             }
             if !lines.is_empty() {
-                parts.push(format!(
-                    "【Recent Global Traces OCR】\n{}",
-                    lines.join("\n")
-                ));
+                recent_ocr = prompts.render(
+                    PromptKind::ContextRecentOcr,
+                    &[("traces", &lines.join("\n"))],
+                );
             }
         }
 
-        let context = {
-            const MAX_CONTEXT_CHARS: usize = 262_144; // 256K
-            let joined = parts.join("\n\n");
-            if joined.chars().count() > MAX_CONTEXT_CHARS {
-                Some(
-                    joined
-                        .chars()
-                        .rev()
-                        .take(MAX_CONTEXT_CHARS)
-                        .collect::<String>()
-                        .chars()
-                        .rev()
-                        .collect(),
-                )
-            } else {
-                Some(joined)
-            }
+        let context = ScreenPromptContext {
+            trace_meta,
+            image_layout: String::new(),
+            active_sessions: session_blocks.join("\n\n"),
+            recent_ocr,
         };
+        Ok((context, active_sessions))
+    }
+
+    /// 截图时记录的焦点窗口与变化区域（用于焦点裁剪和分块优先级）
+    fn frame_regions(db: &Database, trace: &Trace) -> anyhow::Result<FrameRegions> {
+        Ok(FrameRegions {
+            focus: db.get_focus_region(trace.id)?,
+            changed: db
+                .get_change_region(trace.id)?
+                .map(|r| (r.x, r.y, r.width, r.height)),
+        })
+    }
 
-        let description = {
+    /// 处理单个 trace
+    async fn process_single_trace(
+        db: &Arc<Database>,
+        vlm: &Arc<RwLock<Option<VlmEngine>>>,
+        tier_engines: &tokio::sync::Mutex<TierEngines>,
+        embedding_queue: &Arc<Mutex<EmbeddingQueue>>,
+        session_config: &SessionConfig,
+        policy: AnalysisPolicy,
+        trace: &Trace,
+    ) -> anyhow::Result<()> {
+        // 1. 近重复截图（光标移动、轻微滚动等）直接复用已分析 trace 的结果，省去 VLM 调用
+        if let Some(reuse) = policy.reuse {
+            if Self::try_reuse_analysis(db, embedding_queue, reuse, trace)? {
+                return Ok(());
+            }
+        }
+
+        // 2. 获取图片路径
+        let image_path_str = trace
+            .image_path
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trace {} has no image_path", trace.id))?;
+
+        // 3. 加载图片（同步操作，在 spawn_blocking 中执行）
+        let path = db.get_full_path(image_path_str);
+        let image = tokio::task::spawn_blocking(move || Self::load_image(&path)).await??;
+
+        // 4. 多线程 Session：提供活跃线程列表作为上下文，并让模型选择 existing_session_id
+        let prompts = vlm
+            .read()
+            .await
+            .as_ref()
+            .map(|engine| engine.prompts())
+            .ok_or_else(|| anyhow::anyhow!("VLM not available"))?;
+        let (context, active_sessions) =
+            Self::build_prompt_context(db, &prompts, session_config, trace)?;
+        let regions = Self::frame_regions(db, trace)?;
+
+        let (description, calls) = Self::analyze_with_cascade(
+            vlm,
+            tier_engines,
+            policy.escalation,
+            &image,
+            &context,
+            &regions,
+        )
+        .await;
+        db.record_vlm_tier_calls(trace.id, &calls)?;
+        let description = description?;
+
+        let parse = &description.parse;
+        db.save_trace_parse_status(
+            trace.id,
+            parse.status.as_str(),
+            parse.attempts,
+            parse.error.as_deref(),
+            parse.raw_output.as_deref(),
+        )?;
+        // 5. 无法解析的输出不写入摘要：标记为已处理（空 OCR 文本），等待用户重新分析
+        if description.parse_failed() {
+            db.update_trace_ocr_text(trace.id, "")?;
+            warn!(
+                "Trace {} analysis could not be parsed after {} attempts: {}",
+                trace.id,
+                parse.attempts,
+                parse.error.as_deref().unwrap_or("")
+            );
+            return Ok(());
+        }
+
+        Self::apply_description(db, embedding_queue, trace, &description, &active_sessions)?;
+
+        info!(
+            "Trace {} processed: tier={}, summary='{}', confidence={:.2}",
+            trace.id,
+            description
+                .model_tier
+                .as_deref()
+                .unwrap_or(PRIMARY_TIER_NAME),
+            description.summary.chars().take(50).collect::<String>(),
+            description.confidence
+        );
+
+        Ok(())
+    }
+
+    /// 分层分析截图：`[vlm]` 先分析，满足升级条件时依次交给升级层级，采用最后一个成功解析的结果
+    ///
+    /// 返回最终结果与每次调用的记录（失败的调用也会记录）。
+    async fn analyze_with_cascade(
+        vlm: &Arc<RwLock<Option<VlmEngine>>>,
+        tier_engines: &tokio::sync::Mutex<TierEngines>,
+        escalation: EscalationPolicy,
+        image: &RgbImage,
+        context: &ScreenPromptContext,
+        regions: &FrameRegions,
+    ) -> (anyhow::Result<ScreenDescription>, Vec<VlmTierCall>) {
+        let mut calls = Vec::new();
+        let mut best: Option<(ScreenDescription, usize)> = None;
+
+        // 第一层：[vlm]
+        let (primary, prompts) = {
             let vlm_guard = vlm.read().await;
-            let vlm_engine = vlm_guard
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("VLM not available"))?;
-            vlm_engine
-                .analyze_screen_with_context(&image, context.as_deref())
-                .await?
+            let Some(engine) = vlm_guard.as_ref() else {
+                return (Err(anyhow::anyhow!("VLM not available")), calls);
+            };
+            let started = Instant::now();
+            let result = engine
+                .analyze_screen_with_context(image, Some(context), regions)
+                .await;
+            calls.push(Self::tier_call(
+                0,
+                PRIMARY_TIER_NAME,
+                engine,
+                &result,
+                started,
+                None,
+            ));
+            (result, engine.prompts())
+        };
+        let mut last_error: Option<anyhow::Error> = None;
+        let mut reason = match primary {
+            Ok(mut description) => {
+                let reason = escalation.reason(&description, true);
+                description.model_tier = Some(PRIMARY_TIER_NAME.to_string());
+                best = Some((description, 0));
+                reason
+            }
+            Err(e) => {
+                last_error = Some(e);
+                Some(EscalationReason::Error)
+            }
         };
 
-        // 4. 轻量 OCR 文本（写回 trace）
+        // 升级层级：依次尝试，直到结果不再满足升级条件
+        let mut index = 0;
+        while let Some(current) = reason {
+            if index >= tier_engines.lock().await.tiers.len() {
+                break;
+            }
+            let tier = TierEngines::engine(tier_engines, index, prompts.clone()).await;
+            index += 1;
+            let Some((name, engine)) = tier else {
+                continue;
+            };
+
+            debug!("Escalating to VLM tier '{}' ({})", name, current.as_str());
+            let started = Instant::now();
+            let result = engine
+                .analyze_screen_with_context(image, Some(context), regions)
+                .await;
+            calls.push(Self::tier_call(
+                index as u32,
+                &name,
+                &engine,
+                &result,
+                started,
+                Some(current),
+            ));
+            match result {
+                Ok(mut description) => {
+                    reason = escalation.reason(&description, false);
+                    description.model_tier = Some(name);
+                    // 无法解析的结果不替换下层已解析的结果
+                    let keep_previous = description.parse_failed()
+                        && best.as_ref().is_some_and(|(b, _)| !b.parse_failed());
+                    if !keep_previous {
+                        best = Some((description, calls.len() - 1));
+                    }
+                }
+                Err(e) => {
+                    warn!("VLM tier '{}' failed: {}", name, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match best {
+            Some((description, accepted)) => {
+                calls[accepted].accepted = true;
+                (Ok(description), calls)
+            }
+            None => {
+                let error = last_error.unwrap_or_else(|| anyhow::anyhow!("VLM analysis failed"));
+                (Err(error), calls)
+            }
+        }
+    }
+
+    /// 一次层级调用的记录
+    fn tier_call(
+        tier: u32,
+        name: &str,
+        engine: &VlmEngine,
+        result: &anyhow::Result<ScreenDescription>,
+        started: Instant,
+        reason: Option<EscalationReason>,
+    ) -> VlmTierCall {
+        let (status, confidence) = match result {
+            Ok(d) if d.parse_failed() => ("parse_failed", Some(d.confidence)),
+            Ok(d) => ("ok", Some(d.confidence)),
+            Err(_) => ("error", None),
+        };
+        VlmTierCall {
+            tier,
+            tier_name: name.to_string(),
+            model: engine.config().model.clone(),
+            status: status.to_string(),
+            confidence,
+            latency_ms: started.elapsed().as_millis() as i64,
+            escalation_reason: reason.map(|r| r.as_str().to_string()),
+            accepted: false,
+        }
+    }
+
+    /// 查找近重复的已分析 trace 并复用其结果；返回是否复用成功
+    ///
+    /// 复用结果不带 Session 选择与关键操作标记：同一画面不是新的关键操作，
+    /// Session 归属交给嵌入阶段按相似度路由（与来源 trace 的文本相同，通常落在同一 Session）。
+    fn try_reuse_analysis(
+        db: &Database,
+        embedding_queue: &Arc<Mutex<EmbeddingQueue>>,
+        policy: ReusePolicy,
+        trace: &Trace,
+    ) -> anyhow::Result<bool> {
+        let Some((source, distance)) =
+            db.find_near_duplicate_analysis(trace, policy.max_distance, policy.window_ms)?
+        else {
+            return Ok(false);
+        };
+        let Some(description) = source
+            .vlm_raw_json
+            .as_deref()
+            .and_then(|json| serde_json::from_str::<ScreenDescription>(json).ok())
+        else {
+            return Ok(false);
+        };
+        let description = ScreenDescription {
+            is_key_action: false,
+            existing_session_id: None,
+            session_title: None,
+            session_description: None,
+            ..description
+        };
+
+        db.record_vlm_reuse(trace.id, source.id, distance)?;
+        Self::apply_description(db, embedding_queue, trace, &description, &[])?;
+
+        info!(
+            "Trace {} reused analysis of trace {} (phash distance {})",
+            trace.id, source.id, distance
+        );
+        Ok(true)
+    }
+
+    /// 把 VLM 结论写回 trace，处理模型选择的 Session，并把嵌入文本放入队列
+    fn apply_description(
+        db: &Database,
+        embedding_queue: &Arc<Mutex<EmbeddingQueue>>,
+        trace: &Trace,
+        description: &ScreenDescription,
+        active_sessions: &[ActivitySession],
+    ) -> anyhow::Result<()> {
+        // 1. 轻量 OCR 文本（写回 trace）
         let ocr_text = description
             .text_content
             .clone()
             .filter(|s| !s.trim().is_empty())
             .unwrap_or_else(|| description.summary.clone());
 
-        // 5. 更新数据库（trace 仅保留轻量 OCR 文本）
+        // 2. 更新数据库（trace 仅保留轻量 OCR 文本）
         db.update_trace_ocr_text(trace.id, &ocr_text)?;
 
-        // 6. 生成嵌入向量（可使用更丰富的文本，不必写回 trace）
-        let embedding_text = VlmEngine::get_text_for_embedding(&description);
-        let embedding = {
-            let embedder_guard = embedder.read().await;
-            embedder_guard.embed(&embedding_text).await?
-        };
-
-        // 7. 序列化嵌入向量
-        let embedding_bytes = Self::serialize_embedding(&embedding);
-
-        // 8. 更新数据库（嵌入向量）
-        db.update_trace_embedding(trace.id, &embedding_bytes)?;
-
-        // 9. 把 VLM 结论同步到 Session（对外的核心视图）
+        // 3. 把 VLM 结论写回 trace
         let is_key_action = description.is_key_action;
         let action_description = description
             .action_description
             .as_deref()
             .map(str::trim)
             .filter(|s| !s.is_empty());
-        let raw_json = serde_json::to_string(&description).ok();
+        let raw_json = serde_json::to_string(description).ok();
         db.update_trace_vlm_analysis(
             trace.id,
             Some(description.summary.as_str()),
@@ -468,63 +1066,421 @@ impl VlmTask {
             raw_json.as_deref(),
             is_key_action,
         )?;
+        db.record_trace_entities(trace.id, &typed_entities(&description.entities))?;
 
-        // 10. 多线程 Session 路由：优先模型选择，其次 embedding 相似度兜底，否则新建
+        // 4. 多线程 Session 路由：模型选中的活跃 Session 立即归入；
+        //    未选中的 trace 由嵌入阶段按 embedding 相似度兜底，否则新建
         let active_ids: std::collections::HashSet<i64> =
             active_sessions.iter().map(|s| s.id).collect();
 
-        let mut chosen_session_id = description
+        if let Some(session_id) = description
             .existing_session_id
-            .filter(|id| active_ids.contains(id));
-
-        if chosen_session_id.is_none() {
-            let candidates = db.get_active_session_last_embeddings(
-                now_ts,
-                session_config.active_window_ms as i64,
-                session_config.max_active_sessions,
+            .filter(|id| active_ids.contains(id))
+        {
+            db.update_activity_session_from_vlm(
+                session_id,
+                trace.id,
+                description.session_title.as_deref(),
+                description.session_description.as_deref(),
             )?;
-            chosen_session_id = Self::pick_best_session_by_embedding(
-                &embedding,
-                &candidates,
-                session_config.similarity_threshold,
+        }
+
+        // 5. 嵌入文本（可使用更丰富的文本，不必写回 trace）交给嵌入阶段批量处理
+        let embedding_text = VlmEngine::get_text_for_embedding(description);
+        embedding_queue
+            .lock()
+            .unwrap()
+            .enqueue(embedding_text, trace.id);
+
+        Ok(())
+    }
+
+    /// 把已有 OCR 文本但尚无向量的 traces 重新放入嵌入队列
+    ///
+    /// 向量由其他模型生成的 traces 也会重新嵌入（切换嵌入模型后逐步迁移）。
+    fn recover_pending_embeddings(&self) {
+        let model_id = self.embedder.try_read().ok().map(|e| e.model_id());
+        let recovered = Self::enqueue_pending_embeddings(
+            &self.db,
+            model_id.as_deref(),
+            &self.embedding_queue,
+            MAX_EMBEDDING_BACKLOG,
+        );
+        if recovered > 0 {
+            info!("Recovered {} traces pending embedding", recovered);
+        }
+    }
+
+    /// 从数据库取出最多 `limit` 条待（重新）嵌入的 traces 放入队列，返回入队数量
+    fn enqueue_pending_embeddings(
+        db: &Database,
+        model_id: Option<&str>,
+        queue: &Mutex<EmbeddingQueue>,
+        limit: usize,
+    ) -> usize {
+        let pending = match db.get_traces_pending_embedding(limit as u32, model_id) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Failed to load traces pending embedding: {}", e);
+                return 0;
+            }
+        };
+
+        let mut queue = queue.lock().unwrap();
+        let mut count = 0;
+        for trace in pending.iter() {
+            if let Some(text) = Self::embedding_text_for_trace(trace) {
+                queue.enqueue(text, trace.id);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// 从已分析的 trace 还原嵌入文本（优先使用完整的 VLM 结果）
+    fn embedding_text_for_trace(trace: &Trace) -> Option<String> {
+        trace
+            .vlm_raw_json
+            .as_deref()
+            .and_then(|s| serde_json::from_str::<ScreenDescription>(s).ok())
+            .map(|desc| VlmEngine::get_text_for_embedding(&desc))
+            .or_else(|| trace.ocr_text.clone())
+            .filter(|s| !s.trim().is_empty())
+    }
+
+    /// 嵌入阶段主循环：任务停止后做最后一次刷新再退出
+    ///
+    /// 队列空闲时定期扫描数据库，补上被丢弃的积压，并把其他模型生成的向量逐批重新嵌入。
+    async fn run_embedding_stage(
+        db: Arc<Database>,
+        embedder: Arc<RwLock<TextEmbedder>>,
+        queue: Arc<Mutex<EmbeddingQueue>>,
+        stats: Arc<EmbeddingStats>,
+        config_rx: watch::Receiver<AppConfig>,
+        is_running: Arc<AtomicBool>,
+    ) {
+        let mut ticker = interval(Duration::from_secs(1));
+        let mut last_scan = Instant::now();
+        info!("Embedding stage started");
+
+        loop {
+            ticker.tick().await;
+            let running = is_running.load(Ordering::SeqCst);
+
+            if running
+                && last_scan.elapsed() >= Duration::from_secs(REEMBED_SCAN_SECS)
+                && queue.lock().unwrap().is_empty()
+            {
+                last_scan = Instant::now();
+                let model_id = {
+                    let embedder = embedder.read().await;
+                    embedder.is_initialized().then(|| embedder.model_id())
+                };
+                if let Some(model_id) = model_id {
+                    let queued = Self::enqueue_pending_embeddings(
+                        &db,
+                        Some(&model_id),
+                        &queue,
+                        REEMBED_SCAN_LIMIT,
+                    );
+                    if queued > 0 {
+                        debug!("Queued {} traces for (re-)embedding", queued);
+                    }
+                }
+            }
+
+            let should_flush = {
+                let q = queue.lock().unwrap();
+                !q.is_empty() && (!running || q.should_flush())
+            };
+
+            if should_flush {
+                let session_config = config_rx.borrow().session.clone();
+                if let Err(e) =
+                    Self::flush_embedding_queue(&db, &embedder, &queue, &stats, &session_config)
+                        .await
+                {
+                    error!("Error flushing embedding queue: {}", e);
+                }
+            }
+
+            if !running {
+                break;
+            }
+        }
+
+        info!("Embedding stage stopped");
+    }
+
+    /// 刷新嵌入队列：一次 embed_batch、一次事务写回，再对未归入 Session 的 trace 路由
+    async fn flush_embedding_queue(
+        db: &Arc<Database>,
+        embedder: &Arc<RwLock<TextEmbedder>>,
+        queue: &Arc<Mutex<EmbeddingQueue>>,
+        stats: &Arc<EmbeddingStats>,
+        session_config: &SessionConfig,
+    ) -> anyhow::Result<usize> {
+        let items = queue.lock().unwrap().drain();
+        if items.is_empty() {
+            return Ok(0);
+        }
+
+        let texts: Vec<String> = items.iter().map(|(text, _)| text.clone()).collect();
+        let started = Instant::now();
+
+        let (result, model_id) = {
+            let embedder_guard = embedder.read().await;
+            (
+                embedder_guard.embed_batch(&texts).await,
+                embedder_guard.model_id(),
+            )
+        };
+
+        let (items, embeddings) = match result {
+            Ok(v) if v.len() == items.len() => (items, v),
+            result => {
+                let error = match result {
+                    Ok(v) => anyhow::anyhow!(
+                        "Embedding count mismatch: expected {}, got {}",
+                        texts.len(),
+                        v.len()
+                    ),
+                    Err(e) => e,
+                };
+                warn!(
+                    "Embedding batch of {} failed ({}), retrying one at a time",
+                    items.len(),
+                    error
+                );
+                match Self::embed_individually(db, embedder, queue, items, &model_id).await {
+                    Some(embedded) => embedded,
+                    None => return Err(error),
+                }
+            }
+        };
+
+        let batch: Vec<(i64, Vec<u8>)> = items
+            .iter()
+            .zip(embeddings.iter())
+            .map(|((_, trace_id), embedding)| (*trace_id, Self::serialize_embedding(embedding)))
+            .collect();
+        db.update_trace_embeddings_batch(&batch, &model_id)?;
+
+        let elapsed_ms = (started.elapsed().as_millis() as u64).max(1);
+        stats
+            .embedded_count
+            .fetch_add(items.len() as u64, Ordering::SeqCst);
+        stats.batch_count.fetch_add(1, Ordering::SeqCst);
+        stats.busy_ms.fetch_add(elapsed_ms, Ordering::SeqCst);
+
+        info!(
+            "Embedded {} traces in one batch ({}ms, {:.1} traces/s)",
+            items.len(),
+            elapsed_ms,
+            items.len() as f64 * 1000.0 / elapsed_ms as f64
+        );
+
+        for ((_, trace_id), embedding) in items.iter().zip(embeddings.iter()) {
+            if let Err(e) =
+                Self::route_trace_by_embedding(db, session_config, *trace_id, embedding, &model_id)
+            {
+                warn!("Failed to route trace {} to session: {}", trace_id, e);
+            }
+        }
+
+        Ok(items.len())
+    }
+
+    /// 整批嵌入失败时逐条重试，返回成功的条目与向量
+    ///
+    /// 单独仍失败的 trace 记为嵌入失败（同一模型不再入队），不再拖住之后的每个批次；
+    /// 全部失败时多半是嵌入服务不可用，整批放回队列并返回 None。
+    async fn embed_individually(
+        db: &Database,
+        embedder: &Arc<RwLock<TextEmbedder>>,
+        queue: &Arc<Mutex<EmbeddingQueue>>,
+        items: Vec<(String, i64)>,
+        model_id: &str,
+    ) -> Option<(Vec<(String, i64)>, Vec<Vec<f32>>)> {
+        let mut embedded = Vec::new();
+        let mut embeddings = Vec::new();
+        let mut failed = Vec::new();
+        {
+            let embedder_guard = embedder.read().await;
+            for (text, trace_id) in items {
+                match embedder_guard
+                    .embed_batch(std::slice::from_ref(&text))
+                    .await
+                {
+                    Ok(mut v) if v.len() == 1 => {
+                        embeddings.push(v.remove(0));
+                        embedded.push((text, trace_id));
+                    }
+                    Ok(v) => failed.push((text, trace_id, format!("got {} embeddings", v.len()))),
+                    Err(e) => failed.push((text, trace_id, e.to_string())),
+                }
+            }
+        }
+
+        if embedded.is_empty() {
+            Self::requeue_embeddings(
+                queue,
+                failed
+                    .into_iter()
+                    .map(|(text, trace_id, _)| (text, trace_id))
+                    .collect(),
+            );
+            return None;
+        }
+        for (_, trace_id, error) in failed {
+            warn!("Trace {} cannot be embedded, skipping: {}", trace_id, error);
+            if let Err(e) = db.mark_trace_embedding_failed(trace_id, model_id, &error) {
+                warn!(
+                    "Failed to record embedding failure of trace {}: {}",
+                    trace_id, e
+                );
+            }
+        }
+        Some((embedded, embeddings))
+    }
+
+    /// 嵌入失败时放回队列，等待下一次刷新重试
+    fn requeue_embeddings(queue: &Arc<Mutex<EmbeddingQueue>>, items: Vec<(String, i64)>) {
+        let mut q = queue.lock().unwrap();
+        if q.len() + items.len() > MAX_EMBEDDING_BACKLOG {
+            warn!(
+                "Embedding backlog full, dropping {} traces (will be recovered by the background scan)",
+                items.len()
             );
+            return;
+        }
+        for (text, trace_id) in items {
+            q.enqueue(text, trace_id);
         }
+    }
+
+    /// 关闭已超过 `gap_threshold_ms` 没有新 trace 的会话
+    ///
+    /// 以最早一条尚未归入会话的 trace 为基准（不早于活跃窗口），积压的 trace 处理完之前
+    /// 不会因为分析延迟而提前关闭它们可能归入的会话。
+    fn close_idle_sessions(db: &Database, session_config: &SessionConfig) -> anyhow::Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let gap = session_config.gap_threshold_ms as i64;
+        let since = now - session_config.active_window_ms as i64;
+        let reference = db
+            .get_oldest_unrouted_trace_timestamp(since)?
+            .map_or(now, |ts| ts.min(now));
+
+        let closed = db.close_idle_sessions(reference - gap, now)?;
+        if closed > 0 {
+            debug!("Closed {} idle sessions", closed);
+        }
+        Ok(())
+    }
 
-        let chosen_session_id = match chosen_session_id {
+    /// 模型未选择 Session 的 trace：按与会话质心的相似度归入 open 会话，否则新建
+    ///
+    /// 已由模型归入会话的 trace 只把向量计入该会话的质心。
+    fn route_trace_by_embedding(
+        db: &Arc<Database>,
+        session_config: &SessionConfig,
+        trace_id: i64,
+        embedding: &[f32],
+        model_id: &str,
+    ) -> anyhow::Result<()> {
+        let Some(trace) = db.get_trace_by_id(trace_id)? else {
+            return Ok(());
+        };
+        if let Some(session_id) = trace.activity_session_id {
+            db.add_to_session_centroid(session_id, embedding, model_id)?;
+            return Ok(());
+        }
+
+        let description = trace
+            .vlm_raw_json
+            .as_deref()
+            .and_then(|s| serde_json::from_str::<ScreenDescription>(s).ok());
+
+        let candidates = db.get_active_session_centroids(
+            trace.timestamp,
+            session_config.active_window_ms as i64,
+            model_id,
+            session_config.max_active_sessions,
+        )?;
+
+        let session_id = match Self::pick_best_session_by_embedding(
+            embedding,
+            &candidates,
+            session_config.similarity_threshold,
+        ) {
             Some(id) => id,
             None => {
                 let seed_app = trace
                     .app_name
                     .as_deref()
-                    .or(description.detected_app.as_deref())
+                    .or(description.as_ref().and_then(|d| d.detected_app.as_deref()))
                     .unwrap_or("unknown");
                 db.create_activity_session(seed_app, trace.timestamp)?
             }
         };
 
         db.update_activity_session_from_vlm(
-            chosen_session_id,
+            session_id,
             trace.id,
-            trace.timestamp,
-            Some(description.summary.as_str()),
-            action_description,
-            description.activity_type.as_deref(),
-            &description.entities,
-            is_key_action,
-            description.session_title.as_deref(),
-            description.session_description.as_deref(),
+            description
+                .as_ref()
+                .and_then(|d| d.session_title.as_deref()),
+            description
+                .as_ref()
+                .and_then(|d| d.session_description.as_deref()),
         )?;
+        db.add_to_session_centroid(session_id, embedding, model_id)?;
 
-        info!(
-            "Trace {} processed: summary='{}', confidence={:.2}",
-            trace.id,
-            description.summary.chars().take(50).collect::<String>(),
-            description.confidence
+        debug!(
+            "Trace {} routed to session {} by embedding",
+            trace.id, session_id
         );
-
         Ok(())
     }
 
+    fn format_routing_corrections(
+        prompts: &PromptLibrary,
+        corrections: &[RoutingCorrection],
+    ) -> Option<String> {
+        let mut lines: Vec<String> = Vec::new();
+        for c in corrections {
+            let summary: String = c
+                .trace_summary
+                .as_deref()
+                .map(str::trim)
+                .unwrap_or("")
+                .chars()
+                .take(120)
+                .collect();
+            if summary.is_empty() {
+                continue;
+            }
+            let app = c.app_name.as_deref().unwrap_or("-");
+            lines.push(match c.wrong_session_title.as_deref() {
+                Some(wrong) => format!(
+                    "- \"{}\" (app_name=\"{}\") \"{}\" → \"{}\"",
+                    summary, app, wrong, c.correct_session_title
+                ),
+                None => format!(
+                    "- \"{}\" (app_name=\"{}\") → \"{}\"",
+                    summary, app, c.correct_session_title
+                ),
+            });
+        }
+        if lines.is_empty() {
+            return None;
+        }
+        Some(prompts.render(
+            PromptKind::ContextRoutingCorrections,
+            &[("corrections", &lines.join("\n"))],
+        ))
+    }
+
     fn format_key_actions_for_context(key_actions_json: &str, take_last: usize) -> Option<String> {
         Self::format_key_actions_for_context_with_header(
             key_actions_json,
@@ -592,7 +1548,7 @@ impl VlmTask {
         best.map(|(sid, _)| sid)
     }
 
-    fn deserialize_embedding(bytes: &[u8]) -> Option<Vec<f32>> {
+    pub(crate) fn deserialize_embedding(bytes: &[u8]) -> Option<Vec<f32>> {
         if bytes.len() % 4 != 0 {
             return None;
         }
@@ -604,7 +1560,7 @@ impl VlmTask {
         Some(v)
     }
 
-    fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
         let mut dot = 0.0f32;
         let mut na = 0.0f32;
         let mut nb = 0.0f32;
@@ -0,0 +1,355 @@
+//! 全文搜索查询构造
+//!
+//! `traces_fts` 使用 FTS5 `trigram` 分词器：任意连续 3 个字符都会被索引，中文、日文
+//! 等不以空格分词的文本也能按子串命中。查询侧先按空白、标点以及 CJK / 非 CJK 的边界切分
+//! 关键词，再把每个关键词作为短语交给 MATCH；trigram 无法匹配不足 3 个字符的关键词
+//! （如“数据”），这类关键词改用 LIKE 子串匹配。索引在后台重建期间所有关键词都用 LIKE。
+//!
+//! 支持按列限定：`summary:数据库`、`title:周报` 只在对应列中匹配，
+//! 列别名见 [`TRACE_COLUMNS`] 与 [`SESSION_COLUMNS`]。
+
+/// FTS 索引列
+#[derive(Debug)]
+pub(crate) struct FtsColumn {
+    /// 列名（与 content 表列名一致）
+    pub name: &'static str,
+    /// 查询中可用的列别名（`alias:term`）
+    pub aliases: &'static [&'static str],
+    /// bm25 权重
+    pub weight: f64,
+    /// LIKE 匹配时使用的列表达式；None 表示 content 表上的同名列
+    pub like_expr: Option<&'static str>,
+}
+
+/// `traces_fts` 的索引列（顺序与建表一致）
+pub(crate) const TRACE_COLUMNS: &[FtsColumn] = &[
+    FtsColumn {
+        name: "ocr_text",
+        aliases: &["ocr"],
+        weight: 1.0,
+        like_expr: None,
+    },
+    FtsColumn {
+        name: "window_title",
+        aliases: &["window"],
+        weight: 2.0,
+        like_expr: None,
+    },
+    FtsColumn {
+        name: "vlm_summary",
+        aliases: &["summary"],
+        weight: 4.0,
+        like_expr: None,
+    },
+    FtsColumn {
+        name: "vlm_action_description",
+        aliases: &["action"],
+        weight: 3.0,
+        like_expr: None,
+    },
+    FtsColumn {
+        name: "vlm_entities_json",
+        aliases: &["entity", "entities"],
+        weight: 3.0,
+        like_expr: None,
+    },
+];
+
+/// `sessions_fts` 的索引列（顺序与建表一致）
+///
+/// 只索引长度有限的标题、描述与关闭时生成的回顾，不索引持续增长的 context_text。
+/// 回顾来自 `session_lifecycle`，查询需 `LEFT JOIN session_lifecycle l`。
+pub(crate) const SESSION_COLUMNS: &[FtsColumn] = &[
+    FtsColumn {
+        name: "title",
+        aliases: &["title"],
+        weight: 5.0,
+        like_expr: None,
+    },
+    FtsColumn {
+        name: "description",
+        aliases: &["desc", "description"],
+        weight: 3.0,
+        like_expr: None,
+    },
+    FtsColumn {
+        name: "recap",
+        aliases: &["recap"],
+        weight: 1.0,
+        like_expr: Some("l.recap"),
+    },
+];
+
+/// 生成按列加权的排序表达式，如 `bm25(traces_fts, 1.0, 2.0, ...)`
+pub(crate) fn bm25_expr(table: &str, columns: &[FtsColumn]) -> String {
+    let weights: Vec<String> = columns.iter().map(|c| format!("{:.1}", c.weight)).collect();
+    format!("bm25({}, {})", table, weights.join(", "))
+}
+
+/// 需要用 LIKE 匹配的短关键词
+#[derive(Debug, PartialEq)]
+pub(crate) struct LikeTerm {
+    /// 限定的列；None 表示全部索引列
+    pub column: Option<&'static str>,
+    /// 已转义的关键词，可直接拼在 `%...%` 中
+    pub pattern: String,
+}
+
+/// 切分后的搜索条件（各关键词之间为 AND 关系）
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct FtsQuery {
+    /// 交给 `MATCH` 的表达式
+    pub match_expr: Option<String>,
+    pub like_terms: Vec<LikeTerm>,
+}
+
+impl FtsQuery {
+    pub fn is_empty(&self) -> bool {
+        self.match_expr.is_none() && self.like_terms.is_empty()
+    }
+
+    /// 生成 `JOIN ... WHERE ... ORDER BY ...` 子句与对应参数
+    ///
+    /// `fts_table` 为索引表名，`alias` 为 content 表在外层查询中的别名；
+    /// 只有 LIKE 条件时按 `fallback_order` 排序。
+    pub fn to_sql(
+        &self,
+        fts_table: &str,
+        alias: &str,
+        columns: &[FtsColumn],
+        fallback_order: &str,
+    ) -> (String, Vec<String>) {
+        let mut clause = String::new();
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        if let Some(expr) = &self.match_expr {
+            clause.push_str(&format!(
+                "JOIN {fts_table} ON {alias}.id = {fts_table}.rowid\n"
+            ));
+            conditions.push(format!("{fts_table} MATCH ?1"));
+            params.push(expr.clone());
+        }
+        let (likes, like_params) = self.like_conditions(alias, columns, params.len() + 1);
+        conditions.extend(likes);
+        params.extend(like_params);
+
+        clause.push_str(&format!("WHERE {}\n", conditions.join(" AND ")));
+        if self.match_expr.is_some() {
+            clause.push_str(&format!("ORDER BY {}", bm25_expr(fts_table, columns)));
+        } else {
+            clause.push_str(&format!("ORDER BY {fallback_order}"));
+        }
+        (clause, params)
+    }
+
+    /// 生成 LIKE 条件与对应参数；`table` 为 content 表别名，参数编号从 `first_param` 开始
+    pub fn like_conditions(
+        &self,
+        table: &str,
+        columns: &[FtsColumn],
+        first_param: usize,
+    ) -> (Vec<String>, Vec<String>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        for (i, term) in self.like_terms.iter().enumerate() {
+            let n = first_param + i;
+            let parts: Vec<String> = columns
+                .iter()
+                .filter(|c| term.column.is_none_or(|name| name == c.name))
+                .map(|c| match c.like_expr {
+                    Some(expr) => format!("{expr} LIKE ?{n} ESCAPE '{LIKE_ESCAPE}'"),
+                    None => format!("{table}.{} LIKE ?{n} ESCAPE '{LIKE_ESCAPE}'", c.name),
+                })
+                .collect();
+            conditions.push(format!("({})", parts.join(" OR ")));
+            params.push(format!("%{}%", term.pattern));
+        }
+        (conditions, params)
+    }
+}
+
+/// trigram 分词器可索引的最短关键词长度（字符数）
+const TRIGRAM_MIN_CHARS: usize = 3;
+
+/// LIKE 转义字符
+pub(crate) const LIKE_ESCAPE: char = '\\';
+
+/// 把用户输入切分为 FTS 查询，`columns` 为目标索引的列（用于解析 `alias:term`）
+///
+/// `use_index` 为 false（索引尚未重建完成）时所有关键词都用 LIKE 匹配。
+pub(crate) fn build_query(input: &str, columns: &[FtsColumn], use_index: bool) -> FtsQuery {
+    let mut match_terms: Vec<String> = Vec::new();
+    let mut like_terms: Vec<LikeTerm> = Vec::new();
+
+    for token in input.split_whitespace() {
+        let (column, text) = split_scope(token, columns);
+        for term in segment(text) {
+            if use_index && term.chars().count() >= TRIGRAM_MIN_CHARS {
+                let phrase = format!("\"{}\"", term.replace('"', "\"\""));
+                let phrase = match column {
+                    Some(name) => format!("{name} : {phrase}"),
+                    None => phrase,
+                };
+                if !match_terms.contains(&phrase) {
+                    match_terms.push(phrase);
+                }
+            } else {
+                let like = LikeTerm {
+                    column,
+                    pattern: escape_like(&term),
+                };
+                if !like_terms.contains(&like) {
+                    like_terms.push(like);
+                }
+            }
+        }
+    }
+
+    FtsQuery {
+        match_expr: (!match_terms.is_empty()).then(|| match_terms.join(" AND ")),
+        like_terms,
+    }
+}
+
+/// 解析 `alias:term` 形式的列限定；别名未知时整个 token 按普通关键词处理
+fn split_scope<'a>(token: &'a str, columns: &[FtsColumn]) -> (Option<&'static str>, &'a str) {
+    if let Some((alias, rest)) = token.split_once(':') {
+        let alias = alias.to_lowercase();
+        if let Some(col) = columns.iter().find(|c| c.aliases.contains(&alias.as_str())) {
+            return (Some(col.name), rest);
+        }
+    }
+    (None, token)
+}
+
+/// 按空白、标点以及 CJK / 非 CJK 边界切分关键词
+///
+/// 例如 `Rust数据库, 连接池` 切分为 `Rust`、`数据库`、`连接池`。
+pub(crate) fn segment(input: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut current_cjk = false;
+
+    for c in input.chars() {
+        if !is_term_char(c) {
+            if !current.is_empty() {
+                terms.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        let cjk = is_cjk(c);
+        if !current.is_empty() && cjk != current_cjk {
+            terms.push(std::mem::take(&mut current));
+        }
+        current_cjk = cjk;
+        current.push(c);
+    }
+    if !current.is_empty() {
+        terms.push(current);
+    }
+    terms
+}
+
+/// 关键词中保留的字符：字母数字与常见的标识符连接符（如 `snake_case`、`v1.2`、`C++`）
+fn is_term_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '+' | '#' | '@' | '/')
+}
+
+/// 是否为中日韩文字（汉字、假名、谚文）
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF   // 平假名、片假名
+        | 0x3400..=0x4DBF // CJK 扩展 A
+        | 0x4E00..=0x9FFF // CJK 统一汉字
+        | 0xAC00..=0xD7AF // 谚文音节
+        | 0xF900..=0xFAFF // CJK 兼容汉字
+        | 0x20000..=0x2FA1F // CJK 扩展 B 及之后
+    )
+}
+
+fn escape_like(term: &str) -> String {
+    let mut escaped = String::with_capacity(term.len());
+    for c in term.chars() {
+        if matches!(c, '%' | '_' | LIKE_ESCAPE) {
+            escaped.push(LIKE_ESCAPE);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment_mixed_language() {
+        assert_eq!(
+            segment("Rust数据库, 连接池 PostgreSQL配置"),
+            vec!["Rust", "数据库", "连接池", "PostgreSQL", "配置"]
+        );
+        assert_eq!(segment("  \"引号\" OR  "), vec!["引号", "OR"]);
+        assert_eq!(segment("データベース接続"), vec!["データベース接続"]);
+    }
+
+    #[test]
+    fn test_build_query_splits_short_terms() {
+        let q = build_query("数据库 配置 C++ api", TRACE_COLUMNS, true);
+        assert_eq!(
+            q.match_expr.as_deref(),
+            Some("\"数据库\" AND \"C++\" AND \"api\"")
+        );
+        assert_eq!(
+            q.like_terms,
+            vec![LikeTerm {
+                column: None,
+                pattern: "配置".to_string()
+            }]
+        );
+
+        let q = build_query("a_b", TRACE_COLUMNS, true);
+        assert_eq!(q.match_expr.as_deref(), Some("\"a_b\""));
+        let q = build_query("_x", TRACE_COLUMNS, true);
+        assert_eq!(q.like_terms[0].pattern, "\\_x");
+
+        assert!(build_query(" ，。 ", TRACE_COLUMNS, true).is_empty());
+
+        // 索引重建完成前全部走 LIKE
+        let q = build_query("数据库 api", TRACE_COLUMNS, false);
+        assert_eq!(q.match_expr, None);
+        assert_eq!(q.like_terms.len(), 2);
+    }
+
+    #[test]
+    fn test_build_query_column_scope() {
+        let q = build_query(
+            "Summary:Rust数据库 周报 url:https://a.b",
+            TRACE_COLUMNS,
+            true,
+        );
+        assert_eq!(
+            q.match_expr.as_deref(),
+            Some("vlm_summary : \"Rust\" AND vlm_summary : \"数据库\" AND \"url\" AND \"https\" AND \"//a.b\"")
+        );
+        assert_eq!(q.like_terms[0].column, None);
+
+        let q = build_query("title:日报", SESSION_COLUMNS, true);
+        assert_eq!(q.match_expr, None);
+        let (conditions, params) = q.like_conditions("s", SESSION_COLUMNS, 2);
+        assert_eq!(conditions, vec!["(s.title LIKE ?2 ESCAPE '\\')"]);
+        assert_eq!(params, vec!["%日报%"]);
+
+        // 回顾不在 content 表上，LIKE 使用覆盖的列表达式
+        let q = build_query("回顾", SESSION_COLUMNS, false);
+        let (conditions, _) = q.like_conditions("s", SESSION_COLUMNS, 1);
+        assert_eq!(
+            conditions,
+            vec!["(s.title LIKE ?1 ESCAPE '\\' OR s.description LIKE ?1 ESCAPE '\\' OR l.recap LIKE ?1 ESCAPE '\\')"]
+        );
+
+        assert_eq!(
+            bm25_expr("sessions_fts", SESSION_COLUMNS),
+            "bm25(sessions_fts, 5.0, 3.0, 1.0)"
+        );
+    }
+}
@@ -2,25 +2,239 @@
 //!
 //! 使用 SQLite 存储痕迹数据、摘要和设置。
 //! 使用 sqlite-vec 扩展进行向量搜索。
+//! 连接按读写分离组织（见 `pool`），阻塞操作可通过 `Database::call` 放到阻塞线程池执行。
 
+mod fts;
 pub mod models;
+mod pool;
 mod schema;
+mod vector;
 
 use anyhow::Result;
 use chrono::{Datelike, Utc};
 use directories::ProjectDirs;
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
 use std::fs;
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Arc, Once, RwLock};
 use tracing::{debug, info};
 
 pub use models::*;
+pub use pool::{ConnectionPool, DEFAULT_READER_COUNT};
+pub use vector::{VectorIndexConfig, VectorQuantization};
+
+/// 查找近重复 trace 时最多比较的候选数（按时间远近）
+const NEAR_DUPLICATE_CANDIDATES: u32 = 64;
+
+/// 会话 context_text 的最大字符数（超出时保留最新部分）
+const MAX_SESSION_CONTEXT_CHARS: usize = 262_144; // 256K
+
+/// 会话 key_actions_json 保留的关键行为数
+const MAX_SESSION_KEY_ACTIONS: usize = 200;
+
+/// 压缩后的 context_text 保留的最近记录条数
+const COMPACT_CONTEXT_LINES: usize = 20;
+
+/// 向量索引回填每批处理的 trace 数（每批单独持有写锁）
+const VECTOR_BACKFILL_BATCH: i64 = 1000;
+
+/// FTS 后台重建每批填充的行数（每批单独持有写锁）
+const FTS_REBUILD_BATCH: i64 = 2000;
+
+/// 压缩会话上下文：回顾 + 最近的 `COMPACT_CONTEXT_LINES` 条记录
+fn compact_context(context_text: Option<&str>, recap: &str) -> String {
+    let lines: Vec<&str> = context_text
+        .unwrap_or("")
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .collect();
+    let tail = &lines[lines.len().saturating_sub(COMPACT_CONTEXT_LINES)..];
+    if tail.is_empty() {
+        return recap.to_string();
+    }
+    let omitted = if tail.len() < lines.len() {
+        "…\n"
+    } else {
+        ""
+    };
+    format!("{}\n\n{}{}", recap, omitted, tail.join("\n"))
+}
+
+/// 会话由 trace 逐条累积的聚合字段：context_text、entities_json（计数）与 key_actions_json
+#[derive(Default)]
+struct SessionAggregate {
+    context: String,
+    counts: serde_json::Map<String, serde_json::Value>,
+    key_actions: Vec<serde_json::Value>,
+}
+
+impl SessionAggregate {
+    /// 从会话表中已保存的聚合字段恢复
+    fn from_columns(
+        context_text: Option<String>,
+        entities_json: Option<&str>,
+        key_actions_json: Option<&str>,
+    ) -> Self {
+        Self {
+            context: context_text.unwrap_or_default(),
+            counts: entities_json
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default(),
+            key_actions: key_actions_json
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// 写回会话表的 (context_text, entities_json, key_actions_json)，空值为 NULL
+    fn into_columns(self) -> (Option<String>, Option<String>, Option<String>) {
+        use serde_json::Value;
+        (
+            (!self.context.is_empty()).then_some(self.context),
+            (!self.counts.is_empty()).then(|| Value::Object(self.counts).to_string()),
+            (!self.key_actions.is_empty()).then(|| Value::Array(self.key_actions).to_string()),
+        )
+    }
+}
+
+/// 参与会话聚合的 trace 字段
+struct TraceRow {
+    id: i64,
+    timestamp: i64,
+    summary: Option<String>,
+    action_description: Option<String>,
+    activity_type: Option<String>,
+    entities_json: Option<String>,
+    is_key_action: bool,
+}
+
+impl TraceRow {
+    /// 按 `id, timestamp, vlm_summary, vlm_action_description, vlm_activity_type,
+    /// vlm_entities_json, is_key_action` 的列顺序读取
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            timestamp: row.get(1)?,
+            summary: row.get(2)?,
+            action_description: row.get(3)?,
+            activity_type: row.get(4)?,
+            entities_json: row.get(5)?,
+            is_key_action: row.get::<_, Option<bool>>(6)?.unwrap_or(false),
+        })
+    }
+}
+
+/// 把一条 trace 累积进会话聚合：实体计数、`[%m-%d %H:%M] 摘要` 上下文行与关键行为，
+/// 并裁剪到 `MAX_SESSION_CONTEXT_CHARS` / `MAX_SESSION_KEY_ACTIONS`
+fn fold_trace_into_session(aggregate: &mut SessionAggregate, trace: &TraceRow) {
+    use chrono::{DateTime, Local};
+    use serde_json::Value;
+
+    let entities: Vec<String> = trace
+        .entities_json
+        .as_deref()
+        .and_then(|s| serde_json::from_str(s).ok())
+        .unwrap_or_default();
+    for e in &entities {
+        if e.trim().is_empty() {
+            continue;
+        }
+        let current = aggregate
+            .counts
+            .get(e)
+            .and_then(|v| v.as_i64())
+            .unwrap_or(0);
+        aggregate.counts.insert(e.clone(), Value::from(current + 1));
+    }
+
+    let summary = trace.summary.as_deref().unwrap_or("").trim();
+    let context = &mut aggregate.context;
+    if !summary.is_empty() {
+        if !context.is_empty() && !context.ends_with('\n') {
+            context.push('\n');
+        }
+        let time_str = DateTime::from_timestamp_millis(trace.timestamp)
+            .map(|t| t.with_timezone(&Local).format("%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| "?".to_string());
+        context.push_str(&format!("[{}] {}\n", time_str, summary));
+    }
+    // 只保留末尾的 MAX_SESSION_CONTEXT_CHARS 个字符（字节数不超过上限时无需计数）
+    if context.len() > MAX_SESSION_CONTEXT_CHARS {
+        let excess = context
+            .chars()
+            .count()
+            .saturating_sub(MAX_SESSION_CONTEXT_CHARS);
+        if let Some((cut, _)) = context.char_indices().nth(excess) {
+            context.drain(..cut);
+        }
+    }
+
+    if trace.is_key_action {
+        let action_text = trace
+            .action_description
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .unwrap_or(summary);
+        let key_actions = &mut aggregate.key_actions;
+        key_actions.push(serde_json::json!({
+            "timestamp": trace.timestamp,
+            "trace_id": trace.id,
+            "summary": summary,
+            "action_description": action_text,
+            "activity_type": trace.activity_type,
+            "entities": entities,
+        }));
+        if key_actions.len() > MAX_SESSION_KEY_ACTIONS {
+            key_actions.drain(..key_actions.len() - MAX_SESSION_KEY_ACTIONS);
+        }
+    }
+}
+
+/// 分批重建 FTS 索引的语句：(content 表, 填充 `?1 < rowid <= ?2` 的 INSERT)
+///
+/// `sessions_fts` 自带内容，从会话表与回顾填充；`traces_fts` 从 content 表逐行写入。
+fn rebuild_fts_batch_sql(table: &str) -> Option<(&'static str, &'static str)> {
+    match table {
+        "traces_fts" => Some((
+            "traces",
+            r#"
+            INSERT INTO traces_fts(rowid, ocr_text, window_title, vlm_summary, vlm_action_description, vlm_entities_json)
+            SELECT id, ocr_text, window_title, vlm_summary, vlm_action_description, vlm_entities_json
+            FROM traces
+            WHERE id > ?1 AND id <= ?2
+            "#,
+        )),
+        "sessions_fts" => Some((
+            "activity_sessions",
+            r#"
+            INSERT INTO sessions_fts(rowid, title, description, recap)
+            SELECT s.id, s.title, s.description, l.recap
+            FROM activity_sessions s
+            LEFT JOIN session_lifecycle l ON l.session_id = s.id
+            WHERE s.id > ?1 AND s.id <= ?2
+            "#,
+        )),
+        _ => None,
+    }
+}
+
+/// 解析 `traces.phash`（16 位十六进制字符串）
+fn parse_phash(bytes: &[u8]) -> Option<u64> {
+    u64::from_str_radix(std::str::from_utf8(bytes).ok()?, 16).ok()
+}
+
+/// sqlite-vec 扩展只需注册一次
+static REGISTER_VEC_EXTENSION: Once = Once::new();
 
 /// 数据库管理器
 pub struct Database {
-    conn: Mutex<Connection>,
+    pool: ConnectionPool,
     data_dir: PathBuf,
+    /// 向量索引布局（由 `apply_vector_index_config` 更新）
+    vector_config: RwLock<VectorIndexConfig>,
+    /// 等待后台重建的 FTS 索引（重建完成前搜索改用 LIKE）
+    fts_pending: RwLock<Vec<String>>,
 }
 
 impl Database {
@@ -46,16 +260,47 @@ impl Database {
         })
     }
 
+    fn activity_session_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<ActivitySession> {
+        Ok(ActivitySession {
+            id: row.get(0)?,
+            app_name: row.get(1)?,
+            title: row.get(2)?,
+            description: row.get(3)?,
+            start_time: row.get(4)?,
+            end_time: row.get(5)?,
+            start_trace_id: row.get(6)?,
+            end_trace_id: row.get(7)?,
+            trace_count: row.get::<_, i64>(8)? as u32,
+            context_text: row.get(9)?,
+            entities_json: row.get(10)?,
+            key_actions_json: row.get(11)?,
+            created_at: row.get(12)?,
+            updated_at: row.get(13)?,
+            state: row.get(14)?,
+            closed_at: row.get(15)?,
+            recap: row.get(16)?,
+        })
+    }
+
     /// 创建或打开数据库
     pub fn new() -> Result<Self> {
+        Self::open_at(Self::resolve_data_dir()?)
+    }
+
+    /// 在指定数据目录创建或打开数据库
+    pub fn open_at(data_dir: PathBuf) -> Result<Self> {
+        Self::open_with_readers(data_dir, DEFAULT_READER_COUNT)
+    }
+
+    /// 在指定数据目录创建或打开数据库，并指定只读连接数
+    pub fn open_with_readers(data_dir: PathBuf, reader_count: usize) -> Result<Self> {
         // 注册 sqlite-vec 扩展（必须在打开任何连接之前）
-        unsafe {
+        REGISTER_VEC_EXTENSION.call_once(|| unsafe {
             rusqlite::ffi::sqlite3_auto_extension(Some(std::mem::transmute(
                 sqlite_vec::sqlite3_vec_init as *const (),
             )));
-        }
+        });
 
-        let data_dir = Self::resolve_data_dir()?;
         fs::create_dir_all(&data_dir)?;
 
         let db_path = data_dir.join("engram.db");
@@ -67,15 +312,93 @@ impl Database {
         let vec_version: String = conn.query_row("SELECT vec_version()", [], |row| row.get(0))?;
         info!("sqlite-vec extension loaded: v{}", vec_version);
 
-        // 初始化 Schema
+        // 初始化 Schema（写连接负责建表与迁移，WAL 模式在此开启）
         schema::init_schema(&conn)?;
+        let fts_pending: Vec<String> = {
+            let mut stmt = conn.prepare("SELECT name FROM fts_rebuilds")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let pool = ConnectionPool::new(conn, &db_path, reader_count)?;
+        info!(
+            "Database pool ready (1 writer, {} readers)",
+            pool.reader_count()
+        );
 
         Ok(Self {
-            conn: Mutex::new(conn),
+            pool,
             data_dir,
+            vector_config: RwLock::new(VectorIndexConfig::default()),
+            fts_pending: RwLock::new(fts_pending),
         })
     }
 
+    /// 重建 Schema 初始化时登记的 FTS 索引（耗时较长，应在后台调用）
+    ///
+    /// 按 rowid 分批填充，每批单独持有写锁并提交进度，批次之间采集与会话的写入可以继续；
+    /// 中断后下次启动从 `fts_rebuilds` 记录的位置继续。全部完成后才移除登记，此前搜索保持 LIKE。
+    pub fn rebuild_pending_fts(&self) -> Result<()> {
+        let pending = self.fts_pending.read().unwrap().clone();
+        for table in pending {
+            let Some((content, insert_sql)) = rebuild_fts_batch_sql(&table) else {
+                continue;
+            };
+            let started = std::time::Instant::now();
+            loop {
+                let mut conn = self.pool.writer();
+                let tx = conn.transaction()?;
+                let (cursor, max_rowid): (i64, i64) = tx
+                    .prepare_cached(
+                        "SELECT last_rowid, max_rowid FROM fts_rebuilds WHERE name = ?1",
+                    )?
+                    .query_row([&table], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                let batch_end: Option<i64> = tx
+                    .prepare_cached(&format!(
+                        "SELECT MAX(id) FROM (SELECT id FROM {content} WHERE id > ?1 AND id <= ?2 ORDER BY id LIMIT ?3)"
+                    ))?
+                    .query_row(
+                        rusqlite::params![cursor, max_rowid, FTS_REBUILD_BATCH],
+                        |row| row.get(0),
+                    )?;
+                let Some(batch_end) = batch_end else {
+                    tx.execute("DELETE FROM fts_rebuilds WHERE name = ?1", [&table])?;
+                    tx.commit()?;
+                    break;
+                };
+                tx.prepare_cached(insert_sql)?
+                    .execute([cursor, batch_end])?;
+                tx.execute(
+                    "UPDATE fts_rebuilds SET last_rowid = ?1 WHERE name = ?2",
+                    rusqlite::params![batch_end, table],
+                )?;
+                tx.commit()?;
+            }
+            self.fts_pending.write().unwrap().retain(|t| t != &table);
+            info!(
+                "Rebuilt {} index in {}ms",
+                table,
+                started.elapsed().as_millis()
+            );
+        }
+        Ok(())
+    }
+
+    /// FTS 索引是否可用（等待后台重建时为 false）
+    fn fts_ready(&self, table: &str) -> bool {
+        !self.fts_pending.read().unwrap().iter().any(|t| t == table)
+    }
+
+    /// 在阻塞线程池中执行数据库操作，避免阻塞异步运行时
+    pub async fn call<F, T>(self: &Arc<Self>, f: F) -> Result<T>
+    where
+        F: FnOnce(&Database) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || f(&db)).await?
+    }
+
     /// 获取数据目录（静态方法）
     fn resolve_data_dir() -> Result<PathBuf> {
         if let Some(proj_dirs) = ProjectDirs::from("com", "engram", "Engram") {
@@ -140,7 +463,7 @@ impl Database {
 
     /// 插入痕迹记录
     pub fn insert_trace(&self, trace: &NewTrace) -> Result<(i64, Option<i64>)> {
-        let mut conn = self.conn.lock().unwrap();
+        let mut conn = self.pool.writer();
         // 多线程 Session：trace 插入时不绑定 session，交由 VlmTask 在拿到 embedding 后路由/聚类。
         let session_id: Option<i64> = None;
 
@@ -182,8 +505,8 @@ impl Database {
         limit: u32,
         offset: u32,
     ) -> Result<Vec<Trace>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
             r#"
             SELECT id, timestamp, image_path, app_name, window_title,
                    is_fullscreen,
@@ -210,6 +533,30 @@ impl Database {
         Ok(result)
     }
 
+    /// 按 ID 获取痕迹
+    pub fn get_trace_by_id(&self, id: i64) -> Result<Option<Trace>> {
+        let conn = self.pool.reader();
+        let result = conn.query_row(
+            r#"
+            SELECT id, timestamp, image_path, app_name, window_title,
+                   is_fullscreen,
+                   is_idle, ocr_text, activity_session_id, is_key_action,
+                   vlm_summary, vlm_action_description, vlm_activity_type, vlm_confidence, vlm_entities_json, vlm_raw_json,
+                   created_at
+            FROM traces
+            WHERE id = ?1
+            "#,
+            rusqlite::params![id],
+            Self::trace_from_row,
+        );
+
+        match result {
+            Ok(trace) => Ok(Some(trace)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     /// 按时间范围和应用过滤查询痕迹
     pub fn get_traces_filtered(
         &self,
@@ -218,7 +565,7 @@ impl Database {
         app_filter: Option<&Vec<String>>,
         limit: u32,
     ) -> Result<Vec<Trace>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.reader();
 
         // 构建动态 SQL
         let (sql, has_filter) = if let Some(apps) = app_filter {
@@ -279,7 +626,7 @@ impl Database {
             )
         };
 
-        let mut stmt = conn.prepare(&sql)?;
+        let mut stmt = conn.prepare_cached(&sql)?;
 
         // 构建参数
         let traces = if has_filter {
@@ -304,9 +651,19 @@ impl Database {
     }
 
     /// 全文搜索
+    ///
+    /// 查询按空白、标点与 CJK 边界切分为关键词（AND）；不足 3 个字符的关键词
+    /// trigram 索引无法匹配，改用 LIKE 子串匹配（索引在后台重建期间全部改用 LIKE）。
+    /// 结果按 bm25 列加权排序，支持 `summary:`、`action:`、`entity:` 等列限定语法。
     pub fn search_text(&self, query: &str, limit: u32) -> Result<Vec<Trace>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
+        let query = fts::build_query(query, fts::TRACE_COLUMNS, self.fts_ready("traces_fts"));
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (clause, params) =
+            query.to_sql("traces_fts", "t", fts::TRACE_COLUMNS, "t.timestamp DESC");
+
+        let sql = format!(
             r#"
             SELECT t.id, t.timestamp, t.image_path, t.app_name, t.window_title,
                    t.is_fullscreen,
@@ -314,14 +671,14 @@ impl Database {
                    t.vlm_summary, t.vlm_action_description, t.vlm_activity_type, t.vlm_confidence, t.vlm_entities_json, t.vlm_raw_json,
                    t.created_at
             FROM traces t
-            JOIN traces_fts fts ON t.id = fts.rowid
-            WHERE traces_fts MATCH ?1
-            ORDER BY rank
-            LIMIT ?2
-            "#,
-        )?;
+            {clause}
+            LIMIT {limit}
+            "#
+        );
 
-        let traces = stmt.query_map(rusqlite::params![query, limit], |row| {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let traces = stmt.query_map(rusqlite::params_from_iter(params.iter()), |row| {
             Self::trace_from_row(row)
         })?;
 
@@ -333,9 +690,48 @@ impl Database {
         Ok(result)
     }
 
+    /// 会话全文搜索（标题、描述与关闭时生成的回顾），语法与 [`Self::search_text`] 相同，
+    /// 支持 `title:`、`desc:`、`recap:` 列限定；持续增长的 context_text 不进索引
+    pub fn search_sessions_text(&self, query: &str, limit: u32) -> Result<Vec<ActivitySession>> {
+        let query = fts::build_query(query, fts::SESSION_COLUMNS, self.fts_ready("sessions_fts"));
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (clause, params) = query.to_sql(
+            "sessions_fts",
+            "activity_sessions",
+            fts::SESSION_COLUMNS,
+            "activity_sessions.end_time DESC",
+        );
+
+        let sql = format!(
+            r#"
+            SELECT
+                activity_sessions.id, activity_sessions.app_name, activity_sessions.title,
+                activity_sessions.description, activity_sessions.start_time, activity_sessions.end_time,
+                activity_sessions.start_trace_id, activity_sessions.end_trace_id, activity_sessions.trace_count,
+                activity_sessions.context_text, activity_sessions.entities_json, activity_sessions.key_actions_json,
+                activity_sessions.created_at, activity_sessions.updated_at,
+                COALESCE(l.state, 'open'), l.closed_at, l.recap
+            FROM activity_sessions
+            LEFT JOIN session_lifecycle l ON l.session_id = activity_sessions.id
+            {clause}
+            LIMIT {limit}
+            "#
+        );
+
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map(
+            rusqlite::params_from_iter(params.iter()),
+            Self::activity_session_from_row,
+        )?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
     /// 获取存储统计
     pub fn get_storage_stats(&self) -> Result<StorageStats> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.reader();
 
         let total_traces: i64 =
             conn.query_row("SELECT COUNT(*) FROM traces", [], |row| row.get(0))?;
@@ -390,10 +786,75 @@ impl Database {
         size
     }
 
+    /// 清理早于 `cutoff` 的截图文件（热数据 -> 温数据），返回清理数量
+    ///
+    /// OCR、向量与元数据保留；尚未完成分析的 trace 不处理，避免 VLM 任务找不到图片。
+    pub fn prune_screenshots_before(&self, cutoff: i64) -> Result<usize> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let paths: Vec<(i64, String)> = {
+            let mut stmt = tx.prepare_cached(
+                "SELECT id, image_path FROM traces
+                 WHERE timestamp < ?1 AND image_path != '' AND ocr_text IS NOT NULL",
+            )?;
+            let rows = stmt.query_map([cutoff], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        {
+            let mut stmt = tx.prepare_cached("UPDATE traces SET image_path = '' WHERE id = ?1")?;
+            for (id, _) in &paths {
+                stmt.execute([id])?;
+            }
+        }
+        tx.commit()?;
+
+        for (_, path) in &paths {
+            self.remove_screenshot(path);
+        }
+
+        Ok(paths.len())
+    }
+
+    /// 删除早于 `cutoff` 的 traces（温数据 -> 冷数据），摘要、实体与会话保留
+    pub fn delete_traces_before(&self, cutoff: i64) -> Result<usize> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let paths: Vec<String> = {
+            let mut stmt = tx.prepare_cached(
+                "SELECT image_path FROM traces WHERE timestamp < ?1 AND image_path != ''",
+            )?;
+            let rows = stmt.query_map([cutoff], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        Self::delete_vec_entries_before(&tx, cutoff)?;
+
+        let deleted = tx.execute("DELETE FROM traces WHERE timestamp < ?1", [cutoff])?;
+        tx.commit()?;
+
+        for path in &paths {
+            self.remove_screenshot(path);
+        }
+
+        Ok(deleted)
+    }
+
+    /// 删除截图文件（文件已不存在时忽略）
+    fn remove_screenshot(&self, relative_path: &str) {
+        if let Err(e) = fs::remove_file(self.data_dir.join(relative_path)) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                debug!("Failed to remove screenshot {}: {}", relative_path, e);
+            }
+        }
+    }
+
     /// 获取时间范围内的不同应用名称
     pub fn get_distinct_apps(&self, start_time: i64, end_time: i64) -> Result<Vec<String>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
             "SELECT DISTINCT app_name FROM traces
              WHERE timestamp >= ?1 AND timestamp <= ?2 AND app_name IS NOT NULL
              ORDER BY app_name",
@@ -411,6 +872,57 @@ impl Database {
         Ok(result)
     }
 
+    /// 统计时间范围 `[start_time, end_time)` 内 trace 的停留时长
+    ///
+    /// 每条 trace 的时长为到下一条 trace（或范围结束）的间隔，最长 `max_span_ms`，
+    /// 避免锁屏、休眠等空档被计入。按会话、应用与活动类型分组；
+    /// 未归入会话的 trace 额外按窗口标题分组。
+    pub fn get_trace_durations(
+        &self,
+        start_time: i64,
+        end_time: i64,
+        max_span_ms: i64,
+    ) -> Result<Vec<TraceDuration>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT activity_session_id, app_name, activity_type, title,
+                   COUNT(*), SUM(duration)
+            FROM (
+                SELECT activity_session_id, app_name,
+                       COALESCE(NULLIF(vlm_activity_type, ''), 'other') AS activity_type,
+                       CASE WHEN activity_session_id IS NULL THEN window_title END AS title,
+                       MIN(COALESCE(LEAD(timestamp) OVER (ORDER BY timestamp), ?2),
+                           ?2, timestamp + ?3) - timestamp AS duration
+                FROM traces
+                WHERE timestamp >= ?1 AND timestamp < ?2 AND is_idle = 0
+            )
+            GROUP BY activity_session_id, app_name, activity_type, title
+            ORDER BY SUM(duration) DESC
+            "#,
+        )?;
+
+        let rows = stmt.query_map(
+            rusqlite::params![start_time, end_time, max_span_ms],
+            |row| {
+                Ok(TraceDuration {
+                    session_id: row.get(0)?,
+                    app_name: row.get(1)?,
+                    activity_type: row.get(2)?,
+                    window_title: row.get(3)?,
+                    trace_count: row.get(4)?,
+                    duration_ms: row.get(5)?,
+                })
+            },
+        )?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
+        }
+        Ok(result)
+    }
+
     /// 获取活动会话列表（对外主入口）
     pub fn get_activity_sessions(
         &self,
@@ -420,7 +932,7 @@ impl Database {
         limit: u32,
         offset: u32,
     ) -> Result<Vec<ActivitySession>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.reader();
 
         let (sql, has_filter) = if let Some(apps) = app_filter {
             if apps.is_empty() {
@@ -430,8 +942,10 @@ impl Database {
                         id, app_name, title, description, start_time, end_time,
                         start_trace_id, end_trace_id, trace_count,
                         context_text, entities_json, key_actions_json,
-                        created_at, updated_at
+                        created_at, updated_at,
+                        COALESCE(l.state, 'open'), l.closed_at, l.recap
                     FROM activity_sessions
+                    LEFT JOIN session_lifecycle l ON l.session_id = activity_sessions.id
                     WHERE start_time <= ?2 AND end_time >= ?1
                     ORDER BY end_time DESC
                     LIMIT ?3 OFFSET ?4
@@ -449,8 +963,10 @@ impl Database {
                             id, app_name, title, description, start_time, end_time,
                             start_trace_id, end_trace_id, trace_count,
                             context_text, entities_json, key_actions_json,
-                            created_at, updated_at
+                            created_at, updated_at,
+                            COALESCE(l.state, 'open'), l.closed_at, l.recap
                         FROM activity_sessions
+                        LEFT JOIN session_lifecycle l ON l.session_id = activity_sessions.id
                         WHERE start_time <= ?2 AND end_time >= ?1
                           AND app_name IN ({})
                         ORDER BY end_time DESC
@@ -468,8 +984,10 @@ impl Database {
                     id, app_name, title, description, start_time, end_time,
                     start_trace_id, end_trace_id, trace_count,
                     context_text, entities_json, key_actions_json,
-                    created_at, updated_at
+                    created_at, updated_at,
+                    COALESCE(l.state, 'open'), l.closed_at, l.recap
                 FROM activity_sessions
+                LEFT JOIN session_lifecycle l ON l.session_id = activity_sessions.id
                 WHERE start_time <= ?2 AND end_time >= ?1
                 ORDER BY end_time DESC
                 LIMIT ?3 OFFSET ?4
@@ -479,7 +997,7 @@ impl Database {
             )
         };
 
-        let mut stmt = conn.prepare(&sql)?;
+        let mut stmt = conn.prepare_cached(&sql)?;
 
         let sessions = if has_filter {
             let apps = app_filter.unwrap();
@@ -494,46 +1012,12 @@ impl Database {
             }
             let params_refs: Vec<&dyn rusqlite::ToSql> =
                 params.iter().map(|p| p.as_ref()).collect();
-            stmt.query_map(params_refs.as_slice(), |row| {
-                Ok(ActivitySession {
-                    id: row.get(0)?,
-                    app_name: row.get(1)?,
-                    title: row.get(2)?,
-                    description: row.get(3)?,
-                    start_time: row.get(4)?,
-                    end_time: row.get(5)?,
-                    start_trace_id: row.get(6)?,
-                    end_trace_id: row.get(7)?,
-                    trace_count: row.get::<_, i64>(8)? as u32,
-                    context_text: row.get(9)?,
-                    entities_json: row.get(10)?,
-                    key_actions_json: row.get(11)?,
-                    created_at: row.get(12)?,
-                    updated_at: row.get(13)?,
-                })
-            })?
-            .collect::<std::result::Result<Vec<_>, _>>()?
+            stmt.query_map(params_refs.as_slice(), Self::activity_session_from_row)?
+                .collect::<std::result::Result<Vec<_>, _>>()?
         } else {
             stmt.query_map(
                 rusqlite::params![start_time, end_time, limit, offset],
-                |row| {
-                    Ok(ActivitySession {
-                        id: row.get(0)?,
-                        app_name: row.get(1)?,
-                        title: row.get(2)?,
-                        description: row.get(3)?,
-                        start_time: row.get(4)?,
-                        end_time: row.get(5)?,
-                        start_trace_id: row.get(6)?,
-                        end_trace_id: row.get(7)?,
-                        trace_count: row.get::<_, i64>(8)? as u32,
-                        context_text: row.get(9)?,
-                        entities_json: row.get(10)?,
-                        key_actions_json: row.get(11)?,
-                        created_at: row.get(12)?,
-                        updated_at: row.get(13)?,
-                    })
-                },
+                Self::activity_session_from_row,
             )?
             .collect::<std::result::Result<Vec<_>, _>>()?
         };
@@ -542,36 +1026,21 @@ impl Database {
     }
 
     pub fn get_activity_session_by_id(&self, id: i64) -> Result<Option<ActivitySession>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.reader();
         let result = conn.query_row(
             r#"
             SELECT
                 id, app_name, title, description, start_time, end_time,
                 start_trace_id, end_trace_id, trace_count,
                 context_text, entities_json, key_actions_json,
-                created_at, updated_at
+                created_at, updated_at,
+                COALESCE(l.state, 'open'), l.closed_at, l.recap
             FROM activity_sessions
+            LEFT JOIN session_lifecycle l ON l.session_id = activity_sessions.id
             WHERE id = ?1
             "#,
             rusqlite::params![id],
-            |row| {
-                Ok(ActivitySession {
-                    id: row.get(0)?,
-                    app_name: row.get(1)?,
-                    title: row.get(2)?,
-                    description: row.get(3)?,
-                    start_time: row.get(4)?,
-                    end_time: row.get(5)?,
-                    start_trace_id: row.get(6)?,
-                    end_trace_id: row.get(7)?,
-                    trace_count: row.get::<_, i64>(8)? as u32,
-                    context_text: row.get(9)?,
-                    entities_json: row.get(10)?,
-                    key_actions_json: row.get(11)?,
-                    created_at: row.get(12)?,
-                    updated_at: row.get(13)?,
-                })
-            },
+            Self::activity_session_from_row,
         );
 
         match result {
@@ -588,7 +1057,7 @@ impl Database {
         } else {
             app_name
         };
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.writer();
         conn.execute(
             r#"
             INSERT INTO activity_sessions (app_name, start_time, end_time, trace_count)
@@ -605,40 +1074,28 @@ impl Database {
         active_window_ms: i64,
         limit: u32,
     ) -> Result<Vec<ActivitySession>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.reader();
         let since = now_ts - active_window_ms;
-        let mut stmt = conn.prepare(
+        let mut stmt = conn.prepare_cached(
             r#"
             SELECT
                 id, app_name, title, description, start_time, end_time,
                 start_trace_id, end_trace_id, trace_count,
                 context_text, entities_json, key_actions_json,
-                created_at, updated_at
+                created_at, updated_at,
+                COALESCE(l.state, 'open'), l.closed_at, l.recap
             FROM activity_sessions
-            WHERE end_time >= ?1
+            LEFT JOIN session_lifecycle l ON l.session_id = activity_sessions.id
+            WHERE end_time >= ?1 AND COALESCE(l.state, 'open') = 'open'
             ORDER BY end_time DESC
             LIMIT ?2
             "#,
         )?;
 
-        let rows = stmt.query_map(rusqlite::params![since, limit], |row| {
-            Ok(ActivitySession {
-                id: row.get(0)?,
-                app_name: row.get(1)?,
-                title: row.get(2)?,
-                description: row.get(3)?,
-                start_time: row.get(4)?,
-                end_time: row.get(5)?,
-                start_trace_id: row.get(6)?,
-                end_trace_id: row.get(7)?,
-                trace_count: row.get::<_, i64>(8)? as u32,
-                context_text: row.get(9)?,
-                entities_json: row.get(10)?,
-                key_actions_json: row.get(11)?,
-                created_at: row.get(12)?,
-                updated_at: row.get(13)?,
-            })
-        })?;
+        let rows = stmt.query_map(
+            rusqlite::params![since, limit],
+            Self::activity_session_from_row,
+        )?;
 
         let mut result = Vec::new();
         for r in rows {
@@ -647,35 +1104,45 @@ impl Database {
         Ok(result)
     }
 
-    pub fn get_active_session_last_embeddings(
+    /// 活跃窗口内 open 会话的质心向量（用于 embedding 路由）
+    ///
+    /// 尚未计算质心的会话退回使用最后一条 trace 的向量。质心与回退向量都只取
+    /// 嵌入模型 `model` 生成的，其他模型的向量不可比较，对应会话不参与路由。
+    pub fn get_active_session_centroids(
         &self,
         now_ts: i64,
         active_window_ms: i64,
+        model: &str,
         limit: u32,
     ) -> Result<Vec<(i64, Vec<u8>)>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.reader();
         let since = now_ts - active_window_ms;
-        let mut stmt = conn.prepare(
+        let mut stmt = conn.prepare_cached(
             r#"
-            SELECT t.activity_session_id, t.embedding
-            FROM traces t
-            JOIN (
-                SELECT activity_session_id, MAX(timestamp) AS max_ts
-                FROM traces
-                WHERE activity_session_id IS NOT NULL
-                  AND embedding IS NOT NULL
-                  AND timestamp >= ?1
-                GROUP BY activity_session_id
-            ) m
-              ON t.activity_session_id = m.activity_session_id
-             AND t.timestamp = m.max_ts
-            ORDER BY t.timestamp DESC
+            SELECT s.id, COALESCE(
+                (
+                    SELECT l.centroid FROM session_centroid_models cm
+                    WHERE cm.session_id = s.id AND cm.model = ?3
+                ),
+                (
+                    SELECT t.embedding FROM traces t
+                    JOIN trace_embedding_models m ON m.trace_id = t.id
+                    WHERE t.activity_session_id = s.id AND t.embedding IS NOT NULL
+                      AND m.model = ?3
+                    ORDER BY t.timestamp DESC
+                    LIMIT 1
+                )
+            )
+            FROM activity_sessions s
+            LEFT JOIN session_lifecycle l ON l.session_id = s.id
+            WHERE s.end_time >= ?1 AND COALESCE(l.state, 'open') = 'open'
+            ORDER BY s.end_time DESC
             LIMIT ?2
             "#,
         )?;
 
-        let rows = stmt.query_map(rusqlite::params![since, limit], |row| {
-            let sid: Option<i64> = row.get(0)?;
+        let rows = stmt.query_map(rusqlite::params![since, limit, model], |row| {
+            let sid: i64 = row.get(0)?;
             let emb: Option<Vec<u8>> = row.get(1)?;
             Ok((sid, emb))
         })?;
@@ -683,69 +1150,272 @@ impl Database {
         let mut result = Vec::new();
         for r in rows {
             let (sid, emb) = r?;
-            if let (Some(sid), Some(emb)) = (sid, emb) {
+            if let Some(emb) = emb {
                 result.push((sid, emb));
             }
         }
         Ok(result)
     }
 
-    pub fn get_recent_traces_before(
+    /// 把一条 trace 的向量计入会话质心（增量均值）
+    ///
+    /// 质心只混合同一嵌入模型 `model` 的向量：模型或维度变化时以新向量重新开始。
+    pub fn add_to_session_centroid(
         &self,
-        before_timestamp: i64,
-        limit: u32,
-    ) -> Result<Vec<Trace>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            r#"
-            SELECT id, timestamp, image_path, app_name, window_title,
-                   is_fullscreen,
-                   is_idle, ocr_text, activity_session_id, is_key_action,
-                   vlm_summary, vlm_action_description, vlm_activity_type, vlm_confidence, vlm_entities_json, vlm_raw_json,
-                   created_at
-            FROM traces
-            WHERE timestamp < ?1
-              AND ocr_text IS NOT NULL
-            ORDER BY timestamp DESC
-            LIMIT ?2
-            "#,
-        )?;
+        session_id: i64,
+        embedding: &[f32],
+        model: &str,
+    ) -> Result<()> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
 
-        let rows = stmt.query_map(rusqlite::params![before_timestamp, limit], |row| {
-            Self::trace_from_row(row)
-        })?;
+        let current: Option<(Option<Vec<u8>>, i64, Option<String>)> = tx
+            .prepare_cached(
+                r#"
+                SELECT l.centroid, l.centroid_count, m.model
+                FROM session_lifecycle l
+                LEFT JOIN session_centroid_models m ON m.session_id = l.session_id
+                WHERE l.session_id = ?1
+                "#,
+            )?
+            .query_row([session_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()?;
 
-        let mut result = Vec::new();
-        for r in rows {
-            result.push(r?);
+        let (mut centroid, mut count) = match current {
+            Some((Some(bytes), count, Some(current_model))) if current_model == model => {
+                (Self::deserialize_embedding(&bytes), count.max(0))
+            }
+            _ => (Vec::new(), 0),
+        };
+        if centroid.len() != embedding.len() {
+            centroid = vec![0.0; embedding.len()];
+            count = 0;
         }
-        Ok(result)
+        let n = count as f32;
+        for (c, e) in centroid.iter_mut().zip(embedding) {
+            *c = (*c * n + e) / (n + 1.0);
+        }
+        Self::store_session_centroid(&tx, session_id, &centroid, count + 1, Some(model))?;
+
+        tx.commit()?;
+        Ok(())
     }
 
-    pub fn get_traces_by_activity_session(
-        &self,
+    /// 保存会话质心及其嵌入模型（`model` 为 None 表示旧版本写入、未记录模型的向量）
+    fn store_session_centroid(
+        conn: &Connection,
         session_id: i64,
-        limit: u32,
-        offset: u32,
-    ) -> Result<Vec<Trace>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
+        centroid: &[f32],
+        count: i64,
+        model: Option<&str>,
+    ) -> Result<()> {
+        let bytes: Option<Vec<u8>> =
+            (!centroid.is_empty()).then(|| centroid.iter().flat_map(|f| f.to_le_bytes()).collect());
+        conn.prepare_cached(
             r#"
-            SELECT id, timestamp, image_path, app_name, window_title,
-                   is_fullscreen,
-                   is_idle, ocr_text, activity_session_id, is_key_action,
-                   vlm_summary, vlm_action_description, vlm_activity_type, vlm_confidence, vlm_entities_json, vlm_raw_json,
-                   created_at
-            FROM traces
-            WHERE activity_session_id = ?1
-            ORDER BY timestamp DESC
-            LIMIT ?2 OFFSET ?3
+            INSERT INTO session_lifecycle (session_id, centroid, centroid_count)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT(session_id) DO UPDATE SET
+                centroid = excluded.centroid,
+                centroid_count = excluded.centroid_count
             "#,
-        )?;
+        )?
+        .execute(rusqlite::params![session_id, bytes, count])?;
+        match model.filter(|_| !centroid.is_empty()) {
+            Some(model) => conn
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO session_centroid_models (session_id, model) VALUES (?1, ?2)",
+                )?
+                .execute(rusqlite::params![session_id, model])?,
+            None => conn
+                .prepare_cached("DELETE FROM session_centroid_models WHERE session_id = ?1")?
+                .execute([session_id])?,
+        };
+        Ok(())
+    }
 
-        let traces = stmt.query_map(rusqlite::params![session_id, limit, offset], |row| {
-            Self::trace_from_row(row)
-        })?;
+    /// `since` 之后最早一条尚未归入会话（待分析或待嵌入）的 trace 时间
+    pub fn get_oldest_unrouted_trace_timestamp(&self, since: i64) -> Result<Option<i64>> {
+        let conn = self.pool.reader();
+        let ts = conn
+            .prepare_cached(
+                r#"
+                SELECT MIN(timestamp) FROM traces
+                WHERE timestamp >= ?1
+                  AND activity_session_id IS NULL
+                  AND (ocr_text IS NULL OR embedding IS NULL)
+                  AND id NOT IN (SELECT trace_id FROM trace_parse_status WHERE status = 'failed')
+                "#,
+            )?
+            .query_row([since], |row| row.get(0))?;
+        Ok(ts)
+    }
+
+    /// 关闭 `cutoff` 之前已无新 trace 的 open 会话，返回关闭的数量
+    pub fn close_idle_sessions(&self, cutoff: i64, now: i64) -> Result<usize> {
+        let conn = self.pool.writer();
+        let closed = conn
+            .prepare_cached(
+                r#"
+                INSERT INTO session_lifecycle (session_id, state, closed_at)
+                SELECT s.id, 'closed', ?2
+                FROM activity_sessions s
+                LEFT JOIN session_lifecycle l ON l.session_id = s.id
+                WHERE s.end_time < ?1 AND COALESCE(l.state, 'open') = 'open'
+                ON CONFLICT(session_id) DO UPDATE SET
+                    state = 'closed',
+                    closed_at = excluded.closed_at
+                "#,
+            )?
+            .execute(rusqlite::params![cutoff, now])?;
+        Ok(closed)
+    }
+
+    /// `since` 之后结束、已关闭但还没有回顾的会话（按结束时间升序）
+    pub fn get_sessions_pending_recap(
+        &self,
+        since: i64,
+        limit: u32,
+    ) -> Result<Vec<ActivitySession>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT
+                s.id, s.app_name, s.title, s.description, s.start_time, s.end_time,
+                s.start_trace_id, s.end_trace_id, s.trace_count,
+                s.context_text, s.entities_json, s.key_actions_json,
+                s.created_at, s.updated_at,
+                l.state, l.closed_at, l.recap
+            FROM session_lifecycle l
+            JOIN activity_sessions s ON s.id = l.session_id
+            WHERE l.state = 'closed' AND l.recap IS NULL
+              AND s.end_time >= ?1 AND s.trace_count > 0
+            ORDER BY s.end_time ASC
+            LIMIT ?2
+            "#,
+        )?;
+        let rows = stmt.query_map(
+            rusqlite::params![since, limit],
+            Self::activity_session_from_row,
+        )?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// 保存会话关闭时生成的回顾，并把 context_text 压缩为回顾 + 最近的记录
+    ///
+    /// 用户修正过标题与描述的会话只保存回顾。会话不存在或已不再关闭时返回 false。
+    pub fn finalize_activity_session(
+        &self,
+        session_id: i64,
+        title: Option<&str>,
+        description: Option<&str>,
+        recap: &str,
+    ) -> Result<bool> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let rows = tx
+            .prepare_cached(
+                "UPDATE session_lifecycle SET recap = ?1 WHERE session_id = ?2 AND state = 'closed'",
+            )?
+            .execute(rusqlite::params![recap, session_id])?;
+        if rows == 0 {
+            return Ok(false);
+        }
+
+        let context_text: Option<String> = tx
+            .prepare_cached("SELECT context_text FROM activity_sessions WHERE id = ?1")?
+            .query_row([session_id], |row| row.get(0))
+            .optional()?
+            .flatten();
+        let context_text = compact_context(context_text.as_deref(), recap);
+
+        let title = title.map(str::trim).filter(|s| !s.is_empty());
+        let description = description.map(str::trim).filter(|s| !s.is_empty());
+        tx.prepare_cached(
+            r#"
+            UPDATE activity_sessions
+            SET
+                context_text = ?1,
+                title = CASE WHEN EXISTS (
+                    SELECT 1 FROM session_corrections WHERE session_id = ?4 AND kind = 'rename'
+                ) THEN title ELSE COALESCE(?2, title) END,
+                description = CASE WHEN EXISTS (
+                    SELECT 1 FROM session_corrections WHERE session_id = ?4 AND kind = 'rename'
+                ) THEN description ELSE COALESCE(?3, description) END,
+                updated_at = (strftime('%s', 'now') * 1000)
+            WHERE id = ?4
+            "#,
+        )?
+        .execute(rusqlite::params![
+            context_text,
+            title,
+            description,
+            session_id
+        ])?;
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    pub fn get_recent_traces_before(
+        &self,
+        before_timestamp: i64,
+        limit: u32,
+    ) -> Result<Vec<Trace>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT id, timestamp, image_path, app_name, window_title,
+                   is_fullscreen,
+                   is_idle, ocr_text, activity_session_id, is_key_action,
+                   vlm_summary, vlm_action_description, vlm_activity_type, vlm_confidence, vlm_entities_json, vlm_raw_json,
+                   created_at
+            FROM traces
+            WHERE timestamp < ?1
+              AND ocr_text IS NOT NULL
+            ORDER BY timestamp DESC
+            LIMIT ?2
+            "#,
+        )?;
+
+        let rows = stmt.query_map(rusqlite::params![before_timestamp, limit], |row| {
+            Self::trace_from_row(row)
+        })?;
+
+        let mut result = Vec::new();
+        for r in rows {
+            result.push(r?);
+        }
+        Ok(result)
+    }
+
+    pub fn get_traces_by_activity_session(
+        &self,
+        session_id: i64,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<Trace>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT id, timestamp, image_path, app_name, window_title,
+                   is_fullscreen,
+                   is_idle, ocr_text, activity_session_id, is_key_action,
+                   vlm_summary, vlm_action_description, vlm_activity_type, vlm_confidence, vlm_entities_json, vlm_raw_json,
+                   created_at
+            FROM traces
+            WHERE activity_session_id = ?1
+            ORDER BY timestamp DESC
+            LIMIT ?2 OFFSET ?3
+            "#,
+        )?;
+
+        let traces = stmt.query_map(rusqlite::params![session_id, limit, offset], |row| {
+            Self::trace_from_row(row)
+        })?;
 
         let mut result = Vec::new();
         for t in traces {
@@ -760,8 +1430,8 @@ impl Database {
         before_timestamp: i64,
         limit: u32,
     ) -> Result<Vec<Trace>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
             r#"
             SELECT id, timestamp, image_path, app_name, window_title,
                    is_fullscreen,
@@ -799,7 +1469,7 @@ impl Database {
         raw_json: Option<&str>,
         is_key_action: bool,
     ) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.writer();
         let entities_json = if entities.is_empty() {
             None
         } else {
@@ -832,264 +1502,674 @@ impl Database {
         Ok(())
     }
 
+    /// 把已完成 VLM 分析的 trace 归入会话，追加到会话聚合字段并更新 VLM 给出的标题与描述
+    ///
+    /// 新 trace 只做增量追加（不重读会话内全部 trace）；trace 已属于某个会话时
+    /// （重新分析）才整体重算相关会话。质心由嵌入阶段通过 `add_to_session_centroid` 计入。
     pub fn update_activity_session_from_vlm(
         &self,
         session_id: i64,
         trace_id: i64,
-        timestamp: i64,
-        summary: Option<&str>,
-        action_description: Option<&str>,
-        activity_type: Option<&str>,
-        entities: &[String],
-        is_key_action: bool,
         session_title: Option<&str>,
         session_description: Option<&str>,
     ) -> Result<()> {
-        use chrono::{DateTime, Local};
-        use serde_json::{Map, Value};
-
-        let mut conn = self.conn.lock().unwrap();
+        let mut conn = self.pool.writer();
         let tx = conn.transaction()?;
 
+        let previous: Option<i64> = tx
+            .prepare_cached("SELECT activity_session_id FROM traces WHERE id = ?1")?
+            .query_row([trace_id], |row| row.get(0))
+            .optional()?
+            .flatten();
+
         // 多线程 Session：先把 trace 归入 session
         tx.execute(
             "UPDATE traces SET activity_session_id = ?1 WHERE id = ?2",
             rusqlite::params![session_id, trace_id],
         )?;
 
-        // 重算 session 的基础聚合字段（避免并发/重复写造成的不一致）
-        let trace_count: i64 = tx.query_row(
-            "SELECT COUNT(*) FROM traces WHERE activity_session_id = ?1",
-            rusqlite::params![session_id],
-            |row| row.get(0),
-        )?;
-
-        let (start_trace_id, start_time): (i64, i64) = tx
-            .query_row(
-                "SELECT id, timestamp FROM traces WHERE activity_session_id = ?1 ORDER BY timestamp ASC LIMIT 1",
-                rusqlite::params![session_id],
-                |row| Ok((row.get(0)?, row.get(1)?)),
-            )
-            .unwrap_or((trace_id, timestamp));
-
-        let (end_trace_id, end_time): (i64, i64) = tx
-            .query_row(
-                "SELECT id, timestamp FROM traces WHERE activity_session_id = ?1 ORDER BY timestamp DESC LIMIT 1",
-                rusqlite::params![session_id],
-                |row| Ok((row.get(0)?, row.get(1)?)),
-            )
-            .unwrap_or((trace_id, timestamp));
-
-        // 读取并合并 entities_json（计数）
-        let existing_entities_json: Option<String> = tx
-            .query_row(
-                "SELECT entities_json FROM activity_sessions WHERE id = ?1",
-                rusqlite::params![session_id],
-                |row| row.get(0),
-            )
-            .ok();
-
-        let mut counts: Map<String, Value> = existing_entities_json
-            .as_deref()
-            .and_then(|s| serde_json::from_str::<Map<String, Value>>(s).ok())
-            .unwrap_or_default();
-
-        for e in entities {
-            if e.trim().is_empty() {
-                continue;
+        match previous {
+            None => Self::append_trace_to_session(&tx, session_id, trace_id)?,
+            Some(previous) => {
+                Self::recompute_activity_session(&tx, session_id)?;
+                if previous != session_id {
+                    Self::recompute_activity_session(&tx, previous)?;
+                }
             }
-            let current = counts.get(e).and_then(|v| v.as_i64()).unwrap_or(0);
-            counts.insert(e.clone(), Value::from(current + 1));
         }
 
-        let merged_entities_json = if counts.is_empty() {
-            None
-        } else {
-            Some(Value::Object(counts).to_string())
-        };
+        // 用户修正过标题与描述的会话不再由 VLM 覆盖
+        let next_title = session_title.map(str::trim).filter(|s| !s.is_empty());
+        let next_description = session_description.map(str::trim).filter(|s| !s.is_empty());
+        tx.execute(
+            r#"
+            UPDATE activity_sessions
+            SET
+                title = COALESCE(?1, title),
+                description = COALESCE(?2, description)
+            WHERE id = ?3
+              AND NOT EXISTS (
+                  SELECT 1 FROM session_corrections
+                  WHERE session_id = ?3 AND kind = 'rename'
+              )
+            "#,
+            rusqlite::params![next_title, next_description, session_id],
+        )?;
 
-        // 追加 context_text
-        let existing_context: Option<String> = tx
-            .query_row(
-                "SELECT context_text FROM activity_sessions WHERE id = ?1",
-                rusqlite::params![session_id],
-                |row| row.get(0),
-            )
-            .ok();
+        tx.commit()?;
+        Ok(())
+    }
 
-        let existing_key_actions: Option<String> = tx
-            .query_row(
-                "SELECT key_actions_json FROM activity_sessions WHERE id = ?1",
-                rusqlite::params![session_id],
-                |row| row.get(0),
-            )
-            .ok();
+    /// 把一条新 trace 追加到会话聚合字段：数量、起止 trace 与时间、entities_json（计数）、
+    /// context_text 与 key_actions_json，并关联 trace 的实体
+    fn append_trace_to_session(conn: &Connection, session_id: i64, trace_id: i64) -> Result<()> {
+        let Some(trace) = conn
+            .prepare_cached(
+                r#"
+                SELECT id, timestamp, vlm_summary, vlm_action_description, vlm_activity_type,
+                       vlm_entities_json, is_key_action
+                FROM traces WHERE id = ?1
+                "#,
+            )?
+            .query_row([trace_id], TraceRow::from_row)
+            .optional()?
+        else {
+            return Ok(());
+        };
 
-        let time_str = DateTime::from_timestamp_millis(timestamp)
-            .map(|t| t.with_timezone(&Local).format("%m-%d %H:%M").to_string())
-            .unwrap_or_else(|| "?".to_string());
+        let Some(mut aggregate) = conn
+            .prepare_cached(
+                "SELECT context_text, entities_json, key_actions_json FROM activity_sessions WHERE id = ?1",
+            )?
+            .query_row([session_id], |row| {
+                Ok(SessionAggregate::from_columns(
+                    row.get(0)?,
+                    row.get::<_, Option<String>>(1)?.as_deref(),
+                    row.get::<_, Option<String>>(2)?.as_deref(),
+                ))
+            })
+            .optional()?
+        else {
+            return Ok(());
+        };
+        fold_trace_into_session(&mut aggregate, &trace);
+        let (context_text, entities_json, key_actions_json) = aggregate.into_columns();
 
-        let mut appended = existing_context.unwrap_or_default();
-        if !appended.is_empty() && !appended.ends_with('\n') {
-            appended.push('\n');
-        }
-        if let Some(s) = summary {
-            let s = s.trim();
-            if !s.is_empty() {
-                appended.push_str(&format!("[{}] {}\n", time_str, s));
-            }
-        }
+        // 新建的会话还没有起止 trace，以第一条 trace 为准
+        conn.prepare_cached(
+            r#"
+            UPDATE activity_sessions
+            SET
+                context_text = ?1,
+                entities_json = ?2,
+                key_actions_json = ?3,
+                start_trace_id = CASE WHEN trace_count = 0 OR ?5 < start_time
+                                      THEN ?4 ELSE start_trace_id END,
+                start_time = CASE WHEN trace_count = 0 THEN ?5 ELSE MIN(start_time, ?5) END,
+                end_trace_id = CASE WHEN trace_count = 0 OR ?5 >= end_time
+                                    THEN ?4 ELSE end_trace_id END,
+                end_time = CASE WHEN trace_count = 0 THEN ?5 ELSE MAX(end_time, ?5) END,
+                trace_count = trace_count + 1,
+                updated_at = (strftime('%s', 'now') * 1000)
+            WHERE id = ?6
+            "#,
+        )?
+        .execute(rusqlite::params![
+            context_text,
+            entities_json,
+            key_actions_json,
+            trace_id,
+            trace.timestamp,
+            session_id,
+        ])?;
+
+        // trace 已关联的实体同时关联到 session
+        conn.prepare_cached(
+            r#"
+            INSERT OR IGNORE INTO entity_sessions (entity_id, session_id)
+            SELECT entity_id, ?1 FROM entity_traces WHERE trace_id = ?2
+            "#,
+        )?
+        .execute(rusqlite::params![session_id, trace_id])?;
+        Ok(())
+    }
 
-        const MAX_CONTEXT_CHARS: usize = 262_144; // 256K
-        if appended.chars().count() > MAX_CONTEXT_CHARS {
-            appended = appended
-                .chars()
-                .rev()
-                .take(MAX_CONTEXT_CHARS)
-                .collect::<String>()
-                .chars()
-                .rev()
-                .collect();
+    /// 由会话内的 trace 重算聚合字段：数量、起止 trace 与时间、entities_json（计数）、
+    /// context_text、key_actions_json，并同步 entity_sessions
+    ///
+    /// 需要读取会话内全部 trace，只用于合并 / 拆分 / 移动等手动修正与重新分析；
+    /// 已关闭并生成回顾的会话保留回顾，context_text 仍按回顾 + 最近记录压缩。
+    /// 质心只使用与最新向量同一嵌入模型的向量。会话已没有 trace 时不修改并返回 false。
+    fn recompute_activity_session(conn: &Connection, session_id: i64) -> Result<bool> {
+        struct SessionTrace {
+            trace: TraceRow,
+            embedding: Option<Vec<u8>>,
+            embedding_model: Option<String>,
         }
 
-        // key_actions_json：仅追加关键行为（限制长度，避免无限增长）
-        let mut next_key_actions: Option<String> = existing_key_actions;
-        if is_key_action {
-            let mut arr = next_key_actions
+        let traces: Vec<SessionTrace> = {
+            let mut stmt = conn.prepare_cached(
+                r#"
+                SELECT t.id, t.timestamp, t.vlm_summary, t.vlm_action_description,
+                       t.vlm_activity_type, t.vlm_entities_json, t.is_key_action, t.embedding,
+                       m.model
+                FROM traces t
+                LEFT JOIN trace_embedding_models m ON m.trace_id = t.id
+                WHERE t.activity_session_id = ?1
+                ORDER BY t.timestamp ASC, t.id ASC
+                "#,
+            )?;
+            let rows = stmt.query_map([session_id], |row| {
+                Ok(SessionTrace {
+                    trace: TraceRow::from_row(row)?,
+                    embedding: row.get(7)?,
+                    embedding_model: row.get(8)?,
+                })
+            })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let centroid_model = traces
+            .iter()
+            .rev()
+            .find(|t| t.embedding.is_some())
+            .map(|t| t.embedding_model.clone());
+        let (Some(first), Some(last)) = (traces.first(), traces.last()) else {
+            return Ok(false);
+        };
+        let (start_trace_id, start_time) = (first.trace.id, first.trace.timestamp);
+        let (end_trace_id, end_time) = (last.trace.id, last.trace.timestamp);
+
+        let mut aggregate = SessionAggregate::default();
+        let mut centroid: Vec<f32> = Vec::new();
+        let mut centroid_count = 0i64;
+        for trace in &traces {
+            let same_model = centroid_model.as_ref() == Some(&trace.embedding_model);
+            if let Some(embedding) = trace
+                .embedding
                 .as_deref()
-                .and_then(|s| serde_json::from_str::<Value>(s).ok())
-                .and_then(|v| v.as_array().cloned())
-                .unwrap_or_default();
-
-            let action_text = action_description
-                .map(str::trim)
-                .filter(|s| !s.is_empty())
-                .unwrap_or_else(|| summary.unwrap_or("").trim());
-
-            let action = serde_json::json!({
-                "timestamp": timestamp,
-                "trace_id": trace_id,
-                "summary": summary.unwrap_or("").trim(),
-                "action_description": action_text,
-                "activity_type": activity_type,
-                "entities": entities,
-            });
-
-            if !arr
-                .iter()
-                .any(|v| v.get("trace_id").and_then(|x| x.as_i64()) == Some(trace_id))
+                .filter(|_| same_model)
+                .map(Self::deserialize_embedding)
             {
-                arr.push(action);
-            }
-
-            const MAX_KEY_ACTIONS: usize = 200;
-            if arr.len() > MAX_KEY_ACTIONS {
-                arr = arr.split_off(arr.len() - MAX_KEY_ACTIONS);
+                if centroid.is_empty() {
+                    centroid = vec![0.0; embedding.len()];
+                }
+                if embedding.len() == centroid.len() {
+                    for (c, e) in centroid.iter_mut().zip(&embedding) {
+                        *c += e;
+                    }
+                    centroid_count += 1;
+                }
             }
-            next_key_actions = Some(Value::Array(arr).to_string());
+            fold_trace_into_session(&mut aggregate, &trace.trace);
         }
 
-        let next_title = session_title.map(str::trim).filter(|s| !s.is_empty());
-        let next_description = session_description.map(str::trim).filter(|s| !s.is_empty());
+        // 已生成回顾的关闭会话保持压缩后的上下文
+        let recap: Option<String> = conn
+            .prepare_cached(
+                "SELECT recap FROM session_lifecycle WHERE session_id = ?1 AND state = 'closed'",
+            )?
+            .query_row([session_id], |row| row.get(0))
+            .optional()?
+            .flatten();
+        if let Some(recap) = recap {
+            aggregate.context = compact_context(Some(&aggregate.context), &recap);
+        }
+        let (context_text, entities_json, key_actions_json) = aggregate.into_columns();
 
-        tx.execute(
+        conn.prepare_cached(
             r#"
             UPDATE activity_sessions
             SET
-                title = COALESCE(?1, title),
-                description = COALESCE(?2, description),
-                context_text = ?3,
-                entities_json = ?4,
-                key_actions_json = ?5,
-                start_trace_id = ?6,
-                end_trace_id = ?7,
-                start_time = ?8,
-                end_time = ?9,
-                trace_count = ?10,
+                context_text = ?1,
+                entities_json = ?2,
+                key_actions_json = ?3,
+                start_trace_id = ?4,
+                end_trace_id = ?5,
+                start_time = ?6,
+                end_time = ?7,
+                trace_count = ?8,
                 updated_at = (strftime('%s', 'now') * 1000)
-            WHERE id = ?11
+            WHERE id = ?9
             "#,
-            rusqlite::params![
-                next_title,
-                next_description,
-                if appended.is_empty() {
-                    None
-                } else {
-                    Some(appended)
-                },
-                merged_entities_json,
-                next_key_actions,
-                start_trace_id,
-                end_trace_id,
-                start_time,
-                end_time,
-                trace_count,
-                session_id,
-            ],
+        )?
+        .execute(rusqlite::params![
+            context_text,
+            entities_json,
+            key_actions_json,
+            start_trace_id,
+            end_trace_id,
+            start_time,
+            end_time,
+            traces.len() as i64,
+            session_id,
+        ])?;
+
+        // 质心按会话内同一模型的 trace 向量重算
+        if centroid_count > 0 {
+            for c in centroid.iter_mut() {
+                *c /= centroid_count as f32;
+            }
+        }
+        Self::store_session_centroid(
+            conn,
+            session_id,
+            &centroid,
+            centroid_count,
+            centroid_model.flatten().as_deref(),
         )?;
 
-        tx.commit()?;
+        // 实体关联以会话内 trace 为准（trace 移出后解除关联）
+        conn.prepare_cached(
+            r#"
+            DELETE FROM entity_sessions
+            WHERE session_id = ?1
+              AND entity_id NOT IN (
+                  SELECT et.entity_id FROM entity_traces et
+                  JOIN traces t ON t.id = et.trace_id
+                  WHERE t.activity_session_id = ?1
+              )
+            "#,
+        )?
+        .execute([session_id])?;
+        conn.prepare_cached(
+            r#"
+            INSERT OR IGNORE INTO entity_sessions (entity_id, session_id)
+            SELECT DISTINCT et.entity_id, ?1 FROM entity_traces et
+            JOIN traces t ON t.id = et.trace_id
+            WHERE t.activity_session_id = ?1
+            "#,
+        )?
+        .execute([session_id])?;
+
+        Ok(true)
+    }
+
+    /// 记录用户对会话的修正
+    fn record_session_correction(
+        conn: &Connection,
+        kind: &str,
+        session_id: i64,
+        from_session_id: Option<i64>,
+        trace_id: Option<i64>,
+    ) -> Result<()> {
+        conn.prepare_cached(
+            r#"
+            INSERT INTO session_corrections (kind, session_id, from_session_id, trace_id)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+        )?
+        .execute(rusqlite::params![
+            kind,
+            session_id,
+            from_session_id,
+            trace_id
+        ])?;
         Ok(())
     }
 
-    pub fn create_chat_thread(&self, title: Option<&str>) -> Result<i64> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO chat_threads (title) VALUES (?1)",
-            rusqlite::params![title],
-        )?;
-        Ok(conn.last_insert_rowid())
+    /// 重算被移出 trace 的会话，已没有 trace 的会话直接删除
+    fn recompute_or_delete_session(conn: &Connection, session_id: i64) -> Result<()> {
+        if !Self::recompute_activity_session(conn, session_id)? {
+            conn.prepare_cached("DELETE FROM activity_sessions WHERE id = ?1")?
+                .execute([session_id])?;
+        }
+        Ok(())
     }
 
-    pub fn append_chat_message(
-        &self,
-        thread_id: i64,
-        role: &str,
-        content: &str,
-        context_json: Option<&str>,
-    ) -> Result<i64> {
-        let mut conn = self.conn.lock().unwrap();
+    /// 把 `source_ids` 会话合并到 `target_id`：trace 全部归入目标会话后删除源会话
+    ///
+    /// 目标会话不存在时返回 false；不存在的源会话被忽略。
+    pub fn merge_activity_sessions(&self, target_id: i64, source_ids: &[i64]) -> Result<bool> {
+        let mut conn = self.pool.writer();
         let tx = conn.transaction()?;
 
-        tx.execute(
-            r#"
-            INSERT INTO chat_messages (thread_id, role, content, context_json)
-            VALUES (?1, ?2, ?3, ?4)
-            "#,
-            rusqlite::params![thread_id, role, content, context_json],
-        )?;
-        let message_id = tx.last_insert_rowid();
+        let exists: bool = tx
+            .prepare_cached("SELECT EXISTS(SELECT 1 FROM activity_sessions WHERE id = ?1)")?
+            .query_row([target_id], |row| row.get(0))?;
+        if !exists {
+            return Ok(false);
+        }
 
-        tx.execute(
-            "UPDATE chat_threads SET updated_at = (strftime('%s', 'now') * 1000) WHERE id = ?1",
-            rusqlite::params![thread_id],
-        )?;
+        for &source_id in source_ids.iter().filter(|&&id| id != target_id) {
+            let merged = tx
+                .prepare_cached(
+                    "UPDATE traces SET activity_session_id = ?1 WHERE activity_session_id = ?2",
+                )?
+                .execute([target_id, source_id])?;
+            let deleted = tx
+                .prepare_cached("DELETE FROM activity_sessions WHERE id = ?1")?
+                .execute([source_id])?;
+            if merged > 0 || deleted > 0 {
+                Self::record_session_correction(&tx, "merge", target_id, Some(source_id), None)?;
+            }
+        }
+        Self::recompute_activity_session(&tx, target_id)?;
 
         tx.commit()?;
-        Ok(message_id)
+        Ok(true)
     }
 
-    pub fn get_chat_messages(
-        &self,
-        thread_id: i64,
-        limit: u32,
-        offset: u32,
-    ) -> Result<Vec<ChatMessage>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
+    /// 从 `trace_id` 起拆分会话：该 trace 及之后的 trace 归入新会话，返回新会话 ID
+    ///
+    /// trace 不属于该会话或是会话的第一条 trace 时返回 None。
+    pub fn split_activity_session(&self, session_id: i64, trace_id: i64) -> Result<Option<i64>> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let split_at: Option<(i64, Option<String>)> = tx
+            .prepare_cached(
+                "SELECT timestamp, app_name FROM traces WHERE id = ?1 AND activity_session_id = ?2",
+            )?
+            .query_row([trace_id, session_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        let Some((timestamp, app_name)) = split_at else {
+            return Ok(None);
+        };
+        let earlier: i64 = tx
+            .prepare_cached(
+                r#"
+                SELECT COUNT(*) FROM traces
+                WHERE activity_session_id = ?1
+                  AND (timestamp < ?2 OR (timestamp = ?2 AND id < ?3))
+                "#,
+            )?
+            .query_row(rusqlite::params![session_id, timestamp, trace_id], |row| {
+                row.get(0)
+            })?;
+        if earlier == 0 {
+            return Ok(None);
+        }
+
+        let session_app: String = tx
+            .prepare_cached("SELECT app_name FROM activity_sessions WHERE id = ?1")?
+            .query_row([session_id], |row| row.get(0))?;
+        tx.prepare_cached(
             r#"
-            SELECT id, thread_id, role, content, context_json, created_at
-            FROM chat_messages
-            WHERE thread_id = ?1
-            ORDER BY created_at ASC
-            LIMIT ?2 OFFSET ?3
+            INSERT INTO activity_sessions (app_name, start_time, end_time, trace_count)
+            VALUES (?1, ?2, ?2, 0)
             "#,
-        )?;
+        )?
+        .execute(rusqlite::params![
+            app_name
+                .filter(|a| !a.trim().is_empty())
+                .unwrap_or(session_app),
+            timestamp
+        ])?;
+        let new_id = tx.last_insert_rowid();
 
-        let rows = stmt.query_map(rusqlite::params![thread_id, limit, offset], |row| {
-            Ok(ChatMessage {
-                id: row.get(0)?,
-                thread_id: row.get(1)?,
+        tx.prepare_cached(
+            r#"
+            UPDATE traces SET activity_session_id = ?1
+            WHERE activity_session_id = ?2
+              AND (timestamp > ?3 OR (timestamp = ?3 AND id >= ?4))
+            "#,
+        )?
+        .execute(rusqlite::params![new_id, session_id, timestamp, trace_id])?;
+        Self::record_session_correction(&tx, "split", new_id, Some(session_id), Some(trace_id))?;
+        Self::recompute_activity_session(&tx, session_id)?;
+        Self::recompute_activity_session(&tx, new_id)?;
+
+        tx.commit()?;
+        Ok(Some(new_id))
+    }
+
+    /// 把 trace 移入 `session_id`（为 None 时新建会话），返回目标会话 ID
+    ///
+    /// 原会话随之重算，被移空的会话删除。目标会话不存在时返回 None。
+    pub fn move_traces_to_session(
+        &self,
+        trace_ids: &[i64],
+        session_id: Option<i64>,
+    ) -> Result<Option<i64>> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let mut traces: Vec<(i64, i64, Option<String>, Option<i64>)> = Vec::new();
+        for &trace_id in trace_ids {
+            let trace = tx
+                .prepare_cached(
+                    "SELECT id, timestamp, app_name, activity_session_id FROM traces WHERE id = ?1",
+                )?
+                .query_row([trace_id], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+                .optional()?;
+            traces.extend(trace);
+        }
+        let Some(first) = traces.iter().min_by_key(|t| t.1) else {
+            return Ok(None);
+        };
+
+        let target_id = match session_id {
+            Some(id) => {
+                let exists: bool = tx
+                    .prepare_cached("SELECT EXISTS(SELECT 1 FROM activity_sessions WHERE id = ?1)")?
+                    .query_row([id], |row| row.get(0))?;
+                if !exists {
+                    return Ok(None);
+                }
+                id
+            }
+            None => {
+                let app_name = first
+                    .2
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|a| !a.is_empty())
+                    .unwrap_or("unknown");
+                tx.prepare_cached(
+                    r#"
+                    INSERT INTO activity_sessions (app_name, start_time, end_time, trace_count)
+                    VALUES (?1, ?2, ?2, 0)
+                    "#,
+                )?
+                .execute(rusqlite::params![app_name, first.1])?;
+                tx.last_insert_rowid()
+            }
+        };
+
+        let mut sources = Vec::new();
+        for (trace_id, _, _, from_session_id) in &traces {
+            if *from_session_id == Some(target_id) {
+                continue;
+            }
+            tx.prepare_cached("UPDATE traces SET activity_session_id = ?1 WHERE id = ?2")?
+                .execute([target_id, *trace_id])?;
+            Self::record_session_correction(
+                &tx,
+                "move",
+                target_id,
+                *from_session_id,
+                Some(*trace_id),
+            )?;
+            sources.extend(*from_session_id);
+        }
+        sources.sort_unstable();
+        sources.dedup();
+        for source_id in sources {
+            Self::recompute_or_delete_session(&tx, source_id)?;
+        }
+        Self::recompute_activity_session(&tx, target_id)?;
+
+        tx.commit()?;
+        Ok(Some(target_id))
+    }
+
+    /// 修改会话标题与描述（为 None 时保持不变），之后 VLM 不再覆盖
+    pub fn rename_activity_session(
+        &self,
+        session_id: i64,
+        title: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<bool> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let rows = tx
+            .prepare_cached(
+                r#"
+                UPDATE activity_sessions
+                SET
+                    title = COALESCE(?1, title),
+                    description = COALESCE(?2, description),
+                    updated_at = (strftime('%s', 'now') * 1000)
+                WHERE id = ?3
+                "#,
+            )?
+            .execute(rusqlite::params![title, description, session_id])?;
+        if rows == 0 {
+            return Ok(false);
+        }
+        Self::record_session_correction(&tx, "rename", session_id, None, None)?;
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// 读取 [start, end] 内最近 `limit` 条已归入 Session 且有向量的 traces（按时间升序），用于路由回放
+    ///
+    /// 只取嵌入模型 `model` 生成的向量，不同模型的向量不可比较。
+    pub fn get_routing_history(
+        &self,
+        start: i64,
+        end: i64,
+        model: &str,
+        limit: u32,
+    ) -> Result<Vec<SessionTraceEmbedding>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT t.id, t.timestamp, t.activity_session_id, t.embedding
+            FROM traces t
+            JOIN trace_embedding_models m ON m.trace_id = t.id
+            WHERE t.timestamp BETWEEN ?1 AND ?2
+              AND t.activity_session_id IS NOT NULL
+              AND t.embedding IS NOT NULL
+              AND m.model = ?3
+            ORDER BY t.timestamp DESC, t.id DESC
+            LIMIT ?4
+            "#,
+        )?;
+        let rows = stmt.query_map(rusqlite::params![start, end, model, limit], |row| {
+            Ok(SessionTraceEmbedding {
+                trace_id: row.get(0)?,
+                timestamp: row.get(1)?,
+                session_id: row.get(2)?,
+                embedding: row.get(3)?,
+            })
+        })?;
+        let mut history = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        history.reverse();
+        Ok(history)
+    }
+
+    /// `since` 之后被用户移动或作为拆分点的 trace
+    pub fn get_corrected_trace_ids(&self, since: i64) -> Result<std::collections::HashSet<i64>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT DISTINCT c.trace_id
+            FROM session_corrections c
+            JOIN traces t ON t.id = c.trace_id
+            WHERE c.kind IN ('move', 'split') AND t.timestamp >= ?1
+            "#,
+        )?;
+        let rows = stmt.query_map([since], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<std::collections::HashSet<i64>>>()?)
+    }
+
+    /// 最近的 trace 归属纠正（新的在前），只保留目标 Session 仍存在的记录
+    pub fn get_recent_routing_corrections(&self, limit: u32) -> Result<Vec<RoutingCorrection>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT
+                c.trace_id,
+                COALESCE(t.vlm_summary, t.ocr_text),
+                t.app_name,
+                COALESCE(NULLIF(TRIM(w.title), ''), w.app_name),
+                COALESCE(NULLIF(TRIM(r.title), ''), r.app_name),
+                c.created_at
+            FROM session_corrections c
+            JOIN traces t ON t.id = c.trace_id
+            JOIN activity_sessions r ON r.id = c.session_id
+            LEFT JOIN activity_sessions w ON w.id = c.from_session_id
+            WHERE c.kind IN ('move', 'split') AND c.from_session_id IS NOT NULL
+            ORDER BY c.id DESC
+            LIMIT ?1
+            "#,
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            Ok(RoutingCorrection {
+                trace_id: row.get(0)?,
+                trace_summary: row.get(1)?,
+                app_name: row.get(2)?,
+                wrong_session_title: row.get(3)?,
+                correct_session_title: row.get(4)?,
+                created_at: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn create_chat_thread(&self, title: Option<&str>) -> Result<i64> {
+        let conn = self.pool.writer();
+        conn.execute(
+            "INSERT INTO chat_threads (title) VALUES (?1)",
+            rusqlite::params![title],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn append_chat_message(
+        &self,
+        thread_id: i64,
+        role: &str,
+        content: &str,
+        context_json: Option<&str>,
+    ) -> Result<i64> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            r#"
+            INSERT INTO chat_messages (thread_id, role, content, context_json)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+            rusqlite::params![thread_id, role, content, context_json],
+        )?;
+        let message_id = tx.last_insert_rowid();
+
+        tx.execute(
+            "UPDATE chat_threads SET updated_at = (strftime('%s', 'now') * 1000) WHERE id = ?1",
+            rusqlite::params![thread_id],
+        )?;
+
+        tx.commit()?;
+        Ok(message_id)
+    }
+
+    pub fn get_chat_messages(
+        &self,
+        thread_id: i64,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<ChatMessage>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT id, thread_id, role, content, context_json, created_at
+            FROM chat_messages
+            WHERE thread_id = ?1
+            ORDER BY created_at ASC
+            LIMIT ?2 OFFSET ?3
+            "#,
+        )?;
+
+        let rows = stmt.query_map(rusqlite::params![thread_id, limit, offset], |row| {
+            Ok(ChatMessage {
+                id: row.get(0)?,
+                thread_id: row.get(1)?,
                 role: row.get(2)?,
                 content: row.get(3)?,
                 context_json: row.get(4)?,
@@ -1106,7 +2186,7 @@ impl Database {
 
     /// 获取设置
     pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.reader();
         let result = conn.query_row(
             "SELECT value FROM settings WHERE key = ?1",
             rusqlite::params![key],
@@ -1122,7 +2202,7 @@ impl Database {
 
     /// 更新设置
     pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.writer();
         conn.execute(
             r#"
             INSERT INTO settings (key, value, updated_at)
@@ -1162,7 +2242,7 @@ impl Database {
 
     /// 更新 trace 的 OCR/文本数据（轻量）
     pub fn update_trace_ocr_text(&self, trace_id: i64, ocr_text: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.pool.writer();
         conn.execute(
             "UPDATE traces SET ocr_text = ?1 WHERE id = ?2",
             rusqlite::params![ocr_text, trace_id],
@@ -1170,687 +2250,2687 @@ impl Database {
         Ok(())
     }
 
-    /// 更新 trace 的向量嵌入
-    pub fn update_trace_embedding(&self, trace_id: i64, embedding: &[u8]) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    /// 更新 trace 的向量嵌入，`model` 为生成该向量的模型标识
+    pub fn update_trace_embedding(
+        &self,
+        trace_id: i64,
+        embedding: &[u8],
+        model: &str,
+    ) -> Result<()> {
+        self.update_trace_embeddings_batch(&[(trace_id, embedding.to_vec())], model)
+    }
 
-        // 更新 traces 表的 embedding 列（保留原有 BLOB 存储）
-        conn.execute(
-            "UPDATE traces SET embedding = ?1 WHERE id = ?2",
-            rusqlite::params![embedding, trace_id],
-        )?;
+    /// 记录单独嵌入仍失败的 trace，同一模型的待嵌入扫描不再返回它
+    pub fn mark_trace_embedding_failed(
+        &self,
+        trace_id: i64,
+        model: &str,
+        error: &str,
+    ) -> Result<()> {
+        let conn = self.pool.writer();
+        conn.prepare_cached(
+            r#"
+            INSERT OR REPLACE INTO trace_embedding_failures (trace_id, model, error, failed_at)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+        )?
+        .execute(rusqlite::params![
+            trace_id,
+            model,
+            error,
+            Utc::now().timestamp_millis()
+        ])?;
+        Ok(())
+    }
+
+    /// 批量更新 traces 的向量嵌入（单个事务），`model` 为生成这批向量的模型标识
+    pub fn update_trace_embeddings_batch(
+        &self,
+        items: &[(i64, Vec<u8>)],
+        model: &str,
+    ) -> Result<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.pool.writer();
+        let config = self.vector_index_config();
+        let tx = conn.transaction()?;
 
-        // 计算向量维度（每个 f32 占 4 字节）
-        let dimension = embedding.len() / 4;
+        // 同一批次由同一模型生成，维度一致
+        let dimension = items[0].1.len() / 4;
 
-        // 确保 vec0 表存在且维度正确
-        Self::ensure_vec_table_inner(&conn, dimension)?;
+        {
+            let mut timestamp_stmt =
+                tx.prepare_cached("SELECT timestamp FROM traces WHERE id = ?1")?;
+            let mut update_stmt =
+                tx.prepare_cached("UPDATE traces SET embedding = ?1 WHERE id = ?2")?;
+            let mut model_stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO trace_embedding_models (trace_id, model, dim) VALUES (?1, ?2, ?3)",
+            )?;
 
-        // 同时插入到 vec0 向量索引表
-        // embedding 是 f32 数组的字节表示，直接传递给 sqlite-vec
-        conn.execute(
-            "INSERT OR REPLACE INTO traces_vec (trace_id, embedding) VALUES (?1, ?2)",
-            rusqlite::params![trace_id, embedding],
-        )?;
+            for (trace_id, embedding) in items {
+                let Some(timestamp) = timestamp_stmt
+                    .query_row([trace_id], |row| row.get::<_, i64>(0))
+                    .optional()?
+                else {
+                    continue;
+                };
+                update_stmt.execute(rusqlite::params![embedding, trace_id])?;
+                model_stmt.execute(rusqlite::params![trace_id, model, dimension as i64])?;
+
+                // vec0 不支持 INSERT OR REPLACE：重新嵌入（或分区刚回填过）时先删除旧条目
+                let (table, quantization) =
+                    Self::ensure_vec_partition(&tx, &config, timestamp, dimension)?;
+                tx.prepare_cached(&format!("DELETE FROM {table} WHERE trace_id = ?1"))?
+                    .execute([trace_id])?;
+                let mut vec_stmt = tx.prepare_cached(&format!(
+                    "INSERT INTO {table} (trace_id, embedding, timestamp, model) VALUES (?1, {}, ?3, ?4)",
+                    quantization.quantize_expr("?2")
+                ))?;
+                vec_stmt.execute(rusqlite::params![trace_id, embedding, timestamp, model])?;
+            }
+        }
+
+        tx.commit()?;
 
         debug!(
-            "Updated embedding for trace {} (vec index synced, dim={})",
-            trace_id, dimension
+            "Updated embeddings for {} traces in one transaction (model={}, dim={})",
+            items.len(),
+            model,
+            dimension
         );
         Ok(())
     }
 
-    /// 确保 vec0 向量索引表存在且维度正确
-    fn ensure_vec_table_inner(conn: &Connection, dimension: usize) -> Result<()> {
-        // 检查表是否存在
-        let table_exists: bool = conn.query_row(
-            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name='traces_vec'",
-            [],
-            |row| row.get(0),
-        )?;
+    /// 当前向量索引配置
+    pub fn vector_index_config(&self) -> VectorIndexConfig {
+        self.vector_config.read().unwrap().clone()
+    }
 
-        if table_exists {
-            // 尝试插入一个测试向量来检查维度
-            // 创建一个正确维度的零向量
-            let test_embedding: Vec<u8> = vec![0u8; dimension * 4];
+    /// 应用向量索引配置；布局变化（或存在旧版单表索引）时按新布局建立空分区
+    ///
+    /// 返回是否发生了重建。向量由 [`Self::backfill_vector_index`] 分批回填，
+    /// 回填完成前 KNN 只覆盖已回填与新写入的向量。
+    pub fn apply_vector_index_config(&self, config: &VectorIndexConfig) -> Result<bool> {
+        // 持有写连接再切换配置，避免并发写入按新旧两种布局建分区
+        let mut conn = self.pool.writer();
+        *self.vector_config.write().unwrap() = config.clone();
 
-            // 尝试执行一个查询来验证维度
-            let dimension_ok = conn
-                .execute(
-                    "INSERT OR REPLACE INTO traces_vec (trace_id, embedding) VALUES (-1, ?1)",
-                    rusqlite::params![test_embedding],
-                )
-                .is_ok();
+        let tx = conn.transaction()?;
+        if Self::vector_layout_matches(&tx, config)? {
+            return Ok(false);
+        }
+        let partitions = Self::rebuild_vector_index_inner(&tx, config)?;
+        tx.commit()?;
+
+        info!(
+            "Reset vector index layout: {} partitions (quantization={}, partition_days={})",
+            partitions,
+            config.quantization.as_str(),
+            config.partition_days
+        );
+        Ok(true)
+    }
 
-            if dimension_ok {
-                // 删除测试数据
-                let _ = conn.execute("DELETE FROM traces_vec WHERE trace_id = -1", []);
-                return Ok(());
+    /// 分批回填布局变化后的向量索引，返回本次回填的向量数（没有待回填时为 0）
+    ///
+    /// 每批单独持有写锁并提交进度，批次之间采集与嵌入的写入可以继续。
+    pub fn backfill_vector_index(&self) -> Result<usize> {
+        let started = std::time::Instant::now();
+        let mut total = 0;
+        loop {
+            let mut conn = self.pool.writer();
+            let tx = conn.transaction()?;
+            let cursor: Option<i64> = tx
+                .prepare_cached("SELECT last_trace_id FROM vec_backfill WHERE id = 1")?
+                .query_row([], |row| row.get(0))
+                .optional()?;
+            let Some(cursor) = cursor else {
+                break;
+            };
+
+            let batch: Vec<(i64, i64, Vec<u8>, String)> = {
+                let mut stmt = tx.prepare_cached(
+                    r#"
+                    SELECT t.id, t.timestamp, t.embedding, COALESCE(m.model, '')
+                    FROM traces t
+                    LEFT JOIN trace_embedding_models m ON m.trace_id = t.id
+                    WHERE t.id > ?1 AND t.embedding IS NOT NULL
+                    ORDER BY t.id
+                    LIMIT ?2
+                    "#,
+                )?;
+                let rows = stmt
+                    .query_map(rusqlite::params![cursor, VECTOR_BACKFILL_BATCH], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                    })?;
+                rows.collect::<rusqlite::Result<_>>()?
+            };
+            let Some(last_id) = batch.last().map(|(id, ..)| *id) else {
+                tx.execute("DELETE FROM vec_backfill", [])?;
+                tx.commit()?;
+                break;
+            };
+
+            let config = self.vector_index_config();
+            for (trace_id, timestamp, embedding, model) in &batch {
+                let (name, _, _) = config.partition_for(*timestamp);
+                let partition: Option<(i64, String)> = tx
+                    .prepare_cached("SELECT dim, quantization FROM vec_partitions WHERE name = ?1")?
+                    .query_row([&name], |row| Ok((row.get(0)?, row.get(1)?)))
+                    .optional()?;
+                // 只回填与分区同维度的向量；其他维度来自旧模型，重新嵌入后再进入索引
+                let Some((_, quantization)) =
+                    partition.filter(|(dim, _)| (*dim * 4) as usize == embedding.len())
+                else {
+                    continue;
+                };
+                let quantization = VectorQuantization::parse(&quantization);
+                tx.prepare_cached(&format!("DELETE FROM {name} WHERE trace_id = ?1"))?
+                    .execute([trace_id])?;
+                tx.prepare_cached(&format!(
+                    "INSERT INTO {name} (trace_id, embedding, timestamp, model) VALUES (?1, {}, ?3, ?4)",
+                    quantization.quantize_expr("?2")
+                ))?
+                .execute(rusqlite::params![trace_id, embedding, timestamp, model])?;
+                total += 1;
             }
+            tx.execute(
+                "UPDATE vec_backfill SET last_trace_id = ?1 WHERE id = 1",
+                [last_id],
+            )?;
+            tx.commit()?;
+        }
 
-            // 维度不匹配，需要重建表
+        if total > 0 {
             info!(
-                "Vector dimension changed, rebuilding traces_vec table with {} dimensions",
-                dimension
+                "Backfilled vector index: {} vectors in {}ms",
+                total,
+                started.elapsed().as_millis()
             );
-            conn.execute_batch("DROP TABLE IF EXISTS traces_vec")?;
         }
-
-        // 表不存在或需要重建，创建新表
-        let sql = format!(
-            "CREATE VIRTUAL TABLE IF NOT EXISTS traces_vec USING vec0(
-                trace_id INTEGER PRIMARY KEY,
-                embedding FLOAT[{}]
-            )",
-            dimension
-        );
-
-        conn.execute_batch(&sql)?;
-        info!("Created traces_vec table with dimension {}", dimension);
-
-        Ok(())
+        Ok(total)
     }
 
-    /// 获取待处理 OCR 的 traces（没有 ocr_text 的）
-    pub fn get_traces_pending_ocr(&self, limit: u32) -> Result<Vec<Trace>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            r#"
-            SELECT id, timestamp, image_path, app_name, window_title,
-                   is_fullscreen,
-                   is_idle, ocr_text, activity_session_id, is_key_action,
-                   vlm_summary, vlm_action_description, vlm_activity_type, vlm_confidence, vlm_entities_json, vlm_raw_json,
-                   created_at
-            FROM traces
-            WHERE ocr_text IS NULL
-            ORDER BY timestamp DESC
-            LIMIT ?1
-            "#,
+    /// 已登记的分区是否都符合配置的布局
+    fn vector_layout_matches(conn: &Connection, config: &VectorIndexConfig) -> Result<bool> {
+        let legacy: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name = ?1",
+            [vector::LEGACY_TABLE],
+            |row| row.get(0),
         )?;
+        if legacy {
+            return Ok(false);
+        }
 
-        let traces = stmt.query_map([limit], |row| Self::trace_from_row(row))?;
-
-        let mut result = Vec::new();
-        for trace in traces {
-            result.push(trace?);
+        let mut stmt =
+            conn.prepare_cached("SELECT start_ts, end_ts, dim, quantization FROM vec_partitions")?;
+        let partitions = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        for partition in partitions {
+            let (start_ts, end_ts, dim, quantization) = partition?;
+            if !config.matches_partition(start_ts, end_ts, dim as usize, &quantization) {
+                return Ok(false);
+            }
         }
-        Ok(result)
+        Ok(true)
     }
 
-    /// 获取待处理嵌入的 traces（有 ocr_text 但没有 embedding 的）
-    pub fn get_traces_pending_embedding(&self, limit: u32) -> Result<Vec<Trace>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            r#"
-            SELECT id, timestamp, image_path, app_name, window_title,
-                   is_fullscreen,
-                   is_idle, ocr_text, activity_session_id, is_key_action,
-                   vlm_summary, vlm_action_description, vlm_activity_type, vlm_confidence, vlm_entities_json, vlm_raw_json,
-                   created_at
-            FROM traces
-            WHERE ocr_text IS NOT NULL AND embedding IS NULL
-            ORDER BY timestamp DESC
-            LIMIT ?1
-            "#,
-        )?;
-
-        let traces = stmt.query_map([limit], |row| Self::trace_from_row(row))?;
-
-        let mut result = Vec::new();
-        for trace in traces {
-            result.push(trace?);
+    /// 删除全部分区，按配置建立空分区并登记分批回填，返回分区数
+    ///
+    /// 只为最新 trace 的向量维度建索引；其他维度的向量来自旧模型，重新嵌入后再进入索引。
+    fn rebuild_vector_index_inner(conn: &Connection, config: &VectorIndexConfig) -> Result<usize> {
+        let names: Vec<String> = {
+            let mut stmt = conn.prepare_cached("SELECT name FROM vec_partitions")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        for name in names {
+            conn.execute_batch(&format!("DROP TABLE IF EXISTS {name}"))?;
         }
-        Ok(result)
-    }
+        conn.execute("DELETE FROM vec_partitions", [])?;
+        conn.execute_batch(&format!("DROP TABLE IF EXISTS {}", vector::LEGACY_TABLE))?;
+        conn.execute("DELETE FROM vec_backfill", [])?;
 
-    /// 向量相似度搜索（使用 sqlite-vec KNN 搜索）
-    pub fn search_by_embedding(
-        &self,
-        query_embedding: &[f32],
-        limit: u32,
-    ) -> Result<Vec<(Trace, f32)>> {
-        let conn = self.conn.lock().unwrap();
+        let dim: Option<i64> = conn
+            .query_row(
+                "SELECT length(embedding) / 4 FROM traces
+                 WHERE embedding IS NOT NULL
+                 ORDER BY timestamp DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(dim) = dim.filter(|d| *d > 0).map(|d| d as usize) else {
+            return Ok(0);
+        };
 
-        // 将查询向量转换为字节数组（sqlite-vec 接受的格式）
-        let query_bytes: Vec<u8> = query_embedding
-            .iter()
-            .flat_map(|f| f.to_le_bytes())
-            .collect();
+        let starts: Vec<i64> = match config.partition_span_ms() {
+            Some(span) => {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT DISTINCT timestamp / ?1 FROM traces WHERE length(embedding) = ?2",
+                )?;
+                let rows = stmt.query_map(rusqlite::params![span, (dim * 4) as i64], |row| {
+                    row.get::<_, i64>(0)
+                })?;
+                rows.map(|bucket| bucket.map(|b| b * span))
+                    .collect::<rusqlite::Result<_>>()?
+            }
+            None => vec![0],
+        };
 
-        // 使用 sqlite-vec 的 KNN 搜索
-        // vec0 表通过 MATCH 子句进行相似度搜索，返回 distance（距离越小越相似）
-        let mut stmt = conn.prepare(
-            r#"
-            SELECT
-                t.id, t.timestamp, t.image_path, t.app_name, t.window_title,
-                t.is_fullscreen,
-                t.is_idle, t.ocr_text, t.activity_session_id, t.is_key_action,
-                t.vlm_summary, t.vlm_action_description, t.vlm_activity_type, t.vlm_confidence, t.vlm_entities_json, t.vlm_raw_json,
-                t.created_at,
-                v.distance
-            FROM traces_vec v
-            INNER JOIN traces t ON v.trace_id = t.id
-            WHERE v.embedding MATCH ?1
-                AND k = ?2
-            ORDER BY v.distance
-            "#,
+        let quantization = config.quantization.effective(dim);
+        for start in &starts {
+            let (name, start_ts, end_ts) = config.partition_for(*start);
+            Self::register_vec_partition(conn, &name, start_ts, end_ts, dim, quantization)?;
+        }
+        conn.execute(
+            "INSERT INTO vec_backfill (id, last_trace_id) VALUES (1, 0)",
+            [],
         )?;
+        Ok(starts.len())
+    }
 
-        let traces = stmt.query_map(rusqlite::params![query_bytes, limit], |row| {
-            let distance: f32 = row.get(17)?;
-            // 将距离转换为相似度（距离越小，相似度越高）
-            // 使用 1 / (1 + distance) 转换
-            let similarity = 1.0 / (1.0 + distance);
-
-            Ok((Self::trace_from_row(row)?, similarity))
-        })?;
-
-        let mut results = Vec::new();
-        for trace_result in traces {
-            if let Ok((trace, similarity)) = trace_result {
-                results.push((trace, similarity));
+    /// 确保 `timestamp` 所在的分区存在且维度、量化方式正确，返回分区表名与量化方式
+    fn ensure_vec_partition(
+        conn: &Connection,
+        config: &VectorIndexConfig,
+        timestamp: i64,
+        dim: usize,
+    ) -> Result<(String, VectorQuantization)> {
+        let (name, start_ts, end_ts) = config.partition_for(timestamp);
+        let quantization = config.quantization.effective(dim);
+
+        let existing: Option<(i64, String)> = conn
+            .prepare_cached("SELECT dim, quantization FROM vec_partitions WHERE name = ?1")?
+            .query_row([&name], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        match existing {
+            Some((d, q)) if d as usize == dim && q == quantization.as_str() => {
+                return Ok((name, quantization));
+            }
+            Some((d, _)) => {
+                // 维度变化（切换了嵌入模型）：只重建这个分区，其他分区等重新嵌入时再重建
+                info!(
+                    "Rebuilding vector partition {} ({} -> {} dimensions)",
+                    name, d, dim
+                );
+                conn.execute_batch(&format!("DROP TABLE IF EXISTS {name}"))?;
+                conn.execute("DELETE FROM vec_partitions WHERE name = ?1", [&name])?;
             }
+            None => {}
         }
 
-        debug!("sqlite-vec KNN search returned {} results", results.len());
-        Ok(results)
+        Self::create_vec_partition(conn, &name, start_ts, end_ts, dim, quantization)?;
+        Ok((name, quantization))
     }
 
-    /// 混合搜索（FTS + 向量）
-    pub fn hybrid_search(
-        &self,
-        query: &str,
-        query_embedding: Option<&[f32]>,
-        limit: u32,
+    /// 创建空分区表并登记
+    fn register_vec_partition(
+        conn: &Connection,
+        name: &str,
+        start_ts: i64,
+        end_ts: i64,
+        dim: usize,
+        quantization: VectorQuantization,
+    ) -> Result<()> {
+        conn.execute_batch(&vector::create_partition_sql(name, dim, quantization))?;
+        conn.execute(
+            "INSERT OR REPLACE INTO vec_partitions (name, start_ts, end_ts, dim, quantization)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![name, start_ts, end_ts, dim as i64, quantization.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// 创建分区并从 `traces.embedding` 回填时间范围内同维度的向量，返回回填数量
+    fn create_vec_partition(
+        conn: &Connection,
+        name: &str,
+        start_ts: i64,
+        end_ts: i64,
+        dim: usize,
+        quantization: VectorQuantization,
+    ) -> Result<usize> {
+        Self::register_vec_partition(conn, name, start_ts, end_ts, dim, quantization)?;
+
+        let filled = conn.execute(
+            &format!(
+                "INSERT INTO {name} (trace_id, embedding, timestamp, model)
+                 SELECT t.id, {}, t.timestamp, COALESCE(m.model, '')
+                 FROM traces t
+                 LEFT JOIN trace_embedding_models m ON m.trace_id = t.id
+                 WHERE t.timestamp >= ?1 AND t.timestamp < ?2 AND length(t.embedding) = ?3",
+                quantization.quantize_expr("t.embedding")
+            ),
+            rusqlite::params![start_ts, end_ts, (dim * 4) as i64],
+        )?;
+        debug!(
+            "Created vector partition {} (dim={}, quantization={}, backfilled={})",
+            name,
+            dim,
+            quantization.as_str(),
+            filled
+        );
+        Ok(filled)
+    }
+
+    /// 删除早于 `cutoff` 的向量索引条目；整个分区都已过期时直接删表
+    fn delete_vec_entries_before(conn: &Connection, cutoff: i64) -> Result<()> {
+        let partitions: Vec<(String, i64)> = {
+            let mut stmt =
+                conn.prepare_cached("SELECT name, end_ts FROM vec_partitions WHERE start_ts < ?1")?;
+            let rows = stmt.query_map([cutoff], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        for (name, end_ts) in partitions {
+            if end_ts <= cutoff {
+                conn.execute_batch(&format!("DROP TABLE IF EXISTS {name}"))?;
+                conn.execute("DELETE FROM vec_partitions WHERE name = ?1", [&name])?;
+                debug!("Dropped expired vector partition {}", name);
+            } else {
+                conn.execute(
+                    &format!(
+                        "DELETE FROM {name} WHERE trace_id IN (SELECT id FROM traces WHERE timestamp < ?1)"
+                    ),
+                    [cutoff],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 查找可复用 VLM 结果的近重复 trace
+    ///
+    /// 条件：应用与窗口标题相同、时间相差不超过 `window_ms`、自身经过 VLM 分析（不是复用结果，
+    /// 避免多次复用后与原截图相差越来越大），且 pHash 汉明距离不超过 `max_distance`。
+    /// 返回距离最小（相同时取时间最近）的 trace 及其距离。
+    ///
+    /// 变化区域与焦点窗口相交的 trace 不复用：输入文字等局部变化几乎不影响整图 pHash，
+    /// 但正是这类变化需要重新分析（没有焦点窗口记录时按整屏处理）。
+    pub fn find_near_duplicate_analysis(
+        &self,
+        trace: &Trace,
+        max_distance: u32,
+        window_ms: i64,
+    ) -> Result<Option<(Trace, u32)>> {
+        let conn = self.pool.reader();
+        let phash: Option<Vec<u8>> = conn
+            .prepare_cached("SELECT phash FROM traces WHERE id = ?1")?
+            .query_row([trace.id], |row| row.get(0))
+            .optional()?
+            .flatten();
+        let Some(phash) = phash.as_deref().and_then(parse_phash) else {
+            return Ok(None);
+        };
+
+        let changed_in_focus: bool = conn
+            .prepare_cached(
+                r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM trace_change_regions c
+                    LEFT JOIN trace_focus_regions f ON f.trace_id = c.trace_id
+                    WHERE c.trace_id = ?1 AND (
+                        f.trace_id IS NULL OR (
+                            c.x < f.x + f.width AND f.x < c.x + c.width
+                            AND c.y < f.y + f.height AND f.y < c.y + c.height
+                        )
+                    )
+                )
+                "#,
+            )?
+            .query_row([trace.id], |row| row.get(0))?;
+        if changed_in_focus {
+            return Ok(None);
+        }
+
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT t.id, t.timestamp, t.image_path, t.app_name, t.window_title,
+                   t.is_fullscreen,
+                   t.is_idle, t.ocr_text, t.activity_session_id, t.is_key_action,
+                   t.vlm_summary, t.vlm_action_description, t.vlm_activity_type, t.vlm_confidence, t.vlm_entities_json, t.vlm_raw_json,
+                   t.created_at,
+                   t.phash
+            FROM traces t
+            WHERE t.id != ?1
+              AND t.app_name IS ?2 AND t.window_title IS ?3
+              AND t.timestamp BETWEEN ?4 - ?5 AND ?4 + ?5
+              AND t.vlm_raw_json IS NOT NULL AND t.phash IS NOT NULL
+              AND NOT EXISTS (SELECT 1 FROM trace_vlm_reuse r WHERE r.trace_id = t.id)
+            ORDER BY ABS(t.timestamp - ?4)
+            LIMIT ?6
+            "#,
+        )?;
+        let rows = stmt.query_map(
+            rusqlite::params![
+                trace.id,
+                trace.app_name,
+                trace.window_title,
+                trace.timestamp,
+                window_ms,
+                NEAR_DUPLICATE_CANDIDATES
+            ],
+            |row| {
+                Ok((
+                    Self::trace_from_row(row)?,
+                    row.get::<_, Option<Vec<u8>>>(17)?,
+                ))
+            },
+        )?;
+
+        let mut best: Option<(Trace, u32)> = None;
+        for row in rows {
+            let (candidate, candidate_phash) = row?;
+            let Some(candidate_phash) = candidate_phash.as_deref().and_then(parse_phash) else {
+                continue;
+            };
+            let distance = (phash ^ candidate_phash).count_ones();
+            if distance <= max_distance && best.as_ref().is_none_or(|(_, d)| distance < *d) {
+                best = Some((candidate, distance));
+            }
+        }
+        Ok(best)
+    }
+
+    /// 保存 trace 的变化区域
+    pub fn save_change_region(&self, trace_id: i64, region: &ChangeRegion) -> Result<()> {
+        let conn = self.pool.writer();
+        conn.prepare_cached(
+            r#"
+            INSERT OR REPLACE INTO trace_change_regions
+                (trace_id, x, y, width, height, changed_tiles, total_tiles, score)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#,
+        )?
+        .execute(rusqlite::params![
+            trace_id,
+            region.x,
+            region.y,
+            region.width,
+            region.height,
+            region.changed_tiles,
+            region.total_tiles,
+            region.score
+        ])?;
+        Ok(())
+    }
+
+    /// 获取 trace 的变化区域（没有记录时返回 None，表示整屏）
+    pub fn get_change_region(&self, trace_id: i64) -> Result<Option<ChangeRegion>> {
+        let conn = self.pool.reader();
+        let region = conn
+            .prepare_cached(
+                r#"
+                SELECT x, y, width, height, changed_tiles, total_tiles, score
+                FROM trace_change_regions WHERE trace_id = ?1
+                "#,
+            )?
+            .query_row([trace_id], |row| {
+                Ok(ChangeRegion {
+                    x: row.get(0)?,
+                    y: row.get(1)?,
+                    width: row.get(2)?,
+                    height: row.get(3)?,
+                    changed_tiles: row.get(4)?,
+                    total_tiles: row.get(5)?,
+                    score: row.get(6)?,
+                })
+            })
+            .optional()?;
+        Ok(region)
+    }
+
+    /// 保存截图时焦点窗口在截图中的范围 (x, y, width, height)
+    pub fn save_focus_region(&self, trace_id: i64, rect: (u32, u32, u32, u32)) -> Result<()> {
+        let conn = self.pool.writer();
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO trace_focus_regions (trace_id, x, y, width, height) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?
+        .execute(rusqlite::params![trace_id, rect.0, rect.1, rect.2, rect.3])?;
+        Ok(())
+    }
+
+    /// 获取截图时焦点窗口在截图中的范围
+    pub fn get_focus_region(&self, trace_id: i64) -> Result<Option<(u32, u32, u32, u32)>> {
+        let conn = self.pool.reader();
+        let rect = conn
+            .prepare_cached(
+                "SELECT x, y, width, height FROM trace_focus_regions WHERE trace_id = ?1",
+            )?
+            .query_row([trace_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .optional()?;
+        Ok(rect)
+    }
+
+    /// 记录 trace 复用了 `source_trace_id` 的 VLM 结果
+    pub fn record_vlm_reuse(
+        &self,
+        trace_id: i64,
+        source_trace_id: i64,
+        distance: u32,
+    ) -> Result<()> {
+        let conn = self.pool.writer();
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO trace_vlm_reuse (trace_id, source_trace_id, distance) VALUES (?1, ?2, ?3)",
+        )?
+        .execute(rusqlite::params![trace_id, source_trace_id, distance])?;
+        Ok(())
+    }
+
+    /// 记录一个 trace 的 VLM 层级调用
+    pub fn record_vlm_tier_calls(&self, trace_id: i64, calls: &[VlmTierCall]) -> Result<()> {
+        if calls.is_empty() {
+            return Ok(());
+        }
+        let now = Utc::now().timestamp_millis();
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                r#"
+                INSERT INTO vlm_tier_calls
+                    (trace_id, tier, tier_name, model, status, confidence, latency_ms,
+                     escalation_reason, accepted, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                "#,
+            )?;
+            for call in calls {
+                stmt.execute(rusqlite::params![
+                    trace_id,
+                    call.tier,
+                    call.tier_name,
+                    call.model,
+                    call.status,
+                    call.confidence,
+                    call.latency_ms,
+                    call.escalation_reason,
+                    call.accepted,
+                    now
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// 统计 `since` 之后各 VLM 层级的调用情况
+    pub fn get_vlm_tier_stats(&self, since: i64) -> Result<Vec<VlmTierStats>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT tier, tier_name, model,
+                   COUNT(*),
+                   SUM(accepted),
+                   SUM(escalation_reason IS NOT NULL),
+                   SUM(status = 'error'),
+                   SUM(status = 'parse_failed'),
+                   AVG(latency_ms),
+                   AVG(CASE WHEN status = 'ok' THEN confidence END)
+            FROM vlm_tier_calls
+            WHERE created_at >= ?1
+            GROUP BY tier, tier_name, model
+            ORDER BY tier, tier_name, model
+            "#,
+        )?;
+        let rows = stmt.query_map([since], |row| {
+            Ok(VlmTierStats {
+                tier: row.get(0)?,
+                tier_name: row.get(1)?,
+                model: row.get(2)?,
+                calls: row.get(3)?,
+                accepted: row.get(4)?,
+                escalated: row.get(5)?,
+                errors: row.get(6)?,
+                parse_failures: row.get(7)?,
+                avg_latency_ms: row.get::<_, Option<f64>>(8)?.unwrap_or(0.0),
+                avg_confidence: row.get::<_, Option<f64>>(9)?.unwrap_or(0.0),
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// 保存 trace 的 VLM 输出解析状态
+    pub fn save_trace_parse_status(
+        &self,
+        trace_id: i64,
+        status: &str,
+        attempts: u32,
+        error: Option<&str>,
+        raw_output: Option<&str>,
+    ) -> Result<()> {
+        let conn = self.pool.writer();
+        conn.prepare_cached(
+            r#"
+            INSERT OR REPLACE INTO trace_parse_status
+                (trace_id, status, attempts, error, raw_output, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )?
+        .execute(rusqlite::params![
+            trace_id,
+            status,
+            attempts,
+            error,
+            raw_output,
+            Utc::now().timestamp_millis()
+        ])?;
+        Ok(())
+    }
+
+    /// 统计 `since` 之后的 trace 的解析状态
+    pub fn get_parse_status_stats(&self, since: i64) -> Result<ParseStatusStats> {
+        let conn = self.pool.reader();
+        let stats = conn
+            .prepare_cached(
+                r#"
+                SELECT COALESCE(SUM(p.status = 'ok'), 0),
+                       COALESCE(SUM(p.status = 'repaired'), 0),
+                       COALESCE(SUM(p.status = 'reasked'), 0),
+                       COALESCE(SUM(p.status = 'failed'), 0)
+                FROM trace_parse_status p
+                JOIN traces t ON t.id = p.trace_id
+                WHERE t.timestamp >= ?1
+                "#,
+            )?
+            .query_row([since], |row| {
+                Ok(ParseStatusStats {
+                    ok: row.get(0)?,
+                    repaired: row.get(1)?,
+                    reasked: row.get(2)?,
+                    failed: row.get(3)?,
+                })
+            })?;
+        Ok(stats)
+    }
+
+    /// 保存摘要周期的输出解析状态
+    pub fn save_summary_parse_status(
+        &self,
+        summary_type: &str,
+        start_time: i64,
+        end_time: i64,
+        report: &crate::ai::ParseReport,
+    ) -> Result<()> {
+        let conn = self.pool.writer();
+        conn.prepare_cached(
+            r#"
+            INSERT OR REPLACE INTO summary_parse_status
+                (summary_type, start_time, end_time, status, attempts, error, raw_output, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#,
+        )?
+        .execute(rusqlite::params![
+            summary_type,
+            start_time,
+            end_time,
+            report.status.as_str(),
+            report.attempts,
+            report.error,
+            report.raw_output,
+            Utc::now().timestamp_millis()
+        ])?;
+        Ok(())
+    }
+
+    /// 统计 `since` 之后结束的摘要周期的解析状态
+    pub fn get_summary_parse_status_stats(&self, since: i64) -> Result<ParseStatusStats> {
+        let conn = self.pool.reader();
+        let stats = conn
+            .prepare_cached(
+                r#"
+                SELECT COALESCE(SUM(status = 'ok'), 0),
+                       COALESCE(SUM(status = 'repaired'), 0),
+                       COALESCE(SUM(status = 'reasked'), 0),
+                       COALESCE(SUM(status = 'failed'), 0)
+                FROM summary_parse_status
+                WHERE end_time >= ?1
+                "#,
+            )?
+            .query_row([since], |row| {
+                Ok(ParseStatusStats {
+                    ok: row.get(0)?,
+                    repaired: row.get(1)?,
+                    reasked: row.get(2)?,
+                    failed: row.get(3)?,
+                })
+            })?;
+        Ok(stats)
+    }
+
+    /// 保存会话回顾、实体提取或关系标注的输出解析状态
+    pub fn save_model_parse_status(
+        &self,
+        task: &str,
+        subject: &str,
+        report: &crate::ai::ParseReport,
+    ) -> Result<()> {
+        let conn = self.pool.writer();
+        conn.prepare_cached(
+            r#"
+            INSERT OR REPLACE INTO model_parse_status
+                (task, subject, status, attempts, error, raw_output, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            "#,
+        )?
+        .execute(rusqlite::params![
+            task,
+            subject,
+            report.status.as_str(),
+            report.attempts,
+            report.error,
+            report.raw_output,
+            Utc::now().timestamp_millis()
+        ])?;
+        Ok(())
+    }
+
+    /// 统计某个任务 `since` 之后记录的输出解析状态
+    pub fn get_model_parse_status_stats(&self, task: &str, since: i64) -> Result<ParseStatusStats> {
+        let conn = self.pool.reader();
+        let stats = conn
+            .prepare_cached(
+                r#"
+                SELECT COALESCE(SUM(status = 'ok'), 0),
+                       COALESCE(SUM(status = 'repaired'), 0),
+                       COALESCE(SUM(status = 'reasked'), 0),
+                       COALESCE(SUM(status = 'failed'), 0)
+                FROM model_parse_status
+                WHERE task = ?1 AND updated_at >= ?2
+                "#,
+            )?
+            .query_row(rusqlite::params![task, since], |row| {
+                Ok(ParseStatusStats {
+                    ok: row.get(0)?,
+                    repaired: row.get(1)?,
+                    reasked: row.get(2)?,
+                    failed: row.get(3)?,
+                })
+            })?;
+        Ok(stats)
+    }
+
+    /// 按解析状态列出 traces（最新的在前）
+    pub fn get_traces_by_parse_status(
+        &self,
+        status: &str,
+        limit: u32,
+    ) -> Result<Vec<TraceParseStatus>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT p.trace_id, t.timestamp, p.status, p.attempts, p.error, p.raw_output, p.updated_at
+            FROM trace_parse_status p
+            JOIN traces t ON t.id = p.trace_id
+            WHERE p.status = ?1
+            ORDER BY t.timestamp DESC
+            LIMIT ?2
+            "#,
+        )?;
+        let rows = stmt.query_map(rusqlite::params![status, limit], |row| {
+            Ok(TraceParseStatus {
+                trace_id: row.get(0)?,
+                timestamp: row.get(1)?,
+                status: row.get(2)?,
+                attempts: row.get(3)?,
+                error: row.get(4)?,
+                raw_output: row.get(5)?,
+                updated_at: row.get(6)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// 把解析失败的 traces 重新放回 VLM 分析队列，返回数量
+    ///
+    /// 清空 OCR 文本与 VLM 结论并删除解析状态记录，VlmTask 会按待分析 trace 重新处理。
+    pub fn reset_failed_analyses(&self, limit: u32) -> Result<usize> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+        let ids: Vec<i64> = {
+            let mut stmt = tx.prepare_cached(
+                r#"
+                SELECT p.trace_id FROM trace_parse_status p
+                JOIN traces t ON t.id = p.trace_id
+                WHERE p.status = 'failed' AND t.image_path IS NOT NULL
+                ORDER BY t.timestamp DESC
+                LIMIT ?1
+                "#,
+            )?;
+            let rows = stmt.query_map([limit], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        {
+            let mut reset = tx.prepare_cached(
+                r#"
+                UPDATE traces SET
+                    ocr_text = NULL,
+                    vlm_summary = NULL,
+                    vlm_action_description = NULL,
+                    vlm_activity_type = NULL,
+                    vlm_confidence = NULL,
+                    vlm_entities_json = NULL,
+                    vlm_raw_json = NULL
+                WHERE id = ?1
+                "#,
+            )?;
+            let mut delete =
+                tx.prepare_cached("DELETE FROM trace_parse_status WHERE trace_id = ?1")?;
+            for id in &ids {
+                reset.execute([id])?;
+                delete.execute([id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(ids.len())
+    }
+
+    /// 统计 `since` 之后的 trace 中 VLM 结果复用情况
+    pub fn get_vlm_reuse_stats(&self, since: i64) -> Result<VlmReuseStats> {
+        let conn = self.pool.reader();
+        let (analyzed, reused, avg_distance): (u64, u64, Option<f64>) = conn
+            .prepare_cached(
+                r#"
+                SELECT COUNT(*), COUNT(r.trace_id), AVG(r.distance)
+                FROM traces t
+                LEFT JOIN trace_vlm_reuse r ON r.trace_id = t.id
+                WHERE t.timestamp >= ?1 AND t.vlm_raw_json IS NOT NULL
+                "#,
+            )?
+            .query_row([since], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        Ok(VlmReuseStats {
+            analyzed,
+            reused,
+            hit_rate: if analyzed > 0 {
+                reused as f64 / analyzed as f64
+            } else {
+                0.0
+            },
+            avg_distance: avg_distance.unwrap_or(0.0),
+        })
+    }
+
+    /// 获取待处理 OCR 的 traces（没有 ocr_text 的）
+    pub fn get_traces_pending_ocr(&self, limit: u32) -> Result<Vec<Trace>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT id, timestamp, image_path, app_name, window_title,
+                   is_fullscreen,
+                   is_idle, ocr_text, activity_session_id, is_key_action,
+                   vlm_summary, vlm_action_description, vlm_activity_type, vlm_confidence, vlm_entities_json, vlm_raw_json,
+                   created_at
+            FROM traces
+            WHERE ocr_text IS NULL
+            ORDER BY timestamp DESC
+            LIMIT ?1
+            "#,
+        )?;
+
+        let traces = stmt.query_map([limit], |row| Self::trace_from_row(row))?;
+
+        let mut result = Vec::new();
+        for trace in traces {
+            result.push(trace?);
+        }
+        Ok(result)
+    }
+
+    /// 获取待处理嵌入的 traces（有 ocr_text 但没有 embedding 的）
+    ///
+    /// 传入当前模型标识时，向量由其他模型生成（或旧版本写入、没有模型记录）的 trace 也需要重新嵌入。
+    pub fn get_traces_pending_embedding(
+        &self,
+        limit: u32,
+        model: Option<&str>,
+    ) -> Result<Vec<Trace>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT t.id, t.timestamp, t.image_path, t.app_name, t.window_title,
+                   t.is_fullscreen,
+                   t.is_idle, t.ocr_text, t.activity_session_id, t.is_key_action,
+                   t.vlm_summary, t.vlm_action_description, t.vlm_activity_type, t.vlm_confidence, t.vlm_entities_json, t.vlm_raw_json,
+                   t.created_at
+            FROM traces t
+            LEFT JOIN trace_embedding_models m ON m.trace_id = t.id
+            WHERE t.ocr_text IS NOT NULL
+              AND (TRIM(t.ocr_text) != '' OR t.vlm_raw_json IS NOT NULL)
+              AND (t.embedding IS NULL OR (?2 IS NOT NULL AND COALESCE(m.model, '') != ?2))
+              AND NOT EXISTS (
+                  SELECT 1 FROM trace_parse_status p WHERE p.trace_id = t.id AND p.status = 'failed'
+              )
+              AND NOT EXISTS (
+                  SELECT 1 FROM trace_embedding_failures f
+                  WHERE f.trace_id = t.id AND f.model = COALESCE(?2, '')
+              )
+            ORDER BY t.embedding IS NOT NULL, t.timestamp DESC
+            LIMIT ?1
+            "#,
+        )?;
+
+        let traces = stmt.query_map(rusqlite::params![limit, model], |row| {
+            Self::trace_from_row(row)
+        })?;
+
+        let mut result = Vec::new();
+        for trace in traces {
+            result.push(trace?);
+        }
+        Ok(result)
+    }
+
+    /// 向量相似度搜索（使用 sqlite-vec KNN 搜索）
+    ///
+    /// `model` 为查询向量的模型标识，只返回同一模型生成的向量；切换模型后尚未重新嵌入的 trace 被跳过。
+    /// `time_range` 为闭区间时间过滤，只扫描时间范围重叠的分区。
+    pub fn search_by_embedding(
+        &self,
+        query_embedding: &[f32],
+        limit: u32,
+        model: &str,
+        time_range: Option<(i64, i64)>,
     ) -> Result<Vec<(Trace, f32)>> {
-        // 1. FTS 搜索
-        let fts_results = self.search_text(query, limit * 2)?;
+        let conn = self.pool.reader();
+        let hits = self.knn_search(&conn, query_embedding, limit, model, time_range)?;
+
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT id, timestamp, image_path, app_name, window_title,
+                   is_fullscreen,
+                   is_idle, ocr_text, activity_session_id, is_key_action,
+                   vlm_summary, vlm_action_description, vlm_activity_type, vlm_confidence, vlm_entities_json, vlm_raw_json,
+                   created_at
+            FROM traces
+            WHERE id = ?1
+            "#,
+        )?;
+        let mut results = Vec::with_capacity(hits.len());
+        for (trace_id, distance) in hits {
+            if let Some(trace) = stmt
+                .query_row([trace_id], Self::trace_from_row)
+                .optional()?
+            {
+                // 将距离转换为相似度（距离越小，相似度越高）
+                results.push((trace, 1.0 / (1.0 + distance)));
+            }
+        }
+
+        debug!("sqlite-vec KNN search returned {} results", results.len());
+        Ok(results)
+    }
+
+    /// 在时间范围重叠的分区中做 KNN，返回按欧氏距离升序的 `(trace_id, distance)`
+    ///
+    /// 量化分区的候选用 `traces.embedding` 的全精度向量重新计算距离。
+    fn knn_search(
+        &self,
+        conn: &Connection,
+        query_embedding: &[f32],
+        limit: u32,
+        model: &str,
+        time_range: Option<(i64, i64)>,
+    ) -> Result<Vec<(i64, f32)>> {
+        let config = self.vector_index_config();
+        let (from, to) = time_range.unwrap_or((i64::MIN, i64::MAX));
+
+        // 将查询向量转换为字节数组（sqlite-vec 接受的格式）
+        let query_bytes: Vec<u8> = query_embedding
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+
+        let partitions: Vec<(String, String)> = {
+            let mut stmt = conn.prepare_cached(
+                "SELECT name, quantization FROM vec_partitions
+                 WHERE dim = ?1 AND start_ts <= ?3 AND end_ts > ?2",
+            )?;
+            let rows = stmt.query_map(
+                rusqlite::params![query_embedding.len() as i64, from, to],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let mut embedding_stmt =
+            conn.prepare_cached("SELECT embedding FROM traces WHERE id = ?1")?;
+        let mut hits: Vec<(i64, f32)> = Vec::new();
+        for (name, quantization) in &partitions {
+            let quantization = VectorQuantization::parse(quantization);
+            // vec0 表通过 MATCH 子句进行 KNN，model / timestamp 元数据列在 KNN 之前过滤；
+            // 分区表名随时间变化，不进语句缓存
+            let mut stmt = conn.prepare(&format!(
+                "SELECT trace_id, distance FROM {name}
+                 WHERE embedding MATCH {} AND k = ?2
+                   AND model = ?3 AND timestamp >= ?4 AND timestamp <= ?5",
+                quantization.quantize_expr("?1")
+            ))?;
+            let k = config.candidates(quantization, limit);
+            let rows = stmt
+                .query_map(rusqlite::params![query_bytes, k, model, from, to], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, f32>(1)?))
+                })?;
+            for row in rows {
+                let (trace_id, distance) = row?;
+                if quantization == VectorQuantization::None {
+                    hits.push((trace_id, distance));
+                    continue;
+                }
+                let embedding: Option<Vec<u8>> = embedding_stmt
+                    .query_row([trace_id], |row| row.get(0))
+                    .optional()?
+                    .flatten();
+                if let Some(bytes) = embedding {
+                    let exact =
+                        vector::l2_distance(query_embedding, &Self::deserialize_embedding(&bytes));
+                    hits.push((trace_id, exact));
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| a.1.total_cmp(&b.1));
+        hits.truncate(limit as usize);
+        Ok(hits)
+    }
+
+    /// 对比当前向量索引与精确 KNN（逐行计算距离）的延迟和召回率
+    ///
+    /// 从 `model` 的向量中随机抽取 `queries` 条作为查询向量，以精确 KNN 的前 `limit` 条为基准计算 recall。
+    pub fn benchmark_vector_search(
+        &self,
+        model: &str,
+        queries: u32,
+        limit: u32,
+    ) -> Result<VectorBenchmark> {
+        let config = self.vector_index_config();
+        let conn = self.pool.reader();
+
+        let samples: Vec<Vec<u8>> = {
+            let mut stmt = conn.prepare_cached(
+                "SELECT t.embedding FROM traces t
+                 JOIN trace_embedding_models m ON m.trace_id = t.id
+                 WHERE m.model = ?1 AND t.embedding IS NOT NULL
+                 ORDER BY random() LIMIT ?2",
+            )?;
+            let rows = stmt.query_map(rusqlite::params![model, queries], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let mut exact_stmt = conn.prepare_cached(
+            "SELECT t.id FROM traces t
+             JOIN trace_embedding_models m ON m.trace_id = t.id
+             WHERE m.model = ?2 AND length(t.embedding) = length(?1)
+             ORDER BY vec_distance_l2(t.embedding, ?1)
+             LIMIT ?3",
+        )?;
+        let mut ann_ms = Vec::with_capacity(samples.len());
+        let mut exact_ms = Vec::with_capacity(samples.len());
+        let mut recall_sum = 0.0;
+        for bytes in &samples {
+            let query = Self::deserialize_embedding(bytes);
+
+            let started = std::time::Instant::now();
+            let ann: Vec<i64> = self
+                .knn_search(&conn, &query, limit, model, None)?
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect();
+            ann_ms.push(started.elapsed().as_secs_f64() * 1000.0);
+
+            let started = std::time::Instant::now();
+            let exact: Vec<i64> = exact_stmt
+                .query_map(rusqlite::params![bytes, model, limit], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            exact_ms.push(started.elapsed().as_secs_f64() * 1000.0);
+
+            if !exact.is_empty() {
+                let found = exact.iter().filter(|id| ann.contains(id)).count();
+                recall_sum += found as f64 / exact.len() as f64;
+            }
+        }
+
+        let partitions: u32 =
+            conn.query_row("SELECT COUNT(*) FROM vec_partitions", [], |row| row.get(0))?;
+        let vectors: u64 = conn.query_row(
+            "SELECT COUNT(*) FROM trace_embedding_models WHERE model = ?1",
+            [model],
+            |row| row.get(0),
+        )?;
+        let (ann_avg_ms, ann_p95_ms) = vector::latency_summary(&mut ann_ms);
+        let (exact_avg_ms, exact_p95_ms) = vector::latency_summary(&mut exact_ms);
+
+        Ok(VectorBenchmark {
+            quantization: config.quantization.as_str().to_string(),
+            partition_days: config.partition_days,
+            partitions,
+            vectors,
+            queries: samples.len() as u32,
+            limit,
+            ann_avg_ms,
+            ann_p95_ms,
+            exact_avg_ms,
+            exact_p95_ms,
+            recall: if samples.is_empty() {
+                0.0
+            } else {
+                recall_sum / samples.len() as f64
+            },
+        })
+    }
+
+    /// 混合搜索（FTS + 向量）
+    pub fn hybrid_search(
+        &self,
+        query: &str,
+        query_embedding: Option<&[f32]>,
+        model: &str,
+        time_range: Option<(i64, i64)>,
+        limit: u32,
+    ) -> Result<Vec<(Trace, f32)>> {
+        // 1. FTS 搜索
+        let fts_results = self.search_text(query, limit * 2)?;
+
+        // 2. 如果没有向量，直接返回 FTS 结果
+        let query_embedding = match query_embedding {
+            Some(emb) => emb,
+            None => {
+                return Ok(fts_results
+                    .into_iter()
+                    .take(limit as usize)
+                    .map(|t| (t, 1.0))
+                    .collect());
+            }
+        };
+
+        // 3. 向量搜索
+        let vec_results =
+            self.search_by_embedding(query_embedding, limit * 2, model, time_range)?;
+
+        // 4. RRF 融合
+        let mut scores: std::collections::HashMap<i64, f32> = std::collections::HashMap::new();
+        let k = 60.0; // RRF 常数
+
+        // FTS 分数
+        for (rank, trace) in fts_results.iter().enumerate() {
+            let score = 1.0 / (k + rank as f32 + 1.0);
+            *scores.entry(trace.id).or_insert(0.0) += score;
+        }
+
+        // 向量分数
+        for (rank, (trace, _)) in vec_results.iter().enumerate() {
+            let score = 1.0 / (k + rank as f32 + 1.0);
+            *scores.entry(trace.id).or_insert(0.0) += score;
+        }
+
+        // 收集所有 traces
+        let all_traces: std::collections::HashMap<i64, Trace> = fts_results
+            .into_iter()
+            .chain(vec_results.into_iter().map(|(t, _)| t))
+            .map(|t| (t.id, t))
+            .collect();
+
+        // 按 RRF 分数排序
+        let mut results: Vec<(Trace, f32)> = scores
+            .into_iter()
+            .filter_map(|(id, score)| all_traces.get(&id).map(|t| (t.clone(), score)))
+            .collect();
+
+        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        results.truncate(limit as usize);
+
+        Ok(results)
+    }
+
+    /// 反序列化向量
+    fn deserialize_embedding(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect()
+    }
+
+    /// 计算余弦相似度
+    #[allow(dead_code)]
+    fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+        if a.len() != b.len() {
+            return 0.0;
+        }
+
+        let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+        let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+        if norm_a == 0.0 || norm_b == 0.0 {
+            return 0.0;
+        }
+
+        dot / (norm_a * norm_b)
+    }
+
+    // ==================== Summary CRUD ====================
+
+    /// 插入摘要记录
+    pub fn insert_summary(&self, summary: &NewSummary) -> Result<i64> {
+        let conn = self.pool.writer();
+        conn.execute(
+            r#"
+            INSERT INTO summaries (
+                start_time, end_time, summary_type, content,
+                structured_data, trace_count
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+            rusqlite::params![
+                summary.start_time,
+                summary.end_time,
+                summary.summary_type,
+                summary.content,
+                summary.structured_data,
+                summary.trace_count,
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// 按时间范围查询摘要
+    pub fn get_summaries(
+        &self,
+        start_time: i64,
+        end_time: i64,
+        summary_type: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<Summary>> {
+        let conn = self.pool.reader();
+
+        let mut result = Vec::new();
+
+        if let Some(stype) = summary_type {
+            let mut stmt = conn.prepare_cached(
+                r#"
+                SELECT id, start_time, end_time, summary_type, content,
+                       structured_data, trace_count, created_at
+                FROM summaries
+                WHERE start_time >= ?1 AND end_time <= ?2 AND summary_type = ?3
+                ORDER BY start_time DESC
+                LIMIT ?4
+                "#,
+            )?;
+
+            let summaries = stmt.query_map(
+                rusqlite::params![start_time, end_time, stype, limit],
+                |row| {
+                    Ok(Summary {
+                        id: row.get(0)?,
+                        start_time: row.get(1)?,
+                        end_time: row.get(2)?,
+                        summary_type: row.get(3)?,
+                        content: row.get(4)?,
+                        structured_data: row.get(5)?,
+                        trace_count: row.get(6)?,
+                        created_at: row.get(7)?,
+                    })
+                },
+            )?;
+
+            for summary in summaries {
+                result.push(summary?);
+            }
+        } else {
+            let mut stmt = conn.prepare_cached(
+                r#"
+                SELECT id, start_time, end_time, summary_type, content,
+                       structured_data, trace_count, created_at
+                FROM summaries
+                WHERE start_time >= ?1 AND end_time <= ?2
+                ORDER BY start_time DESC
+                LIMIT ?3
+                "#,
+            )?;
+
+            let summaries =
+                stmt.query_map(rusqlite::params![start_time, end_time, limit], |row| {
+                    Ok(Summary {
+                        id: row.get(0)?,
+                        start_time: row.get(1)?,
+                        end_time: row.get(2)?,
+                        summary_type: row.get(3)?,
+                        content: row.get(4)?,
+                        structured_data: row.get(5)?,
+                        trace_count: row.get(6)?,
+                        created_at: row.get(7)?,
+                    })
+                })?;
+
+            for summary in summaries {
+                result.push(summary?);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// 记录汇总摘要与下级摘要的层级关系
+    pub fn link_summary_children(&self, parent_id: i64, child_ids: &[i64]) -> Result<()> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR IGNORE INTO summary_links (parent_id, child_id) VALUES (?1, ?2)",
+            )?;
+            for child_id in child_ids {
+                stmt.execute(rusqlite::params![parent_id, child_id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// 获取汇总摘要的下级摘要（按时间升序）
+    pub fn get_child_summaries(&self, parent_id: i64) -> Result<Vec<Summary>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT s.id, s.start_time, s.end_time, s.summary_type, s.content,
+                   s.structured_data, s.trace_count, s.created_at
+            FROM summary_links l
+            JOIN summaries s ON s.id = l.child_id
+            WHERE l.parent_id = ?1
+            ORDER BY s.start_time ASC
+            "#,
+        )?;
+
+        let summaries = stmt.query_map([parent_id], |row| {
+            Ok(Summary {
+                id: row.get(0)?,
+                start_time: row.get(1)?,
+                end_time: row.get(2)?,
+                summary_type: row.get(3)?,
+                content: row.get(4)?,
+                structured_data: row.get(5)?,
+                trace_count: row.get(6)?,
+                created_at: row.get(7)?,
+            })
+        })?;
+
+        let mut result = Vec::new();
+        for summary in summaries {
+            result.push(summary?);
+        }
+        Ok(result)
+    }
+
+    /// 记录摘要周期已完成（`summary_id` 为空表示周期内无数据）
+    pub fn mark_summary_period(
+        &self,
+        summary_type: &str,
+        period_start: i64,
+        period_end: i64,
+        summary_id: Option<i64>,
+    ) -> Result<()> {
+        let conn = self.pool.writer();
+        conn.prepare_cached(
+            r#"
+            INSERT OR REPLACE INTO summary_periods (summary_type, period_start, period_end, summary_id)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+        )?
+        .execute(rusqlite::params![
+            summary_type,
+            period_start,
+            period_end,
+            summary_id
+        ])?;
+        Ok(())
+    }
+
+    /// 已完成周期的最晚结束时间（之前的周期都已处理）
+    pub fn get_summary_watermark(&self, summary_type: &str) -> Result<Option<i64>> {
+        let conn = self.pool.reader();
+        let watermark = conn
+            .prepare_cached("SELECT MAX(period_end) FROM summary_periods WHERE summary_type = ?1")?
+            .query_row([summary_type], |row| row.get(0))?;
+        Ok(watermark)
+    }
+
+    /// 按 ID 获取摘要
+    pub fn get_summary_by_id(&self, id: i64) -> Result<Option<Summary>> {
+        let conn = self.pool.reader();
+        let result = conn.query_row(
+            r#"
+            SELECT id, start_time, end_time, summary_type, content,
+                   structured_data, trace_count, created_at
+            FROM summaries
+            WHERE id = ?1
+            "#,
+            rusqlite::params![id],
+            |row| {
+                Ok(Summary {
+                    id: row.get(0)?,
+                    start_time: row.get(1)?,
+                    end_time: row.get(2)?,
+                    summary_type: row.get(3)?,
+                    content: row.get(4)?,
+                    structured_data: row.get(5)?,
+                    trace_count: row.get(6)?,
+                    created_at: row.get(7)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(summary) => Ok(Some(summary)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 获取最近的摘要
+    pub fn get_latest_summary(&self, summary_type: &str) -> Result<Option<Summary>> {
+        let conn = self.pool.reader();
+        let result = conn.query_row(
+            r#"
+            SELECT id, start_time, end_time, summary_type, content,
+                   structured_data, trace_count, created_at
+            FROM summaries
+            WHERE summary_type = ?1
+            ORDER BY end_time DESC
+            LIMIT 1
+            "#,
+            rusqlite::params![summary_type],
+            |row| {
+                Ok(Summary {
+                    id: row.get(0)?,
+                    start_time: row.get(1)?,
+                    end_time: row.get(2)?,
+                    summary_type: row.get(3)?,
+                    content: row.get(4)?,
+                    structured_data: row.get(5)?,
+                    trace_count: row.get(6)?,
+                    created_at: row.get(7)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(summary) => Ok(Some(summary)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 删除摘要
+    pub fn delete_summary(&self, id: i64) -> Result<bool> {
+        let conn = self.pool.writer();
+        let rows = conn.execute("DELETE FROM summaries WHERE id = ?1", rusqlite::params![id])?;
+        Ok(rows > 0)
+    }
 
-        // 2. 如果没有向量，直接返回 FTS 结果
-        let query_embedding = match query_embedding {
-            Some(emb) => emb,
+    // ==================== Entity CRUD ====================
+
+    /// 插入或更新实体（按别名解析到已有实体，并为类型计票）
+    pub fn upsert_entity(&self, entity: &NewEntity) -> Result<i64> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let id = Self::ensure_entity(&tx, &entity.name, entity.first_seen)?;
+        tx.prepare_cached(
+            r#"
+            UPDATE entities SET
+                mention_count = mention_count + 1,
+                first_seen = MIN(first_seen, ?2),
+                last_seen = MAX(last_seen, ?3),
+                metadata = COALESCE(?4, metadata)
+            WHERE id = ?1
+            "#,
+        )?
+        .execute(rusqlite::params![
+            id,
+            entity.first_seen,
+            entity.last_seen,
+            entity.metadata
+        ])?;
+        Self::vote_entity_type(&tx, id, &entity.entity_type, 1)?;
+
+        tx.commit()?;
+        Ok(id)
+    }
+
+    fn entity_from_row(row: &rusqlite::Row) -> rusqlite::Result<Entity> {
+        Self::entity_from_row_at(row, 0)
+    }
+
+    /// 从第 `offset` 列起读取实体（用于与其他表联查的结果）
+    fn entity_from_row_at(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<Entity> {
+        Ok(Entity {
+            id: row.get(offset)?,
+            name: row.get(offset + 1)?,
+            entity_type: row.get(offset + 2)?,
+            mention_count: row.get(offset + 3)?,
+            first_seen: row.get(offset + 4)?,
+            last_seen: row.get(offset + 5)?,
+            metadata: row.get(offset + 6)?,
+        })
+    }
+
+    /// 查找或创建实体（新实体类型为 `other`、提及次数为 0，由调用方计票与累加）
+    ///
+    /// 先按归一化名称查别名表，再按原始名称精确匹配（早期数据没有别名），
+    /// 都找不到时新建实体；找到或新建后确保别名存在。
+    fn ensure_entity(conn: &Connection, name: &str, timestamp: i64) -> Result<i64> {
+        let alias = crate::ai::entity::normalize_entity_name(name);
+
+        let by_alias: Option<i64> = conn
+            .prepare_cached("SELECT entity_id FROM entity_aliases WHERE alias = ?1")?
+            .query_row([&alias], |row| row.get(0))
+            .optional()?;
+        if let Some(id) = by_alias {
+            return Ok(id);
+        }
+
+        let by_name: Option<i64> = conn
+            .prepare_cached("SELECT id FROM entities WHERE name = ?1")?
+            .query_row([name], |row| row.get(0))
+            .optional()?;
+        let id = match by_name {
+            Some(id) => id,
             None => {
-                return Ok(fts_results
-                    .into_iter()
-                    .take(limit as usize)
-                    .map(|t| (t, 1.0))
-                    .collect());
+                conn.prepare_cached(
+                    r#"
+                    INSERT INTO entities (name, type, mention_count, first_seen, last_seen)
+                    VALUES (?1, ?2, 0, ?3, ?3)
+                    "#,
+                )?
+                .execute(rusqlite::params![
+                    name,
+                    crate::ai::entity::UNKNOWN_ENTITY_TYPE,
+                    timestamp
+                ])?;
+                conn.last_insert_rowid()
             }
         };
 
-        // 3. 向量搜索
-        let vec_results = self.search_by_embedding(query_embedding, limit * 2)?;
+        conn.prepare_cached(
+            "INSERT OR IGNORE INTO entity_aliases (alias, entity_id, name) VALUES (?1, ?2, ?3)",
+        )?
+        .execute(rusqlite::params![alias, id, name])?;
+        Ok(id)
+    }
 
-        // 4. RRF 融合
-        let mut scores: std::collections::HashMap<i64, f32> = std::collections::HashMap::new();
-        let k = 60.0; // RRF 常数
+    /// 为实体类型计票，并把实体类型设为得票最多的类型（平票时保留当前类型）
+    ///
+    /// `other` 不计票，只有没有任何具体类型的实体才保持 `other`。
+    fn vote_entity_type(
+        conn: &Connection,
+        entity_id: i64,
+        entity_type: &str,
+        votes: i64,
+    ) -> Result<()> {
+        if entity_type != crate::ai::entity::UNKNOWN_ENTITY_TYPE {
+            conn.prepare_cached(
+                r#"
+                INSERT INTO entity_type_votes (entity_id, type, votes) VALUES (?1, ?2, ?3)
+                ON CONFLICT(entity_id, type) DO UPDATE SET votes = votes + excluded.votes
+                "#,
+            )?
+            .execute(rusqlite::params![entity_id, entity_type, votes])?;
+        }
+        Self::apply_entity_type_votes(conn, entity_id)
+    }
 
-        // FTS 分数
-        for (rank, trace) in fts_results.iter().enumerate() {
-            let score = 1.0 / (k + rank as f32 + 1.0);
-            *scores.entry(trace.id).or_insert(0.0) += score;
+    /// 按计票结果更新实体类型
+    fn apply_entity_type_votes(conn: &Connection, entity_id: i64) -> Result<()> {
+        conn.prepare_cached(
+            r#"
+            UPDATE entities SET type = (
+                SELECT v.type FROM entity_type_votes v
+                JOIN entities e ON e.id = v.entity_id
+                WHERE v.entity_id = ?1
+                ORDER BY v.votes DESC, v.type = e.type DESC
+                LIMIT 1
+            )
+            WHERE id = ?1
+              AND EXISTS (SELECT 1 FROM entity_type_votes WHERE entity_id = ?1)
+            "#,
+        )?
+        .execute([entity_id])?;
+        Ok(())
+    }
+
+    /// 记录 trace 中出现的实体（名称, 类型）：插入或更新实体，关联 trace 及其所属会话
+    ///
+    /// 同一实体与 trace 只关联一次，重复处理不会增加提及次数；`first_seen` / `last_seen`
+    /// 取关联 trace 的时间戳范围。返回新建的 trace 关联数。
+    pub fn record_trace_entities(
+        &self,
+        trace_id: i64,
+        entities: &[(String, String)],
+    ) -> Result<usize> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let Some((timestamp, session_id)): Option<(i64, Option<i64>)> = tx
+            .prepare_cached("SELECT timestamp, activity_session_id FROM traces WHERE id = ?1")?
+            .query_row([trace_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?
+        else {
+            return Ok(0);
+        };
+
+        let mut linked = 0;
+        for (name, entity_type) in entities {
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let entity_id = Self::ensure_entity(&tx, name, timestamp)?;
+            let inserted = tx
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO entity_traces (entity_id, trace_id) VALUES (?1, ?2)",
+                )?
+                .execute(rusqlite::params![entity_id, trace_id])?;
+            if inserted == 0 {
+                continue;
+            }
+            linked += 1;
+
+            tx.prepare_cached(
+                r#"
+                UPDATE entities SET
+                    mention_count = mention_count + 1,
+                    first_seen = MIN(first_seen, ?2),
+                    last_seen = MAX(last_seen, ?2)
+                WHERE id = ?1
+                "#,
+            )?
+            .execute(rusqlite::params![entity_id, timestamp])?;
+            Self::vote_entity_type(&tx, entity_id, entity_type, 1)?;
+
+            if let Some(session_id) = session_id {
+                tx.prepare_cached(
+                    "INSERT OR IGNORE INTO entity_sessions (entity_id, session_id) VALUES (?1, ?2)",
+                )?
+                .execute(rusqlite::params![entity_id, session_id])?;
+            }
         }
 
-        // 向量分数
-        for (rank, (trace, _)) in vec_results.iter().enumerate() {
-            let score = 1.0 / (k + rank as f32 + 1.0);
-            *scores.entry(trace.id).or_insert(0.0) += score;
+        tx.commit()?;
+        Ok(linked)
+    }
+
+    /// 记录摘要中提取的实体，`first_seen` / `last_seen` 取摘要时间范围 `[start_time, end_time)`
+    /// 内已关联该实体的 trace 的时间戳（没有时使用 `entity` 中的值）。返回实体 ID。
+    ///
+    /// trace 关联只来自 VLM 实体（`record_trace_entities`），不在 OCR / 标题中做子串匹配，
+    /// 避免 "Go"、"AI" 等短名称误关联；摘要本身不计入 `mention_count`。
+    pub fn record_summary_entity(
+        &self,
+        entity: &NewEntity,
+        start_time: i64,
+        end_time: i64,
+    ) -> Result<i64> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let entity_id = Self::ensure_entity(&tx, &entity.name, entity.first_seen)?;
+        let (first_seen, last_seen): (Option<i64>, Option<i64>) = tx
+            .prepare_cached(
+                r#"
+                SELECT MIN(t.timestamp), MAX(t.timestamp)
+                FROM entity_traces et
+                JOIN traces t ON t.id = et.trace_id
+                WHERE et.entity_id = ?1 AND t.timestamp >= ?2 AND t.timestamp < ?3
+                "#,
+            )?
+            .query_row(rusqlite::params![entity_id, start_time, end_time], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+
+        tx.prepare_cached(
+            r#"
+            UPDATE entities SET
+                first_seen = MIN(first_seen, ?2),
+                last_seen = MAX(last_seen, ?3),
+                metadata = COALESCE(?4, metadata)
+            WHERE id = ?1
+            "#,
+        )?
+        .execute(rusqlite::params![
+            entity_id,
+            first_seen.unwrap_or(entity.first_seen),
+            last_seen.unwrap_or(entity.last_seen),
+            entity.metadata
+        ])?;
+        Self::vote_entity_type(&tx, entity_id, &entity.entity_type, 1)?;
+
+        tx.commit()?;
+        Ok(entity_id)
+    }
+
+    /// 获取 `after_id` 之后带 VLM 实体的 trace（用于回填 entity_traces）
+    pub fn get_traces_with_entities_after(
+        &self,
+        after_id: i64,
+        limit: u32,
+    ) -> Result<Vec<(i64, String)>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT id, vlm_entities_json FROM traces
+            WHERE id > ?1 AND vlm_entities_json IS NOT NULL
+            ORDER BY id ASC
+            LIMIT ?2
+            "#,
+        )?;
+        let rows = stmt.query_map(rusqlite::params![after_id, limit], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row?);
         }
+        Ok(result)
+    }
 
-        // 收集所有 traces
-        let all_traces: std::collections::HashMap<i64, Trace> = fts_results
-            .into_iter()
-            .chain(vec_results.into_iter().map(|(t, _)| t))
-            .map(|t| (t.id, t))
-            .collect();
+    /// 获取实体关联的活动会话（按结束时间倒序）
+    pub fn get_sessions_by_entity(
+        &self,
+        entity_id: i64,
+        limit: u32,
+    ) -> Result<Vec<ActivitySession>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT s.id, s.app_name, s.title, s.description, s.start_time, s.end_time,
+                   s.start_trace_id, s.end_trace_id, s.trace_count,
+                   s.context_text, s.entities_json, s.key_actions_json,
+                   s.created_at, s.updated_at,
+                   COALESCE(l.state, 'open'), l.closed_at, l.recap
+            FROM activity_sessions s
+            LEFT JOIN session_lifecycle l ON l.session_id = s.id
+            JOIN entity_sessions es ON s.id = es.session_id
+            WHERE es.entity_id = ?1
+            ORDER BY s.end_time DESC
+            LIMIT ?2
+            "#,
+        )?;
 
-        // 按 RRF 分数排序
-        let mut results: Vec<(Trace, f32)> = scores
-            .into_iter()
-            .filter_map(|(id, score)| all_traces.get(&id).map(|t| (t.clone(), score)))
-            .collect();
+        let sessions = stmt.query_map(rusqlite::params![entity_id, limit], |row| {
+            Self::activity_session_from_row(row)
+        })?;
 
-        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-        results.truncate(limit as usize);
+        let mut result = Vec::new();
+        for session in sessions {
+            result.push(session?);
+        }
+        Ok(result)
+    }
 
-        Ok(results)
+    /// 获取实体列表
+    pub fn get_entities(
+        &self,
+        entity_type: Option<&str>,
+        limit: u32,
+        order_by_mentions: bool,
+    ) -> Result<Vec<Entity>> {
+        let conn = self.pool.reader();
+
+        let order = if order_by_mentions {
+            "mention_count DESC"
+        } else {
+            "last_seen DESC"
+        };
+
+        let mut result = Vec::new();
+
+        if let Some(etype) = entity_type {
+            let sql = format!(
+                r#"
+                SELECT id, name, type, mention_count, first_seen, last_seen, metadata
+                FROM entities
+                WHERE type = ?1
+                ORDER BY {}
+                LIMIT ?2
+                "#,
+                order
+            );
+
+            let mut stmt = conn.prepare_cached(&sql)?;
+            let entities =
+                stmt.query_map(rusqlite::params![etype, limit], Self::entity_from_row)?;
+
+            for entity in entities {
+                result.push(entity?);
+            }
+        } else {
+            let sql = format!(
+                r#"
+                SELECT id, name, type, mention_count, first_seen, last_seen, metadata
+                FROM entities
+                ORDER BY {}
+                LIMIT ?1
+                "#,
+                order
+            );
+
+            let mut stmt = conn.prepare_cached(&sql)?;
+            let entities = stmt.query_map(rusqlite::params![limit], Self::entity_from_row)?;
+
+            for entity in entities {
+                result.push(entity?);
+            }
+        }
+
+        Ok(result)
     }
 
-    /// 反序列化向量
-    #[allow(dead_code)]
-    fn deserialize_embedding(bytes: &[u8]) -> Vec<f32> {
-        bytes
-            .chunks_exact(4)
-            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-            .collect()
+    /// 按 ID 获取实体
+    pub fn get_entity_by_id(&self, id: i64) -> Result<Option<Entity>> {
+        let conn = self.pool.reader();
+        let entity = conn
+            .prepare_cached(
+                r#"
+                SELECT id, name, type, mention_count, first_seen, last_seen, metadata
+                FROM entities
+                WHERE id = ?1
+                "#,
+            )?
+            .query_row([id], Self::entity_from_row)
+            .optional()?;
+        Ok(entity)
+    }
+
+    /// 按名称获取实体（精确名称优先，其次按归一化别名）
+    pub fn get_entity_by_name(&self, name: &str) -> Result<Option<Entity>> {
+        let conn = self.pool.reader();
+        let by_alias: Option<Entity> = conn
+            .prepare_cached(
+                r#"
+                SELECT e.id, e.name, e.type, e.mention_count, e.first_seen, e.last_seen, e.metadata
+                FROM entities e
+                JOIN entity_aliases a ON e.id = a.entity_id
+                WHERE a.alias = ?1
+                "#,
+            )?
+            .query_row(
+                [crate::ai::entity::normalize_entity_name(name)],
+                Self::entity_from_row,
+            )
+            .optional()?;
+
+        let result = conn.query_row(
+            r#"
+            SELECT id, name, type, mention_count, first_seen, last_seen, metadata
+            FROM entities
+            WHERE name = ?1
+            "#,
+            rusqlite::params![name],
+            Self::entity_from_row,
+        );
+
+        match result {
+            Ok(entity) => Ok(Some(entity)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(by_alias),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 关联实体和痕迹
+    pub fn link_entity_to_trace(&self, entity_id: i64, trace_id: i64) -> Result<()> {
+        let conn = self.pool.writer();
+        conn.execute(
+            "INSERT OR IGNORE INTO entity_traces (entity_id, trace_id) VALUES (?1, ?2)",
+            rusqlite::params![entity_id, trace_id],
+        )?;
+        Ok(())
+    }
+
+    /// 获取实体关联的痕迹
+    pub fn get_traces_by_entity(&self, entity_id: i64, limit: u32) -> Result<Vec<Trace>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT t.id, t.timestamp, t.image_path, t.app_name, t.window_title,
+                   t.is_fullscreen,
+                   t.is_idle, t.ocr_text, t.activity_session_id, t.is_key_action,
+                   t.vlm_summary, t.vlm_action_description, t.vlm_activity_type, t.vlm_confidence, t.vlm_entities_json, t.vlm_raw_json,
+                   t.created_at
+            FROM traces t
+            JOIN entity_traces et ON t.id = et.trace_id
+            WHERE et.entity_id = ?1
+            ORDER BY t.timestamp DESC
+            LIMIT ?2
+            "#,
+        )?;
+
+        let traces = stmt.query_map(rusqlite::params![entity_id, limit], |row| {
+            Self::trace_from_row(row)
+        })?;
+
+        let mut result = Vec::new();
+        for trace in traces {
+            result.push(trace?);
+        }
+        Ok(result)
+    }
+
+    /// 删除实体
+    pub fn delete_entity(&self, id: i64) -> Result<bool> {
+        let conn = self.pool.writer();
+        let rows = conn.execute("DELETE FROM entities WHERE id = ?1", rusqlite::params![id])?;
+        Ok(rows > 0)
     }
 
-    /// 计算余弦相似度
-    #[allow(dead_code)]
-    fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
-        if a.len() != b.len() {
-            return 0.0;
-        }
-
-        let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-        let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-        let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    /// 获取实体的别名
+    pub fn get_entity_aliases(&self, entity_id: i64) -> Result<Vec<EntityAlias>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT alias, entity_id, name, created_at
+            FROM entity_aliases
+            WHERE entity_id = ?1
+            ORDER BY created_at ASC, alias ASC
+            "#,
+        )?;
+        let aliases = stmt.query_map([entity_id], |row| {
+            Ok(EntityAlias {
+                alias: row.get(0)?,
+                entity_id: row.get(1)?,
+                name: row.get(2)?,
+                created_at: row.get(3)?,
+            })
+        })?;
 
-        if norm_a == 0.0 || norm_b == 0.0 {
-            return 0.0;
+        let mut result = Vec::new();
+        for alias in aliases {
+            result.push(alias?);
         }
+        Ok(result)
+    }
 
-        dot / (norm_a * norm_b)
+    /// 将 `source` 合并到 `target`：迁移 trace / 会话关联、别名与类型票数后删除 `source`
+    ///
+    /// 任一实体不存在或两者相同时返回 false。
+    pub fn merge_entities(&self, source_id: i64, target_id: i64) -> Result<bool> {
+        if source_id == target_id {
+            return Ok(false);
+        }
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+        let merged = Self::merge_entities_in(&tx, source_id, target_id)?;
+        tx.commit()?;
+        Ok(merged)
     }
 
-    // ==================== Summary CRUD ====================
+    fn merge_entities_in(conn: &Connection, source_id: i64, target_id: i64) -> Result<bool> {
+        let existing: i64 = conn
+            .prepare_cached("SELECT COUNT(*) FROM entities WHERE id IN (?1, ?2)")?
+            .query_row([source_id, target_id], |row| row.get(0))?;
+        if existing != 2 {
+            return Ok(false);
+        }
 
-    /// 插入摘要记录
-    pub fn insert_summary(&self, summary: &NewSummary) -> Result<i64> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
+        // 两个实体都关联的 trace 只算一次提及
+        let shared: i64 = conn
+            .prepare_cached(
+                r#"
+                SELECT COUNT(*) FROM entity_traces s
+                JOIN entity_traces t ON s.trace_id = t.trace_id
+                WHERE s.entity_id = ?1 AND t.entity_id = ?2
+                "#,
+            )?
+            .query_row([source_id, target_id], |row| row.get(0))?;
+
+        conn.prepare_cached(
             r#"
-            INSERT INTO summaries (
-                start_time, end_time, summary_type, content,
-                structured_data, trace_count
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            INSERT OR IGNORE INTO entity_traces (entity_id, trace_id)
+            SELECT ?2, trace_id FROM entity_traces WHERE entity_id = ?1
             "#,
-            rusqlite::params![
-                summary.start_time,
-                summary.end_time,
-                summary.summary_type,
-                summary.content,
-                summary.structured_data,
-                summary.trace_count,
-            ],
-        )?;
-        Ok(conn.last_insert_rowid())
+        )?
+        .execute([source_id, target_id])?;
+        conn.prepare_cached(
+            r#"
+            INSERT OR IGNORE INTO entity_sessions (entity_id, session_id)
+            SELECT ?2, session_id FROM entity_sessions WHERE entity_id = ?1
+            "#,
+        )?
+        .execute([source_id, target_id])?;
+        conn.prepare_cached("UPDATE entity_aliases SET entity_id = ?2 WHERE entity_id = ?1")?
+            .execute([source_id, target_id])?;
+        conn.prepare_cached(
+            r#"
+            INSERT INTO entity_type_votes (entity_id, type, votes)
+            SELECT ?2, type, votes FROM entity_type_votes WHERE entity_id = ?1
+            ON CONFLICT(entity_id, type) DO UPDATE SET votes = votes + excluded.votes
+            "#,
+        )?
+        .execute([source_id, target_id])?;
+        conn.prepare_cached(
+            r#"
+            UPDATE entities SET
+                mention_count = entities.mention_count + MAX(0, s.mention_count - ?3),
+                first_seen = MIN(entities.first_seen, s.first_seen),
+                last_seen = MAX(entities.last_seen, s.last_seen),
+                metadata = COALESCE(entities.metadata, s.metadata)
+            FROM (SELECT mention_count, first_seen, last_seen, metadata
+                  FROM entities WHERE id = ?1) AS s
+            WHERE entities.id = ?2
+            "#,
+        )?
+        .execute(rusqlite::params![source_id, target_id, shared])?;
+
+        conn.prepare_cached("DELETE FROM entities WHERE id = ?1")?
+            .execute([source_id])?;
+        Self::apply_entity_type_votes(conn, target_id)?;
+        Ok(true)
     }
 
-    /// 按时间范围查询摘要
-    pub fn get_summaries(
-        &self,
-        start_time: i64,
-        end_time: i64,
-        summary_type: Option<&str>,
-        limit: u32,
-    ) -> Result<Vec<Summary>> {
-        let conn = self.conn.lock().unwrap();
+    /// 把别名从实体中拆分为新实体，返回新实体 ID
+    ///
+    /// VLM 实体名称归一化后等于该别名的 trace 关联随之迁移（同时提到实体其他别名的除外），
+    /// 会话关联按迁移后的 trace 重新计算。别名不属于该实体、或是实体当前名称时返回 None。
+    pub fn split_entity(&self, entity_id: i64, alias: &str) -> Result<Option<i64>> {
+        use crate::ai::entity::{infer_entity_type, normalize_entity_name};
 
-        let mut result = Vec::new();
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
 
-        if let Some(stype) = summary_type {
-            let mut stmt = conn.prepare(
+        let Some((entity_name, alias_name)): Option<(String, String)> = tx
+            .prepare_cached(
                 r#"
-                SELECT id, start_time, end_time, summary_type, content,
-                       structured_data, trace_count, created_at
-                FROM summaries
-                WHERE start_time >= ?1 AND end_time <= ?2 AND summary_type = ?3
-                ORDER BY start_time DESC
-                LIMIT ?4
+                SELECT e.name, a.name FROM entity_aliases a
+                JOIN entities e ON e.id = a.entity_id
+                WHERE a.alias = ?1 AND a.entity_id = ?2
                 "#,
-            )?;
+            )?
+            .query_row(rusqlite::params![alias, entity_id], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        if normalize_entity_name(&entity_name) == alias {
+            return Ok(None);
+        }
 
-            let summaries = stmt.query_map(
-                rusqlite::params![start_time, end_time, stype, limit],
-                |row| {
-                    Ok(Summary {
-                        id: row.get(0)?,
-                        start_time: row.get(1)?,
-                        end_time: row.get(2)?,
-                        summary_type: row.get(3)?,
-                        content: row.get(4)?,
-                        structured_data: row.get(5)?,
-                        trace_count: row.get(6)?,
-                        created_at: row.get(7)?,
-                    })
-                },
+        let other_aliases: Vec<String> = {
+            let mut stmt = tx.prepare_cached(
+                "SELECT alias FROM entity_aliases WHERE entity_id = ?1 AND alias != ?2",
             )?;
-
-            for summary in summaries {
-                result.push(summary?);
-            }
-        } else {
-            let mut stmt = conn.prepare(
+            let rows = stmt.query_map(rusqlite::params![entity_id, alias], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let traces: Vec<(i64, i64, Option<i64>, Option<String>)> = {
+            let mut stmt = tx.prepare_cached(
                 r#"
-                SELECT id, start_time, end_time, summary_type, content,
-                       structured_data, trace_count, created_at
-                FROM summaries
-                WHERE start_time >= ?1 AND end_time <= ?2
-                ORDER BY start_time DESC
-                LIMIT ?3
+                SELECT t.id, t.timestamp, t.activity_session_id, t.vlm_entities_json
+                FROM traces t
+                JOIN entity_traces et ON t.id = et.trace_id
+                WHERE et.entity_id = ?1
                 "#,
             )?;
+            let rows = stmt.query_map([entity_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let moved: Vec<&(i64, i64, Option<i64>, Option<String>)> = traces
+            .iter()
+            .filter(|(_, _, _, json)| {
+                let names: Vec<String> = json
+                    .as_deref()
+                    .and_then(|j| serde_json::from_str(j).ok())
+                    .unwrap_or_default();
+                let keys: Vec<String> = names.iter().map(|n| normalize_entity_name(n)).collect();
+                keys.iter().any(|k| k == alias) && !keys.iter().any(|k| other_aliases.contains(k))
+            })
+            .collect();
 
-            let summaries =
-                stmt.query_map(rusqlite::params![start_time, end_time, limit], |row| {
-                    Ok(Summary {
-                        id: row.get(0)?,
-                        start_time: row.get(1)?,
-                        end_time: row.get(2)?,
-                        summary_type: row.get(3)?,
-                        content: row.get(4)?,
-                        structured_data: row.get(5)?,
-                        trace_count: row.get(6)?,
-                        created_at: row.get(7)?,
-                    })
-                })?;
+        let first_seen = moved.iter().map(|t| t.1).min().unwrap_or(0);
+        let last_seen = moved.iter().map(|t| t.1).max().unwrap_or(0);
+        tx.prepare_cached(
+            r#"
+            INSERT INTO entities (name, type, mention_count, first_seen, last_seen)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+        )?
+        .execute(rusqlite::params![
+            alias_name,
+            crate::ai::entity::UNKNOWN_ENTITY_TYPE,
+            moved.len() as i64,
+            first_seen,
+            last_seen
+        ])?;
+        let new_id = tx.last_insert_rowid();
+        tx.prepare_cached("UPDATE entity_aliases SET entity_id = ?1 WHERE alias = ?2")?
+            .execute(rusqlite::params![new_id, alias])?;
+        Self::vote_entity_type(
+            &tx,
+            new_id,
+            infer_entity_type(&alias_name),
+            moved.len().max(1) as i64,
+        )?;
 
-            for summary in summaries {
-                result.push(summary?);
+        for (trace_id, _, session_id, _) in &moved {
+            tx.prepare_cached(
+                "UPDATE entity_traces SET entity_id = ?1 WHERE entity_id = ?2 AND trace_id = ?3",
+            )?
+            .execute(rusqlite::params![new_id, entity_id, trace_id])?;
+            if let Some(session_id) = session_id {
+                tx.prepare_cached(
+                    "INSERT OR IGNORE INTO entity_sessions (entity_id, session_id) VALUES (?1, ?2)",
+                )?
+                .execute(rusqlite::params![new_id, session_id])?;
+                // 原实体在该会话中已没有其他 trace 时解除会话关联
+                tx.prepare_cached(
+                    r#"
+                    DELETE FROM entity_sessions
+                    WHERE entity_id = ?1 AND session_id = ?2
+                      AND NOT EXISTS (
+                          SELECT 1 FROM entity_traces et
+                          JOIN traces t ON t.id = et.trace_id
+                          WHERE et.entity_id = ?1 AND t.activity_session_id = ?2
+                      )
+                    "#,
+                )?
+                .execute(rusqlite::params![entity_id, session_id])?;
             }
         }
+        tx.prepare_cached(
+            "UPDATE entities SET mention_count = MAX(0, mention_count - ?2) WHERE id = ?1",
+        )?
+        .execute(rusqlite::params![entity_id, moved.len() as i64])?;
 
-        Ok(result)
+        tx.commit()?;
+        Ok(Some(new_id))
     }
 
-    /// 按 ID 获取摘要
-    pub fn get_summary_by_id(&self, id: i64) -> Result<Option<Summary>> {
-        let conn = self.conn.lock().unwrap();
-        let result = conn.query_row(
-            r#"
-            SELECT id, start_time, end_time, summary_type, content,
-                   structured_data, trace_count, created_at
-            FROM summaries
-            WHERE id = ?1
-            "#,
-            rusqlite::params![id],
-            |row| {
-                Ok(Summary {
-                    id: row.get(0)?,
-                    start_time: row.get(1)?,
-                    end_time: row.get(2)?,
-                    summary_type: row.get(3)?,
-                    content: row.get(4)?,
-                    structured_data: row.get(5)?,
-                    trace_count: row.get(6)?,
-                    created_at: row.get(7)?,
-                })
-            },
-        );
+    /// 重命名实体，并把新名称加入别名
+    ///
+    /// 新名称已是其他实体的名称或别名时返回 false（应先合并）。
+    pub fn rename_entity(&self, entity_id: i64, name: &str) -> Result<bool> {
+        let alias = crate::ai::entity::normalize_entity_name(name);
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
 
-        match result {
-            Ok(summary) => Ok(Some(summary)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+        let conflict: i64 = tx
+            .prepare_cached(
+                r#"
+                SELECT COUNT(*) FROM entities
+                WHERE id != ?1
+                  AND (name = ?2 OR id IN (SELECT entity_id FROM entity_aliases WHERE alias = ?3))
+                "#,
+            )?
+            .query_row(rusqlite::params![entity_id, name, alias], |row| row.get(0))?;
+        if conflict > 0 {
+            return Ok(false);
         }
-    }
-
-    /// 获取最近的摘要
-    pub fn get_latest_summary(&self, summary_type: &str) -> Result<Option<Summary>> {
-        let conn = self.conn.lock().unwrap();
-        let result = conn.query_row(
-            r#"
-            SELECT id, start_time, end_time, summary_type, content,
-                   structured_data, trace_count, created_at
-            FROM summaries
-            WHERE summary_type = ?1
-            ORDER BY end_time DESC
-            LIMIT 1
-            "#,
-            rusqlite::params![summary_type],
-            |row| {
-                Ok(Summary {
-                    id: row.get(0)?,
-                    start_time: row.get(1)?,
-                    end_time: row.get(2)?,
-                    summary_type: row.get(3)?,
-                    content: row.get(4)?,
-                    structured_data: row.get(5)?,
-                    trace_count: row.get(6)?,
-                    created_at: row.get(7)?,
-                })
-            },
-        );
 
-        match result {
-            Ok(summary) => Ok(Some(summary)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+        let rows = tx
+            .prepare_cached("UPDATE entities SET name = ?2 WHERE id = ?1")?
+            .execute(rusqlite::params![entity_id, name])?;
+        if rows == 0 {
+            return Ok(false);
         }
-    }
+        tx.prepare_cached(
+            "INSERT OR IGNORE INTO entity_aliases (alias, entity_id, name) VALUES (?1, ?2, ?3)",
+        )?
+        .execute(rusqlite::params![alias, entity_id, name])?;
 
-    /// 删除摘要
-    pub fn delete_summary(&self, id: i64) -> Result<bool> {
-        let conn = self.conn.lock().unwrap();
-        let rows = conn.execute("DELETE FROM summaries WHERE id = ?1", rusqlite::params![id])?;
-        Ok(rows > 0)
+        tx.commit()?;
+        Ok(true)
     }
 
-    // ==================== Entity CRUD ====================
-
-    /// 插入或更新实体
-    pub fn upsert_entity(&self, entity: &NewEntity) -> Result<i64> {
-        let conn = self.conn.lock().unwrap();
+    /// 为早期实体补建别名，并合并归一化名称相同的实体（提及次数少的并入多的）
+    ///
+    /// 返回合并的实体数。
+    pub fn backfill_entity_aliases(&self) -> Result<usize> {
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
 
-        // 先尝试查找现有实体
-        let existing: Result<i64, _> = conn.query_row(
-            "SELECT id FROM entities WHERE name = ?1",
-            rusqlite::params![entity.name],
-            |row| row.get(0),
-        );
+        let entities: Vec<(i64, String, String)> = {
+            let mut stmt = tx.prepare_cached(
+                r#"
+                SELECT id, name, type FROM entities
+                WHERE id NOT IN (SELECT entity_id FROM entity_aliases)
+                ORDER BY mention_count DESC, id ASC
+                "#,
+            )?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
 
-        match existing {
-            Ok(id) => {
-                // 更新现有实体
-                conn.execute(
-                    r#"
-                    UPDATE entities SET
-                        mention_count = mention_count + 1,
-                        last_seen = ?1,
-                        metadata = COALESCE(?2, metadata)
-                    WHERE id = ?3
-                    "#,
-                    rusqlite::params![entity.last_seen, entity.metadata, id],
-                )?;
-                Ok(id)
-            }
-            Err(rusqlite::Error::QueryReturnedNoRows) => {
-                // 插入新实体
-                conn.execute(
-                    r#"
-                    INSERT INTO entities (name, type, first_seen, last_seen, metadata)
-                    VALUES (?1, ?2, ?3, ?4, ?5)
-                    "#,
-                    rusqlite::params![
-                        entity.name,
-                        entity.entity_type,
-                        entity.first_seen,
-                        entity.last_seen,
-                        entity.metadata,
-                    ],
-                )?;
-                Ok(conn.last_insert_rowid())
+        let mut merged = 0;
+        for (id, name, entity_type) in &entities {
+            // 早期实体的类型作为一次投票
+            tx.prepare_cached(
+                r#"
+                INSERT OR IGNORE INTO entity_type_votes (entity_id, type, votes)
+                SELECT ?1, ?2, 1 WHERE ?2 != ?3
+                "#,
+            )?
+            .execute(rusqlite::params![
+                id,
+                entity_type,
+                crate::ai::entity::UNKNOWN_ENTITY_TYPE
+            ])?;
+
+            let alias = crate::ai::entity::normalize_entity_name(name);
+            let owner: Option<i64> = tx
+                .prepare_cached("SELECT entity_id FROM entity_aliases WHERE alias = ?1")?
+                .query_row([&alias], |row| row.get(0))
+                .optional()?;
+            match owner {
+                Some(target) if target != *id => {
+                    Self::merge_entities_in(&tx, *id, target)?;
+                    merged += 1;
+                }
+                _ => {
+                    tx.prepare_cached(
+                        "INSERT OR IGNORE INTO entity_aliases (alias, entity_id, name) VALUES (?1, ?2, ?3)",
+                    )?
+                    .execute(rusqlite::params![alias, id, name])?;
+                }
             }
-            Err(e) => Err(e.into()),
         }
+
+        tx.commit()?;
+        Ok(merged)
     }
 
-    /// 获取实体列表
-    pub fn get_entities(
-        &self,
-        entity_type: Option<&str>,
-        limit: u32,
-        order_by_mentions: bool,
-    ) -> Result<Vec<Entity>> {
-        let conn = self.conn.lock().unwrap();
+    /// 搜索实体
+    pub fn search_entities(&self, query: &str, limit: u32) -> Result<Vec<Entity>> {
+        let conn = self.pool.reader();
+        let pattern = format!("%{}%", query);
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT id, name, type, mention_count, first_seen, last_seen, metadata
+            FROM entities
+            WHERE name LIKE ?1
+            ORDER BY mention_count DESC
+            LIMIT ?2
+            "#,
+        )?;
 
-        let order = if order_by_mentions {
-            "mention_count DESC"
-        } else {
-            "last_seen DESC"
-        };
+        let entities = stmt.query_map(rusqlite::params![pattern, limit], Self::entity_from_row)?;
+
+        let mut result = Vec::new();
+        for entity in entities {
+            result.push(entity?);
+        }
+        Ok(result)
+    }
 
-        let mut result = Vec::new();
+    // ==================== Entity Relations ====================
 
-        if let Some(etype) = entity_type {
-            let sql = format!(
+    /// 由 trace 与会话中的实体共现重建 `entity_relations`，返回关系数
+    ///
+    /// 会话实体取 `entity_sessions` 与 `entities_json`（按别名解析）的并集，
+    /// 已标注的关系类型在重建后保留。
+    pub fn rebuild_entity_relations(&self, now: i64) -> Result<usize> {
+        self.rebuild_entity_relations_in(None, now)
+    }
+
+    /// 只重算涉及 `entity_ids` 的关系（合并 / 拆分实体后调用），返回重算的关系数
+    ///
+    /// 只读取关联这些实体的 trace 与会话；其他实体之间的关系保持不变，由每日的全量重建刷新。
+    pub fn rebuild_entity_relations_for(&self, entity_ids: &[i64], now: i64) -> Result<usize> {
+        self.rebuild_entity_relations_in(Some(entity_ids), now)
+    }
+
+    /// `scope` 为 None 时全量重建，否则只重算一端在 `scope` 中的关系
+    fn rebuild_entity_relations_in(&self, scope: Option<&[i64]>, now: i64) -> Result<usize> {
+        use crate::ai::entity::{normalize_entity_name, RelationBuilder};
+        use std::collections::HashMap;
+
+        // ?1 为实体 ID 的 JSON 数组（NULL 表示不限）
+        const SCOPED_SESSIONS: &str = r#"
+            SELECT session_id FROM entity_sessions
+            WHERE entity_id IN (SELECT value FROM json_each(?1))
+            UNION
+            SELECT t.activity_session_id FROM entity_traces et
+            JOIN traces t ON t.id = et.trace_id
+            WHERE et.entity_id IN (SELECT value FROM json_each(?1))
+        "#;
+        let scope_json = scope.map(serde_json::to_string).transpose()?;
+
+        let mut builder = RelationBuilder::new(now);
+        {
+            let conn = self.pool.reader();
+
+            let mut stmt = conn.prepare_cached(
                 r#"
-                SELECT id, name, type, mention_count, first_seen, last_seen, metadata
-                FROM entities
-                WHERE type = ?1
-                ORDER BY {}
-                LIMIT ?2
+                SELECT et.trace_id, et.entity_id, t.timestamp
+                FROM entity_traces et
+                JOIN traces t ON t.id = et.trace_id
+                WHERE ?1 IS NULL OR et.trace_id IN (
+                    SELECT trace_id FROM entity_traces
+                    WHERE entity_id IN (SELECT value FROM json_each(?1))
+                )
+                ORDER BY et.trace_id
                 "#,
-                order
-            );
+            )?;
+            let mut rows = stmt.query([&scope_json])?;
+            let mut current: Option<(i64, i64)> = None;
+            let mut ids = Vec::new();
+            while let Some(row) = rows.next()? {
+                let (trace_id, entity_id, timestamp): (i64, i64, i64) =
+                    (row.get(0)?, row.get(1)?, row.get(2)?);
+                if let Some((id, ts)) = current {
+                    if id != trace_id {
+                        builder.add_trace(&ids, ts);
+                        ids.clear();
+                    }
+                }
+                current = Some((trace_id, timestamp));
+                ids.push(entity_id);
+            }
+            if let Some((_, ts)) = current {
+                builder.add_trace(&ids, ts);
+            }
 
-            let mut stmt = conn.prepare(&sql)?;
-            let entities = stmt.query_map(rusqlite::params![etype, limit], |row| {
-                Ok(Entity {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    entity_type: row.get(2)?,
-                    mention_count: row.get(3)?,
-                    first_seen: row.get(4)?,
-                    last_seen: row.get(5)?,
-                    metadata: row.get(6)?,
-                })
-            })?;
+            let mut session_entities: HashMap<i64, Vec<i64>> = HashMap::new();
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT session_id, entity_id FROM entity_sessions
+                 WHERE ?1 IS NULL OR session_id IN ({SCOPED_SESSIONS})"
+            ))?;
+            let links = stmt.query_map([&scope_json], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for link in links {
+                let (session_id, entity_id): (i64, i64) = link?;
+                session_entities
+                    .entry(session_id)
+                    .or_default()
+                    .push(entity_id);
+            }
 
-            for entity in entities {
-                result.push(entity?);
+            let mut stmt = conn.prepare_cached("SELECT alias, entity_id FROM entity_aliases")?;
+            let aliases: HashMap<String, i64> = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT id, end_time, entities_json FROM activity_sessions
+                 WHERE ?1 IS NULL OR id IN ({SCOPED_SESSIONS})"
+            ))?;
+            let sessions = stmt.query_map([&scope_json], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?;
+            for session in sessions {
+                let (session_id, end_time, entities_json) = session?;
+                let mut ids = session_entities.remove(&session_id).unwrap_or_default();
+                let counts: serde_json::Map<String, serde_json::Value> = entities_json
+                    .as_deref()
+                    .and_then(|j| serde_json::from_str(j).ok())
+                    .unwrap_or_default();
+                ids.extend(
+                    counts
+                        .keys()
+                        .filter_map(|name| aliases.get(&normalize_entity_name(name)).copied()),
+                );
+                builder.add_session(&ids, end_time);
             }
-        } else {
-            let sql = format!(
+        }
+
+        let mut relations = builder.into_relations();
+        // 范围内的 trace 与会话只覆盖一端在范围内的实体对；其他实体对的计数不完整，保留原值
+        if let Some(scope) = scope {
+            relations.retain(|(a, b), _| scope.contains(a) || scope.contains(b));
+        }
+        let mut conn = self.pool.writer();
+        let tx = conn.transaction()?;
+
+        let labels: HashMap<(i64, i64), String> = {
+            let mut stmt = tx.prepare_cached(
                 r#"
-                SELECT id, name, type, mention_count, first_seen, last_seen, metadata
-                FROM entities
-                ORDER BY {}
-                LIMIT ?1
+                SELECT source_id, target_id, relation_type FROM entity_relations
+                WHERE relation_type IS NOT NULL
+                  AND (?1 IS NULL
+                       OR source_id IN (SELECT value FROM json_each(?1))
+                       OR target_id IN (SELECT value FROM json_each(?1)))
                 "#,
-                order
-            );
-
-            let mut stmt = conn.prepare(&sql)?;
-            let entities = stmt.query_map(rusqlite::params![limit], |row| {
-                Ok(Entity {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    entity_type: row.get(2)?,
-                    mention_count: row.get(3)?,
-                    first_seen: row.get(4)?,
-                    last_seen: row.get(5)?,
-                    metadata: row.get(6)?,
-                })
+            )?;
+            let rows = stmt.query_map([&scope_json], |row| {
+                Ok(((row.get(0)?, row.get(1)?), row.get(2)?))
             })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
 
-            for entity in entities {
-                result.push(entity?);
-            }
+        tx.execute(
+            r#"
+            DELETE FROM entity_relations
+            WHERE ?1 IS NULL
+               OR source_id IN (SELECT value FROM json_each(?1))
+               OR target_id IN (SELECT value FROM json_each(?1))
+            "#,
+            [&scope_json],
+        )?;
+        for ((source_id, target_id), relation) in &relations {
+            tx.prepare_cached(
+                r#"
+                INSERT INTO entity_relations
+                    (source_id, target_id, weight, trace_count, session_count,
+                     first_seen, last_seen, relation_type, updated_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                "#,
+            )?
+            .execute(rusqlite::params![
+                source_id,
+                target_id,
+                relation.weight,
+                relation.trace_count,
+                relation.session_count,
+                relation.first_seen,
+                relation.last_seen,
+                labels.get(&(*source_id, *target_id)),
+                now
+            ])?;
         }
 
-        Ok(result)
+        tx.commit()?;
+        Ok(relations.len())
     }
 
-    /// 按名称获取实体
-    pub fn get_entity_by_name(&self, name: &str) -> Result<Option<Entity>> {
-        let conn = self.conn.lock().unwrap();
-        let result = conn.query_row(
+    fn entity_relation_from_row(row: &rusqlite::Row, now: i64) -> rusqlite::Result<EntityRelation> {
+        let weight: f64 = row.get(2)?;
+        let updated_at: i64 = row.get(8)?;
+        Ok(EntityRelation {
+            source_id: row.get(0)?,
+            target_id: row.get(1)?,
+            weight: weight * crate::ai::entity::relation_decay(now - updated_at),
+            trace_count: row.get(3)?,
+            session_count: row.get(4)?,
+            first_seen: row.get(5)?,
+            last_seen: row.get(6)?,
+            relation_type: row.get(7)?,
+        })
+    }
+
+    /// 获取实体的相邻实体（按权重降序，可按类型过滤）
+    ///
+    /// 合并 / 拆分后的局部重建会使关系的衰减基准不同，因此按衰减后的权重排序。
+    pub fn get_entity_neighbors(
+        &self,
+        entity_id: i64,
+        entity_type: Option<&str>,
+        limit: u32,
+        now: i64,
+    ) -> Result<Vec<EntityNeighbor>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
             r#"
-            SELECT id, name, type, mention_count, first_seen, last_seen, metadata
-            FROM entities
-            WHERE name = ?1
+            SELECT r.source_id, r.target_id, r.weight, r.trace_count, r.session_count,
+                   r.first_seen, r.last_seen, r.relation_type, r.updated_at,
+                   e.id, e.name, e.type, e.mention_count, e.first_seen, e.last_seen, e.metadata
+            FROM entity_relations r
+            JOIN entities e
+              ON e.id = CASE WHEN r.source_id = ?1 THEN r.target_id ELSE r.source_id END
+            WHERE (r.source_id = ?1 OR r.target_id = ?1)
+              AND (?2 IS NULL OR e.type = ?2)
             "#,
-            rusqlite::params![name],
-            |row| {
-                Ok(Entity {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    entity_type: row.get(2)?,
-                    mention_count: row.get(3)?,
-                    first_seen: row.get(4)?,
-                    last_seen: row.get(5)?,
-                    metadata: row.get(6)?,
-                })
-            },
-        );
+        )?;
+        let neighbors = stmt.query_map(rusqlite::params![entity_id, entity_type], |row| {
+            Ok(EntityNeighbor {
+                relation: Self::entity_relation_from_row(row, now)?,
+                entity: Self::entity_from_row_at(row, 9)?,
+            })
+        })?;
 
-        match result {
-            Ok(entity) => Ok(Some(entity)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+        let mut result = Vec::new();
+        for neighbor in neighbors {
+            result.push(neighbor?);
         }
+        result.sort_by(|a, b| b.relation.weight.total_cmp(&a.relation.weight));
+        result.truncate(limit as usize);
+        Ok(result)
     }
 
-    /// 关联实体和痕迹
-    pub fn link_entity_to_trace(&self, entity_id: i64, trace_id: i64) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT OR IGNORE INTO entity_traces (entity_id, trace_id) VALUES (?1, ?2)",
-            rusqlite::params![entity_id, trace_id],
-        )?;
-        Ok(())
-    }
-
-    /// 获取实体关联的痕迹
-    pub fn get_traces_by_entity(&self, entity_id: i64, limit: u32) -> Result<Vec<Trace>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
+    /// 获取一组实体之间的关系
+    pub fn get_entity_relations_among(
+        &self,
+        entity_ids: &[i64],
+        now: i64,
+    ) -> Result<Vec<EntityRelation>> {
+        let conn = self.pool.reader();
+        let ids = serde_json::to_string(entity_ids)?;
+        let mut stmt = conn.prepare_cached(
             r#"
-            SELECT t.id, t.timestamp, t.image_path, t.app_name, t.window_title,
-                   t.is_fullscreen,
-                   t.is_idle, t.ocr_text, t.activity_session_id, t.is_key_action,
-                   t.vlm_summary, t.vlm_action_description, t.vlm_activity_type, t.vlm_confidence, t.vlm_entities_json, t.vlm_raw_json,
-                   t.created_at
-            FROM traces t
-            JOIN entity_traces et ON t.id = et.trace_id
-            WHERE et.entity_id = ?1
-            ORDER BY t.timestamp DESC
-            LIMIT ?2
+            SELECT source_id, target_id, weight, trace_count, session_count,
+                   first_seen, last_seen, relation_type, updated_at
+            FROM entity_relations
+            WHERE source_id IN (SELECT value FROM json_each(?1))
+              AND target_id IN (SELECT value FROM json_each(?1))
             "#,
         )?;
-
-        let traces = stmt.query_map(rusqlite::params![entity_id, limit], |row| {
-            Self::trace_from_row(row)
-        })?;
+        let relations = stmt.query_map([ids], |row| Self::entity_relation_from_row(row, now))?;
 
         let mut result = Vec::new();
-        for trace in traces {
-            result.push(trace?);
+        for relation in relations {
+            result.push(relation?);
         }
         Ok(result)
     }
 
-    /// 删除实体
-    pub fn delete_entity(&self, id: i64) -> Result<bool> {
-        let conn = self.conn.lock().unwrap();
-        let rows = conn.execute("DELETE FROM entities WHERE id = ?1", rusqlite::params![id])?;
+    /// 设置两个实体之间的关系类型，关系不存在时返回 false
+    pub fn set_entity_relation_type(
+        &self,
+        entity_a: i64,
+        entity_b: i64,
+        relation_type: Option<&str>,
+    ) -> Result<bool> {
+        let conn = self.pool.writer();
+        let rows = conn
+            .prepare_cached(
+                "UPDATE entity_relations SET relation_type = ?3 WHERE source_id = ?1 AND target_id = ?2",
+            )?
+            .execute(rusqlite::params![
+                entity_a.min(entity_b),
+                entity_a.max(entity_b),
+                relation_type
+            ])?;
         Ok(rows > 0)
     }
 
-    /// 搜索实体
-    pub fn search_entities(&self, query: &str, limit: u32) -> Result<Vec<Entity>> {
-        let conn = self.conn.lock().unwrap();
-        let pattern = format!("%{}%", query);
-        let mut stmt = conn.prepare(
+    /// 实体时间线：实体出现过的会话（按开始时间升序）
+    ///
+    /// 指定 `related_id` 时只返回两个实体都出现过的会话。
+    pub fn get_entity_timeline(
+        &self,
+        entity_id: i64,
+        related_id: Option<i64>,
+        start_time: i64,
+        end_time: i64,
+        limit: u32,
+    ) -> Result<Vec<EntityTimelineEntry>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
             r#"
-            SELECT id, name, type, mention_count, first_seen, last_seen, metadata
-            FROM entities
-            WHERE name LIKE ?1
-            ORDER BY mention_count DESC
-            LIMIT ?2
+            SELECT s.id, s.app_name, s.title, s.start_time, s.end_time,
+                   (SELECT COUNT(*) FROM entity_traces et
+                    JOIN traces t ON t.id = et.trace_id
+                    WHERE et.entity_id = ?1 AND t.activity_session_id = s.id)
+            FROM activity_sessions s
+            JOIN entity_sessions es ON es.session_id = s.id AND es.entity_id = ?1
+            WHERE s.end_time >= ?3 AND s.start_time <= ?4
+              AND (?2 IS NULL OR EXISTS (
+                  SELECT 1 FROM entity_sessions r WHERE r.session_id = s.id AND r.entity_id = ?2
+              ))
+            ORDER BY s.start_time ASC
+            LIMIT ?5
             "#,
         )?;
-
-        let entities = stmt.query_map(rusqlite::params![pattern, limit], |row| {
-            Ok(Entity {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                entity_type: row.get(2)?,
-                mention_count: row.get(3)?,
-                first_seen: row.get(4)?,
-                last_seen: row.get(5)?,
-                metadata: row.get(6)?,
-            })
-        })?;
+        let sessions = stmt.query_map(
+            rusqlite::params![entity_id, related_id, start_time, end_time, limit],
+            |row| {
+                Ok(EntityTimelineEntry {
+                    session_id: row.get(0)?,
+                    app_name: row.get(1)?,
+                    title: row.get(2)?,
+                    start_time: row.get(3)?,
+                    end_time: row.get(4)?,
+                    mention_count: row.get(5)?,
+                    co_entities: Vec::new(),
+                })
+            },
+        )?;
 
         let mut result = Vec::new();
-        for entity in entities {
-            result.push(entity?);
+        for session in sessions {
+            result.push(session?);
+        }
+
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT e.name FROM entity_sessions es
+            JOIN entities e ON e.id = es.entity_id
+            WHERE es.session_id = ?1 AND es.entity_id != ?2
+            ORDER BY e.mention_count DESC
+            LIMIT 5
+            "#,
+        )?;
+        for entry in &mut result {
+            let names = stmt.query_map(rusqlite::params![entry.session_id, entity_id], |row| {
+                row.get(0)
+            })?;
+            entry.co_entities = names.collect::<rusqlite::Result<_>>()?;
         }
         Ok(result)
     }
+
+    /// 两个实体共同出现的会话标题（用于关系标注的上下文）
+    pub fn get_shared_session_titles(
+        &self,
+        entity_a: i64,
+        entity_b: i64,
+        limit: u32,
+    ) -> Result<Vec<String>> {
+        let conn = self.pool.reader();
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT COALESCE(s.title, s.app_name) FROM activity_sessions s
+            JOIN entity_sessions a ON a.session_id = s.id AND a.entity_id = ?1
+            JOIN entity_sessions b ON b.session_id = s.id AND b.entity_id = ?2
+            ORDER BY s.end_time DESC
+            LIMIT ?3
+            "#,
+        )?;
+        let titles = stmt.query_map(rusqlite::params![entity_a, entity_b, limit], |row| {
+            row.get(0)
+        })?;
+        Ok(titles.collect::<rusqlite::Result<_>>()?)
+    }
 }
 
 // 添加 dirs crate 作为辅助
@@ -1863,3 +4943,879 @@ mod dirs {
             .map(PathBuf::from)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert_trace(db: &Database, session_id: i64, timestamp: i64, summary: &str) -> i64 {
+        let (id, _) = db
+            .insert_trace(&NewTrace {
+                timestamp,
+                image_path: String::new(),
+                app_name: Some("Code".to_string()),
+                window_title: None,
+                is_fullscreen: false,
+                is_idle: false,
+                ocr_text: None,
+                phash: None,
+            })
+            .unwrap();
+        db.update_trace_vlm_analysis(
+            id,
+            Some(summary),
+            None,
+            None,
+            None,
+            &[summary.to_string()],
+            None,
+            false,
+        )
+        .unwrap();
+        db.update_activity_session_from_vlm(session_id, id, Some("VLM 标题"), None)
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn test_session_edits_recompute_aggregates() {
+        let dir = std::env::temp_dir().join(format!("engram-sessions-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let a = db.create_activity_session("Code", 1_000).unwrap();
+        insert_trace(&db, a, 1_000, "alpha");
+        let split_at = insert_trace(&db, a, 2_000, "beta");
+        insert_trace(&db, a, 3_000, "gamma");
+        let b = db.create_activity_session("Code", 5_000).unwrap();
+        let moved = insert_trace(&db, b, 5_000, "delta");
+
+        // 拆分：第一条 trace 不能作为拆分点
+        assert!(db.split_activity_session(a, 1).unwrap().is_none());
+        let c = db.split_activity_session(a, split_at).unwrap().unwrap();
+        let first = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!((first.trace_count, first.end_time), (1, 1_000));
+        let second = db.get_activity_session_by_id(c).unwrap().unwrap();
+        assert_eq!((second.trace_count, second.start_time), (2, 2_000));
+        assert!(second.context_text.unwrap().contains("gamma"));
+
+        // 移动：源会话被移空后删除
+        assert_eq!(
+            db.move_traces_to_session(&[moved], Some(a)).unwrap(),
+            Some(a)
+        );
+        assert!(db.get_activity_session_by_id(b).unwrap().is_none());
+        let first = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!((first.trace_count, first.end_time), (2, 5_000));
+        assert!(first.entities_json.unwrap().contains("delta"));
+
+        // 合并
+        assert!(db.merge_activity_sessions(a, &[c]).unwrap());
+        assert!(db.get_activity_session_by_id(c).unwrap().is_none());
+        let merged = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!(merged.trace_count, 4);
+        assert_eq!(
+            db.get_traces_by_activity_session(a, 10, 0).unwrap().len(),
+            4
+        );
+
+        // 用户改过的标题不再被 VLM 覆盖
+        assert!(db
+            .rename_activity_session(a, Some("手动标题"), None)
+            .unwrap());
+        insert_trace(&db, a, 6_000, "epsilon");
+        let renamed = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!(renamed.title.as_deref(), Some("手动标题"));
+        assert_eq!(renamed.trace_count, 5);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_vlm_traces_append_to_session() {
+        let dir = std::env::temp_dir().join(format!("engram-append-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let a = db.create_activity_session("Code", 2_000).unwrap();
+        let second = insert_trace(&db, a, 2_000, "beta");
+        let first = insert_trace(&db, a, 1_000, "alpha");
+        let last = insert_trace(&db, a, 3_000, "alpha");
+
+        let session = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!(session.trace_count, 3);
+        assert_eq!(
+            (session.start_trace_id, session.start_time),
+            (Some(first), 1_000)
+        );
+        assert_eq!(
+            (session.end_trace_id, session.end_time),
+            (Some(last), 3_000)
+        );
+        assert!(session.context_text.unwrap().contains("beta"));
+        assert!(session.entities_json.unwrap().contains("\"alpha\":2"));
+
+        // 重新分析已归入会话的 trace 时整体重算，不重复计数
+        db.update_activity_session_from_vlm(a, second, None, None)
+            .unwrap();
+        let session = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!(session.trace_count, 3);
+        assert_eq!(session.context_text.unwrap().matches("beta").count(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_session_append_matches_recompute() {
+        let dir = std::env::temp_dir().join(format!("engram-fold-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let a = db.create_activity_session("Code", 1_000).unwrap();
+        for (i, summary) in ["alpha", "", "beta", "alpha"].iter().enumerate() {
+            let (id, _) = db
+                .insert_trace(&NewTrace {
+                    timestamp: 1_000 * (i as i64 + 1),
+                    image_path: String::new(),
+                    app_name: Some("Code".to_string()),
+                    window_title: None,
+                    is_fullscreen: false,
+                    is_idle: false,
+                    ocr_text: None,
+                    phash: None,
+                })
+                .unwrap();
+            db.update_trace_vlm_analysis(
+                id,
+                Some(summary),
+                Some("提交代码"),
+                Some("coding"),
+                None,
+                &[summary.to_string(), "git".to_string()],
+                None,
+                i % 2 == 0,
+            )
+            .unwrap();
+            db.update_activity_session_from_vlm(a, id, None, None)
+                .unwrap();
+        }
+
+        // 逐条追加与整体重算共用同一折叠逻辑，结果一致
+        let appended = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert!(Database::recompute_activity_session(&db.pool.writer(), a).unwrap());
+        let recomputed = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!(appended.context_text, recomputed.context_text);
+        assert_eq!(appended.key_actions_json, recomputed.key_actions_json);
+        let counts = |s: &ActivitySession| -> serde_json::Value {
+            serde_json::from_str(s.entities_json.as_deref().unwrap()).unwrap()
+        };
+        assert_eq!(counts(&appended), counts(&recomputed));
+        assert_eq!(counts(&recomputed)["git"], 4);
+        assert_eq!(recomputed.context_text.unwrap().lines().count(), 3);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_session_centroid_and_lifecycle() {
+        let dir = std::env::temp_dir().join(format!("engram-lifecycle-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let a = db.create_activity_session("Code", 1_000).unwrap();
+        insert_trace(&db, a, 1_000, "alpha");
+        db.add_to_session_centroid(a, &[1.0, 0.0], "local:a")
+            .unwrap();
+        db.add_to_session_centroid(a, &[0.0, 1.0], "local:a")
+            .unwrap();
+        let centroids = db
+            .get_active_session_centroids(1_000, 60_000, "local:a", 8)
+            .unwrap();
+        assert_eq!(centroids.len(), 1);
+        assert_eq!(
+            Database::deserialize_embedding(&centroids[0].1),
+            vec![0.5, 0.5]
+        );
+
+        // 换用其他模型后质心以新向量重新开始，不混合两种模型的向量
+        db.add_to_session_centroid(a, &[1.0, 1.0], "local:b")
+            .unwrap();
+        let centroids = db
+            .get_active_session_centroids(1_000, 60_000, "local:b", 8)
+            .unwrap();
+        assert_eq!(
+            Database::deserialize_embedding(&centroids[0].1),
+            vec![1.0, 1.0]
+        );
+        // 其他模型的查询向量不与该质心比较
+        assert!(db
+            .get_active_session_centroids(1_000, 60_000, "local:a", 8)
+            .unwrap()
+            .is_empty());
+
+        // 空闲超过间隔后关闭，不再参与路由
+        let b = db.create_activity_session("Code", 50_000).unwrap();
+        insert_trace(&db, b, 50_000, "beta");
+        assert_eq!(db.close_idle_sessions(10_000, 60_000).unwrap(), 1);
+        let closed = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!(
+            (closed.state.as_str(), closed.closed_at),
+            ("closed", Some(60_000))
+        );
+        let active = db
+            .get_active_session_centroids(50_000, 60_000, "local:b", 8)
+            .unwrap();
+        assert!(active.iter().all(|(sid, _)| *sid != a));
+
+        // 生成回顾后压缩上下文，并且不再待处理
+        let pending = db.get_sessions_pending_recap(0, 10).unwrap();
+        assert_eq!(pending.iter().map(|s| s.id).collect::<Vec<_>>(), vec![a]);
+        assert!(db
+            .finalize_activity_session(a, Some("回顾标题"), None, "做了 alpha")
+            .unwrap());
+        let finalized = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!(finalized.recap.as_deref(), Some("做了 alpha"));
+        assert_eq!(finalized.title.as_deref(), Some("回顾标题"));
+        assert!(db.get_sessions_pending_recap(0, 10).unwrap().is_empty());
+
+        // 手动移动 trace 后重算：保留回顾与压缩后的上下文
+        let moved = insert_trace(&db, b, 55_000, "gamma");
+        db.move_traces_to_session(&[moved], Some(a)).unwrap();
+        let recomputed = db.get_activity_session_by_id(a).unwrap().unwrap();
+        assert_eq!(recomputed.recap.as_deref(), Some("做了 alpha"));
+        let context = recomputed.context_text.unwrap();
+        assert!(context.starts_with("做了 alpha\n\n"));
+        assert!(context.contains("gamma"));
+        // open 会话不能写入回顾
+        assert!(!db.finalize_activity_session(b, None, None, "x").unwrap());
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[test]
+    fn test_search_text_mixed_language_ocr() {
+        let dir = std::env::temp_dir().join(format!("engram-fts-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let fixtures = [
+            (
+                "正在配置PostgreSQL数据库连接池，max_connections=20",
+                "pgAdmin 4",
+            ),
+            ("cargo build 失败：找不到 crate serde_json", "终端 — zsh"),
+            ("会议纪要：讨论 Q3 OKR 与数据看板", "飞书文档"),
+        ];
+        let mut ids = Vec::new();
+        for (i, (ocr, title)) in fixtures.iter().enumerate() {
+            let (id, _) = db
+                .insert_trace(&NewTrace {
+                    timestamp: 1_000 * (i as i64 + 1),
+                    image_path: String::new(),
+                    app_name: None,
+                    window_title: Some(title.to_string()),
+                    is_fullscreen: false,
+                    is_idle: false,
+                    ocr_text: Some(ocr.to_string()),
+                    phash: None,
+                })
+                .unwrap();
+            ids.push(id);
+        }
+        let search = |db: &Database, q: &str| -> Vec<i64> {
+            let mut found: Vec<i64> = db
+                .search_text(q, 10)
+                .unwrap()
+                .into_iter()
+                .map(|t| t.id)
+                .collect();
+            found.sort();
+            found
+        };
+
+        // 长行中间的中文词（unicode61 会把整行当作一个词）
+        assert_eq!(search(&db, "数据库"), vec![ids[0]]);
+        // 中英文紧挨在一起时按边界切分
+        assert_eq!(search(&db, "postgresql数据库"), vec![ids[0]]);
+        assert_eq!(search(&db, "连接池 max_connections"), vec![ids[0]]);
+        // 不足 3 个字符的中文关键词走 LIKE
+        assert_eq!(search(&db, "数据"), vec![ids[0], ids[2]]);
+        assert_eq!(search(&db, "数据 看板"), vec![ids[2]]);
+        // 窗口标题与英文关键词
+        assert_eq!(search(&db, "终端 serde"), vec![ids[1]]);
+        assert_eq!(search(&db, "OKR"), vec![ids[2]]);
+        // FTS 语法字符不会导致查询出错
+        assert_eq!(search(&db, "\"crate* (serde"), vec![ids[1]]);
+        assert!(search(&db, "，").is_empty());
+        drop(db);
+
+        // 旧版 unicode61 索引在打开时迁移并重建
+        {
+            let conn = Connection::open(dir.join("engram.db")).unwrap();
+            conn.execute_batch(
+                r#"
+                DROP TABLE traces_fts;
+                CREATE VIRTUAL TABLE traces_fts USING fts5(
+                    ocr_text, window_title,
+                    content='traces', content_rowid='id', tokenize='unicode61'
+                );
+                "#,
+            )
+            .unwrap();
+        }
+        let db = Database::open_at(dir.clone()).unwrap();
+        // 重建完成前走 LIKE；尚未入索引的行被修改或删除时触发器跳过，由重建读取最新内容
+        assert!(!db.fts_ready("traces_fts"));
+        assert_eq!(search(&db, "数据库"), vec![ids[0]]);
+        {
+            let conn = db.pool.writer();
+            conn.execute(
+                "UPDATE traces SET ocr_text = 'cargo test 通过' WHERE id = ?1",
+                [ids[1]],
+            )
+            .unwrap();
+            conn.execute("DELETE FROM traces WHERE id = ?1", [ids[2]])
+                .unwrap();
+        }
+        db.rebuild_pending_fts().unwrap();
+        assert!(db.fts_ready("traces_fts"));
+        db.pool
+            .writer()
+            .execute_batch("INSERT INTO traces_fts(traces_fts) VALUES('integrity-check');")
+            .unwrap();
+        assert_eq!(search(&db, "数据库"), vec![ids[0]]);
+        assert_eq!(search(&db, "cargo test"), vec![ids[1]]);
+        assert!(search(&db, "serde").is_empty());
+        assert!(search(&db, "OKR").is_empty());
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[test]
+    fn test_fts_weights_columns_and_sessions() {
+        let dir = std::env::temp_dir().join(format!("engram-fts-cols-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let a = db.create_activity_session("Code", 1_000).unwrap();
+        // OCR 命中与 VLM 摘要命中：摘要列权重更高，排在前面
+        let (ocr_hit, _) = db
+            .insert_trace(&NewTrace {
+                timestamp: 1_000,
+                image_path: String::new(),
+                app_name: Some("Code".to_string()),
+                window_title: None,
+                is_fullscreen: false,
+                is_idle: false,
+                ocr_text: Some("日志里提到迁移脚本 migration 失败后重试".to_string()),
+                phash: None,
+            })
+            .unwrap();
+        let summary_hit = insert_trace(&db, a, 2_000, "编写数据库迁移脚本");
+
+        let ids = |traces: Vec<Trace>| traces.into_iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(db.search_text("迁移脚本", 10).unwrap()),
+            vec![summary_hit, ocr_hit]
+        );
+        assert_eq!(
+            ids(db.search_text("summary:迁移脚本", 10).unwrap()),
+            vec![summary_hit]
+        );
+        assert_eq!(ids(db.search_text("ocr:迁移", 10).unwrap()), vec![ocr_hit]);
+        // 实体列（entities_json）
+        assert_eq!(
+            ids(db.search_text("entity:数据库迁移", 10).unwrap()),
+            vec![summary_hit]
+        );
+
+        // 会话索引随标题修改同步
+        let sessions = |q: &str| {
+            db.search_sessions_text(q, 10)
+                .unwrap()
+                .into_iter()
+                .map(|s| s.id)
+                .collect::<Vec<_>>()
+        };
+        // 持续增长的 context_text 不进索引
+        assert!(sessions("迁移脚本").is_empty());
+        assert!(sessions("title:周报整理").is_empty());
+        db.rename_activity_session(a, Some("周报整理"), None)
+            .unwrap();
+        assert_eq!(sessions("title:周报整理"), vec![a]);
+        assert_eq!(sessions("周报"), vec![a]);
+
+        // 关闭时生成的回顾进入索引；短关键词走 LIKE，同样匹配回顾
+        assert!(sessions("recap:重构导出模块").is_empty());
+        db.close_idle_sessions(i64::MAX, 3_000).unwrap();
+        db.finalize_activity_session(a, None, None, "重构导出模块并补充测试")
+            .unwrap();
+        assert_eq!(sessions("recap:重构导出模块"), vec![a]);
+        assert_eq!(sessions("测试"), vec![a]);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_embeddings_track_model_id() {
+        let dir = std::env::temp_dir().join(format!("engram-emb-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let mut ids = Vec::new();
+        for ts in [1_000, 2_000, 3_000] {
+            let (id, _) = db
+                .insert_trace(&NewTrace {
+                    timestamp: ts,
+                    image_path: String::new(),
+                    app_name: None,
+                    window_title: None,
+                    is_fullscreen: false,
+                    is_idle: false,
+                    ocr_text: Some(format!("trace {}", ts)),
+                    phash: None,
+                })
+                .unwrap();
+            ids.push(id);
+        }
+        let bytes = |v: [f32; 2]| v.iter().flat_map(|f| f.to_le_bytes()).collect::<Vec<u8>>();
+        db.update_trace_embeddings_batch(&[(ids[0], bytes([1.0, 0.0]))], "local:a")
+            .unwrap();
+        db.update_trace_embedding(ids[1], &bytes([0.9, 0.1]), "local:b")
+            .unwrap();
+
+        let pending = |model: Option<&str>| {
+            db.get_traces_pending_embedding(10, model)
+                .unwrap()
+                .into_iter()
+                .map(|t| t.id)
+                .collect::<Vec<_>>()
+        };
+        // 未嵌入的优先，其次是其他模型生成的向量
+        assert_eq!(pending(None), vec![ids[2]]);
+        assert_eq!(pending(Some("local:a")), vec![ids[2], ids[1]]);
+
+        // 单独嵌入仍失败的 trace 对同一模型不再返回，换模型后重新尝试
+        db.mark_trace_embedding_failed(ids[2], "local:a", "input too long")
+            .unwrap();
+        assert_eq!(pending(Some("local:a")), vec![ids[1]]);
+        assert_eq!(pending(Some("local:c")), vec![ids[2], ids[1], ids[0]]);
+
+        // 向量搜索只比较同一模型的向量
+        let hits = db
+            .search_by_embedding(&[1.0, 0.0], 10, "local:a", None)
+            .unwrap();
+        assert_eq!(
+            hits.iter().map(|(t, _)| t.id).collect::<Vec<_>>(),
+            vec![ids[0]]
+        );
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_vector_index_partitions_and_quantization() {
+        let dir = std::env::temp_dir().join(format!("engram-vec-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+        const DAY: i64 = 24 * 60 * 60 * 1000;
+
+        // 三个分区（默认 30 天一个分区），8 维向量
+        let mut ids = Vec::new();
+        for (i, ts) in [DAY, 2 * DAY, 40 * DAY, 70 * DAY].into_iter().enumerate() {
+            let (id, _) = db
+                .insert_trace(&NewTrace {
+                    timestamp: ts,
+                    image_path: String::new(),
+                    app_name: None,
+                    window_title: None,
+                    is_fullscreen: false,
+                    is_idle: false,
+                    ocr_text: Some(format!("trace {}", i)),
+                    phash: None,
+                })
+                .unwrap();
+            ids.push(id);
+        }
+        let vector = |i: usize| -> Vec<f32> {
+            (0..8)
+                .map(|j| {
+                    if j == i {
+                        0.5 + 0.1 * i as f32
+                    } else if j % 2 == 0 {
+                        0.1
+                    } else {
+                        -0.1
+                    }
+                })
+                .collect()
+        };
+        let bytes = |v: &[f32]| v.iter().flat_map(|f| f.to_le_bytes()).collect::<Vec<u8>>();
+        let items: Vec<(i64, Vec<u8>)> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, bytes(&vector(i))))
+            .collect();
+        db.update_trace_embeddings_batch(&items, "local:a").unwrap();
+
+        let partitions = |db: &Database| -> i64 {
+            db.pool
+                .reader()
+                .query_row("SELECT COUNT(*) FROM vec_partitions", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(partitions(&db), 3);
+        let top = |db: &Database, query: &[f32], range: Option<(i64, i64)>| {
+            db.search_by_embedding(query, 2, "local:a", range)
+                .unwrap()
+                .into_iter()
+                .map(|(t, _)| t.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(top(&db, &vector(2), None)[0], ids[2]);
+        // 时间过滤只扫描重叠的分区
+        assert_eq!(
+            top(&db, &vector(2), Some((0, 3 * DAY))),
+            vec![ids[0], ids[1]]
+        );
+
+        // 切换为二值量化并取消分区：整体重建，候选用全精度距离重排
+        let binary = VectorIndexConfig {
+            quantization: VectorQuantization::Binary,
+            partition_days: 0,
+            ..VectorIndexConfig::default()
+        };
+        assert!(db.apply_vector_index_config(&binary).unwrap());
+        assert!(!db.apply_vector_index_config(&binary).unwrap());
+        assert_eq!(partitions(&db), 1);
+        // 新布局先建空分区，分批回填后才能检索到旧向量
+        assert!(top(&db, &vector(3), None).is_empty());
+        assert_eq!(db.backfill_vector_index().unwrap(), 4);
+        assert_eq!(db.backfill_vector_index().unwrap(), 0);
+        let hits = db
+            .search_by_embedding(&vector(3), 1, "local:a", None)
+            .unwrap();
+        assert_eq!(hits[0].0.id, ids[3]);
+        assert!((hits[0].1 - 1.0).abs() < 1e-6);
+
+        let report = db.benchmark_vector_search("local:a", 4, 2).unwrap();
+        assert_eq!((report.queries, report.vectors), (4, 4));
+        assert_eq!(report.quantization, "binary");
+        assert!(report.recall > 0.99);
+
+        let int8 = VectorIndexConfig {
+            quantization: VectorQuantization::Int8,
+            ..VectorIndexConfig::default()
+        };
+        assert!(db.apply_vector_index_config(&int8).unwrap());
+        db.backfill_vector_index().unwrap();
+        assert_eq!(top(&db, &vector(1), None)[0], ids[1]);
+
+        // 恢复默认配置后，过期的分区整表删除
+        assert!(db
+            .apply_vector_index_config(&VectorIndexConfig::default())
+            .unwrap());
+        db.backfill_vector_index().unwrap();
+        db.delete_traces_before(30 * DAY).unwrap();
+        assert_eq!(partitions(&db), 2);
+        assert_eq!(top(&db, &vector(0), None), vec![ids[2], ids[3]]);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_near_duplicate_analysis_reuse() {
+        let dir = std::env::temp_dir().join(format!("engram-reuse-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let insert = |ts: i64, window: &str, phash: u64| {
+            db.insert_trace(&NewTrace {
+                timestamp: ts,
+                image_path: String::new(),
+                app_name: Some("Code".to_string()),
+                window_title: Some(window.to_string()),
+                is_fullscreen: false,
+                is_idle: false,
+                ocr_text: None,
+                phash: Some(format!("{:016x}", phash).into_bytes()),
+            })
+            .unwrap()
+            .0
+        };
+        let source = insert(1_000, "main.rs", 0xff00_ff00_ff00_ff00);
+        let near = insert(2_000, "main.rs", 0xff00_ff00_ff00_ff03);
+        let far = insert(3_000, "main.rs", 0x00ff_00ff_00ff_00ff);
+        let other_window = insert(4_000, "lib.rs", 0xff00_ff00_ff00_ff00);
+        let trace = |id: i64| db.get_trace_by_id(id).unwrap().unwrap();
+
+        // 来源尚未分析时没有可复用的结果
+        assert!(db
+            .find_near_duplicate_analysis(&trace(near), 6, 60_000)
+            .unwrap()
+            .is_none());
+
+        db.update_trace_vlm_analysis(
+            source,
+            Some("编辑 main.rs"),
+            None,
+            Some("coding"),
+            Some(0.9),
+            &[],
+            Some(r#"{"summary":"编辑 main.rs","text_content":null,"detected_app":null,"activity_type":"coding","entities":[],"confidence":0.9}"#),
+            false,
+        )
+        .unwrap();
+
+        let (found, distance) = db
+            .find_near_duplicate_analysis(&trace(near), 6, 60_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!((found.id, distance), (source, 2));
+        assert!(db
+            .find_near_duplicate_analysis(&trace(far), 6, 60_000)
+            .unwrap()
+            .is_none());
+        assert!(db
+            .find_near_duplicate_analysis(&trace(other_window), 6, 60_000)
+            .unwrap()
+            .is_none());
+        assert!(db
+            .find_near_duplicate_analysis(&trace(near), 6, 500)
+            .unwrap()
+            .is_none());
+
+        // 焦点窗口之外的局部变化仍可复用，焦点窗口内的变化（如输入文字）需要重新分析
+        let region = |x: u32| ChangeRegion {
+            x,
+            y: 0,
+            width: 40,
+            height: 20,
+            changed_tiles: 1,
+            total_tiles: 64,
+            score: 0.1,
+        };
+        db.save_focus_region(near, (0, 0, 800, 600)).unwrap();
+        db.save_change_region(near, &region(1_000)).unwrap();
+        assert!(db
+            .find_near_duplicate_analysis(&trace(near), 6, 60_000)
+            .unwrap()
+            .is_some());
+        db.save_change_region(near, &region(100)).unwrap();
+        assert!(db
+            .find_near_duplicate_analysis(&trace(near), 6, 60_000)
+            .unwrap()
+            .is_none());
+        db.pool
+            .writer()
+            .execute(
+                "DELETE FROM trace_change_regions WHERE trace_id = ?1",
+                [near],
+            )
+            .unwrap();
+
+        // 复用结果不会再作为来源
+        db.record_vlm_reuse(near, source, distance).unwrap();
+        db.update_trace_vlm_analysis(
+            near,
+            Some("编辑 main.rs"),
+            None,
+            None,
+            None,
+            &[],
+            Some("{}"),
+            false,
+        )
+        .unwrap();
+        let stats = db.get_vlm_reuse_stats(0).unwrap();
+        assert_eq!((stats.analyzed, stats.reused), (2, 1));
+        assert!((stats.hit_rate - 0.5).abs() < 1e-9);
+        assert!((stats.avg_distance - 2.0).abs() < 1e-9);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_summary_entity_uses_vlm_links() {
+        let dir =
+            std::env::temp_dir().join(format!("engram-summary-entity-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let session = db.create_activity_session("Code", 1_000).unwrap();
+        let go = insert_trace(&db, session, 1_000, "writing Go code");
+        insert_trace(&db, session, 2_000, "searching Google");
+        db.record_trace_entities(go, &[("Go".to_string(), "tech".to_string())])
+            .unwrap();
+
+        let entity = NewEntity {
+            name: "Go".to_string(),
+            entity_type: "tech".to_string(),
+            first_seen: 9_000,
+            last_seen: 9_000,
+            metadata: None,
+        };
+        let id = db.record_summary_entity(&entity, 0, 10_000).unwrap();
+        let traces = db.get_traces_by_entity(id, 10).unwrap();
+        assert_eq!(traces.iter().map(|t| t.id).collect::<Vec<_>>(), vec![go]);
+
+        let saved = db.get_entity_by_id(id).unwrap().unwrap();
+        assert_eq!(saved.mention_count, 1);
+        assert_eq!((saved.first_seen, saved.last_seen), (1_000, 1_000));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_vlm_tier_stats() {
+        let dir = std::env::temp_dir().join(format!("engram-tiers-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let (trace_id, _) = db
+            .insert_trace(&NewTrace {
+                timestamp: 1_000,
+                image_path: String::new(),
+                app_name: Some("Code".to_string()),
+                window_title: None,
+                is_fullscreen: false,
+                is_idle: false,
+                ocr_text: None,
+                phash: None,
+            })
+            .unwrap();
+        let call = |tier: u32,
+                    name: &str,
+                    status: &str,
+                    confidence: Option<f32>,
+                    reason: Option<&str>,
+                    accepted: bool| {
+            VlmTierCall {
+                tier,
+                tier_name: name.to_string(),
+                model: format!("{name}-model"),
+                status: status.to_string(),
+                confidence,
+                latency_ms: 100 * (tier as i64 + 1),
+                escalation_reason: reason.map(str::to_string),
+                accepted,
+            }
+        };
+        // 第一层低置信度 → 第二层失败 → 第三层成功
+        db.record_vlm_tier_calls(
+            trace_id,
+            &[
+                call(0, "primary", "ok", Some(0.3), None, false),
+                call(1, "cloud", "error", None, Some("low_confidence"), false),
+                call(2, "large", "ok", Some(0.9), Some("low_confidence"), true),
+            ],
+        )
+        .unwrap();
+        db.record_vlm_tier_calls(
+            trace_id,
+            &[call(0, "primary", "parse_failed", Some(0.5), None, true)],
+        )
+        .unwrap();
+
+        let stats = db.get_vlm_tier_stats(0).unwrap();
+        assert_eq!(stats.len(), 3);
+        let primary = &stats[0];
+        assert_eq!((primary.tier, primary.calls, primary.accepted), (0, 2, 1));
+        assert_eq!((primary.parse_failures, primary.escalated), (1, 0));
+        assert!((primary.avg_confidence - 0.3).abs() < 1e-6);
+        assert_eq!(
+            (stats[1].errors, stats[1].escalated, stats[1].accepted),
+            (1, 1, 0)
+        );
+        assert_eq!(
+            (stats[2].tier_name.as_str(), stats[2].accepted),
+            ("large", 1)
+        );
+        assert!((stats[2].avg_latency_ms - 300.0).abs() < 1e-9);
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_status_and_reprocessing() {
+        let dir = std::env::temp_dir().join(format!("engram-parse-{}", uuid::Uuid::new_v4()));
+        let db = Database::open_at(dir.clone()).unwrap();
+
+        let insert = |timestamp: i64| {
+            db.insert_trace(&NewTrace {
+                timestamp,
+                image_path: format!("{timestamp}.jpg"),
+                app_name: Some("Code".to_string()),
+                window_title: None,
+                is_fullscreen: false,
+                is_idle: false,
+                ocr_text: None,
+                phash: None,
+            })
+            .unwrap()
+            .0
+        };
+        let parsed = insert(1_000);
+        let failed = insert(2_000);
+
+        db.update_trace_ocr_text(parsed, "fn main()").unwrap();
+        db.save_trace_parse_status(parsed, "repaired", 1, None, None)
+            .unwrap();
+        db.update_trace_ocr_text(failed, "").unwrap();
+        db.save_trace_parse_status(failed, "failed", 2, Some("EOF"), Some("Sorry"))
+            .unwrap();
+
+        let stats = db.get_parse_status_stats(0).unwrap();
+        assert_eq!((stats.ok, stats.repaired, stats.failed), (0, 1, 1));
+        let rows = db.get_traces_by_parse_status("failed", 10).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].trace_id, failed);
+        assert_eq!(rows[0].raw_output.as_deref(), Some("Sorry"));
+
+        // 解析失败的 trace 不进入嵌入，也不再等待 VLM 分析
+        let pending: Vec<i64> = db
+            .get_traces_pending_embedding(10, None)
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(pending, vec![parsed]);
+        assert!(db.get_traces_pending_ocr(10).unwrap().is_empty());
+
+        // 重新分析：回到待分析队列，状态记录删除
+        assert_eq!(db.reset_failed_analyses(10).unwrap(), 1);
+        let pending = db.get_traces_pending_ocr(10).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, failed);
+        assert_eq!(db.get_parse_status_stats(0).unwrap().failed, 0);
+
+        // 摘要按周期记录，重试同一周期时覆盖
+        use crate::ai::{ParseReport, ParseStatus};
+        let failed = ParseReport {
+            status: ParseStatus::Failed,
+            attempts: 3,
+            error: Some("EOF".to_string()),
+            raw_output: Some("Sorry".to_string()),
+        };
+        let reasked = ParseReport {
+            status: ParseStatus::Reasked,
+            attempts: 2,
+            ..Default::default()
+        };
+        db.save_summary_parse_status("short", 0, 900, &failed)
+            .unwrap();
+        db.save_summary_parse_status("short", 0, 900, &reasked)
+            .unwrap();
+        db.save_summary_parse_status("daily", 0, 86_400, &failed)
+            .unwrap();
+        let stats = db.get_summary_parse_status_stats(0).unwrap();
+        assert_eq!((stats.reasked, stats.failed), (1, 1));
+        assert_eq!(db.get_summary_parse_status_stats(1_000).unwrap().failed, 1);
+
+        db.save_model_parse_status("session_recap", "7", &failed)
+            .unwrap();
+        db.save_model_parse_status("entity_relations", "3", &reasked)
+            .unwrap();
+        let stats = db.get_model_parse_status_stats("session_recap", 0).unwrap();
+        assert_eq!((stats.reasked, stats.failed), (0, 1));
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,6 @@
 //! 数据模型定义
 
+use crate::config::Locale;
 use serde::{Deserialize, Serialize};
 
 /// 活动会话（用户行为 Session）
@@ -19,6 +20,33 @@ pub struct ActivitySession {
     pub key_actions_json: Option<String>,
     pub created_at: i64,
     pub updated_at: i64,
+    /// 生命周期状态：open（可继续归入 trace）/ closed
+    pub state: String,
+    pub closed_at: Option<i64>,
+    /// 会话关闭时由 LLM 生成的回顾
+    pub recap: Option<String>,
+}
+
+/// 已归入 Session 的 trace 向量（用于路由回放评估）
+#[derive(Debug, Clone)]
+pub struct SessionTraceEmbedding {
+    pub trace_id: i64,
+    pub timestamp: i64,
+    pub session_id: i64,
+    pub embedding: Vec<u8>,
+}
+
+/// 用户对 trace 归属的纠正（用作路由 few-shot 示例）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingCorrection {
+    pub trace_id: i64,
+    pub trace_summary: Option<String>,
+    pub app_name: Option<String>,
+    /// 原先被归入的 Session 标题（Session 已删除时为空）
+    pub wrong_session_title: Option<String>,
+    /// 用户指定的 Session 标题
+    pub correct_session_title: String,
+    pub created_at: i64,
 }
 
 /// Chat 线程（与“活动 Session”概念区分）
@@ -92,6 +120,18 @@ pub struct TextHighlight {
     pub end: usize,
 }
 
+/// 按会话 / 应用 / 活动类型聚合的 trace 停留时长
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceDuration {
+    pub session_id: Option<i64>,
+    pub app_name: Option<String>,
+    pub activity_type: String,
+    /// 仅未归入会话的 trace 按窗口标题分组
+    pub window_title: Option<String>,
+    pub trace_count: u32,
+    pub duration_ms: i64,
+}
+
 /// 摘要记录（用于插入）
 #[derive(Debug, Clone)]
 pub struct NewSummary {
@@ -139,6 +179,62 @@ pub struct Entity {
     pub metadata: Option<String>,
 }
 
+/// 实体别名
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntityAlias {
+    /// 归一化名称
+    pub alias: String,
+    pub entity_id: i64,
+    /// 首次出现时的原始写法
+    pub name: String,
+    pub created_at: i64,
+}
+
+/// 实体关系（共现边，`source_id < target_id`）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntityRelation {
+    pub source_id: i64,
+    pub target_id: i64,
+    /// 衰减到查询时刻的共现权重
+    pub weight: f64,
+    /// 共同出现的 trace 数
+    pub trace_count: u32,
+    /// 共同出现的会话数
+    pub session_count: u32,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    /// LLM 标注的关系类型（works_with / uses / part_of / depends_on / discusses / related_to）
+    pub relation_type: Option<String>,
+}
+
+/// 实体的相邻实体
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntityNeighbor {
+    pub entity: Entity,
+    pub relation: EntityRelation,
+}
+
+/// 以某个实体为中心的关系图
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntityGraph {
+    pub nodes: Vec<Entity>,
+    pub edges: Vec<EntityRelation>,
+}
+
+/// 实体时间线条目（实体出现过的会话）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntityTimelineEntry {
+    pub session_id: i64,
+    pub app_name: String,
+    pub title: Option<String>,
+    pub start_time: i64,
+    pub end_time: i64,
+    /// 会话中提到该实体的 trace 数
+    pub mention_count: u32,
+    /// 同一会话中的其他实体（按提及次数）
+    pub co_entities: Vec<String>,
+}
+
 /// 简化的实体（用于 JSON 序列化）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EntityRef {
@@ -174,13 +270,25 @@ pub struct Settings {
     pub capture_interval_ms: u64,
     pub idle_threshold_ms: u64,
     pub similarity_threshold: u32,
+    #[serde(default = "default_region_detection")]
+    pub region_detection: bool,
+    #[serde(default = "default_min_change_score")]
+    pub min_change_score: f32,
     pub hot_data_days: u32,
     pub warm_data_days: u32,
+    /// 是否按保留天数自动清理历史数据（默认关闭）
+    #[serde(default)]
+    pub retention_enabled: bool,
     pub summary_interval_min: u32,
     pub session_active_window_ms: u64,
     pub session_max_active_sessions: u32,
     pub session_similarity_threshold: f32,
     pub session_gap_threshold_ms: u64,
+    pub session_adaptive_threshold: bool,
+    #[serde(default)]
+    pub locale: Locale,
+    #[serde(default)]
+    pub language: Locale,
 }
 
 impl Default for Settings {
@@ -189,17 +297,31 @@ impl Default for Settings {
             capture_interval_ms: 2000,
             idle_threshold_ms: 30000,
             similarity_threshold: 5,
+            region_detection: default_region_detection(),
+            min_change_score: default_min_change_score(),
             hot_data_days: 7,
             warm_data_days: 30,
+            retention_enabled: false,
             summary_interval_min: 15,
             session_active_window_ms: 20 * 60 * 1000,
             session_max_active_sessions: 8,
             session_similarity_threshold: 0.78,
             session_gap_threshold_ms: 300000,
+            session_adaptive_threshold: false,
+            locale: Locale::default(),
+            language: Locale::default(),
         }
     }
 }
 
+fn default_region_detection() -> bool {
+    true
+}
+
+fn default_min_change_score() -> f32 {
+    2.0
+}
+
 /// 黑名单规则
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlacklistRule {
@@ -209,3 +331,121 @@ pub struct BlacklistRule {
     pub enabled: bool,
     pub created_at: i64,
 }
+
+/// 向量检索基准测试结果（当前索引 vs 精确 KNN）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VectorBenchmark {
+    /// 量化方式（none / int8 / binary）
+    pub quantization: String,
+    /// 分区天数（0 表示不分区）
+    pub partition_days: u32,
+    /// 已登记的分区数
+    pub partitions: u32,
+    /// 当前模型的向量数
+    pub vectors: u64,
+    /// 实际执行的查询数
+    pub queries: u32,
+    pub limit: u32,
+    pub ann_avg_ms: f64,
+    pub ann_p95_ms: f64,
+    pub exact_avg_ms: f64,
+    pub exact_p95_ms: f64,
+    /// 平均 recall@limit（以精确 KNN 结果为基准）
+    pub recall: f64,
+}
+
+/// 一次 VLM 层级调用（见 `VlmTaskConfig::escalation_tiers`）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VlmTierCall {
+    /// 层级序号：0 为 `[vlm]`，之后依次为升级层级
+    pub tier: u32,
+    pub tier_name: String,
+    pub model: String,
+    /// ok / parse_failed / error
+    pub status: String,
+    pub confidence: Option<f32>,
+    pub latency_ms: i64,
+    /// 升级到该层的原因（第一层为 None）
+    pub escalation_reason: Option<String>,
+    /// 该结果是否写回 trace
+    pub accepted: bool,
+}
+
+/// trace 的 VLM 输出解析状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceParseStatus {
+    pub trace_id: i64,
+    pub timestamp: i64,
+    /// ok / repaired / reasked / failed
+    pub status: String,
+    /// 请求次数（含重新请求）
+    pub attempts: u32,
+    pub error: Option<String>,
+    /// 解析失败时的原始输出（截断）
+    pub raw_output: Option<String>,
+    pub updated_at: i64,
+}
+
+/// VLM 输出解析状态统计
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParseStatusStats {
+    /// 直接解析成功
+    pub ok: u64,
+    /// 修复后解析成功
+    pub repaired: u64,
+    /// 重新请求后解析成功
+    pub reasked: u64,
+    /// 无法解析
+    pub failed: u64,
+}
+
+/// VLM 各层级的使用统计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VlmTierStats {
+    pub tier: u32,
+    pub tier_name: String,
+    pub model: String,
+    /// 调用次数
+    pub calls: u64,
+    /// 结果被采用（写回 trace）的次数
+    pub accepted: u64,
+    /// 由下层升级而来的调用次数
+    pub escalated: u64,
+    /// 请求失败次数
+    pub errors: u64,
+    /// 输出无法解析为 JSON 的次数
+    pub parse_failures: u64,
+    /// 平均耗时（毫秒）
+    pub avg_latency_ms: f64,
+    /// 成功调用的平均置信度
+    pub avg_confidence: f64,
+}
+
+/// trace 相对上一张已保存截图的变化区域（截图像素坐标）
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChangeRegion {
+    /// 变化块外接矩形
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// 变化块数量
+    pub changed_tiles: u32,
+    /// 网格总块数
+    pub total_tiles: u32,
+    /// 按焦点窗口加权后的变化显著度
+    pub score: f64,
+}
+
+/// 近重复截图复用 VLM 结果的统计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VlmReuseStats {
+    /// 统计窗口内完成分析的 trace 数（含复用）
+    pub analyzed: u64,
+    /// 其中复用了近重复 trace 结果的数量
+    pub reused: u64,
+    /// 复用率（reused / analyzed）
+    pub hit_rate: f64,
+    /// 复用时的平均 pHash 汉明距离
+    pub avg_distance: f64,
+}
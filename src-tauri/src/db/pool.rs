@@ -0,0 +1,125 @@
+//! SQLite 连接池
+//!
+//! 读写分离：一个写连接串行化所有写操作，多个 WAL 只读连接并发服务查询。
+//! WAL 模式下读事务不会被写事务阻塞，因此 UI 的长查询（会话列表、混合搜索）
+//! 不会再拖慢截图循环的 `insert_trace` 和 VlmTask 的写回。
+
+use anyhow::Result;
+use rusqlite::{Connection, OpenFlags};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// 默认只读连接数
+pub const DEFAULT_READER_COUNT: usize = 4;
+
+/// 每个连接的预编译语句缓存容量
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// 连接忙等待超时
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 读写分离的连接池
+pub struct ConnectionPool {
+    writer: Mutex<Connection>,
+    readers: Vec<Mutex<Connection>>,
+    next_reader: AtomicUsize,
+}
+
+impl ConnectionPool {
+    /// 使用已初始化 Schema 的写连接创建连接池，并打开只读连接
+    pub fn new(writer: Connection, db_path: &Path, reader_count: usize) -> Result<Self> {
+        Self::configure(&writer)?;
+
+        let mut readers = Vec::with_capacity(reader_count.max(1));
+        for _ in 0..reader_count.max(1) {
+            let conn = Connection::open_with_flags(
+                db_path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | OpenFlags::SQLITE_OPEN_URI
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            Self::configure(&conn)?;
+            readers.push(Mutex::new(conn));
+        }
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+            readers,
+            next_reader: AtomicUsize::new(0),
+        })
+    }
+
+    fn configure(conn: &Connection) -> Result<()> {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        Ok(())
+    }
+
+    /// 获取写连接（所有写操作串行化）
+    pub fn writer(&self) -> MutexGuard<'_, Connection> {
+        self.writer.lock().unwrap()
+    }
+
+    /// 获取只读连接：优先选择空闲连接，全部繁忙时按轮询等待
+    pub fn reader(&self) -> MutexGuard<'_, Connection> {
+        let n = self.readers.len();
+        let start = self.next_reader.fetch_add(1, Ordering::Relaxed) % n;
+
+        for i in 0..n {
+            if let Ok(guard) = self.readers[(start + i) % n].try_lock() {
+                return guard;
+            }
+        }
+
+        self.readers[start].lock().unwrap()
+    }
+
+    /// 只读连接数
+    pub fn reader_count(&self) -> usize {
+        self.readers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_sees_committed_writes() {
+        let dir = std::env::temp_dir().join(format!("engram-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("pool.db");
+
+        let writer = Connection::open(&db_path).unwrap();
+        writer
+            .execute_batch("PRAGMA journal_mode=WAL; CREATE TABLE t (v INTEGER);")
+            .unwrap();
+
+        let pool = ConnectionPool::new(writer, &db_path, 2).unwrap();
+        assert_eq!(pool.reader_count(), 2);
+
+        pool.writer()
+            .execute("INSERT INTO t (v) VALUES (?1)", [42])
+            .unwrap();
+
+        // 写连接持锁时读连接仍可查询
+        let _writer_guard = pool.writer();
+        let v: i64 = pool
+            .reader()
+            .query_row("SELECT v FROM t", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(v, 42);
+
+        // 只读连接拒绝写入
+        assert!(pool
+            .reader()
+            .execute("INSERT INTO t (v) VALUES (1)", [])
+            .is_err());
+
+        drop(_writer_guard);
+        drop(pool);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,7 +1,7 @@
 //! 数据库 Schema 初始化
 
 use anyhow::Result;
-use rusqlite::Connection;
+use rusqlite::{params, Connection, OptionalExtension};
 use tracing::info;
 
 const SCHEMA_VERSION: i32 = 4;
@@ -22,6 +22,7 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
             current_version, SCHEMA_VERSION
         );
 
+        drop_vec_partitions(conn)?;
         conn.execute_batch(
             r#"
             PRAGMA foreign_keys = OFF;
@@ -30,21 +31,49 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
             DROP TRIGGER IF EXISTS traces_ad;
             DROP TRIGGER IF EXISTS traces_au;
 
+            DROP TABLE IF EXISTS trace_embedding_models;
+            DROP TABLE IF EXISTS trace_embedding_failures;
+            DROP TABLE IF EXISTS vec_partitions;
+            DROP TABLE IF EXISTS vec_backfill;
+            DROP TABLE IF EXISTS trace_vlm_reuse;
+            DROP TABLE IF EXISTS trace_change_regions;
+            DROP TABLE IF EXISTS trace_focus_regions;
+            DROP TABLE IF EXISTS vlm_tier_calls;
+            DROP TABLE IF EXISTS trace_parse_status;
+            DROP TABLE IF EXISTS summary_parse_status;
+            DROP TABLE IF EXISTS model_parse_status;
             DROP TABLE IF EXISTS traces_fts;
             DROP TABLE IF EXISTS traces_vec;
             DROP TABLE IF EXISTS traces;
 
+            DROP TRIGGER IF EXISTS sessions_ai;
+            DROP TRIGGER IF EXISTS sessions_ad;
+            DROP TRIGGER IF EXISTS sessions_au;
+            DROP TRIGGER IF EXISTS sessions_recap_ai;
+            DROP TRIGGER IF EXISTS sessions_recap_au;
+
+            DROP TABLE IF EXISTS sessions_fts;
+            DROP TABLE IF EXISTS session_corrections;
+            DROP TABLE IF EXISTS session_centroid_models;
+            DROP TABLE IF EXISTS session_lifecycle;
             DROP TABLE IF EXISTS activity_sessions;
 
             DROP TABLE IF EXISTS chat_messages;
             DROP TABLE IF EXISTS chat_threads;
 
+            DROP TABLE IF EXISTS summary_links;
+            DROP TABLE IF EXISTS summary_periods;
             DROP TABLE IF EXISTS summaries;
+            DROP TABLE IF EXISTS entity_relations;
+            DROP TABLE IF EXISTS entity_type_votes;
+            DROP TABLE IF EXISTS entity_aliases;
+            DROP TABLE IF EXISTS entity_sessions;
             DROP TABLE IF EXISTS entity_traces;
             DROP TABLE IF EXISTS entities;
 
             DROP TABLE IF EXISTS settings;
             DROP TABLE IF EXISTS blacklist;
+            DROP TABLE IF EXISTS fts_rebuilds;
 
             PRAGMA foreign_keys = ON;
             "#,
@@ -86,6 +115,51 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
         "#,
     )?;
 
+    // 用户对活动会话的修正记录（merge / split / move / rename）
+    // 有 rename 记录的会话标题与描述不再被 VLM 覆盖；会话被合并删除后记录仍保留
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS session_corrections (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            session_id INTEGER NOT NULL,
+            from_session_id INTEGER,
+            trace_id INTEGER,
+            created_at INTEGER DEFAULT (strftime('%s', 'now') * 1000)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_session_corrections_session ON session_corrections(session_id);
+        "#,
+    )?;
+
+    // 活动会话的生命周期：open / closed、trace 向量的质心与关闭时生成的回顾
+    // 没有记录的会话视为 open
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS session_lifecycle (
+            session_id INTEGER PRIMARY KEY REFERENCES activity_sessions(id) ON DELETE CASCADE,
+            state TEXT NOT NULL DEFAULT 'open',
+            -- 会话内 trace embedding 的均值（f32 小端序）
+            centroid BLOB,
+            centroid_count INTEGER NOT NULL DEFAULT 0,
+            closed_at INTEGER,
+            recap TEXT
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_session_lifecycle_state ON session_lifecycle(state);
+        "#,
+    )?;
+
+    // 会话质心由哪个嵌入模型的向量计算（只混合同一模型的向量）；没有记录的是旧版本写入的质心
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS session_centroid_models (
+            session_id INTEGER PRIMARY KEY REFERENCES activity_sessions(id) ON DELETE CASCADE,
+            model TEXT NOT NULL
+        );
+        "#,
+    )?;
+
     // 核心：traces（原子事实流）
     conn.execute_batch(
         r#"
@@ -140,41 +214,304 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
         "#,
     )?;
 
+    // 每条向量由哪个嵌入模型生成（TextEmbedder::model_id）；没有记录的是旧版本写入的向量
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS trace_embedding_models (
+            trace_id INTEGER PRIMARY KEY REFERENCES traces(id) ON DELETE CASCADE,
+            model TEXT NOT NULL,
+            dim INTEGER NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_trace_embedding_models_model ON trace_embedding_models(model);
+        "#,
+    )?;
+
+    // 单独嵌入仍失败的 trace：同一模型不再重试，换模型后重新尝试
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS trace_embedding_failures (
+            trace_id INTEGER PRIMARY KEY REFERENCES traces(id) ON DELETE CASCADE,
+            model TEXT NOT NULL,
+            error TEXT NOT NULL,
+            failed_at INTEGER NOT NULL
+        );
+        "#,
+    )?;
+
+    // 复用了近重复 trace 分析结果的 trace（不调用 VLM；来源 trace 本身不会是复用结果）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS trace_vlm_reuse (
+            trace_id INTEGER PRIMARY KEY REFERENCES traces(id) ON DELETE CASCADE,
+            source_trace_id INTEGER NOT NULL,
+            distance INTEGER NOT NULL           -- pHash 汉明距离
+        );
+        "#,
+    )?;
+
+    // trace 相对上一张已保存截图的变化区域（截图像素坐标；没有记录表示整屏）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS trace_change_regions (
+            trace_id INTEGER PRIMARY KEY REFERENCES traces(id) ON DELETE CASCADE,
+            x INTEGER NOT NULL,
+            y INTEGER NOT NULL,
+            width INTEGER NOT NULL,
+            height INTEGER NOT NULL,
+            changed_tiles INTEGER NOT NULL,
+            total_tiles INTEGER NOT NULL,
+            score REAL NOT NULL                 -- 按焦点窗口加权的变化显著度
+        );
+        "#,
+    )?;
+
+    // 截图时焦点窗口在截图中的范围（截图像素坐标，用于 VLM 焦点裁剪）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS trace_focus_regions (
+            trace_id INTEGER PRIMARY KEY REFERENCES traces(id) ON DELETE CASCADE,
+            x INTEGER NOT NULL,
+            y INTEGER NOT NULL,
+            width INTEGER NOT NULL,
+            height INTEGER NOT NULL
+        );
+        "#,
+    )?;
+
+    // VLM 层级调用记录（第一层分析所有 trace，满足条件时升级到更强的模型）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS vlm_tier_calls (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            trace_id INTEGER NOT NULL REFERENCES traces(id) ON DELETE CASCADE,
+            tier INTEGER NOT NULL,              -- 0 为 [vlm]，之后依次为升级层级
+            tier_name TEXT NOT NULL,
+            model TEXT NOT NULL,
+            status TEXT NOT NULL,               -- ok / parse_failed / error
+            confidence REAL,
+            latency_ms INTEGER NOT NULL,
+            escalation_reason TEXT,             -- 升级到该层的原因（第一层为 NULL）
+            accepted INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_vlm_tier_calls_created ON vlm_tier_calls(created_at);
+        "#,
+    )?;
+
+    // VLM 结构化输出的解析状态（复用结果的 trace 没有记录）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS trace_parse_status (
+            trace_id INTEGER PRIMARY KEY REFERENCES traces(id) ON DELETE CASCADE,
+            status TEXT NOT NULL,               -- ok / repaired / reasked / failed
+            attempts INTEGER NOT NULL,          -- 请求次数（含重新请求）
+            error TEXT,                         -- 最后一次解析错误
+            raw_output TEXT,                    -- 解析失败时的原始输出（截断）
+            updated_at INTEGER NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_trace_parse_status_status ON trace_parse_status(status);
+        "#,
+    )?;
+
+    // 摘要结构化输出的解析状态（按周期记录，解析失败的周期没有摘要）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS summary_parse_status (
+            summary_type TEXT NOT NULL,
+            start_time INTEGER NOT NULL,
+            end_time INTEGER NOT NULL,
+            status TEXT NOT NULL,               -- ok / repaired / reasked / failed
+            attempts INTEGER NOT NULL,          -- 请求次数（含重新请求）
+            error TEXT,                         -- 最后一次解析错误
+            raw_output TEXT,                    -- 解析失败时的原始输出（截断）
+            updated_at INTEGER NOT NULL,
+            PRIMARY KEY (summary_type, start_time, end_time)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_summary_parse_status_end ON summary_parse_status(end_time);
+        "#,
+    )?;
+
+    // 会话回顾、实体提取与关系标注的输出解析状态（按任务与对象记录，重试时覆盖）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS model_parse_status (
+            task TEXT NOT NULL,                 -- session_recap / entity_relations / entity_extraction
+            subject TEXT NOT NULL,              -- 会话 ID / 实体 ID / 文本哈希
+            status TEXT NOT NULL,               -- ok / repaired / reasked / failed
+            attempts INTEGER NOT NULL,          -- 请求次数（含重新请求）
+            error TEXT,                         -- 最后一次解析错误
+            raw_output TEXT,                    -- 解析失败时的原始输出（截断）
+            updated_at INTEGER NOT NULL,
+            PRIMARY KEY (task, subject)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_model_parse_status_updated ON model_parse_status(updated_at);
+        "#,
+    )?;
+
+    // vec0 向量索引分区登记（分区表本身按需创建，见 db::vector）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS vec_partitions (
+            name TEXT PRIMARY KEY,
+            start_ts INTEGER NOT NULL,
+            end_ts INTEGER NOT NULL,            -- 不含
+            dim INTEGER NOT NULL,
+            quantization TEXT NOT NULL          -- none / int8 / binary
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_vec_partitions_range ON vec_partitions(start_ts, end_ts);
+
+        -- 布局变化后的分批回填进度（只有一行；回填完成后删除）
+        CREATE TABLE IF NOT EXISTS vec_backfill (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            last_trace_id INTEGER NOT NULL
+        );
+        "#,
+    )?;
+
     // 创建 FTS5 全文索引
+    // trigram 分词器按 3 字符子串索引，中文等不以空格分词的 OCR 文本也能命中；
+    // 列顺序与 db::fts::TRACE_COLUMNS / SESSION_COLUMNS 的 bm25 权重一一对应
+    let rebuild_traces_fts = drop_outdated_fts(
+        conn,
+        "traces_fts",
+        &["traces_ai", "traces_ad", "traces_au"],
+        &["trigram", "vlm_entities_json"],
+    )?;
+    let rebuild_sessions_fts = drop_outdated_fts(
+        conn,
+        "sessions_fts",
+        &[
+            "sessions_ai",
+            "sessions_ad",
+            "sessions_au",
+            "sessions_recap_ai",
+            "sessions_recap_au",
+        ],
+        &["trigram", "recap"],
+    )?;
     conn.execute_batch(
         r#"
         CREATE VIRTUAL TABLE IF NOT EXISTS traces_fts USING fts5(
             ocr_text,
             window_title,
+            vlm_summary,
+            vlm_action_description,
+            vlm_entities_json,
             content='traces',
             content_rowid='id',
-            tokenize='unicode61'
+            tokenize='trigram'
+        );
+
+        -- 会话索引自带内容（rowid 即会话 id）：回顾在 session_lifecycle 中，
+        -- 持续增长的 context_text 不进索引，避免每次追加记录都重写整篇文档
+        CREATE VIRTUAL TABLE IF NOT EXISTS sessions_fts USING fts5(
+            title,
+            description,
+            recap,
+            tokenize='trigram'
         );
         "#,
     )?;
 
-    // vec0 向量索引表由 Database::ensure_vec_table() 按需创建
+    // 新建或迁移后的索引需要从 content 表重建；数据量大时耗时较长，
+    // 这里只登记，由 Database::rebuild_pending_fts 在后台按 rowid 分批执行（完成前搜索改用 LIKE）。
+    // 登记时已有的行 (last_rowid, max_rowid] 由重建填充，之后新增的行由触发器同步
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS fts_rebuilds (
+            name TEXT PRIMARY KEY,
+            last_rowid INTEGER NOT NULL DEFAULT 0,  -- 已填充到的 rowid（含）
+            max_rowid INTEGER NOT NULL              -- 登记时 content 表的最大 rowid
+        );
+        "#,
+    )?;
+    for (table, content, rebuild) in [
+        ("traces_fts", "traces", rebuild_traces_fts),
+        ("sessions_fts", "activity_sessions", rebuild_sessions_fts),
+    ] {
+        let max_rowid: Option<i64> =
+            conn.query_row(&format!("SELECT MAX(id) FROM {content}"), [], |row| {
+                row.get(0)
+            })?;
+        // 索引表刚重新创建时从头开始；否则保留上次中断时的进度
+        if let (true, Some(max_rowid)) = (rebuild, max_rowid) {
+            conn.execute(
+                "INSERT OR REPLACE INTO fts_rebuilds (name, last_rowid, max_rowid) VALUES (?1, 0, ?2)",
+                params![table, max_rowid],
+            )?;
+            info!("Scheduled background rebuild of {}", table);
+        }
+    }
+
+    // vec0 向量索引分区由 Database::ensure_vec_partition() 按需创建
     // 支持任意维度的 embedding 模型（如 384、768、1024、2048 等）
-    // 首次插入向量时会自动检测维度并创建表
+    // 首次插入向量时会自动检测维度并创建分区
 
-    // 创建同步触发器
+    // 创建同步触发器（更新只在索引列变化时重建对应行）。
+    // traces_fts 是外部内容表，对未入索引的行执行 'delete' 会破坏索引，
+    // 因此分批重建尚未覆盖的行跳过同步，由重建读取其最新内容
     conn.execute_batch(
         r#"
         CREATE TRIGGER IF NOT EXISTS traces_ai AFTER INSERT ON traces BEGIN
-            INSERT INTO traces_fts(rowid, ocr_text, window_title)
-            VALUES (new.id, new.ocr_text, new.window_title);
+            INSERT INTO traces_fts(rowid, ocr_text, window_title, vlm_summary, vlm_action_description, vlm_entities_json)
+            VALUES (new.id, new.ocr_text, new.window_title, new.vlm_summary, new.vlm_action_description, new.vlm_entities_json);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS traces_ad AFTER DELETE ON traces
+        WHEN NOT EXISTS (
+            SELECT 1 FROM fts_rebuilds r
+            WHERE r.name = 'traces_fts' AND old.id > r.last_rowid AND old.id <= r.max_rowid
+        )
+        BEGIN
+            INSERT INTO traces_fts(traces_fts, rowid, ocr_text, window_title, vlm_summary, vlm_action_description, vlm_entities_json)
+            VALUES ('delete', old.id, old.ocr_text, old.window_title, old.vlm_summary, old.vlm_action_description, old.vlm_entities_json);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS traces_au
+        AFTER UPDATE OF ocr_text, window_title, vlm_summary, vlm_action_description, vlm_entities_json ON traces
+        WHEN NOT EXISTS (
+            SELECT 1 FROM fts_rebuilds r
+            WHERE r.name = 'traces_fts' AND old.id > r.last_rowid AND old.id <= r.max_rowid
+        )
+        BEGIN
+            INSERT INTO traces_fts(traces_fts, rowid, ocr_text, window_title, vlm_summary, vlm_action_description, vlm_entities_json)
+            VALUES ('delete', old.id, old.ocr_text, old.window_title, old.vlm_summary, old.vlm_action_description, old.vlm_entities_json);
+            INSERT INTO traces_fts(rowid, ocr_text, window_title, vlm_summary, vlm_action_description, vlm_entities_json)
+            VALUES (new.id, new.ocr_text, new.window_title, new.vlm_summary, new.vlm_action_description, new.vlm_entities_json);
         END;
 
-        CREATE TRIGGER IF NOT EXISTS traces_ad AFTER DELETE ON traces BEGIN
-            INSERT INTO traces_fts(traces_fts, rowid, ocr_text, window_title)
-            VALUES ('delete', old.id, old.ocr_text, old.window_title);
+        CREATE TRIGGER IF NOT EXISTS sessions_ai AFTER INSERT ON activity_sessions BEGIN
+            INSERT INTO sessions_fts(rowid, title, description)
+            VALUES (new.id, new.title, new.description);
         END;
 
-        CREATE TRIGGER IF NOT EXISTS traces_au AFTER UPDATE ON traces BEGIN
-            INSERT INTO traces_fts(traces_fts, rowid, ocr_text, window_title)
-            VALUES ('delete', old.id, old.ocr_text, old.window_title);
-            INSERT INTO traces_fts(rowid, ocr_text, window_title)
-            VALUES (new.id, new.ocr_text, new.window_title);
+        CREATE TRIGGER IF NOT EXISTS sessions_ad AFTER DELETE ON activity_sessions BEGIN
+            DELETE FROM sessions_fts WHERE rowid = old.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS sessions_au
+        AFTER UPDATE OF title, description ON activity_sessions
+        BEGIN
+            UPDATE sessions_fts SET title = new.title, description = new.description
+            WHERE rowid = new.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS sessions_recap_ai
+        AFTER INSERT ON session_lifecycle WHEN new.recap IS NOT NULL
+        BEGIN
+            UPDATE sessions_fts SET recap = new.recap WHERE rowid = new.session_id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS sessions_recap_au
+        AFTER UPDATE OF recap ON session_lifecycle
+        BEGIN
+            UPDATE sessions_fts SET recap = new.recap WHERE rowid = new.session_id;
         END;
         "#,
     )?;
@@ -199,6 +536,37 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
         "#,
     )?;
 
+    // 摘要层级关系（周/月/自定义摘要 -> 参与汇总的下级摘要）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS summary_links (
+            parent_id INTEGER NOT NULL,
+            child_id INTEGER NOT NULL,
+            PRIMARY KEY (parent_id, child_id),
+            FOREIGN KEY (parent_id) REFERENCES summaries(id) ON DELETE CASCADE,
+            FOREIGN KEY (child_id) REFERENCES summaries(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_summary_links_child ON summary_links(child_id);
+        "#,
+    )?;
+
+    // 摘要周期完成记录（定时任务据此补齐漏掉的周期）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS summary_periods (
+            summary_type TEXT NOT NULL,
+            period_start INTEGER NOT NULL,
+            period_end INTEGER NOT NULL,
+            -- 周期内无数据时为 NULL
+            summary_id INTEGER,
+            completed_at INTEGER DEFAULT (strftime('%s', 'now') * 1000),
+            PRIMARY KEY (summary_type, period_start),
+            FOREIGN KEY (summary_id) REFERENCES summaries(id) ON DELETE SET NULL
+        );
+        "#,
+    )?;
+
     // 创建 entities 表
     conn.execute_batch(
         r#"
@@ -227,6 +595,75 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
             FOREIGN KEY (entity_id) REFERENCES entities(id) ON DELETE CASCADE,
             FOREIGN KEY (trace_id) REFERENCES traces(id) ON DELETE CASCADE
         );
+
+        CREATE INDEX IF NOT EXISTS idx_entity_traces_trace ON entity_traces(trace_id);
+        "#,
+    )?;
+
+    // 实体与活动会话的关联（trace 被清理后仍保留）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS entity_sessions (
+            entity_id INTEGER NOT NULL,
+            session_id INTEGER NOT NULL,
+            PRIMARY KEY (entity_id, session_id),
+            FOREIGN KEY (entity_id) REFERENCES entities(id) ON DELETE CASCADE,
+            FOREIGN KEY (session_id) REFERENCES activity_sessions(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_entity_sessions_session ON entity_sessions(session_id);
+        "#,
+    )?;
+
+    // 实体别名（归一化名称 -> 实体），name 为首次出现时的原始写法
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS entity_aliases (
+            alias TEXT PRIMARY KEY,
+            entity_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            created_at INTEGER DEFAULT (strftime('%s', 'now') * 1000),
+            FOREIGN KEY (entity_id) REFERENCES entities(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_entity_aliases_entity ON entity_aliases(entity_id);
+        "#,
+    )?;
+
+    // 实体类型投票（每次提及按推断或摘要给出的类型计票）
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS entity_type_votes (
+            entity_id INTEGER NOT NULL,
+            type TEXT NOT NULL,
+            votes INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (entity_id, type),
+            FOREIGN KEY (entity_id) REFERENCES entities(id) ON DELETE CASCADE
+        );
+        "#,
+    )?;
+
+    // 实体关系（共现边，source_id < target_id），weight 为衰减到 updated_at 时的权重，
+    // relation_type 由 LLM 标注（可为空），重建时保留
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS entity_relations (
+            source_id INTEGER NOT NULL,
+            target_id INTEGER NOT NULL,
+            weight REAL NOT NULL,
+            trace_count INTEGER NOT NULL DEFAULT 0,
+            session_count INTEGER NOT NULL DEFAULT 0,
+            first_seen INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL,
+            relation_type TEXT,
+            updated_at INTEGER NOT NULL,
+            PRIMARY KEY (source_id, target_id),
+            CHECK (source_id < target_id),
+            FOREIGN KEY (source_id) REFERENCES entities(id) ON DELETE CASCADE,
+            FOREIGN KEY (target_id) REFERENCES entities(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_entity_relations_target ON entity_relations(target_id);
         "#,
     )?;
 
@@ -327,3 +764,55 @@ pub fn init_schema(conn: &Connection) -> Result<()> {
     info!("Database schema initialized successfully");
     Ok(())
 }
+
+/// 检查 FTS 索引表是否需要（重新）创建
+///
+/// 表不存在，或建表语句缺少 `markers` 中任一项（如旧版本的 unicode61 分词器、缺少新增列）时，
+/// 删除索引表与同步触发器（content 表数据不受影响），返回 true 表示建表后需要重建索引。
+fn drop_outdated_fts(
+    conn: &Connection,
+    table: &str,
+    triggers: &[&str],
+    markers: &[&str],
+) -> Result<bool> {
+    let sql: Option<String> = conn
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match sql {
+        None => Ok(true),
+        Some(sql) if markers.iter().any(|m| !sql.contains(m)) => {
+            info!("Migrating {} to the current FTS schema...", table);
+            for trigger in triggers {
+                conn.execute_batch(&format!("DROP TRIGGER IF EXISTS {};", trigger))?;
+            }
+            conn.execute_batch(&format!("DROP TABLE IF EXISTS {};", table))?;
+            Ok(true)
+        }
+        Some(_) => Ok(false),
+    }
+}
+
+/// 删除已登记的 vec0 分区表（分区表名是动态的，无法写进重建列表）
+fn drop_vec_partitions(conn: &Connection) -> Result<()> {
+    let registered: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name='vec_partitions'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !registered {
+        return Ok(());
+    }
+    let names: Vec<String> = {
+        let mut stmt = conn.prepare("SELECT name FROM vec_partitions")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    for name in names {
+        conn.execute_batch(&format!("DROP TABLE IF EXISTS {name}"))?;
+    }
+    Ok(())
+}
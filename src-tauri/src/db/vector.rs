@@ -0,0 +1,223 @@
+//! 向量索引布局
+//!
+//! trace 的全精度向量始终保存在 `traces.embedding`（f32 小端序）中，vec0 表只作为可重建的
+//! KNN 索引：
+//! - 按时间分区：每 `partition_days` 天一张 vec0 表（`traces_vec_p{bucket}`），带时间过滤的
+//!   查询只扫描时间范围重叠的分区，数据保留清理可以整表删除过期分区
+//! - 可选量化：int8（约 1/4 体积）或二值（约 1/32 体积），KNN 取回
+//!   `limit * rescore_factor` 个候选后，再用 `traces.embedding` 的全精度向量重新计算距离排序
+//!
+//! 分区登记在 `vec_partitions` 表中；布局配置变化时整体重建：先建空分区，再按 trace id
+//! 分批回填（每批单独持有写锁，进度记录在 `vec_backfill`，中断后继续）。
+
+use serde::{Deserialize, Serialize};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// 不分区时唯一一张 vec0 表的表名
+pub(crate) const UNPARTITIONED_TABLE: &str = "traces_vec_all";
+
+/// 旧版本的单表索引（未登记到 `vec_partitions`，升级时重建）
+pub(crate) const LEGACY_TABLE: &str = "traces_vec";
+
+/// 向量量化方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorQuantization {
+    /// 不量化（float32，精确 KNN）
+    #[default]
+    None,
+    /// int8 标量量化（适用于归一化向量）
+    Int8,
+    /// 二值量化（按符号取 1 bit，汉明距离）
+    Binary,
+}
+
+impl VectorQuantization {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VectorQuantization::None => "none",
+            VectorQuantization::Int8 => "int8",
+            VectorQuantization::Binary => "binary",
+        }
+    }
+
+    /// 从 `vec_partitions.quantization` 还原（未知值按不量化处理）
+    pub(crate) fn parse(s: &str) -> Self {
+        match s {
+            "int8" => VectorQuantization::Int8,
+            "binary" => VectorQuantization::Binary,
+            _ => VectorQuantization::None,
+        }
+    }
+
+    /// 实际使用的量化方式：二值量化要求维度是 8 的倍数，否则退回 int8
+    pub fn effective(self, dim: usize) -> Self {
+        if self == VectorQuantization::Binary && !dim.is_multiple_of(8) {
+            VectorQuantization::Int8
+        } else {
+            self
+        }
+    }
+
+    /// vec0 向量列类型
+    pub(crate) fn column_type(&self, dim: usize) -> String {
+        match self {
+            VectorQuantization::None => format!("float[{dim}]"),
+            VectorQuantization::Int8 => format!("int8[{dim}]"),
+            VectorQuantization::Binary => format!("bit[{dim}]"),
+        }
+    }
+
+    /// 把 f32 向量（SQL 表达式 `value`）转换为该列类型的 SQL 表达式
+    pub(crate) fn quantize_expr(&self, value: &str) -> String {
+        match self {
+            VectorQuantization::None => value.to_string(),
+            VectorQuantization::Int8 => format!("vec_quantize_int8({value}, 'unit')"),
+            VectorQuantization::Binary => format!("vec_quantize_binary({value})"),
+        }
+    }
+}
+
+/// 向量索引配置（`[vector_index]`）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorIndexConfig {
+    /// 量化方式
+    #[serde(default)]
+    pub quantization: VectorQuantization,
+    /// 每个分区覆盖的天数（0 表示不分区）
+    #[serde(default = "default_partition_days")]
+    pub partition_days: u32,
+    /// 量化索引的候选倍数：KNN 取回 `limit * rescore_factor` 个候选再全精度重排
+    #[serde(default = "default_rescore_factor")]
+    pub rescore_factor: u32,
+}
+
+fn default_partition_days() -> u32 {
+    30
+}
+
+fn default_rescore_factor() -> u32 {
+    8
+}
+
+impl Default for VectorIndexConfig {
+    fn default() -> Self {
+        Self {
+            quantization: VectorQuantization::default(),
+            partition_days: default_partition_days(),
+            rescore_factor: default_rescore_factor(),
+        }
+    }
+}
+
+impl VectorIndexConfig {
+    /// 分区时长（毫秒）；不分区时为 None
+    pub(crate) fn partition_span_ms(&self) -> Option<i64> {
+        (self.partition_days > 0).then(|| self.partition_days as i64 * DAY_MS)
+    }
+
+    /// 包含 `timestamp` 的分区：(表名, 起始时间, 结束时间（不含）)
+    pub(crate) fn partition_for(&self, timestamp: i64) -> (String, i64, i64) {
+        match self.partition_span_ms() {
+            Some(span) => {
+                let bucket = timestamp.div_euclid(span);
+                (
+                    format!("traces_vec_p{bucket}"),
+                    bucket * span,
+                    (bucket + 1) * span,
+                )
+            }
+            None => (UNPARTITIONED_TABLE.to_string(), i64::MIN, i64::MAX),
+        }
+    }
+
+    /// 已登记的分区是否符合当前布局
+    pub(crate) fn matches_partition(
+        &self,
+        start_ts: i64,
+        end_ts: i64,
+        dim: usize,
+        quantization: &str,
+    ) -> bool {
+        let span_ok = match self.partition_span_ms() {
+            Some(span) => end_ts.checked_sub(start_ts) == Some(span),
+            None => start_ts == i64::MIN && end_ts == i64::MAX,
+        };
+        span_ok && self.quantization.effective(dim).as_str() == quantization
+    }
+
+    /// KNN 候选数量
+    pub(crate) fn candidates(&self, quantization: VectorQuantization, limit: u32) -> u32 {
+        if quantization == VectorQuantization::None {
+            limit
+        } else {
+            limit.saturating_mul(self.rescore_factor.max(1))
+        }
+    }
+}
+
+/// vec0 分区表定义
+pub(crate) fn create_partition_sql(
+    name: &str,
+    dim: usize,
+    quantization: VectorQuantization,
+) -> String {
+    format!(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS {name} USING vec0(
+            trace_id INTEGER PRIMARY KEY,
+            embedding {},
+            timestamp INTEGER,
+            model TEXT
+        )",
+        quantization.column_type(dim)
+    )
+}
+
+/// 欧氏距离（与 vec0 float 列的默认距离一致）
+pub(crate) fn l2_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// 延迟样本的 (平均值, P95)
+pub(crate) fn latency_summary(samples: &mut [f64]) -> (f64, f64) {
+    if samples.is_empty() {
+        return (0.0, 0.0);
+    }
+    samples.sort_by(|a, b| a.total_cmp(b));
+    let avg = samples.iter().sum::<f64>() / samples.len() as f64;
+    let p95 = samples[((samples.len() as f64 * 0.95).ceil() as usize).clamp(1, samples.len()) - 1];
+    (avg, p95)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_layout() {
+        let config = VectorIndexConfig::default();
+        let (name, start, end) = config.partition_for(31 * DAY_MS);
+        assert_eq!(name, "traces_vec_p1");
+        assert_eq!((start, end), (30 * DAY_MS, 60 * DAY_MS));
+        assert!(config.matches_partition(start, end, 384, "none"));
+        assert!(!config.matches_partition(start, end, 384, "int8"));
+
+        let config = VectorIndexConfig {
+            quantization: VectorQuantization::Binary,
+            partition_days: 0,
+            ..VectorIndexConfig::default()
+        };
+        assert_eq!(config.partition_for(0).0, UNPARTITIONED_TABLE);
+        assert!(config.matches_partition(i64::MIN, i64::MAX, 384, "binary"));
+        // 维度不是 8 的倍数时退回 int8
+        assert!(config.matches_partition(i64::MIN, i64::MAX, 3, "int8"));
+        assert_eq!(config.candidates(VectorQuantization::Binary, 10), 80);
+        assert_eq!(config.candidates(VectorQuantization::None, 10), 10);
+        assert_eq!(VectorQuantization::parse("int8"), VectorQuantization::Int8);
+    }
+}
@@ -0,0 +1,253 @@
+//! 命令错误类型
+//!
+//! 所有 Tauri 命令统一返回 `EngramError`，序列化为
+//! `{ "code": "...", "message": "...", "details": {...} }`：
+//! - `code` 为稳定的错误码，供前端与 API 客户端分支处理
+//! - `message` 按当前界面语言（`AppConfig::locale`）本地化
+//! - `details` 为结构化附加信息（字段名、资源 ID 等）
+
+use crate::config::Locale;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// 当前错误消息语言（序列化时读取）
+static CURRENT_LOCALE: AtomicU8 = AtomicU8::new(0);
+
+/// 设置错误消息语言
+pub fn set_locale(locale: Locale) {
+    CURRENT_LOCALE.store(locale as u8, Ordering::Relaxed);
+}
+
+/// 获取当前错误消息语言
+pub fn current_locale() -> Locale {
+    Locale::from_u8(CURRENT_LOCALE.load(Ordering::Relaxed))
+}
+
+/// 命令返回结果
+pub type CommandResult<T> = Result<T, EngramError>;
+
+/// Engram 命令错误
+#[derive(Debug, thiserror::Error)]
+pub enum EngramError {
+    /// VLM 未初始化
+    #[error("VLM is not initialized")]
+    VlmNotInitialized,
+
+    /// 嵌入模型未初始化
+    #[error("embedder is not initialized")]
+    EmbedderNotInitialized,
+
+    /// 数据库繁忙或被锁定
+    #[error("database is busy: {0}")]
+    DatabaseBusy(String),
+
+    /// 其他数据库错误
+    #[error("database error: {0}")]
+    Database(String),
+
+    /// 参数无效
+    #[error("invalid input for `{field}`: {reason}")]
+    InvalidInput { field: String, reason: String },
+
+    /// 资源不存在
+    #[error("{resource} not found: {id}")]
+    NotFound { resource: String, id: String },
+
+    /// 文件读写错误
+    #[error("io error: {0}")]
+    Io(String),
+
+    /// 配置读写错误
+    #[error("config error: {0}")]
+    Config(String),
+
+    /// AI 模型调用失败
+    #[error("{operation} failed: {reason}")]
+    AiRequest { operation: String, reason: String },
+
+    /// 未分类的内部错误
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl EngramError {
+    /// 参数无效
+    pub fn invalid_input(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::InvalidInput {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// 资源不存在
+    pub fn not_found(resource: impl Into<String>, id: impl ToString) -> Self {
+        Self::NotFound {
+            resource: resource.into(),
+            id: id.to_string(),
+        }
+    }
+
+    /// AI 调用失败
+    pub fn ai_request(operation: impl Into<String>, reason: impl ToString) -> Self {
+        Self::AiRequest {
+            operation: operation.into(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// 配置错误
+    pub fn config(e: impl ToString) -> Self {
+        Self::Config(e.to_string())
+    }
+
+    /// 稳定的错误码
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::VlmNotInitialized => "VLM_NOT_INITIALIZED",
+            Self::EmbedderNotInitialized => "EMBEDDER_NOT_INITIALIZED",
+            Self::DatabaseBusy(_) => "DB_BUSY",
+            Self::Database(_) => "DB_ERROR",
+            Self::InvalidInput { .. } => "INVALID_INPUT",
+            Self::NotFound { .. } => "NOT_FOUND",
+            Self::Io(_) => "IO_ERROR",
+            Self::Config(_) => "CONFIG_ERROR",
+            Self::AiRequest { .. } => "AI_REQUEST_FAILED",
+            Self::Internal(_) => "INTERNAL",
+        }
+    }
+
+    /// 结构化附加信息
+    pub fn details(&self) -> serde_json::Value {
+        match self {
+            Self::VlmNotInitialized | Self::EmbedderNotInitialized => serde_json::Value::Null,
+            Self::DatabaseBusy(reason)
+            | Self::Database(reason)
+            | Self::Io(reason)
+            | Self::Config(reason)
+            | Self::Internal(reason) => serde_json::json!({ "reason": reason }),
+            Self::InvalidInput { field, reason } => {
+                serde_json::json!({ "field": field, "reason": reason })
+            }
+            Self::NotFound { resource, id } => {
+                serde_json::json!({ "resource": resource, "id": id })
+            }
+            Self::AiRequest { operation, reason } => {
+                serde_json::json!({ "operation": operation, "reason": reason })
+            }
+        }
+    }
+
+    /// 按语言生成面向用户的消息
+    pub fn localized_message(&self, locale: Locale) -> String {
+        match locale {
+            Locale::En => match self {
+                Self::VlmNotInitialized => {
+                    "VLM is not initialized. Please configure an AI model in Settings.".to_string()
+                }
+                Self::EmbedderNotInitialized => "Embedding model is not initialized.".to_string(),
+                Self::DatabaseBusy(_) => "Database is busy, please try again later.".to_string(),
+                Self::Database(reason) => format!("Database error: {}", reason),
+                Self::InvalidInput { field, reason } => {
+                    format!("Invalid value for {}: {}", field, reason)
+                }
+                Self::NotFound { resource, id } => format!("{} not found: {}", resource, id),
+                Self::Io(reason) => format!("File access failed: {}", reason),
+                Self::Config(reason) => format!("Failed to save config: {}", reason),
+                Self::AiRequest { operation, reason } => {
+                    format!("{} failed: {}", operation, reason)
+                }
+                Self::Internal(reason) => reason.clone(),
+            },
+            Locale::Zh => match self {
+                Self::VlmNotInitialized => "VLM 未初始化。请先在设置中配置 AI 模型。".to_string(),
+                Self::EmbedderNotInitialized => "嵌入模型未初始化。".to_string(),
+                Self::DatabaseBusy(_) => "数据库繁忙，请稍后重试。".to_string(),
+                Self::Database(reason) => format!("数据库错误: {}", reason),
+                Self::InvalidInput { field, reason } => {
+                    format!("参数 {} 无效: {}", field, reason)
+                }
+                Self::NotFound { resource, id } => format!("未找到 {}: {}", resource, id),
+                Self::Io(reason) => format!("文件访问失败: {}", reason),
+                Self::Config(reason) => format!("保存配置失败: {}", reason),
+                Self::AiRequest { operation, reason } => {
+                    format!("{} 失败: {}", operation, reason)
+                }
+                Self::Internal(reason) => reason.clone(),
+            },
+        }
+    }
+}
+
+impl Serialize for EngramError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("EngramError", 3)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.localized_message(current_locale()))?;
+        state.serialize_field("details", &self.details())?;
+        state.end()
+    }
+}
+
+impl From<rusqlite::Error> for EngramError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::DatabaseBusy) | Some(rusqlite::ErrorCode::DatabaseLocked) => {
+                Self::DatabaseBusy(e.to_string())
+            }
+            _ => Self::Database(e.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for EngramError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.to_string())
+    }
+}
+
+impl From<anyhow::Error> for EngramError {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<rusqlite::Error>() {
+            Ok(e) => return e.into(),
+            Err(e) => e,
+        };
+        match e.downcast::<std::io::Error>() {
+            Ok(e) => e.into(),
+            Err(e) => Self::Internal(format!("{:#}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_busy_sqlite_error_maps_to_db_busy() {
+        let err = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+            None,
+        );
+        let err: EngramError = anyhow::Error::from(err).into();
+        assert_eq!(err.code(), "DB_BUSY");
+    }
+
+    #[test]
+    fn test_serialized_shape_is_stable() {
+        let err = EngramError::invalid_input("summary_type", "weekly");
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["code"], "INVALID_INPUT");
+        assert_eq!(json["details"]["field"], "summary_type");
+        assert!(json["message"].is_string());
+    }
+
+    #[test]
+    fn test_localized_messages_differ_by_locale() {
+        let err = EngramError::VlmNotInitialized;
+        assert!(err.localized_message(Locale::Zh).contains("未初始化"));
+        assert!(err
+            .localized_message(Locale::En)
+            .contains("not initialized"));
+    }
+}
@@ -7,32 +7,44 @@ pub mod commands;
 pub mod config;
 pub mod daemon;
 pub mod db;
+pub mod error;
 
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
-pub use ai::{ScreenDescription, TextEmbedder, VlmEngine};
-pub use config::AppConfig;
-pub use daemon::{EngramDaemon, SummarizerTask, SummarizerTaskConfig, VlmTask, VlmTaskConfig};
+pub use ai::{PromptLibrary, ScreenDescription, TextEmbedder, VlmEngine};
+pub use config::{AppConfig, LiveConfig, ModelTask};
+pub use daemon::{
+    EngramDaemon, RetentionTask, SummarizerTask, SummarizerTaskConfig, VlmTask, VlmTaskConfig,
+};
 pub use db::Database;
+pub use error::{CommandResult, EngramError};
 
 /// 应用全局状态
 pub struct AppState {
     /// 应用配置（TOML 文件）
     pub config: Arc<RwLock<AppConfig>>,
+    /// 配置变更广播（后台任务订阅，`config.toml` 修改后热重载）
+    pub live_config: Arc<LiveConfig>,
     /// 数据库连接
     pub db: Arc<Database>,
     /// 后台守护进程
     pub daemon: Arc<RwLock<EngramDaemon>>,
     /// VLM 引擎 (Qwen3-VL)
     pub vlm: Arc<RwLock<Option<VlmEngine>>>,
+    /// 对话模型客户端（`[chat]`，首次对话时初始化，配置变化后重建）
+    pub chat_llm: Arc<tokio::sync::Mutex<Option<ai::Summarizer>>>,
     /// 文本嵌入器
     pub embedder: Arc<RwLock<TextEmbedder>>,
+    /// Prompt 模板库（按 `language` 选择语言，支持用户覆盖）
+    pub prompts: Arc<PromptLibrary>,
     /// VLM 分析后台任务
     pub vlm_task: Arc<RwLock<VlmTask>>,
     /// 摘要生成后台任务
     pub summarizer_task: Arc<RwLock<SummarizerTask>>,
+    /// 数据保留后台任务
+    pub retention_task: Arc<RwLock<RetentionTask>>,
 }
 
 impl AppState {
@@ -40,22 +52,58 @@ impl AppState {
     pub async fn new() -> anyhow::Result<Self> {
         // 1. 加载配置（从文件，不存在则创建默认）
         let app_config = AppConfig::load()?;
+        error::set_locale(app_config.locale);
         let config = Arc::new(RwLock::new(app_config.clone()));
+        let prompts = Arc::new(match AppConfig::prompts_dir() {
+            Ok(dir) => PromptLibrary::with_overrides_dir(app_config.language, dir),
+            Err(e) => {
+                warn!(
+                    "Failed to resolve prompts dir: {}, using built-in prompts",
+                    e
+                );
+                PromptLibrary::builtin(app_config.language)
+            }
+        });
+        if let Err(e) = prompts.start_watching() {
+            warn!("Failed to watch prompt templates: {}", e);
+        }
+
+        let live_config = Arc::new(LiveConfig::new(app_config.clone()));
+        match AppConfig::config_path() {
+            Ok(path) => {
+                if let Err(e) = live_config.watch_file(path) {
+                    warn!("Failed to watch config file: {}", e);
+                }
+            }
+            Err(e) => warn!("Failed to resolve config path: {}", e),
+        }
+        Self::spawn_config_sync(live_config.subscribe(), config.clone(), prompts.clone());
 
         // 2. 初始化数据库
         let db = Arc::new(Database::new()?);
+        Self::spawn_vector_index_sync(live_config.subscribe(), db.clone());
+
+        // 迁移后的全文索引在后台重建（完成前搜索改用 LIKE）
+        let fts_db = db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = fts_db.call(|db| db.rebuild_pending_fts()).await {
+                warn!("Failed to rebuild full-text index: {}", e);
+            }
+        });
+
+        // 为旧数据回填实体与 trace / 会话的关联（后台执行，不阻塞启动）
+        tokio::spawn(daemon::run_entity_backfill(db.clone()));
 
         // 3. 创建 daemon（使用配置中的参数）
         let daemon = Arc::new(RwLock::new(EngramDaemon::new_with_config(
             db.clone(),
-            app_config.capture.interval_ms,
-            app_config.capture.idle_threshold_ms,
-            app_config.capture.similarity_threshold,
-            app_config.capture.mode,
+            live_config.subscribe(),
         )?));
 
         let vlm = Arc::new(RwLock::new(None)); // 延迟初始化
-        let embedder = Arc::new(RwLock::new(TextEmbedder::new()));
+        let embedder = Arc::new(RwLock::new(
+            TextEmbedder::new().with_models_dir(db.get_models_dir()),
+        ));
 
         // 4. 创建 VLM 任务（使用配置）
         let vlm_task = Arc::new(RwLock::new(VlmTask::new(
@@ -63,36 +111,112 @@ impl AppState {
             vlm.clone(),
             embedder.clone(),
             app_config.vlm_task.clone(),
-            app_config.session.clone(),
+            live_config.subscribe(),
         )));
 
         // 5. 创建摘要任务（使用配置）
-        let summarizer_task = Arc::new(RwLock::new(SummarizerTask::new(
-            db.clone(),
-            SummarizerTaskConfig::default(),
-        )));
+        let summarizer_task = Arc::new(RwLock::new(
+            SummarizerTask::new(db.clone(), Self::summarizer_task_config(&app_config))
+                .with_prompts(prompts.clone())
+                .with_config_updates(live_config.subscribe()),
+        ));
+
+        // 6. 启动数据保留任务（未开启 storage.retention_enabled 时不做清理）
+        let mut retention_task = RetentionTask::new(db.clone(), live_config.subscribe());
+        retention_task.start();
+        let retention_task = Arc::new(RwLock::new(retention_task));
 
         let state = Self {
             config,
+            live_config,
             db,
             daemon,
             vlm,
+            chat_llm: Arc::new(tokio::sync::Mutex::new(None)),
             embedder,
+            prompts,
             vlm_task,
             summarizer_task,
+            retention_task,
         };
 
-        // 6. 尝试自动初始化 AI
+        // 7. 尝试自动初始化 AI
         state.try_auto_initialize_ai().await;
 
         Ok(state)
     }
 
+    /// 把广播的配置同步到共享状态（`config` 快照、错误消息语言、Prompt 语言）
+    fn spawn_config_sync(
+        mut config_rx: tokio::sync::watch::Receiver<AppConfig>,
+        config: Arc<RwLock<AppConfig>>,
+        prompts: Arc<PromptLibrary>,
+    ) {
+        tokio::spawn(async move {
+            while config_rx.changed().await.is_ok() {
+                let new_config = config_rx.borrow_and_update().clone();
+                error::set_locale(new_config.locale);
+                prompts.set_language(new_config.language);
+                *config.write().await = new_config;
+            }
+        });
+    }
+
+    /// 按 `vector_index` 配置维护向量索引布局（启动时与配置变更时，布局变化则分批重建）
+    fn spawn_vector_index_sync(
+        mut config_rx: tokio::sync::watch::Receiver<AppConfig>,
+        db: Arc<Database>,
+    ) {
+        tokio::spawn(async move {
+            let mut applied = None;
+            loop {
+                let vector_index = config_rx.borrow_and_update().vector_index.clone();
+                if applied.as_ref() != Some(&vector_index) {
+                    let config = vector_index.clone();
+                    match db
+                        .call(move |db| db.apply_vector_index_config(&config))
+                        .await
+                    {
+                        Ok(_) => applied = Some(vector_index),
+                        Err(e) => warn!("Failed to apply vector index config: {}", e),
+                    }
+                    // 布局变化（或上次回填中断）时分批回填，批次之间不阻塞写入
+                    if let Err(e) = db.call(|db| db.backfill_vector_index()).await {
+                        warn!("Failed to backfill vector index: {}", e);
+                    }
+                }
+                if config_rx.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// 修改、保存并广播配置，运行中的后台任务即时生效
+    ///
+    /// 读取→修改→保存期间一直持有配置写锁，并发更新不会互相覆盖；
+    /// `edit` 返回错误时配置保持不变，校验放在 `edit` 内完成。
+    pub async fn update_config(
+        &self,
+        edit: impl FnOnce(&mut AppConfig) -> CommandResult<()>,
+    ) -> CommandResult<()> {
+        let mut config = self.config.write().await;
+        let mut new_config = config.clone();
+        edit(&mut new_config)?;
+        new_config.save().map_err(EngramError::config)?;
+        error::set_locale(new_config.locale);
+        self.prompts.set_language(new_config.language);
+        *config = new_config.clone();
+        self.live_config.publish(new_config);
+        Ok(())
+    }
+
     /// 尝试自动初始化 AI（基于配置文件）
     async fn try_auto_initialize_ai(&self) {
-        let app_config = self.config.read().await;
-        let vlm_config = app_config.vlm.clone();
-        let embedding_config = app_config.embedding.clone();
+        let (vlm_config, embedding_config) = {
+            let app_config = self.config.read().await;
+            (app_config.vlm.clone(), app_config.embedding.clone())
+        };
 
         // 检查是否有有效的 VLM 配置
         let has_custom_vlm =
@@ -112,7 +236,8 @@ impl AppState {
                 "  VLM endpoint: {}, model: {}",
                 vlm_config.endpoint, vlm_config.model
             );
-            let mut engine = ai::VlmEngine::new(vlm_config.clone());
+            let mut engine =
+                ai::VlmEngine::new(vlm_config.clone()).with_prompts(self.prompts.clone());
             match engine.initialize().await {
                 Ok(_) => {
                     info!(
@@ -132,7 +257,8 @@ impl AppState {
                 "  Embedding endpoint: {:?}, model: {}",
                 embedding_config.endpoint, embedding_config.model
             );
-            let mut embedder = ai::TextEmbedder::with_config(embedding_config);
+            let mut embedder = ai::TextEmbedder::with_config(embedding_config)
+                .with_models_dir(self.db.get_models_dir());
             match embedder.initialize().await {
                 Ok(_) => {
                     info!(
@@ -146,14 +272,15 @@ impl AppState {
                 }
             }
 
-            // 如果 VLM 初始化成功，启动后台任务
+            // 如果 VLM 初始化成功，启动 VLM 分析任务
             if vlm_initialized {
                 if let Err(e) = self.start_vlm_task().await {
                     warn!("Failed to start VLM task: {}", e);
                 }
-                if let Err(e) = self.start_summarizer_task_with_vlm_config(vlm_config).await {
-                    warn!("Failed to start summarizer task: {}", e);
-                }
+            }
+            // 摘要任务使用 `[summarizer]` 模型，不依赖 VLM 引擎
+            if let Err(e) = self.restart_summarizer_task().await {
+                warn!("Failed to start summarizer task: {}", e);
             }
         } else {
             info!("No custom AI config found, skipping auto-initialization");
@@ -167,7 +294,8 @@ impl AppState {
         // 自动检测并初始化 VLM（优先本地 Ollama/vLLM）
         {
             match ai::VlmEngine::auto_detect().await {
-                Ok(mut engine) => {
+                Ok(engine) => {
+                    let mut engine = engine.with_prompts(self.prompts.clone());
                     if let Err(e) = engine.initialize().await {
                         warn!("Failed to initialize VLM engine: {}", e);
                     } else {
@@ -198,13 +326,14 @@ impl AppState {
             }
         }
 
-        // 如果 VLM 初始化成功，启动 VLM 分析任务和摘要任务
+        // 如果 VLM 初始化成功，启动 VLM 分析任务
         if vlm_initialized {
             self.start_vlm_task().await?;
-            // 启动摘要任务（使用默认配置）
-            if let Err(e) = self.start_summarizer_task().await {
-                warn!("Failed to start summarizer task: {}", e);
-            }
+        }
+
+        // 启动摘要任务（使用 `[summarizer]` 模型配置）
+        if let Err(e) = self.start_summarizer_task().await {
+            warn!("Failed to start summarizer task: {}", e);
         }
 
         Ok(())
@@ -231,13 +360,12 @@ impl AppState {
         }
 
         // 创建新任务
-        let session_config = { self.config.read().await.session.clone() };
         let new_task = VlmTask::new(
             self.db.clone(),
             self.vlm.clone(),
             self.embedder.clone(),
             config,
-            session_config,
+            self.live_config.subscribe(),
         );
 
         // 替换并启动
@@ -256,27 +384,25 @@ impl AppState {
         task.start().await
     }
 
-    /// 使用 VLM 配置启动摘要任务
-    pub async fn start_summarizer_task_with_vlm_config(
-        &self,
-        vlm_config: ai::VlmConfig,
-    ) -> anyhow::Result<()> {
-        // 从 VLM 配置创建 Summarizer 配置（复用 endpoint 和 api_key）
-        // 视觉模型也能很好地处理纯文本任务，直接使用用户配置的模型
-        let summarizer_config = ai::SummarizerConfig {
-            endpoint: vlm_config.endpoint,
-            model: vlm_config.model,
-            api_key: vlm_config.api_key,
-            max_tokens: 1024,
-            temperature: 0.3,
-        };
-
-        // 创建新的摘要任务配置
-        let task_config = SummarizerTaskConfig {
-            interval_ms: 15 * 60 * 1000, // 15 分钟
-            llm_config: summarizer_config,
+    /// 摘要任务配置（模型取自 `summarizer` 与 `entities`，未配置的字段回退到 `vlm`）
+    fn summarizer_task_config(app_config: &AppConfig) -> SummarizerTaskConfig {
+        SummarizerTaskConfig {
+            interval_ms: app_config.summary.interval_min as u64 * 60 * 1000,
+            llm_config: app_config.task_model(ModelTask::Summarizer),
+            entities_config: app_config.task_model(ModelTask::Entities),
             enabled: true,
+        }
+    }
+
+    /// 按当前配置重新创建并启动摘要任务
+    pub async fn restart_summarizer_task(&self) -> anyhow::Result<()> {
+        // 间隔取自 summary.interval_min，修改后由配置广播即时调整
+        let task_config = {
+            let app_config = self.config.read().await;
+            Self::summarizer_task_config(&app_config)
         };
+        let interval_min = task_config.interval_ms / 60_000;
+        let model = task_config.llm_config.model.clone();
 
         // 停止旧任务
         {
@@ -285,7 +411,9 @@ impl AppState {
         }
 
         // 创建新任务
-        let new_task = SummarizerTask::new(self.db.clone(), task_config);
+        let new_task = SummarizerTask::new(self.db.clone(), task_config)
+            .with_prompts(self.prompts.clone())
+            .with_config_updates(self.live_config.subscribe());
 
         // 替换并启动
         {
@@ -294,7 +422,10 @@ impl AppState {
             task.start().await?;
         }
 
-        info!("Summarizer task started (interval: 15 min)");
+        info!(
+            "Summarizer task started (interval: {} min, model: {})",
+            interval_min, model
+        );
         Ok(())
     }
 
@@ -304,6 +435,25 @@ impl AppState {
         task.stop();
     }
 
+    /// 对话模型客户端（`[chat]`）；首次使用或配置变化时初始化
+    pub async fn chat_model(&self) -> anyhow::Result<ai::Summarizer> {
+        let chat_config = self.config.read().await.task_model(ModelTask::Chat);
+        let mut guard = self.chat_llm.lock().await;
+        if let Some(chat) = guard.as_ref().filter(|c| *c.config() == chat_config) {
+            return Ok(chat.clone());
+        }
+
+        let mut chat = ai::Summarizer::new(chat_config).with_prompts(self.prompts.clone());
+        chat.initialize().await?;
+        info!(
+            "Chat model initialized: {} ({})",
+            chat.backend_name(),
+            chat.config().model
+        );
+        *guard = Some(chat.clone());
+        Ok(chat)
+    }
+
     /// 检查 VLM 是否可用
     pub async fn is_vlm_ready(&self) -> bool {
         let vlm = self.vlm.read().await;
@@ -48,28 +48,57 @@ fn main() {
             commands::get_traces,
             commands::get_activity_sessions,
             commands::get_activity_session_traces,
+            commands::merge_activity_sessions,
+            commands::split_activity_session,
+            commands::move_traces_to_session,
+            commands::update_activity_session,
+            commands::evaluate_session_routing,
             commands::get_image_path,
             commands::get_image_data,
             commands::search_traces,
+            commands::search_activity_sessions,
             commands::get_settings,
             commands::update_settings,
             commands::get_storage_stats,
             commands::initialize_ai,
             commands::get_ai_status,
+            commands::get_vlm_reuse_stats,
+            commands::get_vlm_tier_stats,
+            commands::get_parse_status_stats,
+            commands::get_summary_parse_status_stats,
+            commands::get_model_parse_status_stats,
+            commands::get_traces_by_parse_status,
+            commands::reprocess_failed_analyses,
             commands::get_ai_config,
+            commands::list_local_embedding_models,
+            commands::benchmark_vector_search,
             commands::update_ai_config,
+            // Prompt commands
+            commands::preview_prompt,
             // Summary commands
             commands::get_summaries,
             commands::get_summary_by_id,
             commands::get_latest_summary,
+            commands::get_summary_children,
             commands::delete_summary,
             commands::trigger_summary,
             // Entity commands
             commands::get_entities,
             commands::get_entity_by_name,
             commands::get_traces_by_entity,
+            commands::get_sessions_by_entity,
             commands::search_entities,
             commands::delete_entity,
+            commands::get_entity_aliases,
+            commands::merge_entities,
+            commands::split_entity,
+            commands::rename_entity,
+            commands::get_entity_merge_suggestions,
+            commands::get_entity_neighbors,
+            commands::get_entity_graph,
+            commands::get_entity_timeline,
+            commands::rebuild_entity_relations,
+            commands::label_entity_relations,
             // Chat commands
             commands::chat_with_memory,
             commands::get_chat_messages,
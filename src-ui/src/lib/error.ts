@@ -0,0 +1,17 @@
+/** 后端命令错误（与 src-tauri/src/error.rs 的序列化格式一致） */
+export interface EngramError {
+  code: string;
+  message: string;
+  details: Record<string, unknown> | null;
+}
+
+export function isEngramError(e: unknown): e is EngramError {
+  return typeof e === "object" && e !== null && "code" in e && "message" in e;
+}
+
+/** 提取可展示的错误消息 */
+export function errorMessage(e: unknown): string {
+  if (isEngramError(e)) return e.message;
+  if (e instanceof Error) return e.message;
+  return String(e);
+}
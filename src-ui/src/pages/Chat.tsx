@@ -1,6 +1,7 @@
 import { Component, createSignal, For, onMount, Show } from "solid-js";
 import { invoke } from "@tauri-apps/api/core";
 import Markdown from "../components/Markdown";
+import { errorMessage } from "../lib/error";
 
 // 类型定义
 interface ChatRequest {
@@ -109,7 +110,7 @@ const Chat: Component = () => {
         ...prev,
         {
           role: "assistant",
-          content: `抱歉，发生了错误: ${e}`,
+          content: `抱歉，发生了错误: ${errorMessage(e)}`,
         },
       ]);
     } finally {
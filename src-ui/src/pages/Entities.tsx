@@ -23,14 +23,80 @@ interface Trace {
   is_key_action?: boolean;
 }
 
+interface ActivitySession {
+  id: number;
+  app_name: string;
+  title: string | null;
+  start_time: number;
+  end_time: number;
+  trace_count: number;
+}
+
+interface EntityAlias {
+  alias: string;
+  entity_id: number;
+  name: string;
+  created_at: number;
+}
+
+interface MergeSuggestion {
+  source: Entity;
+  target: Entity;
+  score: number;
+  reason: string;
+}
+
+interface EntityRelation {
+  source_id: number;
+  target_id: number;
+  weight: number;
+  trace_count: number;
+  session_count: number;
+  first_seen: number;
+  last_seen: number;
+  relation_type: string | null;
+}
+
+interface EntityNeighbor {
+  entity: Entity;
+  relation: EntityRelation;
+}
+
+interface EntityTimelineEntry {
+  session_id: number;
+  app_name: string;
+  title: string | null;
+  start_time: number;
+  end_time: number;
+  mention_count: number;
+  co_entities: string[];
+}
+
+const RELATION_LABELS: Record<string, string> = {
+  works_with: "合作",
+  uses: "使用",
+  part_of: "属于",
+  depends_on: "依赖",
+  discusses: "讨论",
+  related_to: "相关",
+};
+
 const Entities: Component = () => {
   const [entities, setEntities] = createSignal<Entity[]>([]);
   const [loading, setLoading] = createSignal(false);
   const [selectedEntity, setSelectedEntity] = createSignal<Entity | null>(null);
   const [relatedTraces, setRelatedTraces] = createSignal<Trace[]>([]);
+  const [relatedSessions, setRelatedSessions] = createSignal<ActivitySession[]>([]);
   const [entityType, setEntityType] = createSignal<string>("all");
   const [searchQuery, setSearchQuery] = createSignal("");
   const [orderByMentions, setOrderByMentions] = createSignal(true);
+  const [aliases, setAliases] = createSignal<EntityAlias[]>([]);
+  const [suggestions, setSuggestions] = createSignal<MergeSuggestion[]>([]);
+  const [showSuggestions, setShowSuggestions] = createSignal(false);
+  const [neighbors, setNeighbors] = createSignal<EntityNeighbor[]>([]);
+  const [selectedNeighbor, setSelectedNeighbor] = createSignal<Entity | null>(null);
+  const [timeline, setTimeline] = createSignal<EntityTimelineEntry[]>([]);
+  const [labeling, setLabeling] = createSignal(false);
 
   // 获取实体列表
   const fetchEntities = async () => {
@@ -86,6 +152,143 @@ const Entities: Component = () => {
     }
   };
 
+  // 获取实体关联的活动会话
+  const fetchRelatedSessions = async (entityId: number) => {
+    try {
+      const result = await invoke<ActivitySession[]>("get_sessions_by_entity", {
+        entityId,
+        limit: 20,
+      });
+      setRelatedSessions(result);
+    } catch (e) {
+      console.error("Failed to fetch related sessions:", e);
+      setRelatedSessions([]);
+    }
+  };
+
+  // 获取相关实体
+  const fetchNeighbors = async (entityId: number) => {
+    try {
+      const result = await invoke<EntityNeighbor[]>("get_entity_neighbors", {
+        entityId,
+        limit: 15,
+      });
+      setNeighbors(result);
+    } catch (e) {
+      console.error("Failed to fetch neighbors:", e);
+      setNeighbors([]);
+    }
+  };
+
+  // 获取两个实体共同出现的会话
+  const fetchSharedTimeline = async (entity: Entity, neighbor: Entity) => {
+    if (selectedNeighbor()?.id === neighbor.id) {
+      setSelectedNeighbor(null);
+      setTimeline([]);
+      return;
+    }
+    try {
+      const result = await invoke<EntityTimelineEntry[]>("get_entity_timeline", {
+        entityId: entity.id,
+        relatedId: neighbor.id,
+        limit: 50,
+      });
+      setSelectedNeighbor(neighbor);
+      setTimeline(result);
+    } catch (e) {
+      console.error("Failed to fetch entity timeline:", e);
+    }
+  };
+
+  // 用 LLM 标注关系类型
+  const labelRelations = async (entity: Entity) => {
+    setLabeling(true);
+    try {
+      await invoke<number>("label_entity_relations", { entityId: entity.id, limit: 10 });
+      await fetchNeighbors(entity.id);
+    } catch (e) {
+      console.error("Failed to label relations:", e);
+      alert(`标注失败: ${e}`);
+    } finally {
+      setLabeling(false);
+    }
+  };
+
+  // 获取实体别名
+  const fetchAliases = async (entityId: number) => {
+    try {
+      const result = await invoke<EntityAlias[]>("get_entity_aliases", { entityId });
+      setAliases(result);
+    } catch (e) {
+      console.error("Failed to fetch aliases:", e);
+      setAliases([]);
+    }
+  };
+
+  // 获取合并建议
+  const fetchSuggestions = async () => {
+    try {
+      const result = await invoke<MergeSuggestion[]>("get_entity_merge_suggestions", {
+        limit: 30,
+      });
+      setSuggestions(result);
+      setShowSuggestions(true);
+    } catch (e) {
+      console.error("Failed to fetch merge suggestions:", e);
+    }
+  };
+
+  // 合并实体
+  const mergeEntities = async (source: Entity, target: Entity) => {
+    if (!confirm(`将「${source.name}」合并到「${target.name}」？`)) return;
+
+    try {
+      await invoke("merge_entities", { sourceId: source.id, targetId: target.id });
+      setSuggestions(
+        suggestions().filter((s) => s.source.id !== source.id && s.target.id !== source.id)
+      );
+      await fetchEntities();
+      const merged = entities().find((e) => e.id === target.id);
+      if (merged) await selectEntity(merged);
+    } catch (e) {
+      console.error("Failed to merge entities:", e);
+      alert(`合并失败: ${e}`);
+    }
+  };
+
+  // 拆分别名为独立实体
+  const splitAlias = async (entity: Entity, alias: EntityAlias) => {
+    if (!confirm(`将别名「${alias.name}」拆分为独立实体？`)) return;
+
+    try {
+      const created = await invoke<Entity>("split_entity", {
+        entityId: entity.id,
+        alias: alias.alias,
+      });
+      await fetchEntities();
+      await selectEntity(created);
+    } catch (e) {
+      console.error("Failed to split entity:", e);
+      alert(`拆分失败: ${e}`);
+    }
+  };
+
+  // 重命名实体
+  const renameEntity = async (entity: Entity) => {
+    const name = prompt("新名称", entity.name)?.trim();
+    if (!name || name === entity.name) return;
+
+    try {
+      await invoke("rename_entity", { entityId: entity.id, name });
+      const renamed = { ...entity, name };
+      setEntities(entities().map((e) => (e.id === entity.id ? renamed : e)));
+      await selectEntity(renamed);
+    } catch (e) {
+      console.error("Failed to rename entity:", e);
+      alert(`重命名失败: ${e}`);
+    }
+  };
+
   // 删除实体
   const deleteEntity = async (id: number) => {
     if (!confirm("确定要删除这个实体吗？")) return;
@@ -96,6 +299,9 @@ const Entities: Component = () => {
       if (selectedEntity()?.id === id) {
         setSelectedEntity(null);
         setRelatedTraces([]);
+        setRelatedSessions([]);
+        setAliases([]);
+        setNeighbors([]);
       }
     } catch (e) {
       console.error("Failed to delete entity:", e);
@@ -105,7 +311,14 @@ const Entities: Component = () => {
   // 选择实体
   const selectEntity = async (entity: Entity) => {
     setSelectedEntity(entity);
-    await fetchRelatedTraces(entity.id);
+    setSelectedNeighbor(null);
+    setTimeline([]);
+    await Promise.all([
+      fetchRelatedTraces(entity.id),
+      fetchRelatedSessions(entity.id),
+      fetchAliases(entity.id),
+      fetchNeighbors(entity.id),
+    ]);
   };
 
   // 格式化时间
@@ -245,9 +458,49 @@ const Entities: Component = () => {
             <option value="mentions">按提及次数</option>
             <option value="recent">按最近出现</option>
           </select>
+
+          <button
+            onClick={() => (showSuggestions() ? setShowSuggestions(false) : fetchSuggestions())}
+            class="px-3 py-2 bg-background-card border border-gray-700 hover:border-accent rounded-lg text-sm transition-colors"
+          >
+            合并建议
+          </button>
         </div>
       </div>
 
+      {/* 合并建议 */}
+      <Show when={showSuggestions()}>
+        <div class="mb-4 bg-background-card rounded-lg p-3 max-h-48 overflow-y-auto">
+          <Show
+            when={suggestions().length > 0}
+            fallback={<p class="text-sm text-foreground-secondary">暂无合并建议</p>}
+          >
+            <div class="space-y-2">
+              <For each={suggestions()}>
+                {(suggestion) => (
+                  <div class="flex items-center justify-between text-sm">
+                    <span class="truncate">
+                      {suggestion.source.name}
+                      <span class="text-foreground-secondary"> → </span>
+                      {suggestion.target.name}
+                      <span class="ml-2 text-xs text-foreground-secondary">
+                        {(suggestion.score * 100).toFixed(0)}%
+                      </span>
+                    </span>
+                    <button
+                      onClick={() => mergeEntities(suggestion.source, suggestion.target)}
+                      class="px-2 py-1 bg-accent hover:bg-accent/80 rounded text-xs transition-colors"
+                    >
+                      合并
+                    </button>
+                  </div>
+                )}
+              </For>
+            </div>
+          </Show>
+        </div>
+      </Show>
+
       {/* 内容区 */}
       <div class="flex-1 flex gap-4 overflow-hidden">
         {/* 实体列表 */}
@@ -339,12 +592,20 @@ const Entities: Component = () => {
                     </div>
                   </div>
 
-                  <button
-                    onClick={() => deleteEntity(entity().id)}
-                    class="text-error hover:text-error/80 text-sm"
-                  >
-                    删除
-                  </button>
+                  <div class="flex items-center gap-3">
+                    <button
+                      onClick={() => renameEntity(entity())}
+                      class="text-foreground-secondary hover:text-foreground text-sm"
+                    >
+                      重命名
+                    </button>
+                    <button
+                      onClick={() => deleteEntity(entity().id)}
+                      class="text-error hover:text-error/80 text-sm"
+                    >
+                      删除
+                    </button>
+                  </div>
                 </div>
 
                 {/* 统计信息 */}
@@ -364,6 +625,126 @@ const Entities: Component = () => {
                   <p class="text-sm">{formatTime(entity().last_seen)}</p>
                 </div>
 
+                {/* 别名 */}
+                <Show when={aliases().length > 1}>
+                  <div>
+                    <h4 class="text-sm font-semibold text-foreground-secondary mb-3">
+                      别名 ({aliases().length})
+                    </h4>
+                    <div class="flex flex-wrap gap-2">
+                      <For each={aliases()}>
+                        {(alias) => (
+                          <span class="flex items-center gap-1 px-2 py-1 bg-background/50 rounded text-xs">
+                            {alias.name}
+                            <Show when={alias.name.toLowerCase() !== entity().name.toLowerCase()}>
+                              <button
+                                onClick={() => splitAlias(entity(), alias)}
+                                class="text-foreground-secondary hover:text-accent"
+                                title="拆分为独立实体"
+                              >
+                                ✂
+                              </button>
+                            </Show>
+                          </span>
+                        )}
+                      </For>
+                    </div>
+                  </div>
+                </Show>
+
+                {/* 相关实体 */}
+                <Show when={neighbors().length > 0}>
+                  <div>
+                    <div class="flex items-center justify-between mb-3">
+                      <h4 class="text-sm font-semibold text-foreground-secondary">
+                        相关实体 ({neighbors().length})
+                      </h4>
+                      <button
+                        onClick={() => labelRelations(entity())}
+                        disabled={labeling()}
+                        class="text-xs text-foreground-secondary hover:text-accent disabled:opacity-50"
+                      >
+                        {labeling() ? "标注中..." : "标注关系"}
+                      </button>
+                    </div>
+                    <div class="flex flex-wrap gap-2">
+                      <For each={neighbors()}>
+                        {(neighbor) => (
+                          <button
+                            onClick={() => fetchSharedTimeline(entity(), neighbor.entity)}
+                            class={`flex items-center gap-1 px-2 py-1 rounded text-xs border ${getEntityTypeColor(
+                              neighbor.entity.type
+                            )} ${selectedNeighbor()?.id === neighbor.entity.id ? "ring-1 ring-accent" : ""}`}
+                            title={`共同出现 ${neighbor.relation.trace_count} 条记录、${neighbor.relation.session_count} 个会话`}
+                          >
+                            {getEntityTypeIcon(neighbor.entity.type)} {neighbor.entity.name}
+                            <Show when={neighbor.relation.relation_type}>
+                              {(type) => (
+                                <span class="text-foreground-secondary">
+                                  · {RELATION_LABELS[type()] ?? type()}
+                                </span>
+                              )}
+                            </Show>
+                          </button>
+                        )}
+                      </For>
+                    </div>
+
+                    {/* 共同出现的会话 */}
+                    <Show when={selectedNeighbor()}>
+                      {(neighbor) => (
+                        <div class="mt-3 space-y-2 max-h-48 overflow-y-auto">
+                          <p class="text-xs text-foreground-secondary">
+                            与「{neighbor().name}」共同出现的会话 ({timeline().length})
+                          </p>
+                          <For each={timeline()}>
+                            {(item) => (
+                              <div class="bg-background/50 rounded-lg p-2 text-xs">
+                                <div class="flex items-center justify-between">
+                                  <span class="truncate max-w-[200px]">
+                                    {item.title || item.app_name}
+                                  </span>
+                                  <span class="text-foreground-secondary">
+                                    {formatTime(item.start_time)}
+                                  </span>
+                                </div>
+                              </div>
+                            )}
+                          </For>
+                        </div>
+                      )}
+                    </Show>
+                  </div>
+                </Show>
+
+                {/* 关联的活动会话 */}
+                <Show when={relatedSessions().length > 0}>
+                  <div>
+                    <h4 class="text-sm font-semibold text-foreground-secondary mb-3">
+                      相关会话 ({relatedSessions().length})
+                    </h4>
+                    <div class="space-y-2 max-h-48 overflow-y-auto">
+                      <For each={relatedSessions()}>
+                        {(session) => (
+                          <div class="bg-background/50 rounded-lg p-3">
+                            <div class="flex items-center justify-between mb-1">
+                              <span class="text-sm font-medium truncate max-w-[200px]">
+                                {session.title || session.app_name}
+                              </span>
+                              <span class="text-xs text-foreground-secondary">
+                                {formatRelativeTime(session.end_time)}
+                              </span>
+                            </div>
+                            <p class="text-xs text-foreground-secondary truncate">
+                              {session.app_name} · {session.trace_count} 条记录
+                            </p>
+                          </div>
+                        )}
+                      </For>
+                    </div>
+                  </div>
+                </Show>
+
                 {/* 关联的痕迹 */}
                 <div>
                   <h4 class="text-sm font-semibold text-foreground-secondary mb-3">
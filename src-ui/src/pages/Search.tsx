@@ -24,6 +24,16 @@ interface SearchResult {
   highlights: { text: string; start: number; end: number }[];
 }
 
+interface SessionHit {
+  id: number;
+  app_name: string;
+  title?: string | null;
+  description?: string | null;
+  start_time: number;
+  end_time: number;
+  trace_count: number;
+}
+
 interface ImageData {
   mime: string;
   bytes: number[];
@@ -49,6 +59,7 @@ const TIME_PRESETS = [
 const Search: Component = () => {
   const [query, setQuery] = createSignal("");
   const [results, setResults] = createSignal<SearchResult[]>([]);
+  const [sessionHits, setSessionHits] = createSignal<SessionHit[]>([]);
   const [loading, setLoading] = createSignal(false);
   const [searched, setSearched] = createSignal(false);
   const [searchMode, setSearchMode] = createSignal<"keyword" | "semantic">("keyword");
@@ -141,15 +152,25 @@ const Search: Component = () => {
 
     try {
       const { start, end } = getTimeRange();
-      const data = await invoke<SearchResult[]>("search_traces", {
-        query: q,
-        mode: searchMode(),
-        startTime: start,
-        endTime: end,
-        appFilter: appFilter().length > 0 ? appFilter() : null,
-        limit: 50,
-      });
+      const [data, sessions] = await Promise.all([
+        invoke<SearchResult[]>("search_traces", {
+          query: q,
+          mode: searchMode(),
+          startTime: start,
+          endTime: end,
+          appFilter: appFilter().length > 0 ? appFilter() : null,
+          limit: 50,
+        }),
+        searchMode() === "keyword"
+          ? invoke<SessionHit[]>("search_activity_sessions", { query: q, limit: 5 })
+          : Promise.resolve([] as SessionHit[]),
+      ]);
       setResults(data);
+      setSessionHits(
+        sessions.filter(
+          (s) => (start == null || s.end_time >= start) && (end == null || s.start_time <= end)
+        )
+      );
 
       // 收集可用的应用列表
       const apps = new Set<string>();
@@ -160,6 +181,7 @@ const Search: Component = () => {
     } catch (e) {
       console.error("Search failed:", e);
       setResults([]);
+      setSessionHits([]);
     } finally {
       setLoading(false);
     }
@@ -489,7 +511,10 @@ const Search: Component = () => {
           <div class="flex flex-col items-center justify-center h-full text-foreground-secondary">
             <p class="text-4xl mb-4">🔍</p>
             <p>输入关键词搜索你的屏幕记忆</p>
-            <p class="text-sm mt-2">支持搜索 OCR 提取的文本和窗口标题</p>
+            <p class="text-sm mt-2">支持搜索 OCR 文本、窗口标题、截图摘要、行为描述与实体</p>
+            <p class="text-xs mt-1">
+              可用 summary: / action: / entity: / ocr: / window: 限定字段，如 summary:数据库
+            </p>
             <p class="text-xs mt-4 text-foreground-secondary/60">
               按 <kbd class="px-1.5 py-0.5 bg-background-card rounded text-xs">Ctrl</kbd>
               {" + "}
@@ -499,7 +524,7 @@ const Search: Component = () => {
           </div>
         </Show>
 
-        <Show when={!loading() && searched() && results().length === 0}>
+        <Show when={!loading() && searched() && results().length === 0 && sessionHits().length === 0}>
           <div class="flex flex-col items-center justify-center h-full text-foreground-secondary">
             <p class="text-4xl mb-4">😔</p>
             <p>没有找到匹配的结果</p>
@@ -507,6 +532,33 @@ const Search: Component = () => {
           </div>
         </Show>
 
+        <Show when={!loading() && sessionHits().length > 0}>
+          <div class="mb-6">
+            <p class="text-sm text-foreground-secondary mb-2">相关会话</p>
+            <div class="space-y-2">
+              <For each={sessionHits()}>
+                {(session) => (
+                  <div class="bg-background-card rounded-lg px-4 py-3">
+                    <div class="flex items-center justify-between gap-4">
+                      <span class="font-medium truncate">{session.title?.trim() || session.app_name}</span>
+                      <span class="text-xs text-foreground-secondary font-mono flex-shrink-0">
+                        {format(new Date(session.start_time), "yyyy-MM-dd HH:mm", { locale: zhCN })}
+                        {" - "}
+                        {format(new Date(session.end_time), "HH:mm")}
+                        {" · "}
+                        {session.trace_count} 条
+                      </span>
+                    </div>
+                    <Show when={session.description}>
+                      <p class="text-sm text-foreground-secondary mt-1 line-clamp-2">{session.description}</p>
+                    </Show>
+                  </div>
+                )}
+              </For>
+            </div>
+          </div>
+        </Show>
+
         <Show when={!loading() && results().length > 0}>
           <div class="space-y-4">
             <div class="flex items-center justify-between">
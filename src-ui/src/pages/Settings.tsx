@@ -1,18 +1,44 @@
-import { Component, createSignal, onMount, Show } from "solid-js";
+import { Component, createSignal, For, onMount, Show } from "solid-js";
 import { invoke } from "@tauri-apps/api/core";
+import { errorMessage } from "../lib/error";
 
 // 类型定义
 interface Settings {
   capture_interval_ms: number;
   idle_threshold_ms: number;
   similarity_threshold: number;
+  region_detection: boolean;
+  min_change_score: number;
   hot_data_days: number;
   warm_data_days: number;
+  retention_enabled: boolean;
   summary_interval_min: number;
   session_active_window_ms: number;
   session_max_active_sessions: number;
   session_similarity_threshold: number;
   session_gap_threshold_ms: number;
+  session_adaptive_threshold: boolean;
+  locale: "zh" | "en";
+  language: "zh" | "en";
+}
+
+interface RoutingEvaluation {
+  threshold: number;
+  total: number;
+  correct: number;
+  accuracy: number;
+  false_joins: number;
+  missed_joins: number;
+  wrong_sessions: number;
+  corrected_total: number;
+  corrected_correct: number;
+}
+
+interface RoutingReport {
+  start_time: number;
+  end_time: number;
+  current: RoutingEvaluation;
+  suggested: RoutingEvaluation | null;
 }
 
 interface StorageStats {
@@ -29,12 +55,27 @@ interface VlmConfig {
   api_key: string | null;
   max_tokens: number;
   temperature: number;
+  image: ImagePrepConfig;
+  structured_output: "auto" | "json_schema" | "json_object" | "off";
+  max_reasks: number;
+}
+
+interface ImagePrepConfig {
+  max_edge: number;
+  focus_crop: boolean;
+  max_tiles: number;
+  tile_size: number;
+  encoding: "auto" | "jpeg" | "png" | "webp";
+  jpeg_quality: number;
+  max_image_bytes: number;
 }
 
 interface EmbeddingConfig {
   endpoint: string | null;
   model: string;
   api_key: string | null;
+  local_model: string;
+  offline: boolean;
 }
 
 interface VlmTaskConfig {
@@ -42,14 +83,89 @@ interface VlmTaskConfig {
   batch_size: number;
   concurrency: number;
   enabled: boolean;
+  reuse_enabled: boolean;
+  reuse_max_distance: number;
+  reuse_window_ms: number;
+  escalation_tiers: VlmTierConfig[];
+  escalation_confidence: number;
+  escalate_key_actions: boolean;
+  escalate_parse_failures: boolean;
+}
+
+interface VlmTierConfig extends VlmConfig {
+  name: string;
+}
+
+interface VlmTierStats {
+  tier: number;
+  tier_name: string;
+  model: string;
+  calls: number;
+  accepted: number;
+  escalated: number;
+  errors: number;
+  parse_failures: number;
+  avg_latency_ms: number;
+  avg_confidence: number;
+}
+
+interface ParseStatusStats {
+  ok: number;
+  repaired: number;
+  reasked: number;
+  failed: number;
+}
+
+interface VlmReuseStats {
+  analyzed: number;
+  reused: number;
+  hit_rate: number;
+  avg_distance: number;
+}
+
+interface LocalEmbeddingModel {
+  name: string;
+  model_code: string;
+  dim: number;
+  description: string;
+  installed: boolean;
+}
+
+interface VectorBenchmark {
+  quantization: string;
+  partition_days: number;
+  partitions: number;
+  vectors: number;
+  queries: number;
+  limit: number;
+  ann_avg_ms: number;
+  ann_p95_ms: number;
+  exact_avg_ms: number;
+  exact_p95_ms: number;
+  recall: number;
 }
 
 interface AiConfig {
   vlm: VlmConfig;
   embedding: EmbeddingConfig;
   vlm_task: VlmTaskConfig;
+  summarizer: TaskModelConfig;
+  chat: TaskModelConfig;
+  entities: TaskModelConfig;
+}
+
+// 文本任务模型，留空的字段回退到 VLM 配置
+interface TaskModelConfig {
+  endpoint?: string | null;
+  model?: string | null;
+  api_key?: string | null;
+  max_tokens?: number | null;
+  temperature?: number | null;
+  context_tokens?: number | null;
 }
 
+type ModelTask = "summarizer" | "chat" | "entities";
+
 interface AiStatus {
   vlm_ready: boolean;
   embedder_ready: boolean;
@@ -62,24 +178,44 @@ const Settings: Component = () => {
   const [stats, setStats] = createSignal<StorageStats | null>(null);
   const [aiConfig, setAiConfig] = createSignal<AiConfig | null>(null);
   const [aiStatus, setAiStatus] = createSignal<AiStatus | null>(null);
+  const [localModels, setLocalModels] = createSignal<LocalEmbeddingModel[]>([]);
+  const [reuseStats, setReuseStats] = createSignal<VlmReuseStats | null>(null);
+  const [tierStats, setTierStats] = createSignal<VlmTierStats[]>([]);
+  const [parseStats, setParseStats] = createSignal<ParseStatusStats | null>(null);
+  const [summaryParseStats, setSummaryParseStats] = createSignal<ParseStatusStats | null>(null);
+  const [reprocessing, setReprocessing] = createSignal(false);
   const [saving, setSaving] = createSignal(false);
   const [savingAi, setSavingAi] = createSignal(false);
   const [message, setMessage] = createSignal<string | null>(null);
+  const [routingReport, setRoutingReport] = createSignal<RoutingReport | null>(null);
+  const [evaluatingRouting, setEvaluatingRouting] = createSignal(false);
+  const [vectorBenchmark, setVectorBenchmark] = createSignal<VectorBenchmark | null>(null);
+  const [benchmarking, setBenchmarking] = createSignal(false);
   const [activeTab, setActiveTab] = createSignal<"capture" | "ai">("capture");
 
   // 加载数据
   onMount(async () => {
     try {
-      const [s, st, ai, status] = await Promise.all([
+      const [s, st, ai, status, models, reuse, tiers, parse, summaryParse] = await Promise.all([
         invoke<Settings>("get_settings"),
         invoke<StorageStats>("get_storage_stats"),
         invoke<AiConfig>("get_ai_config"),
         invoke<AiStatus>("get_ai_status"),
+        invoke<LocalEmbeddingModel[]>("list_local_embedding_models"),
+        invoke<VlmReuseStats>("get_vlm_reuse_stats", {}),
+        invoke<VlmTierStats[]>("get_vlm_tier_stats", {}),
+        invoke<ParseStatusStats>("get_parse_status_stats", {}),
+        invoke<ParseStatusStats>("get_summary_parse_status_stats", {}),
       ]);
       setSettings(s);
       setStats(st);
       setAiConfig(ai);
       setAiStatus(status);
+      setLocalModels(models);
+      setReuseStats(reuse);
+      setTierStats(tiers);
+      setParseStats(parse);
+      setSummaryParseStats(summaryParse);
     } catch (e) {
       console.error("Failed to load settings:", e);
     }
@@ -99,12 +235,59 @@ const Settings: Component = () => {
       setTimeout(() => setMessage(null), 3000);
     } catch (e) {
       console.error("Failed to save settings:", e);
-      setMessage("保存失败: " + e);
+      setMessage("保存失败: " + errorMessage(e));
     } finally {
       setSaving(false);
     }
   };
 
+  // 评估 Session 路由准确率（最近 14 天）
+  const evaluateRouting = async () => {
+    setEvaluatingRouting(true);
+    setMessage(null);
+    try {
+      setRoutingReport(await invoke<RoutingReport>("evaluate_session_routing", {}));
+    } catch (e) {
+      console.error("Failed to evaluate session routing:", e);
+      setMessage("评估失败: " + errorMessage(e));
+    } finally {
+      setEvaluatingRouting(false);
+    }
+  };
+
+  const benchmarkVectorSearch = async () => {
+    setBenchmarking(true);
+    setMessage(null);
+    try {
+      setVectorBenchmark(await invoke<VectorBenchmark>("benchmark_vector_search", {}));
+    } catch (e) {
+      console.error("Failed to benchmark vector search:", e);
+      setMessage("基准测试失败: " + errorMessage(e));
+    } finally {
+      setBenchmarking(false);
+    }
+  };
+
+  // 把解析失败的截图放回 VLM 分析队列
+  const reprocessFailedAnalyses = async () => {
+    setReprocessing(true);
+    setMessage(null);
+    try {
+      const count = await invoke<number>("reprocess_failed_analyses", {});
+      setParseStats(await invoke<ParseStatusStats>("get_parse_status_stats", {}));
+      setMessage(`已将 ${count} 张截图放回分析队列`);
+      setTimeout(() => setMessage(null), 3000);
+    } catch (e) {
+      console.error("Failed to reprocess failed analyses:", e);
+      setMessage("重新分析失败: " + errorMessage(e));
+    } finally {
+      setReprocessing(false);
+    }
+  };
+
+  const formatAccuracy = (e: RoutingEvaluation) =>
+    `${(e.accuracy * 100).toFixed(1)}%（${e.correct}/${e.total}）`;
+
   // 保存 AI 配置
   const saveAiConfig = async () => {
     const config = aiConfig();
@@ -122,7 +305,7 @@ const Settings: Component = () => {
       setTimeout(() => setMessage(null), 3000);
     } catch (e) {
       console.error("Failed to save AI config:", e);
-      setMessage("保存失败: " + e);
+      setMessage("保存失败: " + errorMessage(e));
     } finally {
       setSavingAi(false);
     }
@@ -152,6 +335,14 @@ const Settings: Component = () => {
     }
   };
 
+  // 更新截图预处理配置
+  const updateImagePrepConfig = <K extends keyof ImagePrepConfig>(key: K, value: ImagePrepConfig[K]) => {
+    const config = aiConfig();
+    if (config) {
+      setAiConfig({ ...config, vlm: { ...config.vlm, image: { ...config.vlm.image, [key]: value } } });
+    }
+  };
+
   // 更新 Embedding 配置
   const updateEmbeddingConfig = <K extends keyof EmbeddingConfig>(key: K, value: EmbeddingConfig[K]) => {
     const config = aiConfig();
@@ -160,6 +351,24 @@ const Settings: Component = () => {
     }
   };
 
+  // 更新文本任务模型配置（空值表示回退到 VLM 配置）
+  const updateTaskModel = <K extends keyof TaskModelConfig>(
+    task: ModelTask,
+    key: K,
+    value: TaskModelConfig[K]
+  ) => {
+    const config = aiConfig();
+    if (config) {
+      setAiConfig({ ...config, [task]: { ...config[task], [key]: value } });
+    }
+  };
+
+  const modelTasks: { task: ModelTask; label: string; description: string }[] = [
+    { task: "summarizer", label: "摘要", description: "周期摘要与会话回顾" },
+    { task: "chat", label: "对话", description: "与记忆对话" },
+    { task: "entities", label: "实体关系", description: "实体关系类型标注" },
+  ];
+
   // 更新 VLM 任务配置
   const updateVlmTaskConfig = <K extends keyof VlmTaskConfig>(key: K, value: VlmTaskConfig[K]) => {
     const config = aiConfig();
@@ -178,7 +387,7 @@ const Settings: Component = () => {
   ];
 
   const embeddingPresets = [
-    { name: "本地 (MiniLM)", endpoint: "", model: "all-MiniLM-L6-v2", needsKey: false },
+    { name: "本地 (多语言)", endpoint: "", model: "", needsKey: false },
     { name: "Ollama", endpoint: "http://127.0.0.1:11434/v1", model: "nomic-embed-text", needsKey: false },
     { name: "OpenAI", endpoint: "https://api.openai.com/v1", model: "text-embedding-3-small", needsKey: true },
   ];
@@ -206,7 +415,7 @@ const Settings: Component = () => {
         embedding: {
           ...config.embedding,
           endpoint: preset.endpoint || null,
-          model: preset.model,
+          model: preset.model || config.embedding.model,
           api_key: preset.needsKey ? config.embedding.api_key : null,
         }
       });
@@ -302,6 +511,36 @@ const Settings: Component = () => {
                   </p>
                 </div>
 
+                <label class="flex items-center gap-2 text-sm">
+                  <input
+                    type="checkbox"
+                    checked={settings()!.region_detection}
+                    onChange={(e) => updateSetting("region_detection", e.currentTarget.checked)}
+                  />
+                  分块变化检测（焦点窗口内的变化权重更高）
+                </label>
+
+                <Show when={settings()!.region_detection}>
+                  <div>
+                    <label class="block text-sm text-foreground-secondary mb-1">
+                      变化显著度阈值
+                    </label>
+                    <input
+                      type="number"
+                      value={settings()!.min_change_score}
+                      onInput={(e) =>
+                        updateSetting("min_change_score", Math.max(0, parseFloat(e.currentTarget.value) || 0))
+                      }
+                      min={0}
+                      step={0.5}
+                      class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                    />
+                    <p class="text-xs text-foreground-secondary mt-1">
+                      焦点窗口内每个变化块计 1，窗口外按背景权重折算，低于此值的帧会被跳过
+                    </p>
+                  </div>
+                </Show>
+
                 <div>
                   <label class="block text-sm text-foreground-secondary mb-1">
                     相似度阈值 (汉明距离)
@@ -317,7 +556,7 @@ const Settings: Component = () => {
                     class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
                   />
                   <p class="text-xs text-foreground-secondary mt-1">
-                    越小越严格，相似帧会被跳过
+                    关闭分块检测时使用，越小越严格，相似帧会被跳过
                   </p>
                 </div>
 
@@ -399,6 +638,88 @@ const Settings: Component = () => {
                     embedding 相似度越高越严格（更容易新开线程）
                   </p>
                 </div>
+
+                <div class="p-3 bg-background rounded space-y-2">
+                  <div class="flex items-center justify-between">
+                    <div>
+                      <p class="font-medium text-sm">根据纠正自动调整阈值</p>
+                      <p class="text-xs text-foreground-secondary">
+                        合并、拆分或移动会话后，按最近的路由回放结果微调相似度阈值
+                      </p>
+                    </div>
+                    <button
+                      onClick={() =>
+                        updateSetting("session_adaptive_threshold", !settings()!.session_adaptive_threshold)
+                      }
+                      class={`relative w-12 h-6 rounded-full transition-colors ${
+                        settings()!.session_adaptive_threshold ? "bg-accent" : "bg-gray-600"
+                      }`}
+                    >
+                      <span
+                        class={`absolute top-1 left-1 w-4 h-4 bg-white rounded-full transition-transform ${
+                          settings()!.session_adaptive_threshold ? "translate-x-6" : "translate-x-0"
+                        }`}
+                      />
+                    </button>
+                  </div>
+                  <div class="flex items-center gap-3 text-sm">
+                    <button
+                      onClick={evaluateRouting}
+                      disabled={evaluatingRouting()}
+                      class="px-3 py-1 bg-background-card hover:bg-background-secondary rounded transition-colors disabled:opacity-50"
+                    >
+                      {evaluatingRouting() ? "评估中..." : "评估路由准确率"}
+                    </button>
+                    <Show when={routingReport()}>
+                      <span>
+                        当前阈值 {routingReport()!.current.threshold.toFixed(2)}：
+                        {formatAccuracy(routingReport()!.current)}
+                        <Show when={routingReport()!.suggested}>
+                          ，建议 {routingReport()!.suggested!.threshold.toFixed(2)}：
+                          {formatAccuracy(routingReport()!.suggested!)}
+                        </Show>
+                      </span>
+                    </Show>
+                  </div>
+                </div>
+
+                <div>
+                  <label class="block text-sm text-foreground-secondary mb-1">
+                    界面语言
+                  </label>
+                  <select
+                    value={settings()!.locale}
+                    onChange={(e) =>
+                      updateSetting("locale", e.currentTarget.value as Settings["locale"])
+                    }
+                    class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                  >
+                    <option value="zh">简体中文</option>
+                    <option value="en">English</option>
+                  </select>
+                  <p class="text-xs text-foreground-secondary mt-1">
+                    用于错误提示等后端返回的消息
+                  </p>
+                </div>
+
+                <div>
+                  <label class="block text-sm text-foreground-secondary mb-1">
+                    模型输出语言
+                  </label>
+                  <select
+                    value={settings()!.language}
+                    onChange={(e) =>
+                      updateSetting("language", e.currentTarget.value as Settings["language"])
+                    }
+                    class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                  >
+                    <option value="zh">简体中文</option>
+                    <option value="en">English</option>
+                  </select>
+                  <p class="text-xs text-foreground-secondary mt-1">
+                    截图分析、摘要与对话使用的 Prompt 语言；可在配置目录 prompts/&lt;语言&gt;/ 下放置同名模板覆盖
+                  </p>
+                </div>
               </div>
             </Show>
           </section>
@@ -412,6 +733,25 @@ const Settings: Component = () => {
 
             <Show when={settings()}>
               <div class="space-y-4">
+                <label class="flex items-center gap-2 text-sm">
+                  <input
+                    type="checkbox"
+                    checked={settings()!.retention_enabled}
+                    onChange={(e) => {
+                      const enabled = e.currentTarget.checked;
+                      if (
+                        enabled &&
+                        !confirm("开启后将按保留天数自动删除过期的截图和记录，且无法恢复。确定开启吗？")
+                      ) {
+                        e.currentTarget.checked = false;
+                        return;
+                      }
+                      updateSetting("retention_enabled", enabled);
+                    }}
+                  />
+                  自动清理过期数据
+                </label>
+
                 <div>
                   <label class="block text-sm text-foreground-secondary mb-1">
                     热数据保留天数
@@ -523,6 +863,50 @@ const Settings: Component = () => {
                 </div>
               </div>
             </Show>
+            <Show when={reuseStats() && reuseStats()!.analyzed > 0}>
+              <p class="text-sm text-foreground-secondary mt-3">
+                近 24 小时复用近重复截图的分析结果 {reuseStats()!.reused}/{reuseStats()!.analyzed} 次（
+                {(reuseStats()!.hit_rate * 100).toFixed(1)}%，平均汉明距离{" "}
+                {reuseStats()!.avg_distance.toFixed(1)}）
+              </p>
+            </Show>
+            <Show when={tierStats().length > 1}>
+              <div class="text-sm text-foreground-secondary mt-3 space-y-1">
+                <p>近 24 小时各层级模型调用：</p>
+                <For each={tierStats()}>
+                  {(t) => (
+                    <p class="text-xs">
+                      {t.tier_name}（{t.model}）：调用 {t.calls} 次，采用 {t.accepted} 次，升级 {t.escalated} 次，
+                      失败 {t.errors + t.parse_failures} 次，平均 {Math.round(t.avg_latency_ms)} ms，平均置信度{" "}
+                      {t.avg_confidence.toFixed(2)}
+                    </p>
+                  )}
+                </For>
+              </div>
+            </Show>
+            <Show when={parseStats()}>
+              <div class="flex items-center justify-between text-sm text-foreground-secondary mt-3">
+                <p>
+                  近 24 小时分析结果解析：直接成功 {parseStats()!.ok}，修复 {parseStats()!.repaired}，重新请求{" "}
+                  {parseStats()!.reasked}，失败 {parseStats()!.failed}
+                </p>
+                <Show when={parseStats()!.failed > 0}>
+                  <button
+                    onClick={reprocessFailedAnalyses}
+                    disabled={reprocessing()}
+                    class="px-3 py-1 text-xs bg-background border border-gray-600 rounded hover:border-accent disabled:opacity-50"
+                  >
+                    {reprocessing() ? "处理中..." : "重新分析失败项"}
+                  </button>
+                </Show>
+              </div>
+            </Show>
+            <Show when={summaryParseStats()}>
+              <p class="text-sm text-foreground-secondary mt-2">
+                近 7 天摘要解析：直接成功 {summaryParseStats()!.ok}，修复 {summaryParseStats()!.repaired}，重新请求{" "}
+                {summaryParseStats()!.reasked}，失败 {summaryParseStats()!.failed}（失败的周期不生成摘要）
+              </p>
+            </Show>
           </section>
 
           {/* VLM 配置 */}
@@ -609,6 +993,184 @@ const Settings: Component = () => {
                     />
                   </div>
                 </div>
+
+                <div class="grid grid-cols-2 gap-4">
+                  <div>
+                    <label class="block text-sm text-foreground-secondary mb-1">结构化输出</label>
+                    <select
+                      value={aiConfig()!.vlm.structured_output}
+                      onChange={(e) =>
+                        updateVlmConfig("structured_output", e.currentTarget.value as VlmConfig["structured_output"])
+                      }
+                      class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                    >
+                      <option value="auto">自动</option>
+                      <option value="json_schema">JSON Schema</option>
+                      <option value="json_object">JSON 对象</option>
+                      <option value="off">不约束</option>
+                    </select>
+                  </div>
+                  <div>
+                    <label class="block text-sm text-foreground-secondary mb-1">解析失败时重新请求次数</label>
+                    <input
+                      type="number"
+                      value={aiConfig()!.vlm.max_reasks}
+                      onInput={(e) =>
+                        updateVlmConfig("max_reasks", Math.min(3, Math.max(0, parseInt(e.currentTarget.value) || 0)))
+                      }
+                      min={0}
+                      max={3}
+                      class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                    />
+                  </div>
+                </div>
+
+                <div class="grid grid-cols-3 gap-4">
+                  <div>
+                    <label class="block text-sm text-foreground-secondary mb-1">图像长边上限</label>
+                    <input
+                      type="number"
+                      value={aiConfig()!.vlm.image.max_edge}
+                      onInput={(e) => updateImagePrepConfig("max_edge", Math.max(256, parseInt(e.currentTarget.value) || 1536))}
+                      min={256}
+                      step={128}
+                      class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                    />
+                  </div>
+                  <div>
+                    <label class="block text-sm text-foreground-secondary mb-1">高清文字分块数</label>
+                    <input
+                      type="number"
+                      value={aiConfig()!.vlm.image.max_tiles}
+                      onInput={(e) => updateImagePrepConfig("max_tiles", Math.max(0, parseInt(e.currentTarget.value) || 0))}
+                      min={0}
+                      max={8}
+                      class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                    />
+                  </div>
+                  <div>
+                    <label class="block text-sm text-foreground-secondary mb-1">图像格式</label>
+                    <select
+                      value={aiConfig()!.vlm.image.encoding}
+                      onChange={(e) =>
+                        updateImagePrepConfig("encoding", e.currentTarget.value as ImagePrepConfig["encoding"])
+                      }
+                      class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                    >
+                      <option value="auto">自动</option>
+                      <option value="jpeg">JPEG</option>
+                      <option value="png">PNG</option>
+                      <option value="webp">WebP</option>
+                    </select>
+                  </div>
+                </div>
+
+                <label class="flex items-center gap-2 text-sm">
+                  <input
+                    type="checkbox"
+                    checked={aiConfig()!.vlm.image.focus_crop}
+                    onChange={(e) => updateImagePrepConfig("focus_crop", e.currentTarget.checked)}
+                  />
+                  按焦点窗口裁剪截图
+                </label>
+                <p class="text-xs text-foreground-secondary">
+                  截图等比缩放后作为概览图发送；缩小明显时再附带原分辨率的文字密集区域，便于识别小字。
+                  自动格式按服务端选择（Ollama 不支持 WebP 时使用 PNG）。
+                </p>
+              </div>
+            </Show>
+          </section>
+
+          {/* 文本任务模型 */}
+          <section class="bg-background-card rounded-lg p-6">
+            <h3 class="text-lg font-semibold mb-4 flex items-center">
+              <span class="mr-2">📝</span>
+              文本任务模型
+            </h3>
+            <p class="text-xs text-foreground-secondary mb-4">
+              摘要、对话与实体关系标注可以各自使用纯文本模型；留空的项沿用上方 VLM 配置
+              （更换端点后不会沿用 VLM 的 API 密钥）。
+            </p>
+
+            <Show when={aiConfig()}>
+              <div class="space-y-4">
+                <For each={modelTasks}>
+                  {({ task, label, description }) => (
+                    <div class="p-3 bg-background rounded space-y-2">
+                      <p class="font-medium">
+                        {label}
+                        <span class="ml-2 text-xs text-foreground-secondary">{description}</span>
+                      </p>
+                      <div class="grid grid-cols-3 gap-2">
+                        <input
+                          type="text"
+                          value={aiConfig()![task].endpoint || ""}
+                          onInput={(e) => updateTaskModel(task, "endpoint", e.currentTarget.value || null)}
+                          placeholder={aiConfig()!.vlm.endpoint}
+                          class="px-3 py-2 bg-background-card border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                        />
+                        <input
+                          type="text"
+                          value={aiConfig()![task].model || ""}
+                          onInput={(e) => updateTaskModel(task, "model", e.currentTarget.value || null)}
+                          placeholder={aiConfig()!.vlm.model}
+                          class="px-3 py-2 bg-background-card border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                        />
+                        <input
+                          type="password"
+                          value={aiConfig()![task].api_key || ""}
+                          onInput={(e) => updateTaskModel(task, "api_key", e.currentTarget.value || null)}
+                          placeholder="API 密钥（可选）"
+                          class="px-3 py-2 bg-background-card border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                        />
+                      </div>
+                      <div class="grid grid-cols-3 gap-2">
+                        <label class="text-xs text-foreground-secondary">
+                          最大 Tokens
+                          <input
+                            type="number"
+                            value={aiConfig()![task].max_tokens ?? ""}
+                            onInput={(e) =>
+                              updateTaskModel(task, "max_tokens", parseInt(e.currentTarget.value) || null)
+                            }
+                            min={64}
+                            placeholder="默认"
+                            class="w-full mt-1 px-2 py-1 bg-background-card border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                          />
+                        </label>
+                        <label class="text-xs text-foreground-secondary">
+                          温度
+                          <input
+                            type="number"
+                            value={aiConfig()![task].temperature ?? ""}
+                            onInput={(e) => {
+                              const value = parseFloat(e.currentTarget.value);
+                              updateTaskModel(task, "temperature", Number.isNaN(value) ? null : value);
+                            }}
+                            min={0}
+                            max={2}
+                            step={0.1}
+                            placeholder="默认"
+                            class="w-full mt-1 px-2 py-1 bg-background-card border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                          />
+                        </label>
+                        <label class="text-xs text-foreground-secondary">
+                          上下文 Tokens
+                          <input
+                            type="number"
+                            value={aiConfig()![task].context_tokens ?? ""}
+                            onInput={(e) =>
+                              updateTaskModel(task, "context_tokens", parseInt(e.currentTarget.value) || null)
+                            }
+                            min={256}
+                            placeholder="默认"
+                            class="w-full mt-1 px-2 py-1 bg-background-card border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                          />
+                        </label>
+                      </div>
+                    </div>
+                  )}
+                </For>
               </div>
             </Show>
           </section>
@@ -645,7 +1207,7 @@ const Settings: Component = () => {
                     type="text"
                     value={aiConfig()!.embedding.endpoint || ""}
                     onInput={(e) => updateEmbeddingConfig("endpoint", e.currentTarget.value || null)}
-                    placeholder="留空使用本地 MiniLM 模型"
+                    placeholder="留空使用本地模型"
                     class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
                   />
                 </div>
@@ -674,9 +1236,61 @@ const Settings: Component = () => {
                   />
                 </div>
 
+                <div>
+                  <label class="block text-sm text-foreground-secondary mb-1">本地模型</label>
+                  <select
+                    value={aiConfig()!.embedding.local_model}
+                    onChange={(e) => updateEmbeddingConfig("local_model", e.currentTarget.value)}
+                    class="w-full px-3 py-2 bg-background border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                  >
+                    <For each={localModels()}>
+                      {(m) => (
+                        <option value={m.name}>
+                          {m.name} ({m.dim}维){m.installed ? " · 已下载" : ""}
+                        </option>
+                      )}
+                    </For>
+                    <Show when={!localModels().some((m) => m.name === aiConfig()!.embedding.local_model)}>
+                      <option value={aiConfig()!.embedding.local_model}>
+                        {aiConfig()!.embedding.local_model} (自定义)
+                      </option>
+                    </Show>
+                  </select>
+                </div>
+
+                <label class="flex items-center gap-2 text-sm">
+                  <input
+                    type="checkbox"
+                    checked={aiConfig()!.embedding.offline}
+                    onChange={(e) => updateEmbeddingConfig("offline", e.currentTarget.checked)}
+                  />
+                  离线模式（只使用数据目录 models/ 中已放置的模型，不联网下载）
+                </label>
+
+                <p class="text-xs text-foreground-secondary">
+                  本地模型首次使用时下载到数据目录的 models/，默认 multilingual-e5-small 支持中文。
+                  如配置 API 但连接失败，将自动回退到本地模型。切换模型后，历史记录会在后台重新嵌入。
+                </p>
+
+                <div class="flex items-center gap-3 text-sm">
+                  <button
+                    onClick={benchmarkVectorSearch}
+                    disabled={benchmarking()}
+                    class="px-3 py-1 bg-background-secondary hover:bg-background rounded transition-colors disabled:opacity-50"
+                  >
+                    {benchmarking() ? "测试中..." : "向量检索基准测试"}
+                  </button>
+                  <Show when={vectorBenchmark()}>
+                    <span>
+                      {vectorBenchmark()!.vectors.toLocaleString()} 条向量 / {vectorBenchmark()!.partitions} 个分区
+                      （{vectorBenchmark()!.quantization}）：索引 {vectorBenchmark()!.ann_avg_ms.toFixed(1)} ms，
+                      精确 {vectorBenchmark()!.exact_avg_ms.toFixed(1)} ms，召回率{" "}
+                      {(vectorBenchmark()!.recall * 100).toFixed(1)}%
+                    </span>
+                  </Show>
+                </div>
                 <p class="text-xs text-foreground-secondary">
-                  本地模式使用 all-MiniLM-L6-v2 模型 (384维)，无需联网。
-                  如配置 API 但连接失败，将自动回退到本地模型。
+                  向量量化与按时间分区在 config.toml 的 [vector_index] 中配置，修改后索引会自动重建。
                 </p>
               </div>
             </Show>
@@ -749,6 +1363,82 @@ const Settings: Component = () => {
                   </button>
                 </div>
 
+                <div class="flex items-center justify-between p-3 bg-background rounded">
+                  <div>
+                    <p class="font-medium">复用近重复截图的分析结果</p>
+                    <p class="text-xs text-foreground-secondary">
+                      同一窗口内画面几乎不变（如只移动了光标）时不再调用 VLM
+                    </p>
+                  </div>
+                  <div class="flex items-center gap-3">
+                    <label class="text-sm text-foreground-secondary">
+                      最大差异
+                      <input
+                        type="number"
+                        value={aiConfig()!.vlm_task.reuse_max_distance}
+                        onInput={(e) =>
+                          updateVlmTaskConfig("reuse_max_distance", parseInt(e.currentTarget.value) || 0)
+                        }
+                        min={0}
+                        max={16}
+                        class="w-16 ml-2 px-2 py-1 bg-background-card border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                      />
+                    </label>
+                    <button
+                      onClick={() => updateVlmTaskConfig("reuse_enabled", !aiConfig()!.vlm_task.reuse_enabled)}
+                      class={`relative w-12 h-6 rounded-full transition-colors ${
+                        aiConfig()!.vlm_task.reuse_enabled ? "bg-accent" : "bg-gray-600"
+                      }`}
+                    >
+                      <span
+                        class={`absolute top-1 left-1 w-4 h-4 bg-white rounded-full transition-transform ${
+                          aiConfig()!.vlm_task.reuse_enabled ? "translate-x-6" : "translate-x-0"
+                        }`}
+                      />
+                    </button>
+                  </div>
+                </div>
+
+                <div class="flex items-center justify-between p-3 bg-background rounded">
+                  <div>
+                    <p class="font-medium">低置信度结果交给更强的模型复核</p>
+                    <p class="text-xs text-foreground-secondary">
+                      已配置 {aiConfig()!.vlm_task.escalation_tiers.length} 个升级层级（在配置文件的
+                      [[vlm_task.escalation_tiers]] 中添加）；关键操作也会复核
+                    </p>
+                  </div>
+                  <div class="flex items-center gap-3">
+                    <label class="text-sm text-foreground-secondary">
+                      置信度阈值
+                      <input
+                        type="number"
+                        value={aiConfig()!.vlm_task.escalation_confidence}
+                        onInput={(e) =>
+                          updateVlmTaskConfig("escalation_confidence", parseFloat(e.currentTarget.value) || 0)
+                        }
+                        min={0}
+                        max={1}
+                        step={0.05}
+                        class="w-20 ml-2 px-2 py-1 bg-background-card border border-gray-600 rounded focus:outline-none focus:ring-2 focus:ring-accent"
+                      />
+                    </label>
+                    <button
+                      onClick={() =>
+                        updateVlmTaskConfig("escalate_key_actions", !aiConfig()!.vlm_task.escalate_key_actions)
+                      }
+                      class={`relative w-12 h-6 rounded-full transition-colors ${
+                        aiConfig()!.vlm_task.escalate_key_actions ? "bg-accent" : "bg-gray-600"
+                      }`}
+                    >
+                      <span
+                        class={`absolute top-1 left-1 w-4 h-4 bg-white rounded-full transition-transform ${
+                          aiConfig()!.vlm_task.escalate_key_actions ? "translate-x-6" : "translate-x-0"
+                        }`}
+                      />
+                    </button>
+                  </div>
+                </div>
+
                 <p class="text-xs text-foreground-secondary">
                   提示：并发数越高处理速度越快，但会增加 API 调用压力。
                   建议本地模型设为 1-2，云端 API 设为 3-5。
@@ -1,5 +1,6 @@
 import { Component, createSignal, onMount, For, Show } from "solid-js";
 import { invoke } from "@tauri-apps/api/core";
+import { errorMessage } from "../lib/error";
 
 // 类型定义
 interface Summary {
@@ -21,6 +22,7 @@ interface Entity {
 interface ActivityCount {
   activity_type: string;
   count: number;
+  duration_ms?: number;
 }
 
 interface StructuredData {
@@ -76,15 +78,21 @@ const Summaries: Component = () => {
   };
 
   // 手动触发摘要生成
-  const triggerSummary = async (type: "short" | "daily") => {
+  const triggerSummary = async (type: "short" | "daily" | "weekly" | "monthly" | "custom") => {
     setGenerating(true);
     try {
-      const result = await invoke<string>("trigger_summary", { summaryType: type });
+      // 自定义范围使用当前选择的时间范围
+      const now = Date.now();
+      const range =
+        type === "custom"
+          ? { startTime: now - dateRange() * 24 * 60 * 60 * 1000, endTime: now }
+          : {};
+      const result = await invoke<string>("trigger_summary", { summaryType: type, ...range });
       alert(result);
       // 刷新列表
       fetchSummaries();
     } catch (e) {
-      alert(`生成失败: ${e}`);
+      alert(`生成失败: ${errorMessage(e)}`);
     } finally {
       setGenerating(false);
     }
@@ -129,6 +137,14 @@ const Summaries: Component = () => {
     return `${formatTime(start)} - ${formatTime(end)}`;
   };
 
+  // 格式化停留时长
+  const formatDuration = (ms: number) => {
+    const minutes = Math.round(ms / 60000);
+    if (minutes < 1) return "不到 1 分钟";
+    if (minutes < 60) return `${minutes} 分钟`;
+    return `${Math.floor(minutes / 60)} 小时 ${minutes % 60} 分钟`;
+  };
+
   // 获取摘要类型标签
   const getSummaryTypeLabel = (type: string) => {
     switch (type) {
@@ -136,6 +152,12 @@ const Summaries: Component = () => {
         return "15分钟摘要";
       case "daily":
         return "每日摘要";
+      case "weekly":
+        return "每周摘要";
+      case "monthly":
+        return "每月摘要";
+      case "custom":
+        return "自定义摘要";
       default:
         return type;
     }
@@ -148,6 +170,10 @@ const Summaries: Component = () => {
         return "bg-blue-500/20 text-blue-400";
       case "daily":
         return "bg-purple-500/20 text-purple-400";
+      case "weekly":
+        return "bg-pink-500/20 text-pink-400";
+      case "monthly":
+        return "bg-amber-500/20 text-amber-400";
       default:
         return "bg-gray-500/20 text-gray-400";
     }
@@ -194,6 +220,9 @@ const Summaries: Component = () => {
             <option value="all">全部类型</option>
             <option value="short">15分钟摘要</option>
             <option value="daily">每日摘要</option>
+            <option value="weekly">每周摘要</option>
+            <option value="monthly">每月摘要</option>
+            <option value="custom">自定义摘要</option>
           </select>
 
           {/* 时间范围 */}
@@ -228,7 +257,7 @@ const Summaries: Component = () => {
             >
               {generating() ? "生成中..." : "生成摘要 ▾"}
             </button>
-            <div class="absolute right-0 mt-1 w-32 bg-background-card border border-gray-700 rounded-lg shadow-lg opacity-0 invisible group-hover:opacity-100 group-hover:visible transition-all z-10">
+            <div class="absolute right-0 mt-1 w-36 bg-background-card border border-gray-700 rounded-lg shadow-lg opacity-0 invisible group-hover:opacity-100 group-hover:visible transition-all z-10">
               <button
                 onClick={() => triggerSummary("short")}
                 disabled={generating()}
@@ -239,10 +268,31 @@ const Summaries: Component = () => {
               <button
                 onClick={() => triggerSummary("daily")}
                 disabled={generating()}
-                class="w-full px-3 py-2 text-sm text-left hover:bg-gray-700 rounded-b-lg disabled:opacity-50"
+                class="w-full px-3 py-2 text-sm text-left hover:bg-gray-700 disabled:opacity-50"
               >
                 每日摘要
               </button>
+              <button
+                onClick={() => triggerSummary("weekly")}
+                disabled={generating()}
+                class="w-full px-3 py-2 text-sm text-left hover:bg-gray-700 disabled:opacity-50"
+              >
+                每周摘要
+              </button>
+              <button
+                onClick={() => triggerSummary("monthly")}
+                disabled={generating()}
+                class="w-full px-3 py-2 text-sm text-left hover:bg-gray-700 disabled:opacity-50"
+              >
+                每月摘要
+              </button>
+              <button
+                onClick={() => triggerSummary("custom")}
+                disabled={generating()}
+                class="w-full px-3 py-2 text-sm text-left hover:bg-gray-700 rounded-b-lg disabled:opacity-50"
+              >
+                所选时间范围
+              </button>
             </div>
           </div>
         </div>
@@ -397,7 +447,9 @@ const Summaries: Component = () => {
                                 {activity.activity_type}
                               </span>
                               <span class="text-sm text-foreground-secondary">
-                                {activity.count} 次
+                                {activity.duration_ms
+                                  ? formatDuration(activity.duration_ms)
+                                  : `${activity.count} 次`}
                               </span>
                             </div>
                           )}
@@ -14,6 +14,9 @@ interface ActivitySession {
   context_text: string | null;
   entities_json?: string | null;
   key_actions_json?: string | null;
+  state?: string;
+  closed_at?: number | null;
+  recap?: string | null;
 }
 
 interface Trace {
@@ -45,6 +48,12 @@ const Timeline: Component = () => {
   const [selectedTrace, setSelectedTrace] = createSignal<Trace | null>(null);
   const [selectedImageSrc, setSelectedImageSrc] = createSignal<string | null>(null);
   const [showSessionContext, setShowSessionContext] = createSignal(false);
+  const [editingSession, setEditingSession] = createSignal(false);
+  const [editTitle, setEditTitle] = createSignal("");
+  const [editDescription, setEditDescription] = createSignal("");
+  // 合并/移动的目标会话，空字符串表示新建会话
+  const [targetSessionId, setTargetSessionId] = createSignal("");
+  const [editError, setEditError] = createSignal<string | null>(null);
 
   const [collapsedHours, setCollapsedHours] = createSignal<Set<number>>(new Set());
   const [imageCache, setImageCache] = createSignal<Map<string, string>>(new Map());
@@ -158,6 +167,9 @@ const Timeline: Component = () => {
   const openSessionDetail = async (session: ActivitySession) => {
     setSelectedSession(session);
     setShowSessionContext(false);
+    setEditingSession(false);
+    setTargetSessionId("");
+    setEditError(null);
     setSelectedTrace(null);
     setSelectedImageSrc(null);
     try {
@@ -181,6 +193,81 @@ const Timeline: Component = () => {
     setSelectedImageSrc(null);
   };
 
+  const otherSessions = () => sessions().filter((s) => s.id !== selectedSession()?.id);
+
+  // 编辑后刷新列表，并重新打开仍然存在的会话
+  const reloadAfterEdit = async (sessionId: number, fallback?: ActivitySession) => {
+    await loadSessions(selectedDate());
+    const session = sessions().find((s) => s.id === sessionId) ?? fallback;
+    if (session) {
+      await openSessionDetail(session);
+    } else {
+      closeDetail();
+    }
+  };
+
+  const runSessionEdit = async (edit: (session: ActivitySession) => Promise<void>) => {
+    const session = selectedSession();
+    if (!session) return;
+    setEditError(null);
+    try {
+      await edit(session);
+    } catch (e) {
+      console.error("Failed to edit session:", e);
+      setEditError(String((e as { message?: string })?.message ?? e));
+    }
+  };
+
+  const startEditSession = () => {
+    const session = selectedSession();
+    if (!session) return;
+    setEditTitle(getSessionTitle(session));
+    setEditDescription(session.description ?? "");
+    setEditingSession(true);
+  };
+
+  const saveSessionText = () =>
+    runSessionEdit(async (session) => {
+      const updated = await invoke<ActivitySession>("update_activity_session", {
+        sessionId: session.id,
+        title: editTitle(),
+        description: editDescription(),
+      });
+      await reloadAfterEdit(session.id, updated);
+    });
+
+  const splitAtSelectedTrace = () =>
+    runSessionEdit(async (session) => {
+      const trace = selectedTrace();
+      if (!trace) return;
+      await invoke<ActivitySession>("split_activity_session", {
+        sessionId: session.id,
+        traceId: trace.id,
+      });
+      await reloadAfterEdit(session.id);
+    });
+
+  const moveSelectedTrace = () =>
+    runSessionEdit(async (session) => {
+      const trace = selectedTrace();
+      if (!trace) return;
+      const target = await invoke<ActivitySession>("move_traces_to_session", {
+        traceIds: [trace.id],
+        sessionId: targetSessionId() ? Number(targetSessionId()) : null,
+      });
+      await reloadAfterEdit(session.id, target);
+    });
+
+  const mergeTargetSession = () =>
+    runSessionEdit(async (session) => {
+      if (!targetSessionId()) return;
+      const merged = await invoke<ActivitySession>("merge_activity_sessions", {
+        targetId: session.id,
+        sourceIds: [Number(targetSessionId())],
+      });
+      await reloadAfterEdit(session.id, merged);
+    });
+
   const formatTime = (timestamp: number) => format(new Date(timestamp), "HH:mm:ss");
 
   return (
@@ -273,7 +360,12 @@ const Timeline: Component = () => {
                               >
                                 <div class="flex items-center justify-between gap-4">
                                   <div class="min-w-0">
-                                    <div class="font-medium truncate">{getSessionTitle(session)}</div>
+                                    <div class="flex items-center gap-2 min-w-0">
+                                      <div class="font-medium truncate">{getSessionTitle(session)}</div>
+                                      <Show when={session.state === "open"}>
+                                        <span class="shrink-0 text-xs px-1.5 py-0.5 rounded bg-accent/20 text-accent">进行中</span>
+                                      </Show>
+                                    </div>
                                     <Show when={session.title && session.title !== session.app_name}>
                                       <div class="text-xs text-foreground-secondary truncate">{session.app_name}</div>
                                     </Show>
@@ -325,7 +417,54 @@ const Timeline: Component = () => {
 
 	            <div class="max-h-[90vh] overflow-auto">
 	              <div class="p-6 pr-14">
-	              <h3 class="text-lg font-semibold mb-1">{getSessionTitle(selectedSession()!)}</h3>
+	              <Show
+	                when={editingSession()}
+	                fallback={
+	                  <div class="flex items-center gap-2 mb-1">
+	                    <h3 class="text-lg font-semibold">{getSessionTitle(selectedSession()!)}</h3>
+	                    <button
+	                      type="button"
+	                      class="text-xs text-foreground-secondary hover:text-white transition-colors"
+	                      onClick={startEditSession}
+	                    >
+	                      编辑
+	                    </button>
+	                  </div>
+	                }
+	              >
+	                <div class="space-y-2 mb-3">
+	                  <input
+	                    type="text"
+	                    class="w-full bg-background px-3 py-2 rounded text-sm"
+	                    value={editTitle()}
+	                    onInput={(e) => setEditTitle(e.currentTarget.value)}
+	                    placeholder="标题"
+	                  />
+	                  <textarea
+	                    class="w-full bg-background px-3 py-2 rounded text-sm"
+	                    rows={3}
+	                    value={editDescription()}
+	                    onInput={(e) => setEditDescription(e.currentTarget.value)}
+	                    placeholder="描述"
+	                  />
+	                  <div class="flex gap-2">
+	                    <button
+	                      type="button"
+	                      class="px-3 py-1 text-sm bg-accent hover:bg-accent-hover rounded transition-colors"
+	                      onClick={saveSessionText}
+	                    >
+	                      保存
+	                    </button>
+	                    <button
+	                      type="button"
+	                      class="px-3 py-1 text-sm bg-background-card hover:bg-background rounded transition-colors"
+	                      onClick={() => setEditingSession(false)}
+	                    >
+	                      取消
+	                    </button>
+	                  </div>
+	                </div>
+	              </Show>
 	              <Show when={selectedSession()!.title && selectedSession()!.title !== selectedSession()!.app_name}>
 	                <div class="text-sm text-foreground-secondary mb-3">{selectedSession()!.app_name}</div>
 	              </Show>
@@ -355,6 +494,59 @@ const Timeline: Component = () => {
 	                </Show>
 	              </dl>
 
+	              <div class="mt-4 flex flex-wrap items-center gap-2 text-sm">
+	                <select
+	                  class="bg-background px-2 py-1 rounded"
+	                  value={targetSessionId()}
+	                  onChange={(e) => setTargetSessionId(e.currentTarget.value)}
+	                >
+	                  <option value="">新会话</option>
+	                  <For each={otherSessions()}>
+	                    {(s) => (
+	                      <option value={String(s.id)}>
+	                        {format(new Date(s.start_time), "HH:mm")} {getSessionTitle(s)}
+	                      </option>
+	                    )}
+	                  </For>
+	                </select>
+	                <button
+	                  type="button"
+	                  class="px-3 py-1 bg-background-card hover:bg-background rounded transition-colors disabled:opacity-50"
+	                  disabled={!targetSessionId()}
+	                  onClick={mergeTargetSession}
+	                >
+	                  合并到本会话
+	                </button>
+	                <button
+	                  type="button"
+	                  class="px-3 py-1 bg-background-card hover:bg-background rounded transition-colors disabled:opacity-50"
+	                  disabled={!selectedTrace()}
+	                  onClick={moveSelectedTrace}
+	                >
+	                  移动选中 Trace
+	                </button>
+	                <button
+	                  type="button"
+	                  class="px-3 py-1 bg-background-card hover:bg-background rounded transition-colors disabled:opacity-50"
+	                  disabled={!selectedTrace()}
+	                  onClick={splitAtSelectedTrace}
+	                >
+	                  从选中 Trace 拆分
+	                </button>
+	              </div>
+	              <Show when={editError()}>
+	                <div class="mt-2 text-sm text-error">{editError()}</div>
+	              </Show>
+
+	              <Show when={selectedSession()?.recap}>
+	                <div class="mt-4">
+	                  <div class="text-sm text-foreground-secondary mb-1">会话回顾</div>
+	                  <div class="bg-background p-3 rounded text-sm whitespace-pre-wrap">
+	                    {selectedSession()!.recap}
+	                  </div>
+	                </div>
+	              </Show>
+
 	              <Show when={selectedSession()?.context_text}>
 	                <div class="mt-4">
 	                  <button